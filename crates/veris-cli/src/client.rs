@@ -214,6 +214,18 @@ impl Client {
                         StatementResult::DropTable(name) => {
                             println!("Dropped table {name}");
                         }
+                        StatementResult::Set(name) => {
+                            println!("Set {name}");
+                        }
+                        StatementResult::Analyze(tables) => {
+                            println!("Analyzed {}", tables.join(", "));
+                        }
+                        StatementResult::Prepare(name) => {
+                            println!("Prepared statement {name}");
+                        }
+                        StatementResult::Deallocate(name) => {
+                            println!("Deallocated statement {name}");
+                        }
                         StatementResult::Null => {}
                     }
 
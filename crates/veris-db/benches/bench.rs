@@ -6,8 +6,10 @@ use std::{
 
 use criterion::Criterion;
 use itertools::Itertools;
+use sqlparser::{dialect::GenericDialect, parser::Parser};
 use veris_db::{
     engine::{Catalog, Engine, Transaction, local::Local},
+    exec::session::Session,
     storage::bitcask::Bitcask,
     types::{
         schema::{Column, Table},
@@ -100,6 +102,15 @@ impl<E: Engine> Bench<E> {
         delta
     }
 
+    fn query(&self, sql: &str, vectorized: bool) -> Duration {
+        let statements = Parser::parse_sql(&GenericDialect {}, sql).unwrap();
+        let mut session = Session::new(&self.engine);
+        session.options_mut().vectorized = vectorized;
+        let now = Instant::now();
+        black_box(session.exec(black_box(&statements[0]))).unwrap();
+        now.elapsed()
+    }
+
     fn row(&self, id: i64) -> Row {
         Row::from(vec![
             Value::Integer(id),
@@ -144,6 +155,25 @@ impl<E: Engine> Bench<E> {
         self.drop_table();
     }
 
+    /// Compares the row executor with the vectorized one on a query over `n` rows.
+    fn bench_query(&self, mode: &str, c: &mut Criterion, name: &str, sql: &str, n: usize) {
+        let rows = self.n_rows(n);
+        self.create_table();
+        self.insert(rows);
+        for (executor, vectorized) in [("row", false), ("vectorized", true)] {
+            c.bench_function(&format!("{mode}_{name}_{n}_{executor}"), |b| {
+                b.iter_custom(|iters| {
+                    let mut delta = Duration::ZERO;
+                    for _ in 0..iters {
+                        delta += self.query(sql, vectorized);
+                    }
+                    delta
+                });
+            });
+        }
+        self.drop_table();
+    }
+
     fn bench_delete(&self, mode: &str, c: &mut Criterion) {
         c.bench_function(&format!("{mode}_delete"), |b| {
             b.iter_custom(|iters| {
@@ -218,6 +248,20 @@ where
     factory().bench_scan(engine, c, 1);
     factory().bench_scan(engine, c, 100);
     factory().bench_scan(engine, c, 10000);
+    factory().bench_query(
+        engine,
+        c,
+        "filter_scan",
+        "SELECT id, age * 2 FROM test WHERE age > 5000 AND age < 15000",
+        10000,
+    );
+    factory().bench_query(
+        engine,
+        c,
+        "aggregate",
+        "SELECT COUNT(*), SUM(age), AVG(age), MAX(name) FROM test WHERE age > 5000",
+        10000,
+    );
     factory().bench_delete(engine, c);
     factory().bench_get(engine, c);
     factory().bench_drop_table(engine, c);
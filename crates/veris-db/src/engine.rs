@@ -5,6 +5,7 @@ use crate::{
     exec::expr::Expr,
     types::{
         schema::Table,
+        statistics::TableStatistics,
         value::{Row, RowIter, Value},
     },
 };
@@ -18,8 +19,19 @@ pub trait Catalog {
     fn drop_table(&self, table: &str) -> Result<(), Error>;
     fn get_table(&self, table: &str) -> Result<Option<Table>, Error>;
     fn list_tables(&self) -> Result<Vec<Table>, Error>;
+    /// Returns a number that changes whenever a table is created or dropped, so that plans
+    /// built against the catalog can tell when they are out of date.
+    fn catalog_version(&self) -> Result<u64, Error>;
+    /// Returns the statistics of a table, if it has any. They are kept alongside the
+    /// table's schema, and refreshed by [`Transaction::analyze`]. In between, the row count
+    /// and the number of modified rows follow the writes to the table.
+    fn get_table_statistics(&self, table: &str) -> Result<Option<TableStatistics>, Error>;
+    fn set_table_statistics(&self, table: &str, statistics: TableStatistics) -> Result<(), Error>;
 }
 
+/// A partition of a table scan, which can be sent to another thread.
+pub type RowPartition = Box<dyn Iterator<Item = Result<Row, Error>> + Send>;
+
 pub trait Transaction: Catalog {
     fn commit(self) -> Result<(), Error>;
     fn rollback(self) -> Result<(), Error>;
@@ -28,12 +40,25 @@ pub trait Transaction: Catalog {
     fn get(&self, table: &str, ids: impl AsRef<[Value]>) -> Result<Box<[Row]>, Error>;
     fn insert(&self, table: &str, rows: impl AsRef<[Row]>) -> Result<(), Error>;
     fn scan(&self, table: &str, filter: Option<Expr>) -> Result<RowIter, Error>;
+    /// Splits a scan of the table into at most `partitions` scans over disjoint ranges of
+    /// primary keys, in key order, which together return the same rows as `scan`.
+    fn scan_partitions(&self, table: &str, partitions: usize) -> Result<Vec<RowPartition>, Error>;
     fn lookup_index(
         &self,
         table: &str,
         column: &str,
         values: &[Value],
     ) -> Result<BTreeSet<Value>, Error>;
+
+    /// Computes the statistics of a table from its rows and stores them in the catalog.
+    fn analyze(&self, table: &str) -> Result<TableStatistics, Error> {
+        let table = self
+            .get_table(table)?
+            .ok_or_else(|| Error::TableDoesNotExist(table.to_owned()))?;
+        let statistics = TableStatistics::analyze(&table, self.scan(&table.name, None)?)?;
+        self.set_table_statistics(&table.name, statistics.clone())?;
+        Ok(statistics)
+    }
 }
 
 pub trait Engine {
@@ -1,4 +1,8 @@
-use std::{borrow::Cow, collections::BTreeSet};
+use std::{
+    borrow::Cow,
+    collections::{BTreeSet, HashMap},
+    sync::Mutex,
+};
 
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
@@ -13,17 +17,22 @@ use crate::{
     },
     types::{
         schema::Table,
+        statistics::TableStatistics,
         value::{Row, RowIter, Value},
     },
 };
 
-use super::{Catalog, Engine, Transaction};
+use super::{Catalog, Engine, RowPartition, Transaction};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum Key<'a> {
     Table(Cow<'a, str>),
     Index(Cow<'a, str>, Cow<'a, str>, Cow<'a, Value>),
     Row(Cow<'a, str>, Cow<'a, Value>),
+    Statistics(Cow<'a, str>),
+    CatalogVersion,
+    /// The [`Modifications`] of a table, which are kept outside of transactions.
+    Modifications(Cow<'a, str>),
 }
 
 impl<'a> KeyEncoding<'a> for Key<'a> {}
@@ -33,10 +42,38 @@ pub enum KeyPrefix<'a> {
     Table,
     Index(Cow<'a, str>, Cow<'a, str>),
     Row(Cow<'a, str>),
+    Statistics,
 }
 
 impl<'a> KeyEncoding<'a> for KeyPrefix<'a> {}
 
+/// The number of rows written to a table since it was last analyzed. They are kept outside
+/// of transactions, so that concurrent writers to a table don't conflict on them, and each
+/// transaction adds its own writes to them when it commits.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Modifications {
+    inserted: u64,
+    deleted: u64,
+}
+
+impl ValueEncoding for Modifications {}
+
+impl Modifications {
+    fn apply(&self, delta: &ModificationsDelta) -> Self {
+        Self {
+            inserted: self.inserted.saturating_add_signed(delta.inserted),
+            deleted: self.deleted.saturating_add_signed(delta.deleted),
+        }
+    }
+}
+
+/// The change a transaction makes to the [`Modifications`] of a table.
+#[derive(Debug, Default, Clone, Copy)]
+struct ModificationsDelta {
+    inserted: i64,
+    deleted: i64,
+}
+
 pub struct Local<E: StorageEngine>(Mvcc<E>);
 
 impl<E: StorageEngine> Local<E> {
@@ -49,16 +86,23 @@ impl<E: StorageEngine + 'static> Engine for Local<E> {
     type Transaction = LocalTransaction<E>;
 
     fn begin(&self) -> Result<Self::Transaction, Error> {
-        Ok(LocalTransaction(self.0.begin()?))
+        Ok(LocalTransaction {
+            txn: self.0.begin()?,
+            modifications: Mutex::default(),
+        })
     }
 }
 
-pub struct LocalTransaction<E: StorageEngine>(MvccTransaction<E>);
+pub struct LocalTransaction<E: StorageEngine> {
+    txn: MvccTransaction<E>,
+    /// The changes to the modification counts of tables, which are applied on commit.
+    modifications: Mutex<HashMap<String, ModificationsDelta>>,
+}
 
 impl<E: StorageEngine> LocalTransaction<E> {
     fn get_row(&self, table: &str, id: &Value) -> Result<Option<Row>, Error> {
         let key = Key::Row(Cow::Borrowed(table), Cow::Borrowed(id)).encode()?;
-        if let Some(row) = self.0.get(&key)? {
+        if let Some(row) = self.txn.get(&key)? {
             return Ok(Some(Row::decode(&row)?));
         }
         Ok(None)
@@ -71,7 +115,7 @@ impl<E: StorageEngine> LocalTransaction<E> {
         value: &Value,
     ) -> Result<BTreeSet<Value>, Error> {
         Ok(self
-            .0
+            .txn
             .get(
                 &Key::Index(
                     Cow::Borrowed(table),
@@ -99,14 +143,57 @@ impl<E: StorageEngine> LocalTransaction<E> {
         )
         .encode()?;
         if ids.is_empty() {
-            self.0.delete(&key)?;
+            self.txn.delete(&key)?;
         } else {
-            self.0.set(&key, &ids.encode()?)?;
+            self.txn.set(&key, &ids.encode()?)?;
         }
 
         Ok(())
     }
 
+    /// Counts rows written to a table, to keep its statistics roughly up to date until it
+    /// is analyzed again.
+    fn add_modifications(&self, table: &str, inserted: usize, deleted: usize) -> Result<(), Error> {
+        let mut modifications = self.modifications.lock()?;
+        let delta = modifications.entry(table.to_owned()).or_default();
+        delta.inserted += i64::try_from(inserted)?;
+        delta.deleted += i64::try_from(deleted)?;
+        Ok(())
+    }
+
+    /// Forgets the rows written to a table, once its statistics account for them. Only
+    /// the rows counted so far are forgotten, so that the rows other transactions write
+    /// in the meantime are still counted once they commit.
+    fn clear_modifications(&self, table: &str) -> Result<(), Error> {
+        let committed = self.committed_modifications(table)?;
+        self.modifications.lock()?.insert(
+            table.to_owned(),
+            ModificationsDelta {
+                inserted: -i64::try_from(committed.inserted)?,
+                deleted: -i64::try_from(committed.deleted)?,
+            },
+        );
+        Ok(())
+    }
+
+    /// Returns the rows written to a table by committed transactions.
+    fn committed_modifications(&self, table: &str) -> Result<Modifications, Error> {
+        let key = Key::Modifications(Cow::Borrowed(table)).encode()?;
+        self.txn
+            .get_unversioned(&key)?
+            .map(|value| Modifications::decode(&value))
+            .transpose()
+            .map(Option::unwrap_or_default)
+    }
+
+    /// Changes the catalog version, after the schema of a table changes.
+    fn bump_catalog_version(&self) -> Result<(), Error> {
+        let version = self.catalog_version()? + 1;
+        self.txn
+            .set(&Key::CatalogVersion.encode()?, &version.encode()?)?;
+        Ok(())
+    }
+
     fn table_refs(&self, referenced_table: &String) -> Result<Vec<(Table, Vec<usize>)>, Error> {
         let tables = self.list_tables()?;
         let mut refs = Vec::new();
@@ -132,12 +219,26 @@ impl<E: StorageEngine> LocalTransaction<E> {
 
 impl<E: StorageEngine + 'static> Transaction for LocalTransaction<E> {
     fn commit(self) -> Result<(), Error> {
-        self.0.commit()?;
+        for (table, delta) in self.modifications.into_inner()? {
+            let key = Key::Modifications(Cow::Borrowed(&table)).encode()?;
+            self.txn.update_unversioned(&key, |value| {
+                let modifications = value
+                    .map(|value| Modifications::decode(&value))
+                    .transpose()?
+                    .unwrap_or_default()
+                    .apply(&delta);
+                if modifications.inserted == 0 && modifications.deleted == 0 {
+                    return Ok(None);
+                }
+                Ok(Some(modifications.encode()?))
+            })?;
+        }
+        self.txn.commit()?;
         Ok(())
     }
 
     fn rollback(self) -> Result<(), Error> {
-        self.0.rollback()?;
+        self.txn.rollback()?;
         Ok(())
     }
 
@@ -185,21 +286,22 @@ impl<E: StorageEngine + 'static> Transaction for LocalTransaction<E> {
             }
         }
 
+        let mut deleted = 0;
         for id in ids {
-            if !indices.is_empty()
-                && let Some(row) = self.get_row(&table.name, id)?
-            {
-                for (i, column) in indices.iter().copied() {
-                    let mut ids = self.get_index(&table.name, &column.name, &row[i])?;
-                    ids.remove(id);
-                    self.set_index(&table.name, &column.name, &row[i], &ids)?;
-                }
+            let Some(row) = self.get_row(&table.name, id)? else {
+                continue;
+            };
+            for (i, column) in indices.iter().copied() {
+                let mut ids = self.get_index(&table.name, &column.name, &row[i])?;
+                ids.remove(id);
+                self.set_index(&table.name, &column.name, &row[i], &ids)?;
             }
 
-            self.0
+            self.txn
                 .delete(&Key::Row(Cow::Borrowed(&table.name), Cow::Borrowed(id)).encode()?)?;
+            deleted += 1;
         }
-        Ok(())
+        self.add_modifications(&table.name, 0, deleted)
     }
 
     fn get(&self, table: &str, ids: impl AsRef<[Value]>) -> Result<Box<[Row]>, Error> {
@@ -221,7 +323,7 @@ impl<E: StorageEngine + 'static> Transaction for LocalTransaction<E> {
             let id = &row[table.primary_key_index];
 
             let key = Key::Row(Cow::Borrowed(&table.name), Cow::Borrowed(id)).encode()?;
-            self.0.set(&key, &row.encode()?)?;
+            self.txn.set(&key, &row.encode()?)?;
 
             for (i, column) in table
                 .columns
@@ -234,7 +336,7 @@ impl<E: StorageEngine + 'static> Transaction for LocalTransaction<E> {
                 self.set_index(&table.name, &column.name, &row[i], &ids)?;
             }
         }
-        Ok(())
+        self.add_modifications(&table.name, rows.len(), 0)
     }
 
     fn lookup_index(
@@ -253,7 +355,7 @@ impl<E: StorageEngine + 'static> Transaction for LocalTransaction<E> {
     fn scan(&self, table: &str, filter: Option<Expr>) -> Result<RowIter, Error> {
         let key = KeyPrefix::Row(Cow::Borrowed(table)).encode()?;
         let rows = self
-            .0
+            .txn
             .scan_prefix(&key)?
             .map(|res| res.and_then(|(_, value)| Row::decode(&value)));
 
@@ -263,23 +365,38 @@ impl<E: StorageEngine + 'static> Transaction for LocalTransaction<E> {
         let rows = rows.filter_map(move |res| {
             res.and_then(|row| match filter.eval(Some(&row))? {
                 Value::Boolean(true) => Ok(Some(row)),
-                Value::Boolean(false) => Ok(None),
+                Value::Boolean(false) | Value::Null => Ok(None),
                 value => Err(Error::InvalidFilterResult(value)),
             })
             .transpose()
         });
         Ok(RowIter::new(rows))
     }
+
+    fn scan_partitions(&self, table: &str, partitions: usize) -> Result<Vec<RowPartition>, Error> {
+        let key = KeyPrefix::Row(Cow::Borrowed(table)).encode()?;
+        Ok(self
+            .txn
+            .scan_prefix_partitions(&key, partitions)?
+            .into_iter()
+            .map(|scan| {
+                Box::new(scan.map(|res| res.and_then(|(_, value)| Row::decode(&value))))
+                    as RowPartition
+            })
+            .collect())
+    }
 }
 
 impl<E: StorageEngine> Catalog for LocalTransaction<E> {
     fn create_table(&self, table: Table) -> Result<(), Error> {
         let table_key = Key::Table(Cow::Borrowed(&table.name)).encode()?;
-        if self.0.get(&table_key)?.is_some() {
+        if self.txn.get(&table_key)?.is_some() {
             return Err(Error::TableAlreadyExists(table.name));
         }
-        self.0.set(&table_key, &table.encode()?)?;
-        Ok(())
+        self.txn.set(&table_key, &table.encode()?)?;
+        self.bump_catalog_version()?;
+        // Track the table's size from the start, so that it is analyzed once it fills up.
+        self.set_table_statistics(&table.name, TableStatistics::default())
     }
 
     fn drop_table(&self, table: &str) -> Result<(), Error> {
@@ -288,18 +405,24 @@ impl<E: StorageEngine> Catalog for LocalTransaction<E> {
         };
 
         // delete the table schema
-        self.0
+        self.txn
             .delete(&Key::Table(Cow::Borrowed(&table.name)).encode()?)?;
+        self.bump_catalog_version()?;
+
+        // delete the statistics
+        self.txn
+            .delete(&Key::Statistics(Cow::Borrowed(&table.name)).encode()?)?;
+        self.clear_modifications(&table.name)?;
 
         // delete the rows
         let prefix = KeyPrefix::Row(Cow::Borrowed(&table.name)).encode()?;
         let elems: Vec<_> = self
-            .0
+            .txn
             .scan_prefix(&prefix)?
             .map_ok(|r| r.0.to_vec())
             .try_collect()?;
         for key in elems {
-            self.0.delete(&key)?;
+            self.txn.delete(&key)?;
         }
 
         // delete any secondary indices
@@ -308,9 +431,9 @@ impl<E: StorageEngine> Catalog for LocalTransaction<E> {
                 let prefix =
                     KeyPrefix::Index(Cow::Borrowed(&table.name), Cow::Borrowed(&column.name))
                         .encode()?;
-                let mut keys = self.0.scan_prefix(&prefix)?.map_ok(|(key, _)| key);
+                let mut keys = self.txn.scan_prefix(&prefix)?.map_ok(|(key, _)| key);
                 while let Some(key) = keys.next().transpose()? {
-                    self.0.delete(&key)?;
+                    self.txn.delete(&key)?;
                 }
             }
         }
@@ -320,7 +443,7 @@ impl<E: StorageEngine> Catalog for LocalTransaction<E> {
 
     fn get_table(&self, table: &str) -> Result<Option<Table>, Error> {
         let table_key = Key::Table(Cow::Borrowed(table)).encode()?;
-        if let Some(table) = self.0.get(&table_key)? {
+        if let Some(table) = self.txn.get(&table_key)? {
             return Ok(Some(Table::decode(&table)?));
         }
         Ok(None)
@@ -328,11 +451,44 @@ impl<E: StorageEngine> Catalog for LocalTransaction<E> {
 
     fn list_tables(&self) -> Result<Vec<Table>, Error> {
         let prefix = KeyPrefix::Table.encode()?;
-        self.0
+        self.txn
             .scan_prefix(&prefix)?
             .map(|r| r.and_then(|(_, v)| Table::decode(&v)))
             .try_collect()
     }
+
+    fn catalog_version(&self) -> Result<u64, Error> {
+        self.txn
+            .get(&Key::CatalogVersion.encode()?)?
+            .map(|version| u64::decode(&version))
+            .transpose()
+            .map(Option::unwrap_or_default)
+    }
+
+    fn get_table_statistics(&self, table: &str) -> Result<Option<TableStatistics>, Error> {
+        let key = Key::Statistics(Cow::Borrowed(table)).encode()?;
+        let Some(statistics) = self.txn.get(&key)? else {
+            return Ok(None);
+        };
+        let mut statistics = TableStatistics::decode(&statistics)?;
+        // The transaction sees its own writes, as well as those of committed ones.
+        let mut modifications = self.committed_modifications(table)?;
+        if let Some(delta) = self.modifications.lock()?.get(table) {
+            modifications = modifications.apply(delta);
+        }
+        statistics.row_count =
+            (statistics.row_count + modifications.inserted).saturating_sub(modifications.deleted);
+        statistics.modified_count = modifications.inserted + modifications.deleted;
+        Ok(Some(statistics))
+    }
+
+    /// Stores the statistics of a table, which are taken to account for every row written
+    /// to it so far.
+    fn set_table_statistics(&self, table: &str, statistics: TableStatistics) -> Result<(), Error> {
+        let key = Key::Statistics(Cow::Borrowed(table)).encode()?;
+        self.txn.set(&key, &statistics.encode()?)?;
+        self.clear_modifications(table)
+    }
 }
 
 #[cfg(test)]
@@ -447,6 +603,42 @@ mod tests {
         assert_eq!(result.count(), 2);
     }
 
+    #[test]
+    fn test_scan_partitions() {
+        let engine = create_test_engine();
+        let table = create_test_table();
+        let tx = engine.begin().unwrap();
+        tx.create_table(table.clone()).unwrap();
+        let rows: Vec<_> = (0..100)
+            .map(|i| Row::from(vec![Value::Integer(i), Value::String(format!("name{i}"))]))
+            .collect();
+        tx.insert(&table.name, rows).unwrap();
+        tx.commit().unwrap();
+
+        // Deleted rows leave several versions of their keys behind.
+        let tx = engine.begin().unwrap();
+        let ids: Vec<_> = (10..20).map(Value::Integer).collect();
+        tx.delete(&table.name, ids).unwrap();
+        tx.commit().unwrap();
+
+        // Writes of uncommitted transactions aren't visible to any partition.
+        let concurrent = engine.begin().unwrap();
+        concurrent
+            .delete(&table.name, vec![Value::Integer(50)])
+            .unwrap();
+
+        let tx = engine.begin().unwrap();
+        let expected: Vec<_> = tx.scan(&table.name, None).unwrap().try_collect().unwrap();
+        assert_eq!(expected.len(), 90);
+        for partitions in [1, 4, 200] {
+            let scans = tx.scan_partitions(&table.name, partitions).unwrap();
+            assert!(scans.len() <= partitions);
+            let rows: Vec<_> = scans.into_iter().flatten().try_collect().unwrap();
+            assert_eq!(rows, expected);
+        }
+        assert_eq!(tx.scan_partitions(&table.name, 4).unwrap().len(), 4);
+    }
+
     #[test]
     fn test_get() {
         let engine = create_test_engine();
@@ -573,4 +765,54 @@ mod tests {
             Error::TableAlreadyExists("test".to_owned())
         );
     }
+
+    #[test]
+    fn test_statistics() {
+        let engine = create_test_engine();
+        let tx = engine.begin().unwrap();
+        let table = create_test_table();
+        tx.create_table(table.clone()).unwrap();
+        assert_eq!(
+            tx.get_table_statistics(&table.name).unwrap(),
+            Some(TableStatistics::default())
+        );
+
+        // A few writes only adjust the row count.
+        let row = |i: i64| {
+            Row::from(vec![
+                Value::Integer(i),
+                Value::String(format!("{}", i % 10)),
+            ])
+        };
+        tx.insert(&table.name, (0..10).map(row).collect_vec())
+            .unwrap();
+        let statistics = tx.get_table_statistics(&table.name).unwrap().unwrap();
+        assert_eq!(statistics.row_count, 10);
+        assert_eq!(statistics.modified_count, 10);
+        assert!(statistics.columns.is_empty());
+
+        assert!(!statistics.is_stale());
+
+        // Many of them make the statistics stale, until the table is analyzed again.
+        tx.insert(&table.name, (10..100).map(row).collect_vec())
+            .unwrap();
+        let statistics = tx.get_table_statistics(&table.name).unwrap().unwrap();
+        assert_eq!(statistics.row_count, 100);
+        assert!(statistics.is_stale());
+        tx.analyze(&table.name).unwrap();
+        let statistics = tx.get_table_statistics(&table.name).unwrap().unwrap();
+        assert_eq!(statistics.modified_count, 0);
+        assert_eq!(statistics.columns[1].distinct_count, 10);
+
+        // Deleting rows that don't exist modifies nothing.
+        tx.delete(&table.name, [Value::Integer(0), Value::Integer(1000)])
+            .unwrap();
+        let statistics = tx.get_table_statistics(&table.name).unwrap().unwrap();
+        assert_eq!(statistics.row_count, 99);
+        assert_eq!(statistics.modified_count, 1);
+        assert_eq!(tx.analyze(&table.name).unwrap().row_count, 99);
+
+        tx.drop_table(&table.name).unwrap();
+        assert_eq!(tx.get_table_statistics(&table.name).unwrap(), None);
+    }
 }
@@ -13,18 +13,35 @@ pub enum Error {
     AggregateNotFound(String),
     #[error("Already in transaction")]
     AlreadyInTransaction,
+    #[error("Ambiguous column: {}", _0)]
+    AmbiguousColumn(String),
     #[error("Column not found: {}", _0)]
     ColumnNotFound(String),
+    #[error(
+        "Column {} must appear in the GROUP BY clause or be used in an aggregate function",
+        _0
+    )]
+    ColumnNotGrouped(String),
+    #[error("Division by zero")]
+    DivisionByZero,
     #[error("Duplicate aggregate function: {}", _0)]
     DuplicateAggregate(String),
     #[error("Duplicate column: {}", _0)]
     DuplicateColumn(ColumnLabel),
+    #[error("Duplicate function: {}", _0)]
+    DuplicateFunction(String),
+    #[error("Duplicate prepared statement: {}", _0)]
+    DuplicatePreparedStatement(String),
     #[error("Duplicate table: {}", _0)]
     DuplicateTable(String),
     #[error("Error parsing AST: {}", _0)]
     FromAst(String),
+    #[error("Function not found: {}", _0)]
+    FunctionNotFound(String),
     #[error("Integer overflow")]
     IntegerOverflow,
+    #[error("Invalid argument: {}", _0)]
+    InvalidArgument(String),
     #[error("Invalid cast from {} to {}", value, to)]
     InvalidCast { value: Value, to: DataType },
     #[error("Invalid column index: {}", _0)]
@@ -39,6 +56,8 @@ pub enum Error {
     InvalidEngineState(String),
     #[error("Invalid filter result: {}", _0)]
     InvalidFilterResult(Value),
+    #[error("Invalid function result: {}", _0)]
+    InvalidFunctionResult(String),
     #[error("Invalid plan")]
     InvalidPlan,
     #[error("Invalid primary key: {}", _0)]
@@ -55,14 +74,23 @@ pub enum Error {
     InvalidValue(Box<ast::Value>),
     #[error("I/O error: {}", _0)]
     Io(String),
+    #[error(
+        "Query exceeded its memory limit of {} bytes and spilling to disk is disabled",
+        _0
+    )]
+    MemoryLimitExceeded(usize),
     #[error("Not in transaction")]
     NotInTransaction,
     #[error("Not yet supported: {}", _0)]
     NotYetSupported(String),
     #[error("Error in order of operations: {}", _0)]
     OutOfOrder(String),
+    #[error("Expected {} parameters, got {}", expected, actual)]
+    ParameterCount { expected: usize, actual: usize },
     #[error("Poisoned mutex")]
     PoisonedMutex,
+    #[error("Prepared statement does not exist: {}", _0)]
+    PreparedStatementDoesNotExist(String),
     #[error(
         "Referential integrity violation: {}.{} = {}",
         table,
@@ -78,12 +106,18 @@ pub enum Error {
     RowNotFound,
     #[error("Error de/serializing: {}", _0)]
     Serialization(String),
+    #[error("Subquery returned more than one row")]
+    SubqueryMultipleRows,
     #[error("Table already exists: {}", _0)]
     TableAlreadyExists(String),
     #[error("Table does not exist: {}", _0)]
     TableDoesNotExist(String),
     #[error("Transaction is read-only")]
     TransactionReadOnly,
+    #[error("No value bound to parameter ${}", _0)]
+    UnboundParameter(usize),
+    #[error("Unknown setting: {}", _0)]
+    UnknownSetting(String),
 }
 
 impl<T> From<PoisonError<T>> for Error {
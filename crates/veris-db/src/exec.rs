@@ -1,12 +1,19 @@
 pub use self::{
-    aggregate::*, executor::*, expr::*, join::*, plan::*, planner::*, scope::*, session::*,
+    aggregate::*, batch::*, executor::*, expr::*, function::*, join::*, plan::*, planner::*,
+    scope::*, session::*,
 };
 
 pub mod aggregate;
+pub mod batch;
+pub mod cost;
 pub mod executor;
 pub mod expr;
+pub mod function;
 pub mod join;
+pub mod optimizer;
 pub mod plan;
 pub mod planner;
 pub mod scope;
 pub mod session;
+pub mod sort;
+pub mod spill;
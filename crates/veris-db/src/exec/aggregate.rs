@@ -1,20 +1,44 @@
-use std::collections::BTreeMap;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt,
+    hash::{Hash, Hasher},
+    ops::ControlFlow,
+    sync::Arc,
+};
 
 use derive_more::Display;
 use itertools::Itertools;
-use sqlparser::ast;
+use serde::{Deserialize, Serialize};
+use sqlparser::ast::{self, Visit, Visitor, helpers::attached_token::AttachedToken};
 
 use crate::{
     error::Error,
-    types::value::{Row, RowIter, Value},
+    types::value::{DataType, Row, RowIter, Value},
+};
+
+use super::{
+    batch::{Batch, ColumnVector},
+    expr::Expr,
+    function::{Volatility, check_result, coerce},
+    spill::{
+        MAX_SPILL_DEPTH, MemoryBudget, MemoryReservation, SPILL_PARTITIONS, SpillFile, SpillReader,
+        partition_of, value_size,
+    },
 };
 
-use super::expr::Expr;
+/// A group's key and the state of its aggregates, as spilled to disk.
+type GroupRecord = (Vec<Value>, Vec<Accumulator>);
 
 pub struct Aggregator {
     group_by: Vec<Expr>,
     aggregates: Vec<Aggregate>,
     groups: BTreeMap<Vec<Value>, Vec<Accumulator>>,
+    memory: MemoryReservation,
+    /// The groups spilled to disk, partitioned by the hash of their key. Empty until the
+    /// groups first go over the memory budget.
+    partitions: Vec<SpillFile<GroupRecord>>,
+    /// How many times the groups have been partitioned already.
+    depth: usize,
 }
 
 impl Aggregator {
@@ -23,30 +47,158 @@ impl Aggregator {
             group_by,
             aggregates,
             groups: BTreeMap::new(),
+            memory: MemoryBudget::unlimited().reserve(),
+            partitions: Vec::new(),
+            depth: 0,
         }
     }
 
+    /// Limits the memory used for the groups to the budget. Once they go over it, the
+    /// groups are spilled to disk, partitioned by their keys, and each partition is
+    /// aggregated separately when finishing.
+    pub fn with_memory(mut self, budget: &MemoryBudget) -> Self {
+        self.memory = budget.reserve();
+        self
+    }
+
     pub fn add_row(&mut self, row: &Row) -> Result<(), Error> {
         let key = self
             .group_by
             .iter()
             .map(|expr| expr.eval(Some(row)))
             .collect::<Result<Vec<_>, _>>()?;
+        let values = self
+            .aggregates
+            .iter()
+            .map(|aggregate| aggregate.expr().eval(Some(row)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let accumulators = self.group(key)?;
+        for (accumulator, value) in accumulators.iter_mut().zip(values) {
+            accumulator.add_value(value)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn add_batch(&mut self, batch: &Batch) -> Result<(), Error> {
+        let values = self
+            .aggregates
+            .iter()
+            .map(|aggregate| aggregate.expr().eval_batch(batch))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Without GROUP BY, every row goes into the same group, so whole vectors can be
+        // accumulated at once.
+        if self.group_by.is_empty() {
+            let accumulators = self.group(Vec::new())?;
+            for (accumulator, values) in accumulators.iter_mut().zip(&values) {
+                accumulator.add_vector(values)?;
+            }
+            return Ok(());
+        }
+
+        let keys = self
+            .group_by
+            .iter()
+            .map(|expr| expr.eval_batch(batch))
+            .collect::<Result<Vec<_>, _>>()?;
+        for i in 0..batch.len() {
+            let key = keys.iter().map(|key| key.get(i)).collect();
+            let accumulators = self.group(key)?;
+            for (accumulator, values) in accumulators.iter_mut().zip(&values) {
+                accumulator.add_value(values.get(i))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the accumulators of the group with the given key, creating it if needed.
+    fn group(&mut self, key: Vec<Value>) -> Result<&mut Vec<Accumulator>, Error> {
+        if !self.groups.contains_key(&key) {
+            let accumulators: Vec<_> = self.aggregates.iter().map(Accumulator::new).collect();
+            let size = size_of::<GroupRecord>()
+                + key.iter().map(value_size).sum::<usize>()
+                + accumulators.len() * size_of::<Accumulator>();
+            if self.depth >= MAX_SPILL_DEPTH {
+                self.memory.force_grow(size);
+            } else if !self.memory.try_grow(size)? {
+                self.spill()?;
+                self.memory.force_grow(size);
+            }
+            self.groups.insert(key.clone(), accumulators);
+        }
+        Ok(self.groups.entry(key).or_default())
+    }
 
-        let accumulators = self
-            .groups
-            .entry(key)
-            .or_insert_with(|| self.aggregates.iter().map(Accumulator::new).collect());
+    /// Moves the groups in memory to their partitions on disk.
+    fn spill(&mut self) -> Result<(), Error> {
+        if self.partitions.is_empty() {
+            self.partitions = (0..SPILL_PARTITIONS)
+                .map(|_| SpillFile::new())
+                .collect::<Result<_, _>>()?;
+        }
+        for (key, accumulators) in std::mem::take(&mut self.groups) {
+            let partition = partition_of(&key, self.depth);
+            self.partitions[partition].write(&(key, accumulators))?;
+        }
+        self.memory.clear();
+        Ok(())
+    }
 
-        for (accumulator, aggregate) in accumulators.iter_mut().zip(&self.aggregates) {
-            accumulator.add_value(aggregate.expr().eval(Some(row))?)?;
+    /// Merges the state of a group into the group with the same key.
+    fn merge_group(
+        &mut self,
+        key: Vec<Value>,
+        accumulators: Vec<Accumulator>,
+    ) -> Result<(), Error> {
+        for (accumulator, other) in self.group(key)?.iter_mut().zip(accumulators) {
+            accumulator.merge(other)?;
         }
+        Ok(())
+    }
 
+    /// Merges the groups of another aggregator over the same aggregates, such as one that
+    /// aggregated another partition of the input.
+    pub fn merge(&mut self, other: Aggregator) -> Result<(), Error> {
+        for (key, accumulators) in other.groups {
+            self.merge_group(key, accumulators)?;
+        }
+        for partition in other.partitions {
+            for record in partition.into_reader()? {
+                let (key, accumulators) = record?;
+                self.merge_group(key, accumulators)?;
+            }
+        }
         Ok(())
     }
 
-    pub fn finish(self) -> Result<RowIter, Error> {
-        let groups = self.groups.into_iter().collect::<Vec<_>>();
+    pub fn finish(mut self) -> Result<RowIter, Error> {
+        if !self.partitions.is_empty() {
+            self.spill()?;
+            return Ok(RowIter::new(SpilledGroups {
+                partitions: self
+                    .partitions
+                    .into_iter()
+                    .map(SpillFile::into_reader)
+                    .collect::<Result<_, _>>()?,
+                current: None,
+                group_by: self.group_by,
+                aggregates: self.aggregates,
+                budget: self.memory.budget().clone(),
+                depth: self.depth + 1,
+            }));
+        }
+
+        let mut groups = self.groups.into_iter().collect::<Vec<_>>();
+        // Without GROUP BY there is always exactly one group, even if there were no rows.
+        if groups.is_empty() && self.group_by.is_empty() {
+            groups.push((
+                Vec::new(),
+                self.aggregates.iter().map(Accumulator::new).collect(),
+            ));
+        }
         Ok(RowIter::new(groups.into_iter().map(|(keys, accums)| {
             keys.into_iter()
                 .map(Ok)
@@ -56,13 +208,67 @@ impl Aggregator {
     }
 }
 
+/// The results of an aggregation whose groups were spilled to disk. The partitions are
+/// aggregated one at a time, so groups come out ordered by key within each partition.
 #[derive(Clone)]
+struct SpilledGroups {
+    partitions: VecDeque<SpillReader<GroupRecord>>,
+    current: Option<RowIter>,
+    group_by: Vec<Expr>,
+    aggregates: Vec<Aggregate>,
+    budget: MemoryBudget,
+    depth: usize,
+}
+
+impl SpilledGroups {
+    fn try_next(&mut self) -> Result<Option<Row>, Error> {
+        loop {
+            if let Some(row) = self.current.as_mut().and_then(Iterator::next) {
+                return row.map(Some);
+            }
+            let Some(partition) = self.partitions.pop_front() else {
+                return Ok(None);
+            };
+
+            // The partition may itself be too large, and be partitioned again.
+            let mut aggregator = Aggregator::new(self.group_by.clone(), self.aggregates.clone())
+                .with_memory(&self.budget);
+            aggregator.depth = self.depth;
+            for record in partition {
+                let (key, accumulators) = record?;
+                aggregator.merge_group(key, accumulators)?;
+            }
+            self.current = Some(aggregator.finish()?);
+        }
+    }
+}
+
+impl Iterator for SpilledGroups {
+    type Item = Result<Row, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.try_next().transpose()
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub enum Accumulator {
-    Average { count: i64, sum: Value },
+    Average {
+        count: i64,
+        sum: Value,
+    },
     Count(i64),
     Max(Option<Value>),
     Min(Option<Value>),
     Sum(Option<Value>),
+    /// The state of a user aggregate. The function itself isn't spilled to disk along
+    /// with the state, but spilled states are only ever merged into accumulators that
+    /// have it.
+    User {
+        #[serde(skip)]
+        function: Option<Arc<UserAggregate>>,
+        state: Vec<Value>,
+    },
 }
 
 impl Accumulator {
@@ -76,6 +282,10 @@ impl Accumulator {
             Aggregate::Max(_) => Self::Max(None),
             Aggregate::Min(_) => Self::Min(None),
             Aggregate::Sum(_) => Self::Sum(None),
+            Aggregate::User(function, _) => Self::User {
+                function: Some(function.clone()),
+                state: (function.init)(),
+            },
         }
     }
 
@@ -104,11 +314,107 @@ impl Accumulator {
             }
             Self::Sum(sum @ None) => *sum = Some(Value::Integer(0).checked_add(&value)?),
             Self::Sum(Some(sum)) => *sum = sum.checked_add(&value)?,
+            Self::User {
+                function: Some(function),
+                state,
+            } => (function.accumulate)(state, coerce(&value, &function.arg)?)?,
+            Self::User { function: None, .. } => return Err(Error::InvalidPlan),
         }
 
         Ok(())
     }
 
+    /// Adds every value of a vector, with the same result as adding them one at a time.
+    pub fn add_vector(&mut self, values: &ColumnVector) -> Result<(), Error> {
+        match (self, values) {
+            (Self::Count(count), values) => {
+                *count += (0..values.len())
+                    .filter(|i| values.get(*i) != Value::Null)
+                    .count() as i64;
+            }
+            (Self::Sum(sum @ (None | Some(Value::Integer(_)))), ColumnVector::Integer(values)) => {
+                for value in values.iter().flatten() {
+                    let total = match sum {
+                        Some(Value::Integer(total)) => *total,
+                        _ => 0,
+                    };
+                    *sum = Some(Value::Integer(
+                        total.checked_add(*value).ok_or(Error::IntegerOverflow)?,
+                    ));
+                }
+            }
+            (Self::Sum(sum @ (None | Some(Value::Float(_)))), ColumnVector::Float(values)) => {
+                for value in values.iter().flatten() {
+                    let total = match sum {
+                        Some(Value::Float(total)) => *total,
+                        _ => 0.0,
+                    };
+                    *sum = Some(Value::Float(total + value));
+                }
+            }
+            (
+                Self::Average {
+                    count,
+                    sum: Value::Integer(sum),
+                },
+                ColumnVector::Integer(values),
+            ) => {
+                for value in values.iter().flatten() {
+                    *sum = sum.checked_add(*value).ok_or(Error::IntegerOverflow)?;
+                    *count += 1;
+                }
+            }
+            (accumulator, values) => {
+                for i in 0..values.len() {
+                    accumulator.add_value(values.get(i))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Merges the state of another accumulator of the same aggregate into this one.
+    pub fn merge(&mut self, other: Accumulator) -> Result<(), Error> {
+        match (self, other) {
+            (
+                Self::Average { count, sum },
+                Self::Average {
+                    count: other_count,
+                    sum: other_sum,
+                },
+            ) => {
+                *sum = sum.checked_add(&other_sum)?;
+                *count += other_count;
+            }
+            (Self::Count(count), Self::Count(other)) => *count += other,
+            (Self::Max(max), Self::Max(Some(other))) => {
+                if max.as_ref().is_none_or(|max| other > *max) {
+                    *max = Some(other);
+                }
+            }
+            (Self::Min(min), Self::Min(Some(other))) => {
+                if min.as_ref().is_none_or(|min| other < *min) {
+                    *min = Some(other);
+                }
+            }
+            (Self::Sum(sum @ None), Self::Sum(other)) => *sum = other,
+            (Self::Sum(Some(sum)), Self::Sum(Some(other))) => *sum = sum.checked_add(&other)?,
+            (
+                Self::User {
+                    function: Some(function),
+                    state,
+                },
+                Self::User { state: other, .. },
+            ) => (function.merge)(state, other)?,
+            (Self::Max(_), Self::Max(None))
+            | (Self::Min(_), Self::Min(None))
+            | (Self::Sum(_), Self::Sum(None)) => {}
+            _ => return Err(Error::InvalidPlan),
+        }
+        Ok(())
+    }
+
     pub fn value(self) -> Result<Value, Error> {
         match self {
             Self::Average { count: 0, .. } => Ok(Value::Null),
@@ -116,10 +422,102 @@ impl Accumulator {
             Self::Count(count) => Ok(Value::Integer(count)),
             Self::Max(Some(value)) | Self::Min(Some(value)) | Self::Sum(Some(value)) => Ok(value),
             Self::Max(None) | Self::Min(None) | Self::Sum(None) => Ok(Value::Null),
+            Self::User {
+                function: Some(function),
+                state,
+            } => check_result(
+                &function.name,
+                (function.finish)(state)?,
+                &function.return_type,
+            ),
+            Self::User { function: None, .. } => Err(Error::InvalidPlan),
+        }
+    }
+}
+
+type InitClosure = dyn Fn() -> Vec<Value> + Send + Sync;
+type AccumulateClosure = dyn Fn(&mut Vec<Value>, Value) -> Result<(), Error> + Send + Sync;
+type MergeClosure = dyn Fn(&mut Vec<Value>, Vec<Value>) -> Result<(), Error> + Send + Sync;
+type FinishClosure = dyn Fn(Vec<Value>) -> Result<Value, Error> + Send + Sync;
+
+/// An aggregate function of one argument implemented by the embedder with Rust closures,
+/// which can be called from SQL by name once registered with
+/// [`Session::register_aggregate`].
+///
+/// Like an [`Accumulator`], each group's state is created by `init`, updated by
+/// `accumulate` with each non-NULL argument value, merged with the state of the same
+/// group from another partition of the input by `merge`, and turned into the result by
+/// `finish`. The state is a list of values, so that it can be spilled to disk. Its
+/// volatility is declared like a [`UserFunction`]'s, though aggregates are never evaluated
+/// ahead of time.
+///
+/// [`Session::register_aggregate`]: super::Session::register_aggregate
+/// [`UserFunction`]: super::UserFunction
+#[derive(Clone)]
+pub struct UserAggregate {
+    pub name: String,
+    pub arg: DataType,
+    pub return_type: DataType,
+    pub volatility: Volatility,
+    init: Arc<InitClosure>,
+    accumulate: Arc<AccumulateClosure>,
+    merge: Arc<MergeClosure>,
+    finish: Arc<FinishClosure>,
+}
+
+impl UserAggregate {
+    #[allow(clippy::too_many_arguments)] // One per field
+    pub fn new(
+        name: impl Into<String>,
+        arg: DataType,
+        return_type: DataType,
+        volatility: Volatility,
+        init: impl Fn() -> Vec<Value> + Send + Sync + 'static,
+        accumulate: impl Fn(&mut Vec<Value>, Value) -> Result<(), Error> + Send + Sync + 'static,
+        merge: impl Fn(&mut Vec<Value>, Vec<Value>) -> Result<(), Error> + Send + Sync + 'static,
+        finish: impl Fn(Vec<Value>) -> Result<Value, Error> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            name: name.into().to_uppercase(),
+            arg,
+            return_type,
+            volatility,
+            init: Arc::new(init),
+            accumulate: Arc::new(accumulate),
+            merge: Arc::new(merge),
+            finish: Arc::new(finish),
         }
     }
 }
 
+impl fmt::Debug for UserAggregate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UserAggregate")
+            .field("name", &self.name)
+            .field("arg", &self.arg)
+            .field("return_type", &self.return_type)
+            .field("volatility", &self.volatility)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Like user functions, user aggregates are identified by their name and signature.
+impl PartialEq for UserAggregate {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.arg == other.arg && self.return_type == other.return_type
+    }
+}
+
+impl Eq for UserAggregate {}
+
+impl Hash for UserAggregate {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name.hash(state);
+        self.arg.hash(state);
+        self.return_type.hash(state);
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Display)]
 pub enum Aggregate {
     Average(Expr),
@@ -127,27 +525,93 @@ pub enum Aggregate {
     Max(Expr),
     Min(Expr),
     Sum(Expr),
+    #[display("{}({})", _0.name, _1)]
+    User(Arc<UserAggregate>, Expr),
 }
 
 impl Aggregate {
+    pub fn map_expr(self, f: impl FnOnce(Expr) -> Result<Expr, Error>) -> Result<Self, Error> {
+        Ok(match self {
+            Self::Average(expr) => Self::Average(f(expr)?),
+            Self::Count(expr) => Self::Count(f(expr)?),
+            Self::Max(expr) => Self::Max(f(expr)?),
+            Self::Min(expr) => Self::Min(f(expr)?),
+            Self::Sum(expr) => Self::Sum(f(expr)?),
+            Self::User(function, expr) => Self::User(function, f(expr)?),
+        })
+    }
+
     pub fn expr(&self) -> &Expr {
         match self {
             Self::Average(expr)
             | Self::Count(expr)
             | Self::Max(expr)
             | Self::Min(expr)
-            | Self::Sum(expr) => expr,
+            | Self::Sum(expr)
+            | Self::User(_, expr) => expr,
         }
     }
 }
 
 pub fn is_aggregate(func: &ast::Function) -> bool {
+    is_aggregate_name(&func.name.to_string())
+}
+
+/// Returns whether a (case-insensitive) name is that of a built-in aggregate function.
+pub fn is_aggregate_name(name: &str) -> bool {
     matches!(
-        func.name.to_string().to_lowercase().as_str(),
+        name.to_lowercase().as_str(),
         "avg" | "count" | "max" | "min" | "sum"
     )
 }
 
+/// Collects every call of an aggregate function, as told by `is_aggregate`, in `node`, in
+/// the order they appear. Subqueries are not descended into, since their aggregates
+/// belong to their own scope.
+pub fn find_aggregates<V: Visit>(
+    node: &V,
+    is_aggregate: &dyn Fn(&ast::Function) -> bool,
+    found: &mut Vec<ast::Function>,
+) {
+    struct AggregateFinder<'a> {
+        depth: usize,
+        is_aggregate: &'a dyn Fn(&ast::Function) -> bool,
+        found: &'a mut Vec<ast::Function>,
+    }
+
+    impl Visitor for AggregateFinder<'_> {
+        type Break = ();
+
+        fn pre_visit_query(&mut self, _query: &ast::Query) -> ControlFlow<()> {
+            self.depth += 1;
+            ControlFlow::Continue(())
+        }
+
+        fn post_visit_query(&mut self, _query: &ast::Query) -> ControlFlow<()> {
+            self.depth -= 1;
+            ControlFlow::Continue(())
+        }
+
+        fn pre_visit_expr(&mut self, expr: &ast::Expr) -> ControlFlow<()> {
+            if self.depth == 0
+                && let ast::Expr::Function(func) = expr
+                && func.over.is_none()
+                && (self.is_aggregate)(func)
+                && !self.found.contains(func)
+            {
+                self.found.push(func.clone());
+            }
+            ControlFlow::Continue(())
+        }
+    }
+
+    let _ = node.visit(&mut AggregateFinder {
+        depth: 0,
+        is_aggregate,
+        found,
+    });
+}
+
 pub fn aggregate_function_args(func: &ast::Function) -> Result<Vec<ast::Expr>, Error> {
     match &func.args {
         ast::FunctionArguments::None => Ok(vec![]),
@@ -155,12 +619,16 @@ pub fn aggregate_function_args(func: &ast::Function) -> Result<Vec<ast::Expr>, E
             let mut args = Vec::new();
             for arg in func_args.args.iter() {
                 if let ast::FunctionArg::Unnamed(arg) = arg {
-                    if let ast::FunctionArgExpr::Expr(expr) = arg {
-                        args.push(expr.clone());
-                    } else {
-                        return Err(Error::NotYetSupported(
-                            "Aggregate function with named arguments".to_string(),
-                        ));
+                    match arg {
+                        ast::FunctionArgExpr::Expr(expr) => args.push(expr.clone()),
+                        ast::FunctionArgExpr::Wildcard => {
+                            args.push(ast::Expr::Wildcard(AttachedToken::empty()))
+                        }
+                        ast::FunctionArgExpr::QualifiedWildcard(_) => {
+                            return Err(Error::NotYetSupported(
+                                "Aggregate function with qualified wildcard".to_string(),
+                            ));
+                        }
                     }
                 }
             }
@@ -171,3 +639,46 @@ pub fn aggregate_function_args(func: &ast::Function) -> Result<Vec<ast::Expr>, E
         )),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        exec::session::Session,
+        types::value::{Row, Value},
+        util::tests::{execute, query, test_engine},
+    };
+
+    #[test]
+    fn test_count_star() {
+        let engine = test_engine();
+        let mut session = Session::new(&engine);
+        execute(
+            &mut session,
+            "CREATE TABLE t (id INT PRIMARY KEY, x INT);
+            INSERT INTO t VALUES (1, NULL), (2, 5), (3, NULL);",
+        )
+        .unwrap();
+        let rows = query(&mut session, "SELECT COUNT(*), COUNT(x) FROM t");
+        assert_eq!(rows, vec![Row::from((3, 1))]);
+    }
+
+    #[test]
+    fn test_aggregate_empty_table() {
+        let engine = test_engine();
+        let mut session = Session::new(&engine);
+        execute(&mut session, "CREATE TABLE t (id INT PRIMARY KEY, x INT)").unwrap();
+
+        let rows = query(&mut session, "SELECT COUNT(*), COUNT(x), SUM(x) FROM t");
+        assert_eq!(
+            rows,
+            vec![Row::from([
+                Value::Integer(0),
+                Value::Integer(0),
+                Value::Null
+            ])]
+        );
+
+        let rows = query(&mut session, "SELECT x, COUNT(*) FROM t GROUP BY x");
+        assert!(rows.is_empty());
+    }
+}
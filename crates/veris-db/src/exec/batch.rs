@@ -0,0 +1,495 @@
+use std::cmp::Ordering;
+
+use dyn_clone::DynClone;
+
+use crate::{
+    error::Error,
+    types::value::{Row, RowIter, Value},
+};
+
+use super::expr::BinaryOp;
+
+/// The number of rows in the batches passed between vectorized operators.
+pub const BATCH_SIZE: usize = 1024;
+
+/// The values of one column of a batch. Columns whose values all have the same type (or
+/// are NULL) are stored as vectors of that type, so that operators can work on them
+/// without matching on every value.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ColumnVector {
+    Boolean(Vec<Option<bool>>),
+    Integer(Vec<Option<i64>>),
+    Float(Vec<Option<f64>>),
+    /// Values of any other type, or of several types.
+    Values(Vec<Value>),
+}
+
+impl ColumnVector {
+    /// Stores the values in a vector of their common type, if they have one.
+    pub fn from_values(values: Vec<Value>) -> Self {
+        let mut non_null = values.iter().filter(|value| **value != Value::Null);
+        let Some(first) = non_null.next() else {
+            return Self::Values(values);
+        };
+        let first = std::mem::discriminant(first);
+        if non_null.any(|value| std::mem::discriminant(value) != first) {
+            return Self::Values(values);
+        }
+
+        match values.iter().find(|value| **value != Value::Null) {
+            Some(Value::Boolean(_)) => Self::Boolean(
+                values
+                    .iter()
+                    .map(|value| match value {
+                        Value::Boolean(b) => Some(*b),
+                        _ => None,
+                    })
+                    .collect(),
+            ),
+            Some(Value::Integer(_)) => Self::Integer(
+                values
+                    .iter()
+                    .map(|value| match value {
+                        Value::Integer(i) => Some(*i),
+                        _ => None,
+                    })
+                    .collect(),
+            ),
+            Some(Value::Float(_)) => Self::Float(
+                values
+                    .iter()
+                    .map(|value| match value {
+                        Value::Float(f) => Some(*f),
+                        _ => None,
+                    })
+                    .collect(),
+            ),
+            _ => Self::Values(values),
+        }
+    }
+
+    /// Returns a vector of `len` copies of `value`.
+    pub fn repeat(value: &Value, len: usize) -> Self {
+        match value {
+            Value::Boolean(b) => Self::Boolean(vec![Some(*b); len]),
+            Value::Integer(i) => Self::Integer(vec![Some(*i); len]),
+            Value::Float(f) => Self::Float(vec![Some(*f); len]),
+            value => Self::Values(vec![value.clone(); len]),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Boolean(values) => values.len(),
+            Self::Integer(values) => values.len(),
+            Self::Float(values) => values.len(),
+            Self::Values(values) => values.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the value at `index`, or NULL if it is out of bounds.
+    pub fn get(&self, index: usize) -> Value {
+        fn value<T: Copy>(values: &[Option<T>], index: usize, f: fn(T) -> Value) -> Value {
+            values.get(index).copied().flatten().map_or(Value::Null, f)
+        }
+        match self {
+            Self::Boolean(values) => value(values, index, Value::Boolean),
+            Self::Integer(values) => value(values, index, Value::Integer),
+            Self::Float(values) => value(values, index, Value::Float),
+            Self::Values(values) => values.get(index).cloned().unwrap_or(Value::Null),
+        }
+    }
+
+    pub fn into_values(self) -> Vec<Value> {
+        match self {
+            Self::Boolean(values) => values
+                .into_iter()
+                .map(|b| b.map_or(Value::Null, Value::Boolean))
+                .collect(),
+            Self::Integer(values) => values
+                .into_iter()
+                .map(|i| i.map_or(Value::Null, Value::Integer))
+                .collect(),
+            Self::Float(values) => values
+                .into_iter()
+                .map(|f| f.map_or(Value::Null, Value::Float))
+                .collect(),
+            Self::Values(values) => values,
+        }
+    }
+
+    /// Keeps the values at the indexes for which `selection` is true.
+    pub fn select(&self, selection: &[bool]) -> Self {
+        fn select<T: Clone>(values: &[T], selection: &[bool]) -> Vec<T> {
+            values
+                .iter()
+                .zip(selection)
+                .filter(|(_, selected)| **selected)
+                .map(|(value, _)| value.clone())
+                .collect()
+        }
+        match self {
+            Self::Boolean(values) => Self::Boolean(select(values, selection)),
+            Self::Integer(values) => Self::Integer(select(values, selection)),
+            Self::Float(values) => Self::Float(select(values, selection)),
+            Self::Values(values) => Self::Values(select(values, selection)),
+        }
+    }
+
+    /// Applies a binary operator to the values of both vectors pairwise, with the same
+    /// results and errors as [`BinaryOp::apply`].
+    pub fn binary_op(&self, op: &BinaryOp, other: &ColumnVector) -> Result<ColumnVector, Error> {
+        use ColumnVector::*;
+
+        match (self, other) {
+            (Integer(a), Integer(b)) => match op {
+                BinaryOp::Add | BinaryOp::Subtract | BinaryOp::Multiply | BinaryOp::Divide => {
+                    let checked = match op {
+                        BinaryOp::Add => i64::checked_add,
+                        BinaryOp::Subtract => i64::checked_sub,
+                        BinaryOp::Multiply => i64::checked_mul,
+                        _ => i64::checked_div,
+                    };
+                    let mut result = Vec::with_capacity(a.len());
+                    for (x, y) in a.iter().zip(b) {
+                        result.push(match (x, y) {
+                            (Some(x), Some(y)) => {
+                                Some(checked(*x, *y).ok_or(Error::IntegerOverflow)?)
+                            }
+                            _ => None,
+                        });
+                    }
+                    Ok(Integer(result))
+                }
+                BinaryOp::Equal
+                | BinaryOp::NotEqual
+                | BinaryOp::GreaterThan
+                | BinaryOp::LessThan
+                | BinaryOp::GreaterThanOrEqual
+                | BinaryOp::LessThanOrEqual => Self::compare(a, b, op, i64::cmp),
+                op => self.apply_each(op, other),
+            },
+            (Float(a), Float(b)) => match op {
+                BinaryOp::Add | BinaryOp::Subtract | BinaryOp::Multiply | BinaryOp::Divide
+                    if a.iter().chain(b).all(Option::is_some) =>
+                {
+                    let f = match op {
+                        BinaryOp::Add => |x: f64, y: f64| x + y,
+                        BinaryOp::Subtract => |x: f64, y: f64| x - y,
+                        BinaryOp::Multiply => |x: f64, y: f64| x * y,
+                        _ => |x: f64, y: f64| x / y,
+                    };
+                    Ok(Float(
+                        a.iter()
+                            .zip(b)
+                            .map(|(x, y)| x.zip(*y).map(|(x, y)| f(x, y)))
+                            .collect(),
+                    ))
+                }
+                BinaryOp::Equal | BinaryOp::NotEqual => {
+                    // Like `Value`, NaNs are equal to each other, and so are 0.0 and -0.0.
+                    let equal = |x: f64, y: f64| x == y || x.is_nan() && y.is_nan();
+                    let negated = *op == BinaryOp::NotEqual;
+                    Ok(Boolean(
+                        a.iter()
+                            .zip(b)
+                            .map(|(x, y)| Some(equal((*x)?, (*y)?) != negated))
+                            .collect(),
+                    ))
+                }
+                BinaryOp::GreaterThan
+                | BinaryOp::LessThan
+                | BinaryOp::GreaterThanOrEqual
+                | BinaryOp::LessThanOrEqual => Self::compare(a, b, op, f64::total_cmp),
+                op => self.apply_each(op, other),
+            },
+            (Boolean(a), Boolean(b)) if matches!(op, BinaryOp::And | BinaryOp::Or) => {
+                // The value that decides the result on its own, as NULL can't.
+                let decisive = *op == BinaryOp::Or;
+                Ok(Boolean(
+                    a.iter()
+                        .zip(b)
+                        .map(|(x, y)| match (x, y) {
+                            (Some(x), _) if *x == decisive => Some(decisive),
+                            (_, Some(y)) if *y == decisive => Some(decisive),
+                            (Some(_), Some(_)) => Some(!decisive),
+                            _ => None,
+                        })
+                        .collect(),
+                ))
+            }
+            _ => self.apply_each(op, other),
+        }
+    }
+
+    /// Compares the values of two typed vectors with a comparison operator, ordering them
+    /// by `cmp`. Comparisons with NULL are NULL.
+    fn compare<T>(
+        a: &[Option<T>],
+        b: &[Option<T>],
+        op: &BinaryOp,
+        cmp: impl Fn(&T, &T) -> Ordering,
+    ) -> Result<ColumnVector, Error> {
+        let matches: fn(Ordering) -> bool = match op {
+            BinaryOp::Equal => Ordering::is_eq,
+            BinaryOp::NotEqual => Ordering::is_ne,
+            BinaryOp::GreaterThan => Ordering::is_gt,
+            BinaryOp::LessThan => Ordering::is_lt,
+            BinaryOp::GreaterThanOrEqual => Ordering::is_ge,
+            BinaryOp::LessThanOrEqual => Ordering::is_le,
+            op => {
+                return Err(Error::NotYetSupported(format!(
+                    "Binary operator {:?} not yet supported",
+                    op
+                )));
+            }
+        };
+        Ok(ColumnVector::Boolean(
+            a.iter()
+                .zip(b)
+                .map(|(x, y)| Some(matches(cmp(x.as_ref()?, y.as_ref()?))))
+                .collect(),
+        ))
+    }
+
+    fn apply_each(&self, op: &BinaryOp, other: &ColumnVector) -> Result<ColumnVector, Error> {
+        let values = (0..self.len())
+            .map(|i| op.apply(&self.get(i), &other.get(i)))
+            .collect::<Result<_, _>>()?;
+        Ok(ColumnVector::from_values(values))
+    }
+}
+
+/// A batch of rows, stored column by column.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Batch {
+    columns: Vec<ColumnVector>,
+    len: usize,
+}
+
+impl Batch {
+    pub fn new(columns: Vec<ColumnVector>, len: usize) -> Self {
+        Self { columns, len }
+    }
+
+    pub fn from_rows(rows: Vec<Row>, num_columns: usize) -> Self {
+        let len = rows.len();
+        let mut columns = vec![Vec::with_capacity(len); num_columns];
+        for row in rows {
+            for (column, value) in columns.iter_mut().zip(row) {
+                column.push(value);
+            }
+        }
+        Self {
+            columns: columns.into_iter().map(ColumnVector::from_values).collect(),
+            len,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn column(&self, index: usize) -> Option<&ColumnVector> {
+        self.columns.get(index)
+    }
+
+    pub fn row(&self, index: usize) -> Row {
+        Row::from(
+            self.columns
+                .iter()
+                .map(|column| column.get(index))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    pub fn into_rows(self) -> Vec<Row> {
+        let mut rows = vec![Vec::with_capacity(self.columns.len()); self.len];
+        for column in self.columns {
+            for (row, value) in rows.iter_mut().zip(column.into_values()) {
+                row.push(value);
+            }
+        }
+        rows.into_iter().map(Row::from).collect()
+    }
+
+    /// Keeps the rows for which `selection` is true.
+    pub fn select(&self, selection: &[bool]) -> Self {
+        Self {
+            columns: self
+                .columns
+                .iter()
+                .map(|column| column.select(selection))
+                .collect(),
+            len: selection.iter().filter(|selected| **selected).count(),
+        }
+    }
+}
+
+pub trait BatchIterImpl: Iterator<Item = Result<Batch, Error>> + DynClone {}
+dyn_clone::clone_trait_object!(BatchIterImpl);
+impl<T: Iterator<Item = Result<Batch, Error>> + DynClone> BatchIterImpl for T {}
+
+/// An iterator over batches of rows, the batch-at-a-time counterpart of [`RowIter`].
+#[derive(Clone)]
+pub struct BatchIter(Box<dyn BatchIterImpl>);
+
+impl BatchIter {
+    pub fn new(batches: impl BatchIterImpl + 'static) -> Self {
+        BatchIter(Box::new(batches))
+    }
+
+    /// Collects the rows of `rows` into batches of [`BATCH_SIZE`] rows.
+    pub fn from_rows(rows: RowIter, num_columns: usize) -> Self {
+        #[derive(Clone)]
+        struct Batcher {
+            rows: RowIter,
+            num_columns: usize,
+        }
+
+        impl Iterator for Batcher {
+            type Item = Result<Batch, Error>;
+
+            fn next(&mut self) -> Option<Self::Item> {
+                let mut rows = Vec::with_capacity(BATCH_SIZE);
+                for row in self.rows.by_ref().take(BATCH_SIZE) {
+                    match row {
+                        Ok(row) => rows.push(row),
+                        Err(err) => return Some(Err(err)),
+                    }
+                }
+                (!rows.is_empty()).then(|| Ok(Batch::from_rows(rows, self.num_columns)))
+            }
+        }
+
+        Self::new(Batcher { rows, num_columns })
+    }
+
+    /// Returns the rows of the batches one at a time.
+    pub fn into_rows(self) -> RowIter {
+        RowIter::new(self.flat_map(|batch| match batch {
+            Ok(batch) => batch.into_rows().into_iter().map(Ok).collect(),
+            Err(err) => vec![Err(err)],
+        }))
+    }
+}
+
+impl Iterator for BatchIter {
+    type Item = Result<Batch, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        exec::session::Session,
+        util::tests::{create_generated_table, execute, query, test_engine},
+    };
+
+    fn integers(values: &[Option<i64>]) -> ColumnVector {
+        ColumnVector::Integer(values.to_vec())
+    }
+
+    #[test]
+    fn test_from_values() {
+        let vector = ColumnVector::from_values(vec![Value::Integer(1), Value::Null]);
+        assert_eq!(vector, integers(&[Some(1), None]));
+        assert_eq!(vector.into_values(), vec![Value::Integer(1), Value::Null]);
+
+        let mixed = vec![Value::Integer(1), Value::Float(1.5)];
+        assert_eq!(
+            ColumnVector::from_values(mixed.clone()),
+            ColumnVector::Values(mixed)
+        );
+    }
+
+    #[test]
+    fn test_binary_op_matches_values() {
+        let a = integers(&[Some(1), Some(5), None, Some(i64::MAX)]);
+        let b = integers(&[Some(2), None, None, Some(-1)]);
+        let ops = [
+            BinaryOp::Add,
+            BinaryOp::Multiply,
+            BinaryOp::Divide,
+            BinaryOp::Equal,
+            BinaryOp::NotEqual,
+            BinaryOp::GreaterThan,
+            BinaryOp::LessThan,
+            BinaryOp::GreaterThanOrEqual,
+            BinaryOp::LessThanOrEqual,
+        ];
+        for op in &ops {
+            let result = a.binary_op(op, &b).unwrap();
+            for i in 0..a.len() {
+                assert_eq!(
+                    result.get(i),
+                    op.apply(&a.get(i), &b.get(i)).unwrap(),
+                    "{op:?} at {i}"
+                );
+            }
+        }
+
+        // Every combination of TRUE, FALSE and NULL.
+        let truth = [Some(true), Some(false), None];
+        let x = ColumnVector::Boolean(truth.iter().flat_map(|x| [*x; 3]).collect());
+        let y = ColumnVector::Boolean(truth.repeat(3));
+        for op in &[BinaryOp::And, BinaryOp::Or] {
+            let result = x.binary_op(op, &y).unwrap();
+            for i in 0..x.len() {
+                assert_eq!(
+                    result.get(i),
+                    op.apply(&x.get(i), &y.get(i)).unwrap(),
+                    "{op:?} at {i}"
+                );
+            }
+        }
+        assert!(a.binary_op(&BinaryOp::And, &b).is_err());
+
+        let c = integers(&[Some(1), Some(1), Some(1), Some(1)]);
+        let d = integers(&[Some(1), Some(5), Some(0), Some(i64::MAX)]);
+        assert!(matches!(
+            d.binary_op(&BinaryOp::Add, &c),
+            Err(Error::IntegerOverflow)
+        ));
+        assert_eq!(
+            c.binary_op(&BinaryOp::Multiply, &integers(&[Some(3); 4]))
+                .unwrap(),
+            integers(&[Some(3); 4])
+        );
+    }
+
+    #[test]
+    fn test_vectorized_queries_match_rows() {
+        let engine = test_engine();
+        let mut session = Session::new(&engine);
+        create_generated_table(&mut session, 3000);
+
+        for sql in [
+            "SELECT id, x * 2, y / 2 FROM t WHERE x > 3 AND y < 5.0",
+            "SELECT id, name FROM t WHERE name = 'name1' OR x = 0",
+            "SELECT COUNT(*), COUNT(x), SUM(x), AVG(y), MIN(name), MAX(x) FROM t WHERE id > 100",
+            "SELECT x, COUNT(*), SUM(y) FROM t GROUP BY x ORDER BY x",
+            "SELECT id FROM t WHERE x IN (1, 2) ORDER BY id DESC LIMIT 5",
+            "SELECT SUM(x) FROM t WHERE id < 0",
+        ] {
+            // The row executor is the default.
+            assert!(!session.options().vectorized);
+            let rows = query(&mut session, sql);
+            execute(&mut session, "SET vectorized = true").unwrap();
+            assert_eq!(query(&mut session, sql), rows, "{sql}");
+            execute(&mut session, "SET vectorized = false").unwrap();
+        }
+    }
+}
@@ -0,0 +1,570 @@
+use std::collections::HashMap;
+
+use crate::{
+    engine::Catalog,
+    error::Error,
+    types::{
+        schema::Table,
+        statistics::{ColumnStatistics, TableStatistics},
+        value::Value,
+    },
+};
+
+use super::{
+    expr::{BinaryOp, Expr, UnaryOp},
+    join::JoinType,
+    plan::{Plan, SetOperator},
+};
+
+/// The number of rows assumed for tables without statistics.
+pub const DEFAULT_ROW_COUNT: f64 = 1000.0;
+
+/// The fraction of rows assumed to satisfy a predicate whose selectivity can't be
+/// estimated, such as a range comparison.
+const DEFAULT_SELECTIVITY: f64 = 1.0 / 3.0;
+
+/// The fraction of rows assumed to equal a constant, in a column with an unknown number
+/// of distinct values.
+const DEFAULT_EQUALITY_SELECTIVITY: f64 = 0.1;
+
+/// The cost of inserting a row into a hash table, relative to reading a row.
+const HASH_BUILD_COST: f64 = 2.0;
+
+/// The estimated size and cost of a plan.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Estimate {
+    /// The number of rows the plan produces.
+    pub rows: f64,
+    /// The work needed to produce them, in units of rows processed.
+    pub cost: f64,
+}
+
+impl Estimate {
+    /// Creates an estimate of at least one row. Estimates below that are too uncertain to
+    /// go by, and would make e.g. cross products with the plan look free.
+    fn new(rows: f64, cost: f64) -> Self {
+        Self {
+            rows: rows.max(1.0),
+            cost,
+        }
+    }
+}
+
+/// What is known about the values of a column, for estimating the selectivity of
+/// predicates on it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ColumnEstimate<'a> {
+    /// The number of distinct non-NULL values.
+    pub distinct: Option<f64>,
+    /// The statistics of the table column that the values are read from.
+    pub statistics: Option<&'a ColumnStatistics>,
+}
+
+/// Estimates the number of rows that plans produce and what it costs to produce them,
+/// from the statistics of the tables they read. Tables without statistics are assumed to
+/// have [`DEFAULT_ROW_COUNT`] rows with unique primary keys.
+#[derive(Debug, Clone, Default)]
+pub struct CostModel {
+    statistics: HashMap<String, TableStatistics>,
+}
+
+impl CostModel {
+    pub fn new(statistics: HashMap<String, TableStatistics>) -> Self {
+        Self { statistics }
+    }
+
+    /// Creates a cost model from the statistics of every table in the catalog.
+    pub fn from_catalog(catalog: &impl Catalog) -> Result<Self, Error> {
+        let mut statistics = HashMap::new();
+        for table in catalog.list_tables()? {
+            if let Some(table_statistics) = catalog.get_table_statistics(&table.name)? {
+                statistics.insert(table.name, table_statistics);
+            }
+        }
+        Ok(Self::new(statistics))
+    }
+
+    pub fn with_table(mut self, table: &str, statistics: TableStatistics) -> Self {
+        self.statistics.insert(table.to_string(), statistics);
+        self
+    }
+
+    pub fn estimate(&self, plan: &Plan) -> Estimate {
+        match plan {
+            Plan::CreateTable(_) | Plan::DropTable(_) | Plan::Delete { .. } => {
+                Estimate::new(0.0, 0.0)
+            }
+            Plan::Insert { source, .. } | Plan::Query(source) | Plan::Explain { source, .. } => {
+                self.estimate(source)
+            }
+            Plan::Scan { table, filter, .. } => {
+                let rows = self.table_rows(table);
+                let selectivity = filter.as_ref().map_or(1.0, |filter| {
+                    self.selectivity(filter, &|i| self.table_column(table, i))
+                });
+                Estimate::new(rows * selectivity, rows)
+            }
+            Plan::KeyLookup { keys, .. } => {
+                let rows = keys.len() as f64;
+                Estimate::new(rows, rows)
+            }
+            Plan::IndexLookup {
+                table,
+                column,
+                values,
+                ..
+            } => {
+                let column = table
+                    .columns
+                    .iter()
+                    .position(|c| &c.name == column)
+                    .map(|i| self.table_column(table, i))
+                    .unwrap_or_default();
+                let rows = values.len() as f64 * self.table_rows(table) * equality(column);
+                Estimate::new(rows, rows)
+            }
+            Plan::Filter { source, predicate } => {
+                let source_estimate = self.estimate(source);
+                let selectivity = self.selectivity(predicate, &|i| self.column(source, i));
+                Estimate::new(
+                    source_estimate.rows * selectivity,
+                    source_estimate.cost + source_estimate.rows,
+                )
+            }
+            Plan::Project { source, .. } => {
+                let source = self.estimate(source);
+                Estimate::new(source.rows, source.cost + source.rows)
+            }
+            Plan::Order { source, .. } => {
+                let source = self.estimate(source);
+                let cost = source.rows * source.rows.max(2.0).log2();
+                Estimate::new(source.rows, source.cost + cost)
+            }
+            Plan::Limit { source, limit } => {
+                let source = self.estimate(source);
+                Estimate::new(source.rows.min(*limit as f64), source.cost)
+            }
+            Plan::Offset { source, offset } => {
+                let source = self.estimate(source);
+                Estimate::new((source.rows - *offset as f64).max(0.0), source.cost)
+            }
+            Plan::Aggregate {
+                source, group_by, ..
+            } => {
+                let source_estimate = self.estimate(source);
+                let groups = group_by
+                    .iter()
+                    .map(|expr| match expr {
+                        Expr::Column(i) => self.distinct(source, *i),
+                        _ => None,
+                    })
+                    .try_fold(1.0, |groups, distinct| Some(groups * distinct?))
+                    .unwrap_or(source_estimate.rows * DEFAULT_EQUALITY_SELECTIVITY);
+                let rows = match group_by.is_empty() {
+                    true => 1.0,
+                    false => groups.min(source_estimate.rows),
+                };
+                Estimate::new(rows, source_estimate.cost + source_estimate.rows)
+            }
+            Plan::Join {
+                left,
+                right,
+                on,
+                join_type,
+            } => {
+                let (left_estimate, right_estimate) = (self.estimate(left), self.estimate(right));
+                let left_cols = left.num_columns();
+                let selectivity = on.as_ref().map_or(1.0, |on| {
+                    self.selectivity(on, &|i| match i < left_cols {
+                        true => self.column(left, i),
+                        false => self.column(right, i - left_cols),
+                    })
+                });
+                self.join_estimate(
+                    left_estimate,
+                    right_estimate,
+                    selectivity,
+                    *join_type,
+                    Self::join_cost(left_estimate.rows, right_estimate.rows, false),
+                )
+            }
+            Plan::HashJoin {
+                left,
+                right,
+                keys,
+                join_type,
+            }
+            | Plan::MergeJoin {
+                left,
+                right,
+                keys,
+                join_type,
+            } => {
+                let (left_estimate, right_estimate) = (self.estimate(left), self.estimate(right));
+                let selectivity = keys
+                    .iter()
+                    .map(|&(l, r)| {
+                        let left_distinct = self.distinct(left, l).unwrap_or(left_estimate.rows);
+                        let right_distinct = self.distinct(right, r).unwrap_or(right_estimate.rows);
+                        1.0 / left_distinct.max(right_distinct).max(1.0)
+                    })
+                    .product();
+                let cost = match plan {
+                    Plan::MergeJoin { .. } => left_estimate.rows + right_estimate.rows,
+                    _ => Self::join_cost(left_estimate.rows, right_estimate.rows, true),
+                };
+                self.join_estimate(left_estimate, right_estimate, selectivity, *join_type, cost)
+            }
+            Plan::Nothing { .. } => Estimate::new(0.0, 0.0),
+            Plan::RecursiveCte {
+                anchor, recursive, ..
+            } => {
+                let (anchor, recursive) = (self.estimate(anchor), self.estimate(recursive));
+                Estimate::new(anchor.rows + recursive.rows, anchor.cost + recursive.cost)
+            }
+            Plan::SetOperation {
+                left, right, op, ..
+            } => {
+                let (left, right) = (self.estimate(left), self.estimate(right));
+                let rows = match op {
+                    SetOperator::Union => left.rows + right.rows,
+                    SetOperator::Intersect => left.rows.min(right.rows),
+                    SetOperator::Except => left.rows,
+                };
+                Estimate::new(rows, left.cost + right.cost + left.rows + right.rows)
+            }
+            Plan::Values { rows } => {
+                let rows = rows.len() as f64;
+                Estimate::new(rows, rows)
+            }
+            Plan::WorkingTable { .. } => Estimate::new(DEFAULT_ROW_COUNT, DEFAULT_ROW_COUNT),
+        }
+    }
+
+    /// The cost of joining inputs with the given numbers of rows, by hashing the right
+    /// input if the join has an equality between their columns, or by comparing every
+    /// pair of rows otherwise.
+    pub fn join_cost(left_rows: f64, right_rows: f64, equi_join: bool) -> f64 {
+        match equi_join {
+            true => left_rows + HASH_BUILD_COST * right_rows,
+            false => left_rows * right_rows,
+        }
+    }
+
+    fn join_estimate(
+        &self,
+        left: Estimate,
+        right: Estimate,
+        selectivity: f64,
+        join_type: JoinType,
+        join_cost: f64,
+    ) -> Estimate {
+        let matches = left.rows * right.rows * selectivity;
+        let rows = match join_type {
+            JoinType::Inner => matches,
+            JoinType::Left => matches.max(left.rows),
+            JoinType::Right => matches.max(right.rows),
+            JoinType::Full => matches.max(left.rows).max(right.rows),
+            JoinType::Semi => left.rows * (right.rows * selectivity).min(1.0),
+        };
+        Estimate::new(rows, left.cost + right.cost + join_cost)
+    }
+
+    /// Estimates the fraction of rows that satisfy `predicate`, given what is known about
+    /// the columns it refers to.
+    pub fn selectivity<'a>(
+        &'a self,
+        predicate: &Expr,
+        columns: &dyn Fn(usize) -> ColumnEstimate<'a>,
+    ) -> f64 {
+        match predicate {
+            Expr::Constant(value) => match value.is_truthy() {
+                true => 1.0,
+                false => 0.0,
+            },
+            Expr::BinaryOp(a, BinaryOp::And, b) => {
+                self.selectivity(a, columns) * self.selectivity(b, columns)
+            }
+            Expr::BinaryOp(a, BinaryOp::Or, b) => {
+                let (a, b) = (self.selectivity(a, columns), self.selectivity(b, columns));
+                a + b - a * b
+            }
+            Expr::BinaryOp(a, BinaryOp::Equal, b) => equality_selectivity(a, b, columns),
+            Expr::BinaryOp(a, BinaryOp::NotEqual, b) => 1.0 - equality_selectivity(a, b, columns),
+            Expr::BinaryOp(a, op, b) => match (&**a, &**b) {
+                (Expr::Column(column), Expr::Constant(value)) => {
+                    range_selectivity(columns(*column), op, value)
+                }
+                (Expr::Constant(value), Expr::Column(column)) => {
+                    // Flip the comparison around, so that the column is on the left.
+                    let op = match op {
+                        BinaryOp::LessThan => BinaryOp::GreaterThan,
+                        BinaryOp::LessThanOrEqual => BinaryOp::GreaterThanOrEqual,
+                        BinaryOp::GreaterThan => BinaryOp::LessThan,
+                        BinaryOp::GreaterThanOrEqual => BinaryOp::LessThanOrEqual,
+                        op => op.clone(),
+                    };
+                    range_selectivity(columns(*column), &op, value)
+                }
+                _ => DEFAULT_SELECTIVITY,
+            },
+            Expr::UnaryOp(UnaryOp::Not, expr) => 1.0 - self.selectivity(expr, columns),
+            Expr::Between(expr, low, high, negated) => {
+                let range = Expr::BinaryOp(
+                    Box::new(Expr::BinaryOp(
+                        expr.clone(),
+                        BinaryOp::GreaterThanOrEqual,
+                        low.clone(),
+                    )),
+                    BinaryOp::And,
+                    Box::new(Expr::BinaryOp(
+                        expr.clone(),
+                        BinaryOp::LessThanOrEqual,
+                        high.clone(),
+                    )),
+                );
+                let selectivity = self.selectivity(&range, columns);
+                match negated {
+                    true => 1.0 - selectivity,
+                    false => selectivity,
+                }
+            }
+            Expr::InList(expr, list, negated) => {
+                let selectivity = match &**expr {
+                    Expr::Column(i) => (list.len() as f64 * equality(columns(*i))).min(1.0),
+                    _ => DEFAULT_SELECTIVITY,
+                };
+                match negated {
+                    true => 1.0 - selectivity,
+                    false => selectivity,
+                }
+            }
+            _ => DEFAULT_SELECTIVITY,
+        }
+    }
+
+    /// What is known about a column of the plan's output.
+    pub fn column(&self, plan: &Plan, column: usize) -> ColumnEstimate<'_> {
+        ColumnEstimate {
+            distinct: self.distinct(plan, column),
+            statistics: self.column_statistics(plan, column),
+        }
+    }
+
+    /// Estimates the number of distinct non-NULL values in a column of the plan's output,
+    /// if it can be traced back to a table column.
+    pub fn distinct(&self, plan: &Plan, column: usize) -> Option<f64> {
+        let distinct = match plan {
+            Plan::Scan { table, .. }
+            | Plan::KeyLookup { table, .. }
+            | Plan::IndexLookup { table, .. } => self.table_distinct(table, column),
+            Plan::Query(source)
+            | Plan::Filter { source, .. }
+            | Plan::Order { source, .. }
+            | Plan::Limit { source, .. }
+            | Plan::Offset { source, .. } => self.distinct(source, column),
+            Plan::Project {
+                source, columns, ..
+            } => match columns.get(column)? {
+                Expr::Column(i) => self.distinct(source, *i),
+                Expr::Constant(_) => Some(1.0),
+                _ => None,
+            },
+            Plan::Aggregate {
+                source, group_by, ..
+            } => match group_by.get(column)? {
+                Expr::Column(i) => self.distinct(source, *i),
+                _ => None,
+            },
+            Plan::Join {
+                left,
+                join_type: JoinType::Semi,
+                ..
+            } => self.distinct(left, column),
+            Plan::Join { left, right, .. }
+            | Plan::HashJoin { left, right, .. }
+            | Plan::MergeJoin { left, right, .. } => {
+                let left_cols = left.num_columns();
+                match column < left_cols {
+                    true => self.distinct(left, column),
+                    false => self.distinct(right, column - left_cols),
+                }
+            }
+            _ => None,
+        }?;
+        // A plan can't produce more distinct values than rows.
+        Some(distinct.min(self.estimate(plan).rows))
+    }
+
+    /// The statistics of the table column that a column of the plan's output is read
+    /// from, if any. Filters are assumed not to change the distribution of its values.
+    fn column_statistics(&self, plan: &Plan, column: usize) -> Option<&ColumnStatistics> {
+        match plan {
+            Plan::Scan { table, .. }
+            | Plan::KeyLookup { table, .. }
+            | Plan::IndexLookup { table, .. } => {
+                self.statistics.get(&table.name)?.columns.get(column)
+            }
+            Plan::Query(source)
+            | Plan::Filter { source, .. }
+            | Plan::Order { source, .. }
+            | Plan::Limit { source, .. }
+            | Plan::Offset { source, .. } => self.column_statistics(source, column),
+            Plan::Project {
+                source, columns, ..
+            } => match columns.get(column)? {
+                Expr::Column(i) => self.column_statistics(source, *i),
+                _ => None,
+            },
+            Plan::Join {
+                left,
+                join_type: JoinType::Semi,
+                ..
+            } => self.column_statistics(left, column),
+            Plan::Join { left, right, .. }
+            | Plan::HashJoin { left, right, .. }
+            | Plan::MergeJoin { left, right, .. } => {
+                let left_cols = left.num_columns();
+                match column < left_cols {
+                    true => self.column_statistics(left, column),
+                    false => self.column_statistics(right, column - left_cols),
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn table_column(&self, table: &Table, column: usize) -> ColumnEstimate<'_> {
+        ColumnEstimate {
+            distinct: self.table_distinct(table, column),
+            statistics: self
+                .statistics
+                .get(&table.name)
+                .and_then(|statistics| statistics.columns.get(column)),
+        }
+    }
+
+    fn table_rows(&self, table: &Table) -> f64 {
+        self.statistics
+            .get(&table.name)
+            .map_or(DEFAULT_ROW_COUNT, |statistics| statistics.row_count as f64)
+    }
+
+    fn table_distinct(&self, table: &Table, column: usize) -> Option<f64> {
+        let rows = self.table_rows(table);
+        if column == table.primary_key_index {
+            return Some(rows);
+        }
+        let statistics = self.statistics.get(&table.name)?.columns.get(column)?;
+        Some((statistics.distinct_count as f64).min(rows))
+    }
+}
+
+/// The fraction of rows in which a column equals a constant.
+fn equality(column: ColumnEstimate) -> f64 {
+    let non_null = column
+        .statistics
+        .map_or(1.0, |statistics| 1.0 - statistics.null_fraction);
+    column
+        .distinct
+        .map_or(DEFAULT_EQUALITY_SELECTIVITY, |distinct| {
+            non_null / distinct.max(1.0)
+        })
+}
+
+fn equality_selectivity<'a>(
+    a: &Expr,
+    b: &Expr,
+    columns: &dyn Fn(usize) -> ColumnEstimate<'a>,
+) -> f64 {
+    match (a, b) {
+        // Each value of the column with fewer distinct values is assumed to match one of
+        // the other's.
+        (Expr::Column(a), Expr::Column(b)) => match (columns(*a).distinct, columns(*b).distinct) {
+            (Some(a), Some(b)) => 1.0 / a.max(b).max(1.0),
+            (Some(distinct), None) | (None, Some(distinct)) => 1.0 / distinct.max(1.0),
+            (None, None) => DEFAULT_EQUALITY_SELECTIVITY,
+        },
+        (Expr::Column(column), other) | (other, Expr::Column(column)) if other.is_constant() => {
+            equality(columns(*column))
+        }
+        _ => DEFAULT_EQUALITY_SELECTIVITY,
+    }
+}
+
+/// The fraction of rows in which a column compares to a constant as `op` says, from the
+/// column's histogram.
+fn range_selectivity(column: ColumnEstimate, op: &BinaryOp, value: &Value) -> f64 {
+    let Some(statistics) = column.statistics else {
+        return DEFAULT_SELECTIVITY;
+    };
+    if *value == Value::Null {
+        return 0.0;
+    }
+    let fraction = match op {
+        BinaryOp::LessThan => statistics.fraction_below(value, false),
+        BinaryOp::LessThanOrEqual => statistics.fraction_below(value, true),
+        BinaryOp::GreaterThan => statistics.fraction_below(value, true).map(|f| 1.0 - f),
+        BinaryOp::GreaterThanOrEqual => statistics.fraction_below(value, false).map(|f| 1.0 - f),
+        _ => None,
+    };
+    fraction.map_or(DEFAULT_SELECTIVITY, |fraction| {
+        fraction * (1.0 - statistics.null_fraction)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{
+        schema::Column,
+        statistics::ColumnStatistics,
+        value::{DataType, Value},
+    };
+
+    fn scan(name: &str, filter: Option<Expr>) -> Plan {
+        Plan::Scan {
+            table: Table::new(name, 0).with_columns([
+                Column::new("id", DataType::Integer),
+                Column::new("x", DataType::Integer),
+            ]),
+            filter,
+            alias: None,
+        }
+    }
+
+    fn eq(a: Expr, b: Expr) -> Expr {
+        Expr::BinaryOp(Box::new(a), BinaryOp::Equal, Box::new(b))
+    }
+
+    #[test]
+    fn test_estimates() {
+        let model = CostModel::default().with_table(
+            "a",
+            TableStatistics::new(10_000)
+                .with_column(ColumnStatistics::new(10_000))
+                .with_column(ColumnStatistics::new(100)),
+        );
+
+        let x_is_one = eq(Expr::Column(1), Expr::Constant(Value::Integer(1)));
+        assert_eq!(
+            model.estimate(&scan("a", Some(x_is_one.clone()))),
+            Estimate::new(100.0, 10_000.0)
+        );
+        // Tables without statistics have the default size, and unknown selectivities.
+        let b = model.estimate(&scan("b", Some(x_is_one)));
+        assert_eq!(b.rows, DEFAULT_ROW_COUNT * DEFAULT_EQUALITY_SELECTIVITY);
+        assert_eq!(model.distinct(&scan("b", None), 0), Some(DEFAULT_ROW_COUNT));
+        assert_eq!(model.distinct(&scan("b", None), 1), None);
+
+        // Each row of a matches one row of b by its primary key.
+        let join = Plan::Join {
+            left: Box::new(scan("a", None)),
+            right: Box::new(scan("b", None)),
+            on: Some(eq(Expr::Column(1), Expr::Column(2))),
+            join_type: JoinType::Inner,
+        };
+        assert_eq!(model.estimate(&join).rows, 10_000.0);
+        assert_eq!(model.distinct(&join, 1), Some(100.0));
+    }
+}
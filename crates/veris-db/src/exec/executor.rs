@@ -1,16 +1,80 @@
+use std::{
+    cell::Cell,
+    collections::{HashMap, HashSet},
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
 use itertools::Itertools;
 
-use crate::{Error, Result, Row, RowIter, Table, Transaction};
+use crate::{
+    ColumnLabel, Error, Result, Row, RowIter, RowPartition, Table, Transaction, Value,
+    exec::batch::BATCH_SIZE,
+};
+
+use super::{
+    Aggregate, Aggregator, Batch, BatchIter, Direction, Expr, JoinType, MergeJoiner,
+    NestedLoopJoiner, Plan, SetOperator, StatementResult, cost::CostModel, hash_join, sort::Sorter,
+    spill::MemoryBudget,
+};
 
-use super::{Aggregate, Aggregator, Expr, JoinType, NestedLoopJoiner, Plan, StatementResult};
+/// Settings that control how plans are executed.
+#[derive(Clone, Debug)]
+pub struct ExecutionOptions {
+    /// Whether scans, filters, projections and aggregates pass batches of rows between
+    /// each other instead of single rows. Other nodes always process one row at a time.
+    /// Off by default, and enabled with `SET vectorized = true`.
+    pub vectorized: bool,
+    /// The number of threads that table scans, along with the filters, projections and
+    /// aggregation above them, are split across. With more than one worker, the results
+    /// of each worker are buffered in memory.
+    pub workers: usize,
+    /// The number of bytes that sorts, hash joins and aggregations of a query may buffer
+    /// in memory between them, or `None` for no limit.
+    pub memory_limit: Option<usize>,
+    /// Whether operators that go over the memory limit spill to temporary files. If not,
+    /// the query fails instead.
+    pub spill: bool,
+}
+
+impl Default for ExecutionOptions {
+    fn default() -> Self {
+        Self {
+            vectorized: false,
+            workers: 1,
+            memory_limit: None,
+            spill: true,
+        }
+    }
+}
 
 pub struct Executor<'a, T: Transaction> {
     txn: &'a T,
+    /// The rows of the previous iteration of each recursive CTE being evaluated.
+    working_tables: HashMap<String, Vec<Row>>,
+    /// The runtime statistics of each plan node executed so far, while running
+    /// `EXPLAIN ANALYZE`. Identical subtrees share their statistics.
+    profile: Option<HashMap<Plan, Rc<NodeStats>>>,
+    options: ExecutionOptions,
+    /// The memory shared by the operators of the query.
+    memory: MemoryBudget,
 }
 
 impl<'a, T: Transaction> Executor<'a, T> {
     pub fn new(txn: &'a T) -> Self {
-        Self { txn }
+        Self {
+            txn,
+            working_tables: HashMap::new(),
+            profile: None,
+            options: ExecutionOptions::default(),
+            memory: MemoryBudget::unlimited(),
+        }
+    }
+
+    pub fn with_options(mut self, options: ExecutionOptions) -> Self {
+        self.memory = MemoryBudget::new(options.memory_limit, options.spill);
+        self.options = options;
+        self
     }
 
     pub fn execute(&mut self, plan: Plan) -> Result<StatementResult> {
@@ -43,10 +107,64 @@ impl<'a, T: Transaction> Executor<'a, T> {
                     columns,
                 })
             }
+            Plan::Explain {
+                source,
+                analyze: false,
+            } => {
+                let cost_model = CostModel::from_catalog(self.txn)?;
+                let annotate = |node: &Plan| {
+                    let estimate = cost_model.estimate(node);
+                    Some(format!(
+                        "cost={:.2} rows={:.0}",
+                        estimate.cost, estimate.rows
+                    ))
+                };
+                Ok(Self::explain_result(
+                    source.display_annotated(&annotate).to_string(),
+                ))
+            }
+            Plan::Explain {
+                source,
+                analyze: true,
+            } => {
+                self.profile = Some(HashMap::new());
+                let start = Instant::now();
+                let result = self.execute((*source).clone());
+                let elapsed = start.elapsed();
+                let profile = self.profile.take().unwrap_or_default();
+                result?;
+
+                let annotate = |node: &Plan| {
+                    profile.get(node).map(|stats| {
+                        format!(
+                            "rows={} loops={} time={:.3}ms",
+                            stats.rows.get(),
+                            stats.loops.get(),
+                            stats.elapsed.get().as_secs_f64() * 1000.0
+                        )
+                    })
+                };
+                Ok(Self::explain_result(format!(
+                    "{}Execution time: {:.3}ms",
+                    source.display_annotated(&annotate),
+                    elapsed.as_secs_f64() * 1000.0
+                )))
+            }
             _ => Err(Error::InvalidPlan),
         }
     }
 
+    /// Returns the lines of an explained plan as rows.
+    fn explain_result(plan: String) -> StatementResult {
+        StatementResult::Query {
+            rows: plan
+                .lines()
+                .map(|line| Row::from(vec![Value::String(line.to_string())]))
+                .collect(),
+            columns: vec![ColumnLabel::Unqualified("plan".to_string())],
+        }
+    }
+
     fn insert(&mut self, table: Table, mut source: RowIter) -> Result<usize> {
         let mut rows = Vec::new();
         while let Some(values) = source.next().transpose()? {
@@ -66,36 +184,241 @@ impl<'a, T: Transaction> Executor<'a, T> {
     }
 
     fn execute_inner(&mut self, plan: Plan) -> Result<RowIter> {
+        let Some(profile) = self.profile.as_mut() else {
+            return self.execute_node(plan);
+        };
+        let stats = profile.entry(plan.clone()).or_default().clone();
+        let start = Instant::now();
+        let rows = self.execute_node(plan)?;
+        stats.loops.set(stats.loops.get() + 1);
+        stats.add_elapsed(start.elapsed());
+        Ok(RowIter::new(InstrumentedRows { rows, stats }))
+    }
+
+    fn execute_node(&mut self, plan: Plan) -> Result<RowIter> {
+        if self.options.workers > 1
+            && let Some(pipeline) = Pipeline::new(&plan)
+        {
+            return self.execute_parallel(pipeline);
+        }
+        if self.is_vectorized(&plan) {
+            return Ok(self.execute_batches(plan)?.into_rows());
+        }
         match plan {
             Plan::Query(node) => self.execute_inner(*node),
             Plan::Values { rows } => self.execute_values(rows),
             Plan::Scan { table, filter, .. } => self.execute_scan(table, filter),
+            Plan::KeyLookup { table, keys, .. } => {
+                let rows = self.txn.get(&table.name, keys)?;
+                Ok(RowIter::new(rows.into_vec().into_iter().map(Ok)))
+            }
+            Plan::IndexLookup {
+                table,
+                column,
+                values,
+                ..
+            } => {
+                let ids: Vec<_> = self
+                    .txn
+                    .lookup_index(&table.name, &column, &values)?
+                    .into_iter()
+                    .collect();
+                let rows = self.txn.get(&table.name, ids)?;
+                Ok(RowIter::new(rows.into_vec().into_iter().map(Ok)))
+            }
+            Plan::HashJoin {
+                left,
+                right,
+                keys,
+                join_type,
+            } => self.execute_hash_join(*left, *right, keys, join_type),
             Plan::Join {
                 left,
                 right,
                 on,
                 join_type,
             } => self.execute_join(*left, *right, join_type, on),
+            Plan::MergeJoin {
+                left,
+                right,
+                keys,
+                join_type,
+            } => self.execute_merge_join(*left, *right, keys, join_type),
             Plan::Aggregate {
                 source,
                 group_by,
                 aggregates,
             } => self.execute_aggregate(*source, group_by, aggregates),
             Plan::Filter { source, predicate } => self.execute_filter(*source, predicate),
+            Plan::Limit { source, limit } => {
+                Ok(RowIter::new(self.execute_inner(*source)?.take(limit)))
+            }
+            Plan::Offset { source, offset } => {
+                Ok(RowIter::new(self.execute_inner(*source)?.skip(offset)))
+            }
+            Plan::Order { source, orders } => self.execute_order(*source, orders),
             Plan::Project {
                 source, columns, ..
             } => self.execute_project(*source, columns),
+            Plan::RecursiveCte {
+                name,
+                anchor,
+                recursive,
+                distinct,
+            } => self.execute_recursive_cte(name, *anchor, *recursive, distinct),
+            Plan::SetOperation {
+                left,
+                right,
+                op,
+                all,
+            } => self.execute_set_operation(*left, *right, op, all),
+            Plan::WorkingTable { name, .. } => {
+                let rows = self
+                    .working_tables
+                    .get(&name)
+                    .cloned()
+                    .ok_or(Error::InvalidPlan)?;
+                Ok(RowIter::new(rows.into_iter().map(Ok)))
+            }
             Plan::Nothing { .. } => Ok(RowIter::new(std::iter::empty())),
             _ => Err(Error::InvalidPlan),
         }
     }
 
+    /// Runs a pipeline on each partition of its table scan in parallel, then combines their
+    /// results in key order.
+    fn execute_parallel(&mut self, pipeline: Pipeline) -> Result<RowIter> {
+        let partitions = self
+            .txn
+            .scan_partitions(&pipeline.table, self.options.workers)?;
+        let vectorized = self.options.vectorized;
+        let memory = &self.memory;
+        let outputs = std::thread::scope(|scope| {
+            let workers: Vec<_> = partitions
+                .into_iter()
+                .map(|partition| {
+                    let pipeline = &pipeline;
+                    scope.spawn(move || pipeline.run(partition, vectorized, memory))
+                })
+                .collect();
+            workers
+                .into_iter()
+                .map(|worker| {
+                    worker
+                        .join()
+                        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+                })
+                .collect::<Result<Vec<_>>>()
+        })?;
+
+        let Some((group_by, aggregates)) = pipeline.aggregate else {
+            let rows: Vec<_> = outputs
+                .into_iter()
+                .flat_map(|output| match output {
+                    PartitionOutput::Rows(rows) => rows,
+                    PartitionOutput::Aggregator(_) => Vec::new(),
+                })
+                .collect();
+            return Ok(RowIter::new(rows.into_iter()));
+        };
+        let mut aggregator = Aggregator::new(group_by, aggregates).with_memory(&self.memory);
+        for output in outputs {
+            if let PartitionOutput::Aggregator(partial) = output {
+                aggregator.merge(partial)?;
+            }
+        }
+        aggregator.finish()
+    }
+
+    /// Returns whether the node is executed a batch at a time.
+    fn is_vectorized(&self, plan: &Plan) -> bool {
+        self.options.vectorized
+            && matches!(
+                plan,
+                Plan::Scan { .. }
+                    | Plan::Filter { .. }
+                    | Plan::Project { .. }
+                    | Plan::Aggregate { .. }
+            )
+    }
+
+    /// Executes a node that feeds into a vectorized node. Nodes that aren't vectorized
+    /// themselves have their rows collected into batches.
+    fn execute_batches_inner(&mut self, plan: Plan) -> Result<BatchIter> {
+        if !self.is_vectorized(&plan) {
+            let num_columns = plan.num_columns();
+            return Ok(BatchIter::from_rows(self.execute_inner(plan)?, num_columns));
+        }
+        let Some(profile) = self.profile.as_mut() else {
+            return self.execute_batches(plan);
+        };
+        let stats = profile.entry(plan.clone()).or_default().clone();
+        let start = Instant::now();
+        let batches = self.execute_batches(plan)?;
+        stats.loops.set(stats.loops.get() + 1);
+        stats.add_elapsed(start.elapsed());
+        Ok(BatchIter::new(InstrumentedBatches { batches, stats }))
+    }
+
+    fn execute_batches(&mut self, plan: Plan) -> Result<BatchIter> {
+        let num_columns = plan.num_columns();
+        match plan {
+            Plan::Scan { table, filter, .. } => {
+                let rows = self.txn.scan(&table.name, None)?;
+                let batches = BatchIter::from_rows(rows, num_columns);
+                Ok(match filter {
+                    Some(filter) => filter_batches(batches, filter, true),
+                    None => batches,
+                })
+            }
+            Plan::Filter { source, predicate } => {
+                let predicate = self.eval_subqueries(predicate, None)?;
+                if predicate.has_subquery() {
+                    let rows = self.execute_filter(*source, predicate)?;
+                    return Ok(BatchIter::from_rows(rows, num_columns));
+                }
+                let source = self.execute_batches_inner(*source)?;
+                Ok(filter_batches(source, predicate, false))
+            }
+            Plan::Project {
+                source, columns, ..
+            } => {
+                let columns: Vec<_> = columns
+                    .into_iter()
+                    .map(|expr| self.eval_subqueries(expr, None))
+                    .try_collect()?;
+                if columns.iter().any(Expr::has_subquery) {
+                    let rows = self.execute_project(*source, columns)?;
+                    return Ok(BatchIter::from_rows(rows, num_columns));
+                }
+                let source = self.execute_batches_inner(*source)?;
+                Ok(BatchIter::new(
+                    source.flat_map(move |batch| project_batch(batch, &columns)),
+                ))
+            }
+            Plan::Aggregate {
+                source,
+                group_by,
+                aggregates,
+            } => {
+                let source = self.execute_batches_inner(*source)?;
+                let mut aggregator =
+                    Aggregator::new(group_by, aggregates).with_memory(&self.memory);
+                for batch in source {
+                    aggregator.add_batch(&batch?)?;
+                }
+                Ok(BatchIter::from_rows(aggregator.finish()?, num_columns))
+            }
+            _ => Err(Error::InvalidPlan),
+        }
+    }
+
     fn execute_values(&mut self, rows: Vec<Vec<Expr>>) -> Result<RowIter> {
         let mut result = Vec::new();
         for row in rows {
             let mut values = Vec::new();
             for expr in row {
-                values.push(expr.eval(None)?);
+                values.push(self.eval_subqueries(expr, None)?.eval(None)?);
             }
             result.push(Row::from(values));
         }
@@ -124,6 +447,46 @@ impl<'a, T: Transaction> Executor<'a, T> {
         Ok(RowIter::new(joiner))
     }
 
+    fn execute_hash_join(
+        &mut self,
+        left: Plan,
+        right: Plan,
+        keys: Vec<(usize, usize)>,
+        join_type: JoinType,
+    ) -> Result<RowIter> {
+        let left_cols = left.num_columns();
+        let right_cols = right.num_columns();
+        let left = self.execute_inner(left)?;
+        let right = self.execute_inner(right)?;
+
+        hash_join(
+            left,
+            right,
+            &keys,
+            left_cols,
+            right_cols,
+            join_type,
+            &self.memory,
+        )
+    }
+
+    fn execute_merge_join(
+        &mut self,
+        left: Plan,
+        right: Plan,
+        keys: Vec<(usize, usize)>,
+        join_type: JoinType,
+    ) -> Result<RowIter> {
+        let left_cols = left.num_columns();
+        let right_cols = right.num_columns();
+        let left = self.execute_inner(left)?;
+        let right = self.execute_inner(right)?;
+
+        let joiner = MergeJoiner::new(left, right, &keys, left_cols, right_cols, join_type)?;
+
+        Ok(RowIter::new(joiner))
+    }
+
     fn execute_aggregate(
         &mut self,
         source: Plan,
@@ -131,7 +494,7 @@ impl<'a, T: Transaction> Executor<'a, T> {
         aggregates: Vec<Aggregate>,
     ) -> Result<RowIter> {
         let source = self.execute_inner(source)?;
-        let mut aggregator = Aggregator::new(group_by, aggregates);
+        let mut aggregator = Aggregator::new(group_by, aggregates).with_memory(&self.memory);
         for row in source {
             let row = row?;
             aggregator.add_row(&row)?;
@@ -140,33 +503,1161 @@ impl<'a, T: Transaction> Executor<'a, T> {
         Ok(result)
     }
 
+    fn execute_recursive_cte(
+        &mut self,
+        name: String,
+        anchor: Plan,
+        recursive: Plan,
+        distinct: bool,
+    ) -> Result<RowIter> {
+        let mut seen = HashSet::new();
+        let mut working: Vec<Row> = self.execute_inner(anchor)?.try_collect()?;
+        if distinct {
+            working.retain(|row| seen.insert(row.clone()));
+        }
+        let mut result = working.clone();
+
+        // Restore any shadowed CTE of the same name once we're done.
+        let shadowed = self.working_tables.remove(&name);
+        while !working.is_empty() {
+            self.working_tables.insert(name.clone(), working);
+            working = self.execute_inner(recursive.clone())?.try_collect()?;
+            if distinct {
+                working.retain(|row| seen.insert(row.clone()));
+            }
+            result.extend(working.iter().cloned());
+        }
+        self.working_tables.remove(&name);
+        if let Some(shadowed) = shadowed {
+            self.working_tables.insert(name, shadowed);
+        }
+
+        Ok(RowIter::new(result.into_iter().map(Ok)))
+    }
+
+    fn execute_set_operation(
+        &mut self,
+        left: Plan,
+        right: Plan,
+        op: SetOperator,
+        all: bool,
+    ) -> Result<RowIter> {
+        let left = self.execute_inner(left)?;
+        let right = self.execute_inner(right)?;
+        if op == SetOperator::Union && all {
+            return Ok(RowIter::new(left.chain(right)));
+        }
+
+        let mut result = Vec::new();
+        let mut seen = HashSet::new();
+        match op {
+            SetOperator::Union => {
+                for row in left.chain(right) {
+                    let row = row?;
+                    if seen.insert(row.clone()) {
+                        result.push(row);
+                    }
+                }
+            }
+            SetOperator::Intersect | SetOperator::Except => {
+                // With ALL, each right row only cancels out a single matching left row.
+                let mut counts: HashMap<Row, usize> = HashMap::new();
+                for row in right {
+                    *counts.entry(row?).or_default() += 1;
+                }
+                for row in left {
+                    let row = row?;
+                    let matched = match counts.get_mut(&row) {
+                        Some(count) if *count > 0 => {
+                            if all {
+                                *count -= 1;
+                            }
+                            true
+                        }
+                        _ => false,
+                    };
+                    let keep = matched == (op == SetOperator::Intersect);
+                    if keep && (all || seen.insert(row.clone())) {
+                        result.push(row);
+                    }
+                }
+            }
+        }
+        Ok(RowIter::new(result.into_iter().map(Ok)))
+    }
+
     fn execute_filter(&mut self, source: Plan, predicate: Expr) -> Result<RowIter> {
         let source = self.execute_inner(source)?;
+        let predicate = self.eval_subqueries(predicate, None)?;
+        if !predicate.has_subquery() {
+            return Ok(RowIter::new(source.filter(move |row| match row {
+                Ok(row) => predicate.eval(Some(row)).is_ok_and(|v| v.is_truthy()),
+                Err(_) => true,
+            })));
+        }
+
+        // Correlated subqueries are executed for each row, which needs the executor, so
+        // the rows are filtered up front.
         let mut result = Vec::new();
         for row in source {
             let row = row?;
-            if predicate
-                .eval(Some(&row))
-                .map(|v| v.is_truthy())
-                .unwrap_or(false)
-            {
+            let value = self
+                .eval_subqueries(predicate.clone(), Some(&row))?
+                .eval(Some(&row));
+            if value.map(|v| v.is_truthy()).unwrap_or(false) {
                 result.push(row);
             }
         }
         Ok(RowIter::new(result.into_iter().map(Ok)))
     }
 
+    fn execute_order(&mut self, source: Plan, orders: Vec<(Expr, Direction)>) -> Result<RowIter> {
+        let source = self.execute_inner(source)?;
+        let directions = orders.iter().map(|(_, direction)| *direction).collect();
+        let mut sorter = Sorter::new(directions, &self.memory);
+        for row in source {
+            let row = row?;
+            let keys: Vec<_> = orders
+                .iter()
+                .map(|(expr, _)| expr.eval(Some(&row)))
+                .try_collect()?;
+            sorter.push(keys, row)?;
+        }
+        sorter.finish()
+    }
+
     fn execute_project(&mut self, source: Plan, columns: Vec<Expr>) -> Result<RowIter> {
         let source = self.execute_inner(source)?;
+        let columns: Vec<_> = columns
+            .into_iter()
+            .map(|expr| self.eval_subqueries(expr, None))
+            .try_collect()?;
+        if !columns.iter().any(Expr::has_subquery) {
+            return Ok(RowIter::new(source.map(move |row| {
+                let row = row?;
+                let values: Vec<_> = columns
+                    .iter()
+                    .map(|expr| expr.eval(Some(&row)))
+                    .try_collect()?;
+                Ok(Row::from(values))
+            })));
+        }
+
+        // Like filters, projections with correlated subqueries are evaluated up front.
         let mut result = Vec::new();
         for row in source {
             let row = row?;
             let mut projected_row = Vec::new();
             for expr in &columns {
-                projected_row.push(expr.eval(Some(&row))?);
+                if expr.has_subquery() {
+                    let expr = self.eval_subqueries(expr.clone(), Some(&row))?;
+                    projected_row.push(expr.eval(Some(&row))?);
+                } else {
+                    projected_row.push(expr.eval(Some(&row))?);
+                }
             }
             result.push(Row::from(projected_row));
         }
         Ok(RowIter::new(result.into_iter().map(Ok)))
     }
+
+    /// Replaces the subqueries in `expr` with their results. Correlated subqueries are
+    /// evaluated against `row`, the current row of the enclosing query, and are left in
+    /// place if there is no row. Uncorrelated `IN` subqueries without a row are
+    /// materialized into `Values` plans, so that they only execute once.
+    fn eval_subqueries(&mut self, expr: Expr, row: Option<&Row>) -> Result<Expr> {
+        if !expr.has_subquery() {
+            return Ok(expr);
+        }
+        expr.transform(&mut |expr| match expr {
+            Expr::Subquery(plan) if row.is_some() || !plan.is_correlated() => {
+                let mut rows = self.execute_subquery(*plan, row)?.into_iter();
+                let value = match (rows.next(), rows.next()) {
+                    (None, _) => Value::Null,
+                    (Some(row), None) => row.into_iter().next().unwrap_or(Value::Null),
+                    (Some(_), Some(_)) => return Err(Error::SubqueryMultipleRows),
+                };
+                Ok(Expr::Constant(value))
+            }
+            Expr::Exists(plan, negated) if row.is_some() || !plan.is_correlated() => {
+                let rows = self.execute_subquery(*plan, row)?;
+                Ok(Expr::Constant(Value::Boolean(rows.is_empty() == negated)))
+            }
+            Expr::InSubquery(expr, plan, negated) if row.is_some() || !plan.is_correlated() => {
+                let rows = self.execute_subquery(*plan, row)?;
+                let Some(row) = row else {
+                    let rows = rows
+                        .into_iter()
+                        .map(|row| row.into_iter().map(Expr::Constant).collect())
+                        .collect();
+                    return Ok(Expr::InSubquery(
+                        expr,
+                        Box::new(Plan::Values { rows }),
+                        negated,
+                    ));
+                };
+
+                let value = expr.eval(Some(row))?;
+                if value == Value::Null {
+                    return Ok(Expr::Constant(Value::Null));
+                }
+                let mut found_null = false;
+                for candidate in rows.into_iter().filter_map(|row| row.into_iter().next()) {
+                    if candidate == Value::Null {
+                        found_null = true;
+                    } else if candidate == value {
+                        return Ok(Expr::Constant(Value::Boolean(!negated)));
+                    }
+                }
+                if found_null {
+                    Ok(Expr::Constant(Value::Null))
+                } else {
+                    Ok(Expr::Constant(Value::Boolean(negated)))
+                }
+            }
+            expr => Ok(expr),
+        })
+    }
+
+    /// Executes a subquery plan, binding its references to the enclosing query's `row`.
+    fn execute_subquery(&mut self, plan: Plan, row: Option<&Row>) -> Result<Vec<Row>> {
+        let plan = match row {
+            Some(row) => plan.map_exprs(&mut |expr| {
+                expr.transform(&mut |expr| match expr {
+                    Expr::OuterColumn(index) => row
+                        .get(index)
+                        .cloned()
+                        .map(Expr::Constant)
+                        .ok_or(Error::InvalidColumnIndex(index)),
+                    expr => Ok(expr),
+                })
+            })?,
+            None => plan,
+        };
+        self.execute_inner(plan)?.try_collect()
+    }
+}
+
+/// Keeps the rows of each batch for which `predicate` is true. With `strict`, like a
+/// scan's filter, the predicate must evaluate to a boolean or NULL and errors are
+/// returned; otherwise, like a filter node, rows whose predicate fails are skipped.
+fn filter_batches(batches: BatchIter, predicate: Expr, strict: bool) -> BatchIter {
+    BatchIter::new(batches.flat_map(move |batch| filter_batch(batch, &predicate, strict)))
+}
+
+/// Keeps the rows of a batch for which `predicate` is true. If that fails, the rows before
+/// the one that failed are still returned, followed by the error.
+fn filter_batch(batch: Result<Batch>, predicate: &Expr, strict: bool) -> Vec<Result<Batch>> {
+    let batch = match batch {
+        Ok(batch) => batch,
+        Err(err) => return vec![Err(err)],
+    };
+    let values = predicate.eval_batch(&batch);
+    let mut selection = Vec::with_capacity(batch.len());
+    for i in 0..batch.len() {
+        // If the batch failed, find the failing rows by evaluating them one at a time.
+        let value = match &values {
+            Ok(values) => Ok(values.get(i)),
+            Err(_) => predicate.eval(Some(&batch.row(i))),
+        };
+        match is_selected(value, strict) {
+            Ok(selected) => selection.push(selected),
+            Err(err) => {
+                selection.resize(batch.len(), false);
+                return vec![Ok(batch.select(&selection)), Err(err)];
+            }
+        }
+    }
+    vec![Ok(batch.select(&selection))]
+}
+
+/// Returns whether a row is kept, given the value its filter evaluated to.
+fn is_selected(value: Result<Value>, strict: bool) -> Result<bool> {
+    match value {
+        Ok(Value::Boolean(selected)) => Ok(selected),
+        Ok(Value::Null) => Ok(false),
+        Ok(value) if strict => Err(Error::InvalidFilterResult(value)),
+        Ok(_) => Ok(false),
+        Err(err) if strict => Err(err),
+        Err(_) => Ok(false),
+    }
+}
+
+/// Evaluates the columns of a projection over a batch. If that fails, the rows before the
+/// one that failed are still returned, followed by the error.
+fn project_batch(batch: Result<Batch>, columns: &[Expr]) -> Vec<Result<Batch>> {
+    let batch = match batch {
+        Ok(batch) => batch,
+        Err(err) => return vec![Err(err)],
+    };
+    if let Ok(vectors) = columns
+        .iter()
+        .map(|expr| expr.eval_batch(&batch))
+        .try_collect()
+    {
+        return vec![Ok(Batch::new(vectors, batch.len()))];
+    }
+
+    let mut rows = Vec::new();
+    for i in 0..batch.len() {
+        let row = batch.row(i);
+        match columns
+            .iter()
+            .map(|expr| expr.eval(Some(&row)))
+            .try_collect::<_, Vec<_>, _>()
+        {
+            Ok(values) => rows.push(Row::from(values)),
+            Err(err) => return vec![Ok(Batch::from_rows(rows, columns.len())), Err(err)],
+        }
+    }
+    vec![Ok(Batch::from_rows(rows, columns.len()))]
+}
+
+/// A table scan with the filters and projections above it, and optionally an aggregation
+/// on top, which can run on each partition of the table separately.
+struct Pipeline {
+    table: String,
+    num_columns: usize,
+    filter: Option<Expr>,
+    /// The filters and projections, from the bottom up.
+    stages: Vec<Stage>,
+    aggregate: Option<(Vec<Expr>, Vec<Aggregate>)>,
+}
+
+enum Stage {
+    Filter(Expr),
+    Project(Vec<Expr>),
+}
+
+/// The result of running a pipeline on one partition.
+enum PartitionOutput {
+    /// The rows of the partition, ending with an error if one occurred.
+    Rows(Vec<Result<Row>>),
+    /// The partially aggregated groups of the partition.
+    Aggregator(Aggregator),
+}
+
+impl Pipeline {
+    /// Returns the pipeline for the plan, if it has that shape. Subqueries need the
+    /// executor, so plans containing them can't be split up.
+    fn new(plan: &Plan) -> Option<Self> {
+        let mut node = plan;
+        let mut aggregate = None;
+        if let Plan::Aggregate {
+            source,
+            group_by,
+            aggregates,
+        } = node
+        {
+            aggregate = Some((group_by.clone(), aggregates.clone()));
+            node = source;
+        }
+
+        let mut stages = Vec::new();
+        let pipeline = loop {
+            match node {
+                Plan::Filter { source, predicate } => {
+                    stages.push(Stage::Filter(predicate.clone()));
+                    node = source;
+                }
+                Plan::Project {
+                    source, columns, ..
+                } => {
+                    stages.push(Stage::Project(columns.clone()));
+                    node = source;
+                }
+                Plan::Scan { table, filter, .. } => {
+                    stages.reverse();
+                    break Pipeline {
+                        table: table.name.clone(),
+                        num_columns: node.num_columns(),
+                        filter: filter.clone(),
+                        stages,
+                        aggregate,
+                    };
+                }
+                _ => return None,
+            }
+        };
+
+        let mut exprs = pipeline
+            .filter
+            .iter()
+            .chain(pipeline.stages.iter().flat_map(|stage| match stage {
+                Stage::Filter(predicate) => std::slice::from_ref(predicate),
+                Stage::Project(columns) => columns.as_slice(),
+            }))
+            .chain(
+                pipeline
+                    .aggregate
+                    .iter()
+                    .flat_map(|(group_by, aggregates)| {
+                        group_by
+                            .iter()
+                            .chain(aggregates.iter().map(Aggregate::expr))
+                    }),
+            );
+        if exprs.any(Expr::has_subquery) {
+            return None;
+        }
+        Some(pipeline)
+    }
+
+    fn run(
+        &self,
+        partition: RowPartition,
+        vectorized: bool,
+        memory: &MemoryBudget,
+    ) -> Result<PartitionOutput> {
+        let mut aggregator = self.aggregate.as_ref().map(|(group_by, aggregates)| {
+            Aggregator::new(group_by.clone(), aggregates.clone()).with_memory(memory)
+        });
+        let mut rows = Vec::new();
+
+        if vectorized {
+            let mut partition = partition.peekable();
+            while partition.peek().is_some() {
+                let mut batches = Vec::new();
+                let mut chunk = Vec::with_capacity(BATCH_SIZE);
+                for row in partition.by_ref().take(BATCH_SIZE) {
+                    match row {
+                        Ok(row) => chunk.push(row),
+                        Err(err) => {
+                            batches.push(Err(err));
+                            break;
+                        }
+                    }
+                }
+                batches.insert(0, Ok(Batch::from_rows(chunk, self.num_columns)));
+
+                for batch in self.run_batches(batches) {
+                    match (aggregator.as_mut(), batch) {
+                        (Some(aggregator), batch) => aggregator.add_batch(&batch?)?,
+                        (None, Ok(batch)) => rows.extend(batch.into_rows().into_iter().map(Ok)),
+                        (None, Err(err)) => {
+                            rows.push(Err(err));
+                            return Ok(PartitionOutput::Rows(rows));
+                        }
+                    }
+                }
+            }
+        } else {
+            for row in partition {
+                match (aggregator.as_mut(), self.run_row(row)) {
+                    (_, Ok(None)) => {}
+                    (Some(aggregator), row) => {
+                        if let Some(row) = row? {
+                            aggregator.add_row(&row)?;
+                        }
+                    }
+                    (None, Ok(Some(row))) => rows.push(Ok(row)),
+                    (None, Err(err)) => {
+                        rows.push(Err(err));
+                        break;
+                    }
+                }
+            }
+        }
+
+        Ok(match aggregator {
+            Some(aggregator) => PartitionOutput::Aggregator(aggregator),
+            None => PartitionOutput::Rows(rows),
+        })
+    }
+
+    fn run_batches(&self, mut batches: Vec<Result<Batch>>) -> Vec<Result<Batch>> {
+        if let Some(filter) = &self.filter {
+            batches = batches
+                .into_iter()
+                .flat_map(|batch| filter_batch(batch, filter, true))
+                .collect();
+        }
+        for stage in &self.stages {
+            batches = batches
+                .into_iter()
+                .flat_map(|batch| match stage {
+                    Stage::Filter(predicate) => filter_batch(batch, predicate, false),
+                    Stage::Project(columns) => project_batch(batch, columns),
+                })
+                .collect();
+        }
+        batches
+    }
+
+    /// Runs a single row through the filters and projections, returning `None` if it is
+    /// filtered out.
+    fn run_row(&self, row: Result<Row>) -> Result<Option<Row>> {
+        let mut row = row?;
+        if let Some(filter) = &self.filter
+            && !is_selected(filter.eval(Some(&row)), true)?
+        {
+            return Ok(None);
+        }
+        for stage in &self.stages {
+            match stage {
+                Stage::Filter(predicate) => {
+                    if !is_selected(predicate.eval(Some(&row)), false)? {
+                        return Ok(None);
+                    }
+                }
+                Stage::Project(columns) => {
+                    let values: Vec<_> = columns
+                        .iter()
+                        .map(|expr| expr.eval(Some(&row)))
+                        .try_collect()?;
+                    row = Row::from(values);
+                }
+            }
+        }
+        Ok(Some(row))
+    }
+}
+
+/// The runtime statistics of a plan node, as shown by `EXPLAIN ANALYZE`.
+#[derive(Debug, Default)]
+pub struct NodeStats {
+    /// The number of rows the node produced.
+    pub rows: Cell<usize>,
+    /// The number of times the node was executed.
+    pub loops: Cell<usize>,
+    /// The time spent producing the node's rows, including the time spent in its inputs.
+    pub elapsed: Cell<Duration>,
+}
+
+impl NodeStats {
+    fn add_elapsed(&self, elapsed: Duration) {
+        self.elapsed.set(self.elapsed.get() + elapsed);
+    }
+}
+
+/// The rows of a plan node, counted and timed as they are produced.
+#[derive(Clone)]
+struct InstrumentedRows {
+    rows: RowIter,
+    stats: Rc<NodeStats>,
+}
+
+impl Iterator for InstrumentedRows {
+    type Item = Result<Row>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = Instant::now();
+        let row = self.rows.next();
+        self.stats.add_elapsed(start.elapsed());
+        if let Some(Ok(_)) = row {
+            self.stats.rows.set(self.stats.rows.get() + 1);
+        }
+        row
+    }
+}
+
+/// The batches of a vectorized plan node, counted and timed as they are produced.
+#[derive(Clone)]
+struct InstrumentedBatches {
+    batches: BatchIter,
+    stats: Rc<NodeStats>,
+}
+
+impl Iterator for InstrumentedBatches {
+    type Item = Result<Batch>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = Instant::now();
+        let batch = self.batches.next();
+        self.stats.add_elapsed(start.elapsed());
+        if let Some(Ok(batch)) = &batch {
+            self.stats.rows.set(self.stats.rows.get() + batch.len());
+        }
+        batch
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use itertools::Itertools;
+    use sqlparser::{dialect::GenericDialect, parser::Parser};
+
+    use super::*;
+    use crate::{
+        engine::Engine,
+        error::Error,
+        exec::{
+            batch::BATCH_SIZE,
+            planner::Planner,
+            session::{Session, StatementResult},
+        },
+        types::value::{ColumnLabel, Row, Value},
+        util::tests::{
+            TestEngine, create_generated_table, execute, peak_memory, query, test_engine,
+            test_engine_with_data,
+        },
+    };
+
+    #[test]
+    fn test_in_subquery() {
+        let engine = test_engine_with_data();
+        let mut session = Session::new(&engine);
+        let rows = query(
+            &mut session,
+            "SELECT username FROM users
+            WHERE user_id IN (SELECT user_id FROM orders WHERE product_id = 2)
+            ORDER BY username",
+        );
+        assert_eq!(
+            rows,
+            vec![
+                Row::from(("alice",)),
+                Row::from(("bob",)),
+                Row::from(("carol",)),
+            ]
+        );
+
+        let rows = query(
+            &mut session,
+            "SELECT username FROM users
+            WHERE user_id NOT IN (SELECT user_id FROM orders WHERE product_id = 2)
+            ORDER BY username",
+        );
+        assert_eq!(rows, vec![Row::from(("dave",)), Row::from(("eve",))]);
+    }
+
+    #[test]
+    fn test_correlated_exists() {
+        let engine = test_engine_with_data();
+        let mut session = Session::new(&engine);
+        let rows = query(
+            &mut session,
+            "SELECT username FROM users u
+            WHERE NOT EXISTS (
+                SELECT order_id FROM orders o
+                WHERE o.user_id = u.user_id AND o.product_id = 2
+            )
+            ORDER BY username",
+        );
+        assert_eq!(rows, vec![Row::from(("dave",)), Row::from(("eve",))]);
+    }
+
+    #[test]
+    fn test_scalar_subquery() {
+        let engine = test_engine_with_data();
+        let mut session = Session::new(&engine);
+        let rows = query(
+            &mut session,
+            "SELECT product_name FROM products
+            WHERE price = (SELECT MAX(price) FROM products)",
+        );
+        assert_eq!(rows, vec![Row::from(("Laptop",))]);
+
+        let rows = query(
+            &mut session,
+            "SELECT username, (SELECT COUNT(*) FROM orders o WHERE o.user_id = u.user_id)
+            FROM users u
+            ORDER BY username",
+        );
+        assert_eq!(
+            rows,
+            vec![
+                Row::from(("alice", 5)),
+                Row::from(("bob", 4)),
+                Row::from(("carol", 4)),
+                Row::from(("dave", 4)),
+                Row::from(("eve", 3)),
+            ]
+        );
+
+        let result = execute(
+            &mut session,
+            "SELECT username FROM users WHERE user_id = (SELECT user_id FROM orders)",
+        );
+        assert_eq!(result, Err(Error::SubqueryMultipleRows));
+    }
+
+    #[test]
+    fn test_set_operations() {
+        let engine = test_engine_with_data();
+        let mut session = Session::new(&engine);
+        let rows = query(
+            &mut session,
+            "SELECT user_id FROM users WHERE user_id < 3
+            UNION ALL
+            SELECT user_id FROM orders WHERE order_id < 3
+            ORDER BY user_id DESC
+            LIMIT 3",
+        );
+        assert_eq!(
+            rows,
+            vec![Row::from((2,)), Row::from((1,)), Row::from((1,))]
+        );
+
+        let rows = query(
+            &mut session,
+            "SELECT user_id FROM users WHERE user_id < 3
+            UNION
+            SELECT user_id FROM orders WHERE order_id < 3
+            ORDER BY user_id",
+        );
+        assert_eq!(rows, vec![Row::from((1,)), Row::from((2,))]);
+
+        let rows = query(
+            &mut session,
+            "SELECT user_id FROM users
+            INTERSECT
+            SELECT user_id FROM orders WHERE product_id = 2
+            ORDER BY user_id
+            LIMIT 2 OFFSET 1",
+        );
+        assert_eq!(rows, vec![Row::from((2,)), Row::from((3,))]);
+
+        let rows = query(
+            &mut session,
+            "SELECT user_id FROM users
+            EXCEPT
+            SELECT user_id FROM orders WHERE product_id = 2
+            ORDER BY user_id",
+        );
+        assert_eq!(rows, vec![Row::from((4,)), Row::from((5,))]);
+
+        // Each user's first order is cancelled out by their row in users.
+        let rows = query(
+            &mut session,
+            "SELECT user_id FROM orders EXCEPT ALL SELECT user_id FROM users",
+        );
+        assert_eq!(rows.len(), 15);
+        let rows = query(
+            &mut session,
+            "SELECT user_id FROM orders INTERSECT ALL SELECT user_id FROM users",
+        );
+        assert_eq!(rows.len(), 5);
+    }
+
+    #[test]
+    fn test_set_operation_mismatch() {
+        let engine = test_engine_with_data();
+        let mut session = Session::new(&engine);
+        let result = execute(
+            &mut session,
+            "SELECT user_id, username FROM users UNION SELECT user_id FROM orders",
+        );
+        assert!(matches!(result, Err(Error::InvalidSql(_))));
+
+        let result = execute(
+            &mut session,
+            "SELECT user_id FROM users UNION SELECT username FROM users",
+        );
+        assert!(matches!(result, Err(Error::InvalidSql(_))));
+    }
+
+    #[test]
+    fn test_outer_joins() {
+        let engine = test_engine_with_data();
+        let mut session = Session::new(&engine);
+        execute(
+            &mut session,
+            "CREATE TABLE a (id INT PRIMARY KEY, x INT);
+            CREATE TABLE b (id INT PRIMARY KEY, x INT);
+            INSERT INTO a VALUES (1, 1), (2, 2);
+            INSERT INTO b VALUES (1, 2), (2, 3);",
+        )
+        .unwrap();
+
+        let result = execute(&mut session, "SELECT * FROM a RIGHT JOIN b ON a.x = b.x").unwrap();
+        let StatementResult::Query { rows, columns } = result else {
+            panic!("expected a query result");
+        };
+        assert_eq!(
+            columns,
+            vec![
+                ColumnLabel::Qualified("a".to_string(), "id".to_string()),
+                ColumnLabel::Qualified("a".to_string(), "x".to_string()),
+                ColumnLabel::Qualified("b".to_string(), "id".to_string()),
+                ColumnLabel::Qualified("b".to_string(), "x".to_string()),
+            ]
+        );
+        assert_eq!(
+            rows,
+            vec![
+                Row::from((2, 2, 1, 2)),
+                Row::from((Value::Null, Value::Null, 2, 3)),
+            ]
+        );
+
+        let rows = query(
+            &mut session,
+            "SELECT a.id, b.id FROM a FULL OUTER JOIN b ON a.x = b.x",
+        );
+        assert_eq!(
+            rows,
+            vec![
+                Row::from((1, Value::Null)),
+                Row::from((2, 1)),
+                Row::from((Value::Null, 2)),
+            ]
+        );
+
+        let rows = query(&mut session, "SELECT a.id, b.id FROM a CROSS JOIN b");
+        assert_eq!(
+            rows,
+            vec![
+                Row::from((1, 1)),
+                Row::from((1, 2)),
+                Row::from((2, 1)),
+                Row::from((2, 2)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_mixed_type_join() {
+        let engine = test_engine();
+        let mut session = Session::new(&engine);
+        execute(
+            &mut session,
+            "CREATE TABLE a (id INT PRIMARY KEY, k INT);
+            CREATE TABLE b (id INT PRIMARY KEY, f FLOAT);
+            CREATE TABLE c (f FLOAT PRIMARY KEY);
+            INSERT INTO a VALUES (1, 1), (2, 2), (3, 3), (4, NULL);
+            INSERT INTO b VALUES (1, 1.0), (2, 2.5), (3, 3.0), (4, NULL);
+            INSERT INTO c VALUES (1.0), (2.5), (3.0);",
+        )
+        .unwrap();
+
+        // Integer keys match the floats equal to them in hash, grace hash and merge joins
+        // alike, just as they do in a nested loop join.
+        for memory_limit in ["0", "1"] {
+            execute(&mut session, &format!("SET memory_limit = {memory_limit}")).unwrap();
+            for sql in [
+                "SELECT a.id, b.id FROM a JOIN b ON a.k = b.f ORDER BY a.id",
+                "SELECT a.id, b.id FROM a JOIN b ON a.k + 0 = b.f ORDER BY a.id",
+                "SELECT a.id, c.f FROM a JOIN c ON a.id = c.f ORDER BY a.id",
+            ] {
+                assert_eq!(
+                    query(&mut session, sql),
+                    vec![Row::from((1, 1)), Row::from((3, 3))],
+                    "{sql}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_join_using() {
+        let engine = test_engine_with_data();
+        let mut session = Session::new(&engine);
+        execute(
+            &mut session,
+            "CREATE TABLE a (id INT PRIMARY KEY, x INT);
+            CREATE TABLE b (id INT PRIMARY KEY, x INT);
+            INSERT INTO a VALUES (1, 1), (2, 2);
+            INSERT INTO b VALUES (1, 2), (2, 3);",
+        )
+        .unwrap();
+
+        let result = execute(&mut session, "SELECT * FROM a JOIN b USING (x)").unwrap();
+        let StatementResult::Query { rows, columns } = result else {
+            panic!("expected a query result");
+        };
+        assert_eq!(
+            columns,
+            vec![
+                ColumnLabel::Unqualified("x".to_string()),
+                ColumnLabel::Qualified("a".to_string(), "id".to_string()),
+                ColumnLabel::Qualified("b".to_string(), "id".to_string()),
+            ]
+        );
+        assert_eq!(rows, vec![Row::from((2, 2, 1))]);
+
+        let rows = query(
+            &mut session,
+            "SELECT x, a.x, b.x, a.id, b.id FROM a FULL JOIN b USING (x) ORDER BY x",
+        );
+        assert_eq!(
+            rows,
+            vec![
+                Row::from((1, 1, 1, 1, Value::Null)),
+                Row::from((2, 2, 2, 2, 1)),
+                Row::from((3, 3, 3, Value::Null, 2)),
+            ]
+        );
+
+        let rows = query(
+            &mut session,
+            "SELECT user_id, username, order_id FROM users NATURAL JOIN orders
+            WHERE order_id < 3",
+        );
+        assert_eq!(
+            rows,
+            vec![Row::from((1, "alice", 1)), Row::from((1, "alice", 2))]
+        );
+
+        let result = execute(&mut session, "SELECT * FROM a JOIN b USING (y)");
+        assert_eq!(result, Err(Error::ColumnNotFound("y".to_string())));
+    }
+
+    /// Returns the lines of an `EXPLAIN` statement's output.
+    fn explain(session: &mut Session<'_, TestEngine>, sql: &str) -> Vec<String> {
+        query(session, sql)
+            .into_iter()
+            .map(|row| match row.into_iter().next() {
+                Some(Value::String(line)) => line,
+                value => panic!("expected a plan line, got {value:?}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_explain() {
+        let engine = test_engine_with_data();
+        let mut session = Session::new(&engine);
+        let sql = "SELECT u.username FROM users u JOIN orders o ON u.user_id = o.user_id
+            WHERE o.quantity > 1";
+
+        let lines = explain(&mut session, &format!("EXPLAIN {sql}"));
+        assert!(lines.iter().any(|line| line.contains("Join")));
+        assert!(lines.iter().all(|line| !line.contains("loops=")));
+        // Each node is annotated with the cost model's estimates.
+        assert!(lines[0].starts_with("Query (cost="));
+        assert!(
+            lines
+                .iter()
+                .any(|line| line.contains("Join") && line.contains(" rows="))
+        );
+
+        let lines = explain(&mut session, &format!("EXPLAIN ANALYZE {sql}"));
+        let project = lines.iter().find(|line| line.contains("Project")).unwrap();
+        assert!(project.contains("(rows=1 loops=1 time="));
+        // The filter on quantity is evaluated by the scan of orders.
+        let scans: Vec<_> = lines
+            .iter()
+            .filter(|line| line.contains("Scan ("))
+            .collect();
+        assert_eq!(scans.len(), 2);
+        assert!(scans.iter().any(|line| line.contains("rows=1 loops=1")));
+        assert!(lines.last().unwrap().starts_with("Execution time: "));
+
+        // EXPLAIN ANALYZE runs the statement.
+        explain(
+            &mut session,
+            "EXPLAIN ANALYZE INSERT INTO users VALUES (6, 'frank', NULL)",
+        );
+        assert_eq!(
+            query(&mut session, "SELECT * FROM users WHERE user_id = 6").len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_join_order() {
+        let engine = test_engine_with_data();
+        let mut session = Session::new(&engine);
+        // As written, users and products would be joined first, as a cross product.
+        let sql = "SELECT u.username, p.product_name, o.order_id
+            FROM users u, products p, orders o
+            WHERE o.user_id = u.user_id AND o.product_id = p.product_id AND o.quantity = 1
+            ORDER BY o.order_id";
+
+        let lines = explain(&mut session, &format!("EXPLAIN {sql}"));
+        assert!(lines.iter().any(|line| line.contains("HashJoin")));
+        assert!(lines.iter().all(|line| !line.contains("Join: None")));
+
+        let rows = query(&mut session, sql);
+        assert_eq!(rows.len(), 19);
+        assert_eq!(rows[0], Row::from(("alice", "Laptop", 1)));
+        assert_eq!(rows[1], Row::from(("bob", "Keyboard", 3)));
+    }
+
+    #[test]
+    fn test_join_order_small_inputs() {
+        let engine = test_engine_with_data();
+        let mut session = Session::new(&engine);
+        // Selective filters must not make the inputs look empty, which would make a cross
+        // product look as cheap as a join on a key.
+        for filter in [
+            "o.order_id = 1",
+            "u.user_id = 1 AND p.product_id = 1",
+            "o.quantity = 5 AND u.username = 'alice'",
+            "o.order_id IN (1, 2) AND p.price > 1000",
+        ] {
+            let sql = format!(
+                "SELECT u.username, p.product_name FROM users u, orders o, products p
+                WHERE o.user_id = u.user_id AND o.product_id = p.product_id AND {filter}"
+            );
+            let lines = explain(&mut session, &format!("EXPLAIN {sql}"));
+            assert!(
+                lines.iter().all(|line| !line.contains("Join: None")),
+                "{filter}: {lines:#?}"
+            );
+            assert!(
+                lines.iter().all(|line| !line.contains(" rows=0 ")),
+                "{filter}: {lines:#?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_parallel_execution() {
+        let engine = test_engine();
+        let mut session = Session::new(&engine);
+        create_generated_table(&mut session, 5000);
+
+        let queries = [
+            "SELECT * FROM t",
+            "SELECT id, x * 2, y / 2 FROM t WHERE x > 3 AND y < 5.0",
+            "SELECT COUNT(*), COUNT(x), SUM(x), AVG(y), MIN(name), MAX(x) FROM t WHERE id > 100",
+            "SELECT x, COUNT(*), SUM(y), MAX(id) FROM t GROUP BY x",
+            "SELECT SUM(x) FROM t WHERE id < 0",
+            "SELECT id FROM t WHERE x = 1 ORDER BY id DESC LIMIT 5",
+        ];
+        let expected: Vec<_> = queries.iter().map(|sql| query(&mut session, sql)).collect();
+        for vectorized in [false, true] {
+            execute(&mut session, &format!("SET vectorized = {vectorized}")).unwrap();
+            execute(&mut session, "SET workers = 4").unwrap();
+            for (sql, expected) in queries.iter().zip(&expected) {
+                assert_eq!(&query(&mut session, sql), expected, "{sql}");
+            }
+            execute(&mut session, "SET workers = 1").unwrap();
+        }
+
+        // Errors in a partition are returned after the rows that came before them.
+        execute(&mut session, "SET workers = 4").unwrap();
+        assert_eq!(
+            execute(
+                &mut session,
+                "SELECT id / x FROM t WHERE id > 3000 AND x >= 0"
+            ),
+            Err(Error::IntegerOverflow)
+        );
+        assert_eq!(
+            execute(&mut session, "SET workers = 0"),
+            Err(Error::InvalidValue(Box::new(
+                sqlparser::ast::Value::Number("0".to_string(), false)
+            )))
+        );
+        assert_eq!(
+            execute(&mut session, "SET nonsense = 1"),
+            Err(Error::UnknownSetting("nonsense".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_spilling() {
+        let engine = test_engine();
+        let mut session = Session::new(&engine);
+        create_generated_table(&mut session, 5000);
+        execute(&mut session, "CREATE TABLE u (id INT PRIMARY KEY, x INT)").unwrap();
+        let values = (0..20).map(|i| format!("({i}, {})", i % 13)).join(", ");
+        execute(&mut session, &format!("INSERT INTO u VALUES {values}")).unwrap();
+
+        let queries = [
+            "SELECT * FROM t ORDER BY x DESC, name",
+            "SELECT name, COUNT(*), SUM(x), MIN(id) FROM t GROUP BY name",
+            "SELECT u.id, t.id, t.name FROM u LEFT JOIN t ON u.x = t.x",
+        ];
+        let run = |session: &mut Session<_>| -> Vec<Vec<Row>> {
+            queries
+                .iter()
+                .enumerate()
+                .map(|(i, sql)| {
+                    let mut rows = query(session, sql);
+                    // Spilled groups and joins come out in a different order.
+                    if i > 0 {
+                        rows.sort();
+                    }
+                    rows
+                })
+                .collect()
+        };
+        let expected = run(&mut session);
+
+        execute(&mut session, "SET memory_limit = 20000").unwrap();
+        for vectorized in [false, true] {
+            execute(&mut session, &format!("SET vectorized = {vectorized}")).unwrap();
+            assert_eq!(run(&mut session), expected);
+        }
+
+        execute(&mut session, "SET spill = false").unwrap();
+        for sql in queries {
+            assert_eq!(
+                execute(&mut session, sql),
+                Err(Error::MemoryLimitExceeded(20000)),
+                "{sql}"
+            );
+        }
+        execute(&mut session, "SET memory_limit = 0").unwrap();
+        assert_eq!(run(&mut session), expected);
+    }
+
+    #[test]
+    fn test_join_above_spilled_hash_join() {
+        let engine = test_engine();
+        let mut session = Session::new(&engine);
+        for (table, column) in [("p", "v"), ("q", "w"), ("r", "x")] {
+            execute(
+                &mut session,
+                &format!("CREATE TABLE {table} (id INT PRIMARY KEY, {column} INT)"),
+            )
+            .unwrap();
+            let values = (0..2000).map(|i| format!("({i}, {})", 1999 - i)).join(", ");
+            execute(
+                &mut session,
+                &format!("INSERT INTO {table} VALUES {values}"),
+            )
+            .unwrap();
+        }
+
+        // The hash join of p and q comes out in partition order once it spills, so the
+        // join with r on p's primary key can't rely on it being sorted by it.
+        let sql = "SELECT COUNT(*) FROM p JOIN q ON p.v = q.w JOIN r ON p.id = r.id";
+        for memory_limit in [50000, 0] {
+            execute(&mut session, &format!("SET memory_limit = {memory_limit}")).unwrap();
+            assert_eq!(query(&mut session, sql), vec![Row::from((2000,))]);
+            let lines = explain(&mut session, &format!("EXPLAIN {sql}"));
+            assert!(
+                !lines.iter().any(|line| line.contains("MergeJoin")),
+                "{lines:#?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_streaming_memory() {
+        // Filters and projections pull rows from their input one at a time, or one batch at
+        // a time when vectorized, so the memory needed to scan through a table doesn't grow
+        // with its size.
+        let peak = |count: usize, vectorized: bool| {
+            let engine = test_engine();
+            let mut session = Session::new(&engine);
+            execute(&mut session, "CREATE TABLE t (id INT PRIMARY KEY, x INT)").unwrap();
+            let values = (0..count).map(|i| format!("({i}, {})", i % 7)).join(", ");
+            execute(&mut session, &format!("INSERT INTO t VALUES {values}")).unwrap();
+
+            // The subquery keeps the filter from being pushed into the scan.
+            let sql = "SELECT id, x * 2 FROM t WHERE x > (SELECT 0)";
+            let stmts = Parser::parse_sql(&GenericDialect {}, sql).unwrap();
+            let txn = engine.begin().unwrap();
+            let Plan::Query(plan) = Planner::new(&txn).plan(&stmts[0]).unwrap() else {
+                panic!("expected a query plan");
+            };
+            let (rows, peak) = peak_memory(|| {
+                let rows = Executor::new(&txn)
+                    .with_options(ExecutionOptions {
+                        vectorized,
+                        ..Default::default()
+                    })
+                    .execute_inner(*plan)
+                    .unwrap();
+                rows.inspect(|row| assert!(row.is_ok())).count()
+            });
+            assert_eq!(rows, count - count.div_ceil(7));
+            peak
+        };
+
+        for vectorized in [false, true] {
+            let small = peak(2 * BATCH_SIZE, vectorized);
+            let large = peak(20 * BATCH_SIZE, vectorized);
+            assert!(
+                large < small * 2,
+                "scanning 10x the rows took {large} bytes instead of {small}"
+            );
+        }
+    }
 }
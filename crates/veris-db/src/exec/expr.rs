@@ -1,20 +1,433 @@
 use std::fmt;
 
+use itertools::Itertools;
 use sqlparser::ast;
 
 use crate::{
     error::Error,
-    types::value::{Row, Value},
+    types::value::{DataType, Row, Value},
+};
+
+use super::{
+    batch::{Batch, ColumnVector},
+    function::{ScalarFunction, Volatility},
+    plan::Plan,
 };
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Expr {
     Constant(Value),
     Column(usize),
+    /// A column of the enclosing query's current row, referenced from a correlated subquery.
+    OuterColumn(usize),
+    /// A parameter of a prepared statement, by its zero-based index, which is bound to a
+    /// value before the plan is executed.
+    Parameter(usize),
     BinaryOp(Box<Expr>, BinaryOp, Box<Expr>),
+    UnaryOp(UnaryOp, Box<Expr>),
+    /// Whether the value of the expression is (or with `true`, is not) NULL.
+    IsNull(Box<Expr>, bool),
+    /// Whether the value of the expression is (or with `true`, is not) between the two
+    /// bounds, inclusive.
+    Between(Box<Expr>, Box<Expr>, Box<Expr>, bool),
+    /// Whether the value of the expression matches a `LIKE` pattern, in which `%` matches
+    /// any sequence of characters and `_` any single character, unless escaped.
+    Like {
+        expr: Box<Expr>,
+        pattern: Box<Expr>,
+        negated: bool,
+        case_insensitive: bool,
+        escape: Option<char>,
+    },
+    /// The result of the first branch whose condition is true, or whose value equals the
+    /// operand if there is one, or else the `otherwise` result or NULL.
+    Case {
+        operand: Option<Box<Expr>>,
+        branches: Vec<(Expr, Expr)>,
+        otherwise: Option<Box<Expr>>,
+    },
+    Cast(Box<Expr>, DataType),
+    /// The first of the expressions that is not NULL, or NULL if they all are.
+    Coalesce(Vec<Expr>),
+    /// A call of a built-in scalar function.
+    Function(ScalarFunction, Vec<Expr>),
+    Subquery(Box<Plan>),
+    Exists(Box<Plan>, bool),
+    InSubquery(Box<Expr>, Box<Plan>, bool),
+    /// Whether the value of the expression is (or with `true`, is not) in the list.
+    InList(Box<Expr>, Vec<Expr>, bool),
 }
 
 impl Expr {
+    /// Walks the expression tree depth-first, calling `visitor` on each node until it
+    /// returns false. Plans nested in subqueries are not descended into.
+    pub fn walk(&self, visitor: &mut impl FnMut(&Expr) -> bool) -> bool {
+        if !visitor(self) {
+            return false;
+        }
+        match self {
+            Expr::Constant(_)
+            | Expr::Column(_)
+            | Expr::OuterColumn(_)
+            | Expr::Parameter(_)
+            | Expr::Subquery(_)
+            | Expr::Exists(..) => true,
+            Expr::BinaryOp(a, _, b) => a.walk(visitor) && b.walk(visitor),
+            Expr::UnaryOp(_, expr) | Expr::IsNull(expr, _) | Expr::Cast(expr, _) => {
+                expr.walk(visitor)
+            }
+            Expr::Between(expr, low, high, _) => {
+                expr.walk(visitor) && low.walk(visitor) && high.walk(visitor)
+            }
+            Expr::Like { expr, pattern, .. } => expr.walk(visitor) && pattern.walk(visitor),
+            Expr::Case {
+                operand,
+                branches,
+                otherwise,
+            } => {
+                operand.as_ref().is_none_or(|operand| operand.walk(visitor))
+                    && branches
+                        .iter()
+                        .all(|(when, then)| when.walk(visitor) && then.walk(visitor))
+                    && otherwise
+                        .as_ref()
+                        .is_none_or(|otherwise| otherwise.walk(visitor))
+            }
+            Expr::Coalesce(exprs) | Expr::Function(_, exprs) => {
+                exprs.iter().all(|expr| expr.walk(visitor))
+            }
+            Expr::InSubquery(expr, ..) => expr.walk(visitor),
+            Expr::InList(expr, list, _) => {
+                expr.walk(visitor) && list.iter().all(|item| item.walk(visitor))
+            }
+        }
+    }
+
+    /// Splits a conjunction into its operands, e.g. `a AND (b AND c)` into `[a, b, c]`.
+    pub fn into_conjuncts(self) -> Vec<Expr> {
+        match self {
+            Expr::BinaryOp(a, BinaryOp::And, b) => {
+                let mut conjuncts = a.into_conjuncts();
+                conjuncts.extend(b.into_conjuncts());
+                conjuncts
+            }
+            expr => vec![expr],
+        }
+    }
+
+    /// Joins expressions into a conjunction, or returns `None` if there are none.
+    pub fn and_all(exprs: impl IntoIterator<Item = Expr>) -> Option<Expr> {
+        exprs
+            .into_iter()
+            .reduce(|a, b| Expr::BinaryOp(Box::new(a), BinaryOp::And, Box::new(b)))
+    }
+
+    /// Returns the indexes of the columns that the expression refers to, in ascending order.
+    pub fn columns(&self) -> Vec<usize> {
+        let mut columns = Vec::new();
+        self.walk(&mut |expr| {
+            if let Expr::Column(index) = expr {
+                columns.push(*index);
+            }
+            true
+        });
+        columns.sort();
+        columns.dedup();
+        columns
+    }
+
+    /// Replaces each column reference with the expression returned by `f` for its index.
+    pub fn map_columns(
+        self,
+        f: &mut impl FnMut(usize) -> Result<Expr, Error>,
+    ) -> Result<Expr, Error> {
+        self.transform(&mut |expr| match expr {
+            Expr::Column(index) => f(index),
+            expr => Ok(expr),
+        })
+    }
+
+    /// Returns whether any node in the expression tree satisfies `predicate`.
+    pub fn contains(&self, predicate: &impl Fn(&Expr) -> bool) -> bool {
+        !self.walk(&mut |expr| !predicate(expr))
+    }
+
+    /// Returns whether the expression contains a subquery.
+    pub fn has_subquery(&self) -> bool {
+        self.contains(&|expr| {
+            matches!(
+                expr,
+                Expr::Subquery(_) | Expr::Exists(..) | Expr::InSubquery(..)
+            )
+        })
+    }
+
+    /// Returns whether evaluating the expression needs more than itself: a row's columns,
+    /// parameter values or the results of subqueries.
+    pub fn needs_context(&self) -> bool {
+        self.contains(&|expr| {
+            matches!(
+                expr,
+                Expr::Column(_)
+                    | Expr::OuterColumn(_)
+                    | Expr::Parameter(_)
+                    | Expr::Subquery(_)
+                    | Expr::Exists(..)
+                    | Expr::InSubquery(..)
+            )
+        })
+    }
+
+    /// Returns whether the expression's value doesn't depend on the row it is evaluated
+    /// for, so that it can be evaluated once ahead of time. Calls of volatile functions
+    /// may have a different value every time.
+    pub fn is_constant(&self) -> bool {
+        !self.needs_context()
+            && !self.contains(&|expr| {
+                matches!(
+                    expr,
+                    Expr::Function(function, _) if function.volatility() == Volatility::Volatile
+                )
+            })
+    }
+
+    /// Returns whether the expression is the constant `TRUE`.
+    pub fn is_true(&self) -> bool {
+        matches!(self, Expr::Constant(Value::Boolean(true)))
+    }
+
+    /// Returns whether the expression is the constant `FALSE`.
+    pub fn is_false(&self) -> bool {
+        matches!(self, Expr::Constant(Value::Boolean(false)))
+    }
+
+    /// Transforms the expression tree bottom-up, applying `f` to each node after its
+    /// children. Plans nested in subqueries are not descended into.
+    pub fn transform(self, f: &mut impl FnMut(Expr) -> Result<Expr, Error>) -> Result<Expr, Error> {
+        let expr = match self {
+            Expr::BinaryOp(a, op, b) => {
+                Expr::BinaryOp(Box::new(a.transform(f)?), op, Box::new(b.transform(f)?))
+            }
+            Expr::UnaryOp(op, expr) => Expr::UnaryOp(op, Box::new(expr.transform(f)?)),
+            Expr::IsNull(expr, negated) => Expr::IsNull(Box::new(expr.transform(f)?), negated),
+            Expr::Between(expr, low, high, negated) => Expr::Between(
+                Box::new(expr.transform(f)?),
+                Box::new(low.transform(f)?),
+                Box::new(high.transform(f)?),
+                negated,
+            ),
+            Expr::Like {
+                expr,
+                pattern,
+                negated,
+                case_insensitive,
+                escape,
+            } => Expr::Like {
+                expr: Box::new(expr.transform(f)?),
+                pattern: Box::new(pattern.transform(f)?),
+                negated,
+                case_insensitive,
+                escape,
+            },
+            Expr::Case {
+                operand,
+                branches,
+                otherwise,
+            } => Expr::Case {
+                operand: match operand {
+                    Some(operand) => Some(Box::new(operand.transform(f)?)),
+                    None => None,
+                },
+                branches: branches
+                    .into_iter()
+                    .map(|(when, then)| Ok::<_, Error>((when.transform(f)?, then.transform(f)?)))
+                    .collect::<Result<_, _>>()?,
+                otherwise: match otherwise {
+                    Some(otherwise) => Some(Box::new(otherwise.transform(f)?)),
+                    None => None,
+                },
+            },
+            Expr::Cast(expr, data_type) => Expr::Cast(Box::new(expr.transform(f)?), data_type),
+            Expr::Coalesce(exprs) => Expr::Coalesce(
+                exprs
+                    .into_iter()
+                    .map(|expr| expr.transform(f))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Expr::Function(function, args) => Expr::Function(
+                function,
+                args.into_iter()
+                    .map(|arg| arg.transform(f))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Expr::InSubquery(expr, plan, negated) => {
+                Expr::InSubquery(Box::new(expr.transform(f)?), plan, negated)
+            }
+            Expr::InList(expr, list, negated) => Expr::InList(
+                Box::new(expr.transform(f)?),
+                list.into_iter()
+                    .map(|item| item.transform(f))
+                    .collect::<Result<_, _>>()?,
+                negated,
+            ),
+            expr => expr,
+        };
+        f(expr)
+    }
+
+    /// Infers the data type of the expression, given the data types of the input columns.
+    /// Returns `None` if the type is unknown, e.g. for NULL.
+    pub fn data_type(&self, column_type: &impl Fn(usize) -> Option<DataType>) -> Option<DataType> {
+        match self {
+            Expr::Constant(value) => value.data_type(),
+            Expr::Column(index) => column_type(*index),
+            Expr::OuterColumn(_) | Expr::Parameter(_) => None,
+            Expr::BinaryOp(a, op, b) => match op {
+                BinaryOp::Add
+                | BinaryOp::Subtract
+                | BinaryOp::Multiply
+                | BinaryOp::Divide
+                | BinaryOp::Modulus => match (a.data_type(column_type), b.data_type(column_type)) {
+                    (Some(DataType::Integer), Some(DataType::Integer)) => Some(DataType::Integer),
+                    (Some(a), Some(b)) if a.is_compatible_with(&DataType::Float) => b
+                        .is_compatible_with(&DataType::Float)
+                        .then_some(DataType::Float),
+                    (a, b) => a.or(b),
+                },
+                _ => Some(DataType::Boolean),
+            },
+            Expr::UnaryOp(UnaryOp::Not, _) => Some(DataType::Boolean),
+            Expr::UnaryOp(UnaryOp::Negate, expr) => expr.data_type(column_type),
+            Expr::IsNull(..) | Expr::Between(..) | Expr::Like { .. } => Some(DataType::Boolean),
+            Expr::Case {
+                branches,
+                otherwise,
+                ..
+            } => branches
+                .iter()
+                .map(|(_, then)| then)
+                .chain(otherwise.as_deref())
+                .find_map(|expr| expr.data_type(column_type)),
+            Expr::Cast(_, data_type) => Some(*data_type),
+            Expr::Coalesce(exprs) => exprs.iter().find_map(|expr| expr.data_type(column_type)),
+            Expr::Function(function, args) => function
+                .return_type(&Self::data_types(args, column_type))
+                .ok()
+                .flatten(),
+            Expr::Subquery(plan) => plan.column_type(0),
+            Expr::Exists(..) | Expr::InSubquery(..) | Expr::InList(..) => Some(DataType::Boolean),
+        }
+    }
+
+    /// Checks the types of the arguments of the function calls in the expression, and of
+    /// those in its subqueries, given the data types of the input columns.
+    pub fn check_types(
+        &self,
+        column_type: &impl Fn(usize) -> Option<DataType>,
+    ) -> Result<(), Error> {
+        let mut result = Ok(());
+        self.walk(&mut |expr| {
+            result = match expr {
+                Expr::Function(function, args) => function
+                    .return_type(&Self::data_types(args, column_type))
+                    .map(|_| ()),
+                Expr::Coalesce(exprs) => {
+                    let types = Self::data_types(exprs, column_type);
+                    match types
+                        .iter()
+                        .flatten()
+                        .tuple_windows()
+                        .find(|(a, b)| !a.is_compatible_with(b))
+                    {
+                        Some((a, b)) => Err(Error::InvalidArgument(format!(
+                            "can not combine {} with {} in COALESCE",
+                            a, b
+                        ))),
+                        None => Ok(()),
+                    }
+                }
+                Expr::UnaryOp(UnaryOp::Not, a) => Self::check_boolean(a, expr, column_type),
+                Expr::BinaryOp(a, BinaryOp::And | BinaryOp::Or, b) => {
+                    Self::check_boolean(a, expr, column_type)
+                        .and_then(|_| Self::check_boolean(b, expr, column_type))
+                }
+                Expr::UnaryOp(UnaryOp::Negate, a) => match a.data_type(column_type) {
+                    Some(DataType::Integer | DataType::Float | DataType::Decimal { .. }) | None => {
+                        Ok(())
+                    }
+                    Some(data_type) => Err(Error::InvalidArgument(format!(
+                        "can not negate {}",
+                        data_type
+                    ))),
+                },
+                Expr::Like { expr, pattern, .. } => {
+                    match [expr, pattern]
+                        .into_iter()
+                        .filter_map(|expr| expr.data_type(column_type))
+                        .find(|data_type| !matches!(data_type, DataType::String { .. }))
+                    {
+                        Some(data_type) => Err(Error::InvalidArgument(format!(
+                            "LIKE expects VARCHAR, got {}",
+                            data_type
+                        ))),
+                        None => Ok(()),
+                    }
+                }
+                Expr::Case {
+                    branches,
+                    otherwise,
+                    ..
+                } => {
+                    match branches
+                        .iter()
+                        .map(|(_, then)| then)
+                        .chain(otherwise.as_deref())
+                        .filter_map(|expr| expr.data_type(column_type))
+                        .tuple_windows()
+                        .find(|(a, b)| !a.is_compatible_with(b))
+                    {
+                        Some((a, b)) => Err(Error::InvalidArgument(format!(
+                            "can not combine {} with {} in CASE",
+                            a, b
+                        ))),
+                        None => Ok(()),
+                    }
+                }
+                Expr::Subquery(plan) | Expr::Exists(plan, _) | Expr::InSubquery(_, plan, _) => {
+                    plan.check_types()
+                }
+                _ => Ok(()),
+            };
+            result.is_ok()
+        });
+        result
+    }
+
+    /// Checks that an operand of a logical operator is a boolean, if its type is known.
+    fn check_boolean(
+        operand: &Expr,
+        expr: &Expr,
+        column_type: &impl Fn(usize) -> Option<DataType>,
+    ) -> Result<(), Error> {
+        match operand.data_type(column_type) {
+            Some(DataType::Boolean) | None => Ok(()),
+            Some(data_type) => Err(Error::InvalidArgument(format!(
+                "expected a boolean in {}, got {}",
+                expr, data_type
+            ))),
+        }
+    }
+
+    fn data_types(
+        exprs: &[Expr],
+        column_type: &impl Fn(usize) -> Option<DataType>,
+    ) -> Vec<Option<DataType>> {
+        exprs
+            .iter()
+            .map(|expr| expr.data_type(column_type))
+            .collect()
+    }
+
     pub fn eval(&self, row: Option<&Row>) -> Result<Value, Error> {
         match self {
             Expr::Constant(value) => Ok(value.clone()),
@@ -28,31 +441,125 @@ impl Expr {
                     Err(Error::RowNotFound)
                 }
             }
-            Expr::BinaryOp(a, op, b) => {
-                let a = a.eval(row)?;
-                let b = b.eval(row)?;
-                let result = match op {
-                    BinaryOp::Add => a.checked_add(&b)?,
-                    BinaryOp::Subtract => a.checked_sub(&b)?,
-                    BinaryOp::Multiply => a.checked_mul(&b)?,
-                    BinaryOp::Divide => a.checked_div(&b)?,
-                    BinaryOp::Equal => Value::Boolean(a == b),
-                    BinaryOp::NotEqual => Value::Boolean(a != b),
-                    BinaryOp::GreaterThan => Value::Boolean(a > b),
-                    BinaryOp::LessThan => Value::Boolean(a < b),
-                    BinaryOp::GreaterThanOrEqual => Value::Boolean(a >= b),
-                    BinaryOp::LessThanOrEqual => Value::Boolean(a <= b),
-                    BinaryOp::And => Value::Boolean(a.is_truthy() && b.is_truthy()),
-                    BinaryOp::Or => Value::Boolean(a.is_truthy() || b.is_truthy()),
-
-                    _ => {
-                        return Err(Error::NotYetSupported(format!(
-                            "Binary operator {:?} not yet supported",
-                            op
-                        )));
+            Expr::BinaryOp(a, op, b) => op.apply(&a.eval(row)?, &b.eval(row)?),
+            Expr::UnaryOp(op, expr) => op.apply(&expr.eval(row)?),
+            Expr::IsNull(expr, negated) => {
+                Ok(Value::Boolean((expr.eval(row)? == Value::Null) != *negated))
+            }
+            Expr::Between(expr, low, high, negated) => {
+                let (value, low, high) = (expr.eval(row)?, low.eval(row)?, high.eval(row)?);
+                if [&value, &low, &high].contains(&&Value::Null) {
+                    return Ok(Value::Null);
+                }
+                Ok(Value::Boolean((low <= value && value <= high) != *negated))
+            }
+            Expr::Like {
+                expr,
+                pattern,
+                negated,
+                case_insensitive,
+                escape,
+            } => match (expr.eval(row)?, pattern.eval(row)?) {
+                (Value::Null, _) | (_, Value::Null) => Ok(Value::Null),
+                (Value::String(value), Value::String(pattern)) => {
+                    let matches = match case_insensitive {
+                        true => {
+                            // The escape character is lowercased along with the pattern.
+                            let escape = escape.map(|c| c.to_lowercase().next().unwrap_or(c));
+                            like(&value.to_lowercase(), &pattern.to_lowercase(), escape)
+                        }
+                        false => like(&value, &pattern, *escape),
+                    };
+                    Ok(Value::Boolean(matches != *negated))
+                }
+                (value, pattern) => Err(Error::InvalidArgument(format!(
+                    "can not match {} against {}",
+                    value, pattern
+                ))),
+            },
+            Expr::Case {
+                operand,
+                branches,
+                otherwise,
+            } => {
+                let operand = operand
+                    .as_ref()
+                    .map(|operand| operand.eval(row))
+                    .transpose()?;
+                for (when, then) in branches {
+                    let when = when.eval(row)?;
+                    let matched = match &operand {
+                        Some(operand) => *operand != Value::Null && *operand == when,
+                        None => when == Value::Boolean(true),
+                    };
+                    if matched {
+                        return then.eval(row);
                     }
-                };
-                Ok(result)
+                }
+                match otherwise {
+                    Some(otherwise) => otherwise.eval(row),
+                    None => Ok(Value::Null),
+                }
+            }
+            Expr::Cast(expr, data_type) => expr.eval(row)?.cast(data_type),
+            Expr::Coalesce(exprs) => {
+                for expr in exprs {
+                    let value = expr.eval(row)?;
+                    if value != Value::Null {
+                        return Ok(value);
+                    }
+                }
+                Ok(Value::Null)
+            }
+            Expr::Function(function, args) => function.call(
+                &args
+                    .iter()
+                    .map(|arg| arg.eval(row))
+                    .collect::<Result<Vec<_>, _>>()?,
+            ),
+            Expr::InList(expr, list, negated) => {
+                let value = expr.eval(row)?;
+                if value == Value::Null {
+                    return Ok(Value::Null);
+                }
+                let mut found_null = false;
+                for item in list {
+                    let item = item.eval(row)?;
+                    if item == Value::Null {
+                        found_null = true;
+                    } else if item == value {
+                        return Ok(Value::Boolean(!negated));
+                    }
+                }
+                if found_null {
+                    Ok(Value::Null)
+                } else {
+                    Ok(Value::Boolean(*negated))
+                }
+            }
+            Expr::Parameter(index) => Err(Error::UnboundParameter(index + 1)),
+            // Subqueries and outer references are replaced by the executor before evaluation.
+            Expr::OuterColumn(_) | Expr::Subquery(_) | Expr::Exists(..) | Expr::InSubquery(..) => {
+                Err(Error::InvalidPlan)
+            }
+        }
+    }
+
+    /// Evaluates the expression for every row of a batch. Operators on typed columns are
+    /// applied to whole vectors at once; anything else is evaluated row by row.
+    pub fn eval_batch(&self, batch: &Batch) -> Result<ColumnVector, Error> {
+        match self {
+            Expr::Constant(value) => Ok(ColumnVector::repeat(value, batch.len())),
+            Expr::Column(index) => batch
+                .column(*index)
+                .cloned()
+                .ok_or(Error::InvalidColumnIndex(*index)),
+            Expr::BinaryOp(a, op, b) => a.eval_batch(batch)?.binary_op(op, &b.eval_batch(batch)?),
+            expr => {
+                let values = (0..batch.len())
+                    .map(|i| expr.eval(Some(&batch.row(i))))
+                    .collect::<Result<_, _>>()?;
+                Ok(ColumnVector::from_values(values))
             }
         }
     }
@@ -63,9 +570,96 @@ impl fmt::Display for Expr {
         match self {
             Expr::Constant(value) => write!(f, "{}", value),
             Expr::Column(index) => write!(f, "col{}", index),
+            Expr::OuterColumn(index) => write!(f, "outer.col{}", index),
+            Expr::Parameter(index) => write!(f, "${}", index + 1),
             Expr::BinaryOp(left, op, right) => {
                 write!(f, "({} {} {})", left, op, right)
             }
+            Expr::UnaryOp(UnaryOp::Not, expr) => write!(f, "NOT {}", expr),
+            Expr::UnaryOp(UnaryOp::Negate, expr) => write!(f, "-{}", expr),
+            Expr::IsNull(expr, false) => write!(f, "{} IS NULL", expr),
+            Expr::IsNull(expr, true) => write!(f, "{} IS NOT NULL", expr),
+            Expr::Between(expr, low, high, negated) => write!(
+                f,
+                "{} {}BETWEEN {} AND {}",
+                expr,
+                if *negated { "NOT " } else { "" },
+                low,
+                high
+            ),
+            Expr::Like {
+                expr,
+                pattern,
+                negated,
+                case_insensitive,
+                escape,
+            } => {
+                write!(
+                    f,
+                    "{} {}{} {}",
+                    expr,
+                    if *negated { "NOT " } else { "" },
+                    if *case_insensitive { "ILIKE" } else { "LIKE" },
+                    pattern
+                )?;
+                match escape {
+                    Some(escape) => write!(f, " ESCAPE '{}'", escape),
+                    None => Ok(()),
+                }
+            }
+            Expr::Case {
+                operand,
+                branches,
+                otherwise,
+            } => {
+                write!(f, "CASE")?;
+                if let Some(operand) = operand {
+                    write!(f, " {}", operand)?;
+                }
+                for (when, then) in branches {
+                    write!(f, " WHEN {} THEN {}", when, then)?;
+                }
+                if let Some(otherwise) = otherwise {
+                    write!(f, " ELSE {}", otherwise)?;
+                }
+                write!(f, " END")
+            }
+            Expr::Cast(expr, data_type) => write!(f, "CAST({} AS {})", expr, data_type),
+            Expr::Coalesce(exprs) => {
+                write!(f, "COALESCE(")?;
+                for (i, expr) in exprs.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", expr)?;
+                }
+                write!(f, ")")
+            }
+            Expr::Function(function, args) => {
+                write!(f, "{}(", function)?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                write!(f, ")")
+            }
+            Expr::Subquery(_) => write!(f, "(subquery)"),
+            Expr::Exists(_, false) => write!(f, "EXISTS (subquery)"),
+            Expr::Exists(_, true) => write!(f, "NOT EXISTS (subquery)"),
+            Expr::InSubquery(expr, _, false) => write!(f, "{} IN (subquery)", expr),
+            Expr::InSubquery(expr, _, true) => write!(f, "{} NOT IN (subquery)", expr),
+            Expr::InList(expr, list, negated) => {
+                write!(f, "{} {}IN (", expr, if *negated { "NOT " } else { "" })?;
+                for (i, item) in list.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, ")")
+            }
         }
     }
 }
@@ -87,6 +681,130 @@ pub enum BinaryOp {
     LessThanOrEqual,
 }
 
+impl BinaryOp {
+    /// Applies the operator to a pair of values.
+    pub fn apply(&self, a: &Value, b: &Value) -> Result<Value, Error> {
+        let result = match self {
+            // NULL is an unknown truth value, which only decides the result if the other
+            // operand doesn't.
+            BinaryOp::And => match (truth(a)?, truth(b)?) {
+                (Some(false), _) | (_, Some(false)) => Value::Boolean(false),
+                (Some(true), Some(true)) => Value::Boolean(true),
+                _ => Value::Null,
+            },
+            BinaryOp::Or => match (truth(a)?, truth(b)?) {
+                (Some(true), _) | (_, Some(true)) => Value::Boolean(true),
+                (Some(false), Some(false)) => Value::Boolean(false),
+                _ => Value::Null,
+            },
+            // Any other result computed from an unknown value is unknown too.
+            _ if *a == Value::Null || *b == Value::Null => Value::Null,
+            BinaryOp::Add => a.checked_add(b)?,
+            BinaryOp::Subtract => a.checked_sub(b)?,
+            BinaryOp::Multiply => a.checked_mul(b)?,
+            BinaryOp::Divide => a.checked_div(b)?,
+            BinaryOp::Modulus => a.checked_rem(b)?,
+            BinaryOp::Equal => Value::Boolean(a == b),
+            BinaryOp::NotEqual => Value::Boolean(a != b),
+            BinaryOp::GreaterThan => Value::Boolean(a > b),
+            BinaryOp::LessThan => Value::Boolean(a < b),
+            BinaryOp::GreaterThanOrEqual => Value::Boolean(a >= b),
+            BinaryOp::LessThanOrEqual => Value::Boolean(a <= b),
+        };
+        Ok(result)
+    }
+}
+
+/// Returns the truth value of an operand of a logical operator, or `None` if it is NULL.
+fn truth(value: &Value) -> Result<Option<bool>, Error> {
+    match value {
+        Value::Null => Ok(None),
+        Value::Boolean(b) => Ok(Some(*b)),
+        value => Err(Error::InvalidArgument(format!(
+            "expected a boolean, got {}",
+            value
+        ))),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UnaryOp {
+    Not,
+    Negate,
+}
+
+impl UnaryOp {
+    /// Applies the operator to a value. Both operators turn NULL into NULL.
+    pub fn apply(&self, value: &Value) -> Result<Value, Error> {
+        match (self, value) {
+            (_, Value::Null) => Ok(Value::Null),
+            (UnaryOp::Not, value) => Ok(Value::Boolean(!truth(value)?.unwrap_or_default())),
+            (UnaryOp::Negate, Value::Integer(v)) => Ok(Value::Integer(
+                v.checked_neg().ok_or(Error::IntegerOverflow)?,
+            )),
+            (UnaryOp::Negate, Value::Float(v)) => Ok(Value::Float(-v)),
+            (UnaryOp::Negate, value) => {
+                Err(Error::InvalidArgument(format!("can not negate {}", value)))
+            }
+        }
+    }
+}
+
+/// Returns whether a string matches a `LIKE` pattern in its entirety. `escape` makes the
+/// character after it match literally.
+fn like(value: &str, pattern: &str, escape: Option<char>) -> bool {
+    enum Token {
+        Char(char),
+        AnyChar,
+        AnyString,
+    }
+    let mut tokens = Vec::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        tokens.push(match c {
+            c if Some(c) == escape => Token::Char(chars.next().unwrap_or(c)),
+            '%' => Token::AnyString,
+            '_' => Token::AnyChar,
+            c => Token::Char(c),
+        });
+    }
+
+    // Match greedily, backtracking to the last `%` on a mismatch, which is enough since
+    // any later `%` can absorb whatever an earlier one would have.
+    let value = value.chars().collect::<Vec<_>>();
+    let (mut v, mut t) = (0, 0);
+    let mut backtrack = None;
+    while v < value.len() {
+        match tokens.get(t) {
+            Some(Token::AnyString) => {
+                backtrack = Some((t, v));
+                t += 1;
+            }
+            Some(Token::AnyChar) => (v, t) = (v + 1, t + 1),
+            Some(Token::Char(c)) if *c == value[v] => (v, t) = (v + 1, t + 1),
+            _ => match backtrack {
+                Some((star, from)) => {
+                    backtrack = Some((star, from + 1));
+                    (v, t) = (from + 1, star + 1);
+                }
+                None => return false,
+            },
+        }
+    }
+    tokens[t..]
+        .iter()
+        .all(|token| matches!(token, Token::AnyString))
+}
+
+impl fmt::Display for UnaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnaryOp::Not => write!(f, "NOT"),
+            UnaryOp::Negate => write!(f, "-"),
+        }
+    }
+}
+
 impl TryFrom<&ast::BinaryOperator> for BinaryOp {
     type Error = Error;
 
@@ -132,3 +850,103 @@ impl fmt::Display for BinaryOp {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_like() {
+        assert!(like("alice", "alice", None));
+        assert!(like("alice", "a%", None));
+        assert!(like("alice", "%ic%", None));
+        assert!(like("alice", "_l_c_", None));
+        assert!(like("", "%", None));
+        assert!(like("abcbd", "a%b%d", None));
+        assert!(!like("alice", "a_", None));
+        assert!(!like("alice", "%x%", None));
+        assert!(!like("alice", "", None));
+        assert!(like("50%", "50!%", Some('!')));
+        assert!(!like("500", "50!%", Some('!')));
+        assert!(like("a_b", "a\\_b", Some('\\')));
+    }
+
+    #[test]
+    fn test_not() {
+        let not = |value: Value| UnaryOp::Not.apply(&value);
+        assert_eq!(not(Value::Boolean(false)), Ok(Value::Boolean(true)));
+        assert_eq!(not(Value::Boolean(true)), Ok(Value::Boolean(false)));
+        assert_eq!(not(Value::Null), Ok(Value::Null));
+        // The types of parameters aren't known when planning, so they are checked here.
+        for value in [Value::Integer(0), Value::Integer(1), Value::from("a")] {
+            assert!(matches!(not(value), Err(Error::InvalidArgument(_))));
+        }
+    }
+
+    #[test]
+    fn test_null_operands() {
+        let ops = [
+            BinaryOp::Add,
+            BinaryOp::Subtract,
+            BinaryOp::Multiply,
+            BinaryOp::Divide,
+            BinaryOp::Modulus,
+            BinaryOp::Equal,
+            BinaryOp::NotEqual,
+            BinaryOp::GreaterThan,
+            BinaryOp::LessThan,
+            BinaryOp::GreaterThanOrEqual,
+            BinaryOp::LessThanOrEqual,
+        ];
+        for op in ops {
+            for (a, b) in [
+                (Value::Null, Value::Integer(0)),
+                (Value::Float(1.5), Value::Null),
+                (Value::Null, Value::Null),
+            ] {
+                assert_eq!(op.apply(&a, &b), Ok(Value::Null), "{a} {op} {b}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        let column = || Box::new(Expr::Column(0));
+        let constant = |value: i64| Box::new(Expr::Constant(Value::Integer(value)));
+        let exprs = [
+            (Expr::UnaryOp(UnaryOp::Not, column()), "NOT col0"),
+            (Expr::UnaryOp(UnaryOp::Negate, column()), "-col0"),
+            (
+                Expr::BinaryOp(column(), BinaryOp::Modulus, constant(2)),
+                "(col0 % 2)",
+            ),
+            (Expr::IsNull(column(), true), "col0 IS NOT NULL"),
+            (
+                Expr::Between(column(), constant(1), constant(3), false),
+                "col0 BETWEEN 1 AND 3",
+            ),
+            (
+                Expr::Like {
+                    expr: column(),
+                    pattern: Box::new(Expr::Constant(Value::from("a!%"))),
+                    negated: true,
+                    case_insensitive: true,
+                    escape: Some('!'),
+                },
+                "col0 NOT ILIKE 'a!%' ESCAPE '!'",
+            ),
+            (
+                Expr::Case {
+                    operand: Some(column()),
+                    branches: vec![(*constant(1), Expr::Constant(Value::from("one")))],
+                    otherwise: Some(Box::new(Expr::Constant(Value::Null))),
+                },
+                "CASE col0 WHEN 1 THEN 'one' ELSE NULL END",
+            ),
+            (Expr::Cast(column(), DataType::Float), "CAST(col0 AS FLOAT)"),
+        ];
+        for (expr, expected) in exprs {
+            assert_eq!(expr.to_string(), expected);
+        }
+    }
+}
@@ -0,0 +1,960 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
+
+use chrono::{Datelike, Days, NaiveDate};
+
+use crate::{
+    error::Error,
+    types::value::{DataType, Value},
+};
+
+use super::aggregate::{UserAggregate, is_aggregate_name};
+
+/// A scalar function, which computes a value from the values of its arguments.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ScalarFunction {
+    Abs,
+    Ceil,
+    Concat,
+    CurrentDate,
+    DateTrunc,
+    Extract,
+    Floor,
+    Length,
+    Lower,
+    LTrim,
+    Mod,
+    NullIf,
+    Power,
+    Replace,
+    Round,
+    RTrim,
+    Sqrt,
+    Substr,
+    Trim,
+    Upper,
+    /// A function registered by the embedder.
+    User(Arc<UserFunction>),
+}
+
+/// Whether a function always returns the same result for the same arguments, which decides
+/// whether calls of it with constant arguments can be evaluated once ahead of time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Volatility {
+    /// Always returns the same result for the same arguments.
+    Immutable,
+    /// Returns the same result for the same arguments within a statement, such as the
+    /// current date. Plans are optimized for each execution, so calls are still folded.
+    Stable,
+    /// May return a different result on every call, or have side effects, such as a random
+    /// number or a sequence. Calls are evaluated for every row.
+    #[default]
+    Volatile,
+}
+
+/// The kinds of values that a function accepts as an argument.
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    Any,
+    Integer,
+    Numeric,
+    String,
+    /// A date, or a string holding one.
+    Date,
+}
+
+impl Kind {
+    fn accepts(&self, data_type: &DataType) -> bool {
+        match self {
+            Kind::Any => true,
+            Kind::Integer => *data_type == DataType::Integer,
+            Kind::Numeric => data_type.is_compatible_with(&DataType::Float),
+            Kind::String => matches!(data_type, DataType::String { .. }),
+            Kind::Date => matches!(data_type, DataType::Date | DataType::String { .. }),
+        }
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Kind::Any => write!(f, "any value"),
+            Kind::Integer => write!(f, "an integer"),
+            Kind::Numeric => write!(f, "a number"),
+            Kind::String => write!(f, "a string"),
+            Kind::Date => write!(f, "a date"),
+        }
+    }
+}
+
+/// The arguments that a function takes: the `required` ones, followed by the `optional`
+/// ones, or by any number of `variadic` ones.
+struct Signature {
+    required: &'static [Kind],
+    optional: &'static [Kind],
+    variadic: Option<Kind>,
+}
+
+impl Signature {
+    const fn new(required: &'static [Kind], optional: &'static [Kind]) -> Self {
+        Self {
+            required,
+            optional,
+            variadic: None,
+        }
+    }
+
+    fn kind(&self, index: usize) -> Option<Kind> {
+        self.required
+            .iter()
+            .chain(self.optional)
+            .nth(index)
+            .copied()
+            .or(self.variadic)
+    }
+}
+
+impl ScalarFunction {
+    /// Looks up a function by its (case-insensitive) name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let function = match name.to_lowercase().as_str() {
+            "abs" => ScalarFunction::Abs,
+            "ceil" | "ceiling" => ScalarFunction::Ceil,
+            "concat" => ScalarFunction::Concat,
+            "current_date" => ScalarFunction::CurrentDate,
+            "date_trunc" => ScalarFunction::DateTrunc,
+            "extract" | "date_part" => ScalarFunction::Extract,
+            "floor" => ScalarFunction::Floor,
+            "length" | "char_length" | "character_length" => ScalarFunction::Length,
+            "lower" => ScalarFunction::Lower,
+            "ltrim" => ScalarFunction::LTrim,
+            "mod" => ScalarFunction::Mod,
+            "nullif" => ScalarFunction::NullIf,
+            "power" | "pow" => ScalarFunction::Power,
+            "replace" => ScalarFunction::Replace,
+            "round" => ScalarFunction::Round,
+            "rtrim" => ScalarFunction::RTrim,
+            "sqrt" => ScalarFunction::Sqrt,
+            "substr" | "substring" => ScalarFunction::Substr,
+            "trim" | "btrim" => ScalarFunction::Trim,
+            "upper" => ScalarFunction::Upper,
+            _ => return None,
+        };
+        Some(function)
+    }
+
+    pub fn volatility(&self) -> Volatility {
+        match self {
+            ScalarFunction::CurrentDate => Volatility::Stable,
+            ScalarFunction::User(function) => function.volatility,
+            _ => Volatility::Immutable,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            ScalarFunction::Abs => "ABS",
+            ScalarFunction::Ceil => "CEIL",
+            ScalarFunction::Concat => "CONCAT",
+            ScalarFunction::CurrentDate => "CURRENT_DATE",
+            ScalarFunction::DateTrunc => "DATE_TRUNC",
+            ScalarFunction::Extract => "EXTRACT",
+            ScalarFunction::Floor => "FLOOR",
+            ScalarFunction::Length => "LENGTH",
+            ScalarFunction::Lower => "LOWER",
+            ScalarFunction::LTrim => "LTRIM",
+            ScalarFunction::Mod => "MOD",
+            ScalarFunction::NullIf => "NULLIF",
+            ScalarFunction::Power => "POWER",
+            ScalarFunction::Replace => "REPLACE",
+            ScalarFunction::Round => "ROUND",
+            ScalarFunction::RTrim => "RTRIM",
+            ScalarFunction::Sqrt => "SQRT",
+            ScalarFunction::Substr => "SUBSTR",
+            ScalarFunction::Trim => "TRIM",
+            ScalarFunction::Upper => "UPPER",
+            ScalarFunction::User(function) => &function.name,
+        }
+    }
+
+    fn signature(&self) -> Signature {
+        use Kind::*;
+        match self {
+            ScalarFunction::Abs
+            | ScalarFunction::Ceil
+            | ScalarFunction::Floor
+            | ScalarFunction::Sqrt => Signature::new(&[Numeric], &[]),
+            ScalarFunction::Round => Signature::new(&[Numeric], &[Integer]),
+            ScalarFunction::Mod | ScalarFunction::Power => Signature::new(&[Numeric, Numeric], &[]),
+            ScalarFunction::Length | ScalarFunction::Lower | ScalarFunction::Upper => {
+                Signature::new(&[String], &[])
+            }
+            ScalarFunction::Trim | ScalarFunction::LTrim | ScalarFunction::RTrim => {
+                Signature::new(&[String], &[String])
+            }
+            ScalarFunction::Substr => Signature::new(&[String, Integer], &[Integer]),
+            ScalarFunction::Replace => Signature::new(&[String, String, String], &[]),
+            ScalarFunction::Concat => Signature {
+                required: &[Any],
+                optional: &[],
+                variadic: Some(Any),
+            },
+            ScalarFunction::NullIf => Signature::new(&[Any, Any], &[]),
+            ScalarFunction::CurrentDate => Signature::new(&[], &[]),
+            ScalarFunction::DateTrunc | ScalarFunction::Extract => {
+                Signature::new(&[String, Date], &[])
+            }
+            // User functions are checked against their declared types instead.
+            ScalarFunction::User(_) => Signature {
+                required: &[],
+                optional: &[],
+                variadic: Some(Any),
+            },
+        }
+    }
+
+    /// Checks the number and types of the arguments, given as their data types (`None`
+    /// where unknown, e.g. for NULL), and returns the data type of the result.
+    pub fn return_type(&self, args: &[Option<DataType>]) -> Result<Option<DataType>, Error> {
+        if let ScalarFunction::User(function) = self {
+            return function.check_args(args).map(Some);
+        }
+        let signature = self.signature();
+        let min = signature.required.len();
+        let max = min + signature.optional.len();
+        if args.len() < min || (signature.variadic.is_none() && args.len() > max) {
+            let expected = match (min, max, signature.variadic) {
+                (min, _, Some(_)) => format!("at least {}", min),
+                (min, max, None) if min == max => min.to_string(),
+                (min, max, None) => format!("{} to {}", min, max),
+            };
+            return Err(Error::InvalidArgument(format!(
+                "{} takes {} arguments, got {}",
+                self,
+                expected,
+                args.len()
+            )));
+        }
+        for (i, arg) in args.iter().enumerate() {
+            if let (Some(kind), Some(data_type)) = (signature.kind(i), arg)
+                && !kind.accepts(data_type)
+            {
+                return Err(Error::InvalidArgument(format!(
+                    "argument {} of {} must be {}, got {}",
+                    i + 1,
+                    self,
+                    kind,
+                    data_type
+                )));
+            }
+        }
+
+        let string = Some(DataType::String { length: None });
+        Ok(match self {
+            ScalarFunction::Abs
+            | ScalarFunction::Ceil
+            | ScalarFunction::Floor
+            | ScalarFunction::Round => args[0],
+            ScalarFunction::Power | ScalarFunction::Sqrt => Some(DataType::Float),
+            ScalarFunction::Mod => match (args[0], args[1]) {
+                (Some(DataType::Integer), Some(DataType::Integer)) => Some(DataType::Integer),
+                (Some(_), Some(_)) => Some(DataType::Float),
+                (a, b) => a.or(b),
+            },
+            ScalarFunction::Length | ScalarFunction::Extract => Some(DataType::Integer),
+            ScalarFunction::Concat
+            | ScalarFunction::Lower
+            | ScalarFunction::LTrim
+            | ScalarFunction::Replace
+            | ScalarFunction::RTrim
+            | ScalarFunction::Substr
+            | ScalarFunction::Trim
+            | ScalarFunction::Upper => string,
+            ScalarFunction::NullIf => match (args[0], args[1]) {
+                (Some(a), Some(b)) if !a.is_compatible_with(&b) => {
+                    return Err(Error::InvalidArgument(format!(
+                        "can not compare {} with {} in {}",
+                        a, b, self
+                    )));
+                }
+                (a, b) => a.or(b),
+            },
+            ScalarFunction::CurrentDate | ScalarFunction::DateTrunc => Some(DataType::Date),
+            ScalarFunction::User(function) => Some(function.return_type),
+        })
+    }
+
+    /// Calls the function with the values of its arguments, which have been checked by
+    /// [`ScalarFunction::return_type`]. Most functions return NULL if any argument is NULL.
+    pub fn call(&self, args: &[Value]) -> Result<Value, Error> {
+        match self {
+            ScalarFunction::User(function) => return function.call(args),
+            ScalarFunction::Concat => {
+                return Ok(Value::String(
+                    args.iter()
+                        .filter(|arg| **arg != Value::Null)
+                        .map(text)
+                        .collect(),
+                ));
+            }
+            ScalarFunction::NullIf => {
+                return Ok(match args {
+                    [a, b] if a == b => Value::Null,
+                    [a, _] => a.clone(),
+                    _ => return Err(self.invalid_arguments(args)),
+                });
+            }
+            _ if args.contains(&Value::Null) => return Ok(Value::Null),
+            _ => {}
+        }
+
+        let value = match (self, args) {
+            (ScalarFunction::Abs, [Value::Integer(v)]) => {
+                Value::Integer(v.checked_abs().ok_or(Error::IntegerOverflow)?)
+            }
+            (ScalarFunction::Abs, [Value::Float(v)]) => Value::Float(v.abs()),
+            (ScalarFunction::Ceil | ScalarFunction::Floor, [Value::Integer(v)]) => {
+                Value::Integer(*v)
+            }
+            (ScalarFunction::Ceil, [Value::Float(v)]) => Value::Float(v.ceil()),
+            (ScalarFunction::Floor, [Value::Float(v)]) => Value::Float(v.floor()),
+            (ScalarFunction::Round, [value]) => round(value, 0)?,
+            (ScalarFunction::Round, [value, Value::Integer(digits)]) => round(value, *digits)?,
+            (ScalarFunction::Power, [a, b]) => match (number(a), number(b)) {
+                (Some(a), Some(b)) => Value::Float(a.powf(b)),
+                _ => return Err(self.invalid_arguments(args)),
+            },
+            (ScalarFunction::Sqrt, [value]) => match number(value) {
+                Some(v) if v < 0.0 => {
+                    return Err(Error::InvalidArgument(
+                        "can not take the square root of a negative number".to_string(),
+                    ));
+                }
+                Some(v) => Value::Float(v.sqrt()),
+                None => return Err(self.invalid_arguments(args)),
+            },
+            (ScalarFunction::Mod, [a, b]) => a.checked_rem(b)?,
+            (ScalarFunction::Length, [Value::String(s)]) => {
+                Value::Integer(s.chars().count() as i64)
+            }
+            (ScalarFunction::Lower, [Value::String(s)]) => Value::String(s.to_lowercase()),
+            (ScalarFunction::Upper, [Value::String(s)]) => Value::String(s.to_uppercase()),
+            (ScalarFunction::Trim | ScalarFunction::LTrim | ScalarFunction::RTrim, args) => {
+                let (s, characters) = match args {
+                    [Value::String(s)] => (s, " "),
+                    [Value::String(s), Value::String(characters)] => (s, characters.as_str()),
+                    _ => return Err(self.invalid_arguments(args)),
+                };
+                let trim = |c: char| characters.contains(c);
+                Value::String(
+                    match self {
+                        ScalarFunction::LTrim => s.trim_start_matches(trim),
+                        ScalarFunction::RTrim => s.trim_end_matches(trim),
+                        _ => s.trim_matches(trim),
+                    }
+                    .to_string(),
+                )
+            }
+            (ScalarFunction::Substr, [Value::String(s), Value::Integer(start), length @ ..]) => {
+                // Characters are numbered from 1, and positions outside the string are
+                // skipped, so SUBSTR('abc', 0, 2) is 'a'.
+                let end = match length {
+                    [] => None,
+                    [Value::Integer(length)] if *length < 0 => {
+                        return Err(Error::InvalidArgument(
+                            "negative substring length".to_string(),
+                        ));
+                    }
+                    [Value::Integer(length)] => Some(start.saturating_add(*length)),
+                    _ => return Err(self.invalid_arguments(args)),
+                };
+                Value::String(
+                    s.chars()
+                        .zip(1..)
+                        .filter(|(_, i)| i >= start && end.is_none_or(|end| *i < end))
+                        .map(|(c, _)| c)
+                        .collect(),
+                )
+            }
+            (
+                ScalarFunction::Replace,
+                [Value::String(s), Value::String(from), Value::String(to)],
+            ) => match from.is_empty() {
+                true => Value::String(s.clone()),
+                false => Value::String(s.replace(from.as_str(), to)),
+            },
+            (ScalarFunction::CurrentDate, []) => Value::Date(chrono::Local::now().date_naive()),
+            (ScalarFunction::Extract, [Value::String(field), value]) => {
+                Value::Integer(DateField::parse(field)?.extract(date(value)?))
+            }
+            (ScalarFunction::DateTrunc, [Value::String(field), value]) => {
+                Value::Date(DateField::parse(field)?.truncate(date(value)?)?)
+            }
+            _ => return Err(self.invalid_arguments(args)),
+        };
+        Ok(value)
+    }
+
+    fn invalid_arguments(&self, args: &[Value]) -> Error {
+        let args = args
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        Error::InvalidArgument(format!("{}({})", self, args))
+    }
+}
+
+impl fmt::Display for ScalarFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+type ScalarClosure = dyn Fn(&[Value]) -> Result<Value, Error> + Send + Sync;
+
+/// A scalar function implemented by the embedder as a Rust closure, which can be called
+/// from SQL by name once registered with [`Session::register_function`].
+///
+/// The closure is called with one value per declared argument type, converted to that
+/// type where the argument is of a compatible one (e.g. an integer for a `FLOAT`
+/// argument). NULL arguments are passed on as is.
+///
+/// Calls with constant arguments are only evaluated ahead of time if the function isn't
+/// [`Volatility::Volatile`], which is the safe choice for a function of unknown behavior.
+///
+/// [`Session::register_function`]: super::Session::register_function
+#[derive(Clone)]
+pub struct UserFunction {
+    pub name: String,
+    pub args: Vec<DataType>,
+    pub return_type: DataType,
+    pub volatility: Volatility,
+    function: Arc<ScalarClosure>,
+}
+
+impl UserFunction {
+    pub fn new(
+        name: impl Into<String>,
+        args: impl IntoIterator<Item = DataType>,
+        return_type: DataType,
+        volatility: Volatility,
+        function: impl Fn(&[Value]) -> Result<Value, Error> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            name: name.into().to_uppercase(),
+            args: args.into_iter().collect(),
+            return_type,
+            volatility,
+            function: Arc::new(function),
+        }
+    }
+
+    /// Checks the types of the arguments against the declared ones, returning the
+    /// declared return type.
+    fn check_args(&self, args: &[Option<DataType>]) -> Result<DataType, Error> {
+        if args.len() != self.args.len() {
+            return Err(Error::InvalidArgument(format!(
+                "{} takes {} arguments, got {}",
+                self.name,
+                self.args.len(),
+                args.len()
+            )));
+        }
+        for (i, (arg, declared)) in args.iter().zip(&self.args).enumerate() {
+            check_arg(&self.name, i, *arg, declared)?;
+        }
+        Ok(self.return_type)
+    }
+
+    fn call(&self, args: &[Value]) -> Result<Value, Error> {
+        let args = args
+            .iter()
+            .zip(&self.args)
+            .map(|(arg, data_type)| coerce(arg, data_type))
+            .collect::<Result<Vec<_>, _>>()?;
+        check_result(&self.name, (self.function)(&args)?, &self.return_type)
+    }
+}
+
+impl fmt::Debug for UserFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UserFunction")
+            .field("name", &self.name)
+            .field("args", &self.args)
+            .field("return_type", &self.return_type)
+            .field("volatility", &self.volatility)
+            .finish_non_exhaustive()
+    }
+}
+
+/// User functions are identified by their name and signature, since closures can't be
+/// compared. A session can only hold one function of each name.
+impl PartialEq for UserFunction {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.args == other.args && self.return_type == other.return_type
+    }
+}
+
+impl Eq for UserFunction {}
+
+impl Hash for UserFunction {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name.hash(state);
+        self.args.hash(state);
+        self.return_type.hash(state);
+    }
+}
+
+/// Checks the type of the argument at `index` of a user function against the declared one.
+pub(super) fn check_arg(
+    name: &str,
+    index: usize,
+    arg: Option<DataType>,
+    declared: &DataType,
+) -> Result<(), Error> {
+    match arg {
+        Some(arg) if !arg.is_compatible_with(declared) => Err(Error::InvalidArgument(format!(
+            "argument {} of {} must be {}, got {}",
+            index + 1,
+            name,
+            declared,
+            arg
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Converts an argument of a user function to its declared type, where it differs but is
+/// compatible.
+pub(super) fn coerce(value: &Value, data_type: &DataType) -> Result<Value, Error> {
+    match (value, data_type) {
+        (Value::Integer(v), DataType::Float | DataType::Decimal { .. }) => {
+            Ok(Value::Float(*v as f64))
+        }
+        (Value::String(_), DataType::Date) => value.try_cast(data_type),
+        _ => Ok(value.clone()),
+    }
+}
+
+/// Checks that the value returned by a user function is of its declared return type.
+pub(super) fn check_result(name: &str, value: Value, data_type: &DataType) -> Result<Value, Error> {
+    match value.data_type() {
+        Some(actual) if !actual.is_compatible_with(data_type) => Err(Error::InvalidFunctionResult(
+            format!("{} returned {}, not {}", name, value, data_type),
+        )),
+        _ => Ok(value),
+    }
+}
+
+/// The functions registered by the embedder, which can be called from SQL by name along
+/// with the built-in ones. Names are case-insensitive.
+#[derive(Debug, Clone, Default)]
+pub struct FunctionRegistry {
+    functions: HashMap<String, Arc<UserFunction>>,
+    aggregates: HashMap<String, Arc<UserAggregate>>,
+}
+
+impl FunctionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register_function(&mut self, function: UserFunction) -> Result<(), Error> {
+        let name = self.check_name(&function.name)?;
+        self.functions.insert(name, Arc::new(function));
+        Ok(())
+    }
+
+    pub fn register_aggregate(&mut self, aggregate: UserAggregate) -> Result<(), Error> {
+        let name = self.check_name(&aggregate.name)?;
+        self.aggregates.insert(name, Arc::new(aggregate));
+        Ok(())
+    }
+
+    /// Returns the lowercase name of a function to register, if no other function has it.
+    fn check_name(&self, name: &str) -> Result<String, Error> {
+        let name = name.to_lowercase();
+        if ScalarFunction::from_name(&name).is_some()
+            || is_aggregate_name(&name)
+            || name == "coalesce"
+            || self.functions.contains_key(&name)
+            || self.aggregates.contains_key(&name)
+        {
+            return Err(Error::DuplicateFunction(name));
+        }
+        Ok(name)
+    }
+
+    pub fn function(&self, name: &str) -> Option<Arc<UserFunction>> {
+        self.functions.get(&name.to_lowercase()).cloned()
+    }
+
+    pub fn aggregate(&self, name: &str) -> Option<Arc<UserAggregate>> {
+        self.aggregates.get(&name.to_lowercase()).cloned()
+    }
+}
+
+/// A part of a date, which can be extracted from it or which it can be truncated to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DateField {
+    Year,
+    Quarter,
+    Month,
+    Week,
+    Day,
+    /// The day of the week, from 0 for Sunday to 6 for Saturday.
+    DayOfWeek,
+    /// The day of the year, from 1.
+    DayOfYear,
+}
+
+impl DateField {
+    pub fn parse(name: &str) -> Result<Self, Error> {
+        match name.to_lowercase().as_str() {
+            "year" | "years" => Ok(DateField::Year),
+            "quarter" => Ok(DateField::Quarter),
+            "month" | "months" => Ok(DateField::Month),
+            "week" | "weeks" => Ok(DateField::Week),
+            "day" | "days" => Ok(DateField::Day),
+            "dow" | "dayofweek" => Ok(DateField::DayOfWeek),
+            "doy" | "dayofyear" => Ok(DateField::DayOfYear),
+            _ => Err(Error::InvalidArgument(format!(
+                "unknown date field {}",
+                name
+            ))),
+        }
+    }
+
+    fn extract(&self, date: NaiveDate) -> i64 {
+        let value = match self {
+            DateField::Year => date.year(),
+            DateField::Quarter => date.month0() as i32 / 3 + 1,
+            DateField::Month => date.month() as i32,
+            DateField::Week => date.iso_week().week() as i32,
+            DateField::Day => date.day() as i32,
+            DateField::DayOfWeek => date.weekday().num_days_from_sunday() as i32,
+            DateField::DayOfYear => date.ordinal() as i32,
+        };
+        value as i64
+    }
+
+    /// Truncates the date to the first day of its year, quarter, month or (ISO) week.
+    fn truncate(&self, date: NaiveDate) -> Result<NaiveDate, Error> {
+        let truncated = match self {
+            DateField::Year => date.with_ordinal(1),
+            DateField::Quarter => date
+                .with_day(1)
+                .and_then(|d| d.with_month0(date.month0() / 3 * 3)),
+            DateField::Month => date.with_day(1),
+            DateField::Week => {
+                date.checked_sub_days(Days::new(date.weekday().num_days_from_monday() as u64))
+            }
+            DateField::Day => Some(date),
+            DateField::DayOfWeek | DateField::DayOfYear => {
+                return Err(Error::InvalidArgument(format!(
+                    "can not truncate a date to {:?}",
+                    self
+                )));
+            }
+        };
+        truncated.ok_or_else(|| Error::InvalidDate(date.to_string()))
+    }
+}
+
+/// Formats a value as text, without the quotes of its SQL representation.
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Date(d) => d.to_string(),
+        value => value.to_string(),
+    }
+}
+
+fn number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Integer(v) => Some(*v as f64),
+        Value::Float(v) => Some(*v),
+        _ => None,
+    }
+}
+
+fn date(value: &Value) -> Result<NaiveDate, Error> {
+    match value.try_cast(&DataType::Date)? {
+        Value::Date(date) => Ok(date),
+        value => Err(Error::InvalidArgument(format!("{} is not a date", value))),
+    }
+}
+
+/// Rounds a number to `digits` decimal places, or to a multiple of a power of ten if
+/// `digits` is negative. Integers are only affected by the latter.
+fn round(value: &Value, digits: i64) -> Result<Value, Error> {
+    let digits = i32::try_from(digits).map_err(|_| Error::IntegerOverflow)?;
+    match value {
+        Value::Integer(v) if digits >= 0 => Ok(Value::Integer(*v)),
+        Value::Integer(v) => {
+            let factor = 10_i64
+                .checked_pow(digits.unsigned_abs())
+                .ok_or(Error::IntegerOverflow)?;
+            let rounded = (*v as f64 / factor as f64).round() as i64;
+            Ok(Value::Integer(
+                rounded.checked_mul(factor).ok_or(Error::IntegerOverflow)?,
+            ))
+        }
+        Value::Float(v) => {
+            let factor = 10_f64.powi(digits);
+            Ok(Value::Float((v * factor).round() / factor))
+        }
+        value => Err(Error::InvalidArgument(format!("ROUND({})", value))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(function: ScalarFunction, args: impl IntoIterator<Item = Value>) -> Value {
+        function
+            .call(&args.into_iter().collect::<Vec<_>>())
+            .unwrap()
+    }
+
+    fn date(s: &str) -> Value {
+        Value::Date(NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap())
+    }
+
+    #[test]
+    fn test_return_type() {
+        let string = Some(DataType::String { length: None });
+        assert_eq!(
+            ScalarFunction::Lower.return_type(&[string]).unwrap(),
+            string
+        );
+        assert_eq!(
+            ScalarFunction::Abs
+                .return_type(&[Some(DataType::Integer)])
+                .unwrap(),
+            Some(DataType::Integer)
+        );
+        assert_eq!(
+            ScalarFunction::Mod
+                .return_type(&[Some(DataType::Integer), Some(DataType::Float)])
+                .unwrap(),
+            Some(DataType::Float)
+        );
+        assert_eq!(
+            ScalarFunction::Concat
+                .return_type(&[string, Some(DataType::Integer), None])
+                .unwrap(),
+            string
+        );
+        assert_eq!(
+            ScalarFunction::Extract
+                .return_type(&[string, Some(DataType::Date)])
+                .unwrap(),
+            Some(DataType::Integer)
+        );
+        // Arguments of unknown type are accepted.
+        assert_eq!(
+            ScalarFunction::Sqrt.return_type(&[None]).unwrap(),
+            Some(DataType::Float)
+        );
+
+        assert!(matches!(
+            ScalarFunction::Lower.return_type(&[Some(DataType::Integer)]),
+            Err(Error::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            ScalarFunction::Lower.return_type(&[string, string]),
+            Err(Error::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            ScalarFunction::Substr.return_type(&[string]),
+            Err(Error::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            ScalarFunction::NullIf.return_type(&[string, Some(DataType::Integer)]),
+            Err(Error::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            ScalarFunction::CurrentDate.return_type(&[string]),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn test_string_functions() {
+        let s = |s: &str| Value::String(s.to_string());
+        assert_eq!(call(ScalarFunction::Lower, [s("AbC")]), s("abc"));
+        assert_eq!(call(ScalarFunction::Upper, [s("AbC")]), s("ABC"));
+        assert_eq!(
+            call(ScalarFunction::Length, [s("héllo")]),
+            Value::Integer(5)
+        );
+        assert_eq!(call(ScalarFunction::Trim, [s("  a b  ")]), s("a b"));
+        assert_eq!(call(ScalarFunction::LTrim, [s("xxaxx"), s("x")]), s("axx"));
+        assert_eq!(call(ScalarFunction::RTrim, [s("xxaxx"), s("x")]), s("xxa"));
+        assert_eq!(
+            call(ScalarFunction::Substr, [s("hello"), Value::Integer(2)]),
+            s("ello")
+        );
+        assert_eq!(
+            call(
+                ScalarFunction::Substr,
+                [s("hello"), Value::Integer(2), Value::Integer(3)]
+            ),
+            s("ell")
+        );
+        assert_eq!(
+            call(
+                ScalarFunction::Substr,
+                [s("hello"), Value::Integer(0), Value::Integer(2)]
+            ),
+            s("h")
+        );
+        assert_eq!(
+            call(
+                ScalarFunction::Concat,
+                [s("a"), Value::Null, Value::Integer(1), date("2024-01-02")]
+            ),
+            s("a12024-01-02")
+        );
+        assert_eq!(
+            call(ScalarFunction::Replace, [s("banana"), s("an"), s("AN")]),
+            s("bANANa")
+        );
+        assert_eq!(call(ScalarFunction::Upper, [Value::Null]), Value::Null);
+    }
+
+    #[test]
+    fn test_numeric_functions() {
+        assert_eq!(
+            call(ScalarFunction::Abs, [Value::Integer(-3)]),
+            Value::Integer(3)
+        );
+        assert_eq!(
+            call(ScalarFunction::Abs, [Value::Float(-1.5)]),
+            Value::Float(1.5)
+        );
+        assert_eq!(
+            call(ScalarFunction::Round, [Value::Float(2.5)]),
+            Value::Float(3.0)
+        );
+        assert_eq!(
+            call(
+                ScalarFunction::Round,
+                [Value::Float(1.2345), Value::Integer(2)]
+            ),
+            Value::Float(1.23)
+        );
+        assert_eq!(
+            call(
+                ScalarFunction::Round,
+                [Value::Integer(1250), Value::Integer(-2)]
+            ),
+            Value::Integer(1300)
+        );
+        assert_eq!(
+            call(ScalarFunction::Floor, [Value::Float(-1.5)]),
+            Value::Float(-2.0)
+        );
+        assert_eq!(
+            call(ScalarFunction::Ceil, [Value::Float(1.2)]),
+            Value::Float(2.0)
+        );
+        assert_eq!(
+            call(
+                ScalarFunction::Power,
+                [Value::Integer(2), Value::Integer(10)]
+            ),
+            Value::Float(1024.0)
+        );
+        assert_eq!(
+            call(ScalarFunction::Sqrt, [Value::Integer(16)]),
+            Value::Float(4.0)
+        );
+        assert_eq!(
+            call(ScalarFunction::Mod, [Value::Integer(-7), Value::Integer(3)]),
+            Value::Integer(-1)
+        );
+        assert_eq!(
+            ScalarFunction::Mod.call(&[Value::Integer(1), Value::Integer(0)]),
+            Err(Error::DivisionByZero)
+        );
+        assert!(ScalarFunction::Sqrt.call(&[Value::Integer(-1)]).is_err());
+    }
+
+    #[test]
+    fn test_null_functions() {
+        assert_eq!(
+            call(
+                ScalarFunction::NullIf,
+                [Value::Integer(1), Value::Integer(1)]
+            ),
+            Value::Null
+        );
+        assert_eq!(
+            call(ScalarFunction::NullIf, [Value::Integer(1), Value::Null]),
+            Value::Integer(1)
+        );
+        assert_eq!(
+            call(ScalarFunction::NullIf, [Value::Null, Value::Integer(1)]),
+            Value::Null
+        );
+    }
+
+    #[test]
+    fn test_date_functions() {
+        let field = |s: &str| Value::String(s.to_string());
+        let day = date("2024-08-15");
+        for (name, expected) in [
+            ("year", 2024),
+            ("quarter", 3),
+            ("month", 8),
+            ("week", 33),
+            ("day", 15),
+            ("dow", 4),
+            ("doy", 228),
+        ] {
+            assert_eq!(
+                call(ScalarFunction::Extract, [field(name), day.clone()]),
+                Value::Integer(expected),
+                "{name}"
+            );
+        }
+        for (name, expected) in [
+            ("year", "2024-01-01"),
+            ("quarter", "2024-07-01"),
+            ("month", "2024-08-01"),
+            ("week", "2024-08-12"),
+            ("day", "2024-08-15"),
+        ] {
+            assert_eq!(
+                call(ScalarFunction::DateTrunc, [field(name), day.clone()]),
+                date(expected),
+                "{name}"
+            );
+        }
+        // Strings are converted to dates.
+        assert_eq!(
+            call(
+                ScalarFunction::Extract,
+                [field("YEAR"), field("1999-12-31")]
+            ),
+            Value::Integer(1999)
+        );
+        assert!(
+            ScalarFunction::Extract
+                .call(&[field("century"), day])
+                .is_err()
+        );
+        assert!(matches!(
+            call(ScalarFunction::CurrentDate, []),
+            Value::Date(_)
+        ));
+    }
+}
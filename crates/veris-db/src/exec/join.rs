@@ -1,17 +1,30 @@
-use std::iter::Peekable;
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet, VecDeque},
+    iter::Peekable,
+};
 
 use crate::{
     error::Error,
     types::value::{Row, RowIter, Value},
 };
 
-use super::expr::Expr;
+use super::{
+    expr::Expr,
+    spill::{
+        MAX_SPILL_DEPTH, MemoryBudget, Reserved, SPILL_PARTITIONS, SpillFile, SpillReader,
+        partition_of, row_size,
+    },
+};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum JoinType {
     Inner,
     Left,
     Right,
+    Full,
+    /// Emits each left row that has at least one match, without any right columns.
+    Semi,
 }
 
 #[allow(dead_code)]
@@ -25,6 +38,10 @@ pub struct NestedLoopJoiner {
     right_cols: usize,
     left_matched: bool,
     right_matched: bool,
+    /// The position of the next row of `right` within the right input.
+    right_index: usize,
+    /// The positions of the right rows that have matched a left row, for full joins.
+    right_matches: HashSet<usize>,
     on: Option<Expr>,
     join_type: JoinType,
 }
@@ -51,6 +68,8 @@ impl NestedLoopJoiner {
             right_cols,
             left_matched: false,
             right_matched: false,
+            right_index: 0,
+            right_matches: HashSet::new(),
             on,
             join_type,
         }
@@ -61,6 +80,8 @@ impl NestedLoopJoiner {
             JoinType::Inner => self.inner_join(),
             JoinType::Left => self.left_join(),
             JoinType::Right => self.right_join(),
+            JoinType::Full => self.full_join(),
+            JoinType::Semi => self.semi_join(),
         }
     }
 
@@ -123,10 +144,105 @@ impl NestedLoopJoiner {
         self.left.next().transpose()
     }
 
+    fn semi_join(&mut self) -> Result<Option<Row>, Error> {
+        while let Some(left) = self.left.next().transpose()? {
+            let mut right = self.right_orig.clone();
+            while let Some(right) = right.next().transpose()? {
+                let row: Row = left.iter().cloned().chain(right).collect();
+                if let Some(pred) = &self.on {
+                    match pred.eval(Some(&row))? {
+                        Value::Boolean(true) => {}
+                        Value::Boolean(false) | Value::Null => continue,
+                        val => {
+                            return Err(Error::InvalidFilterResult(val));
+                        }
+                    }
+                }
+                return Ok(Some(left));
+            }
+        }
+        Ok(None)
+    }
+
     fn right_join(&mut self) -> Result<Option<Row>, Error> {
-        Err(Error::NotYetSupported(
-            "Right join is not implemented".to_string(),
-        ))
+        while let Some(Ok(right)) = self.right.peek() {
+            while let Some(left) = self.left.next().transpose()? {
+                let row = left.into_iter().chain(right.iter().cloned()).collect();
+                if let Some(pred) = &self.on {
+                    match pred.eval(Some(&row))? {
+                        Value::Boolean(true) => {}
+                        Value::Boolean(false) | Value::Null => continue,
+                        val => {
+                            return Err(Error::InvalidFilterResult(val));
+                        }
+                    }
+                }
+                self.left_matched = true;
+                return Ok(Some(row));
+            }
+
+            if !self.left_matched {
+                let null_row = std::iter::repeat_n(Value::Null, self.left_cols);
+                let row = null_row.chain(right.iter().cloned()).collect();
+                self.left_matched = true;
+                return Ok(Some(row));
+            }
+
+            self.left = self.left_orig.clone();
+            self.left_matched = false;
+            self.right.next().transpose()?;
+        }
+
+        self.right.next().transpose()
+    }
+
+    /// Performs a left join while recording which right rows matched, then emits the
+    /// unmatched right rows padded with nulls.
+    fn full_join(&mut self) -> Result<Option<Row>, Error> {
+        while let Some(Ok(left)) = self.left.peek() {
+            while let Some(right) = self.right.next().transpose()? {
+                let index = self.right_index;
+                self.right_index += 1;
+                let row = left.iter().cloned().chain(right).collect();
+                if let Some(pred) = &self.on {
+                    match pred.eval(Some(&row))? {
+                        Value::Boolean(true) => {}
+                        Value::Boolean(false) | Value::Null => continue,
+                        val => {
+                            return Err(Error::InvalidFilterResult(val));
+                        }
+                    }
+                }
+                self.right_matched = true;
+                self.right_matches.insert(index);
+                return Ok(Some(row));
+            }
+
+            if !self.right_matched {
+                let null_row = std::iter::repeat_n(Value::Null, self.right_cols);
+                let row = left.iter().cloned().chain(null_row).collect();
+                self.right_matched = true;
+                return Ok(Some(row));
+            }
+
+            self.right = self.right_orig.clone();
+            self.right_index = 0;
+            self.right_matched = false;
+            self.left.next().transpose()?;
+        }
+        self.left.next().transpose()?;
+
+        // The right input has been rewound, so go through it once more for the rows that
+        // never matched.
+        while let Some(right) = self.right.next().transpose()? {
+            let index = self.right_index;
+            self.right_index += 1;
+            if !self.right_matches.contains(&index) {
+                let null_row = std::iter::repeat_n(Value::Null, self.left_cols);
+                return Ok(Some(null_row.chain(right).collect()));
+            }
+        }
+        Ok(None)
     }
 }
 
@@ -137,3 +253,557 @@ impl Iterator for NestedLoopJoiner {
         self.try_next().transpose()
     }
 }
+
+/// Joins rows on equal key columns by building a hash table of the right input, then
+/// probing it with each left row. Rows with a NULL key never match.
+#[derive(Clone)]
+pub struct HashJoiner {
+    left: RowIter,
+    left_keys: Vec<usize>,
+    left_cols: usize,
+    right_rows: Vec<Row>,
+    right_cols: usize,
+    /// The positions in `right_rows` of the rows with each key.
+    table: HashMap<Vec<Value>, Vec<usize>>,
+    right_matched: Vec<bool>,
+    /// The position of the next right row to check once the left input is exhausted.
+    right_index: usize,
+    pending: VecDeque<Row>,
+    join_type: JoinType,
+}
+
+impl HashJoiner {
+    pub fn new(
+        left: RowIter,
+        right: RowIter,
+        keys: &[(usize, usize)],
+        left_cols: usize,
+        right_cols: usize,
+        join_type: JoinType,
+    ) -> Result<Self, Error> {
+        let (left_keys, right_keys): (Vec<_>, Vec<_>) = keys.iter().copied().unzip();
+        let mut right_rows = Vec::new();
+        let mut table: HashMap<Vec<Value>, Vec<usize>> = HashMap::new();
+        for row in right {
+            let row = row?;
+            let key = join_key(&row, &right_keys);
+            if let Some(key) = key {
+                table.entry(key).or_default().push(right_rows.len());
+            }
+            right_rows.push(row);
+        }
+
+        Ok(Self {
+            left,
+            left_keys,
+            left_cols,
+            right_matched: vec![false; right_rows.len()],
+            right_rows,
+            right_cols,
+            table,
+            right_index: 0,
+            pending: VecDeque::new(),
+            join_type,
+        })
+    }
+
+    fn try_next(&mut self) -> Result<Option<Row>, Error> {
+        loop {
+            if let Some(row) = self.pending.pop_front() {
+                return Ok(Some(row));
+            }
+
+            let Some(left) = self.left.next().transpose()? else {
+                break;
+            };
+            let matches = join_key(&left, &self.left_keys)
+                .and_then(|key| self.table.get(&key))
+                .map_or(&[][..], |matches| &matches[..]);
+            if matches.is_empty() {
+                if matches!(self.join_type, JoinType::Left | JoinType::Full) {
+                    let null_row = std::iter::repeat_n(Value::Null, self.right_cols);
+                    return Ok(Some(left.into_iter().chain(null_row).collect()));
+                }
+                continue;
+            }
+            for &i in matches {
+                self.right_matched[i] = true;
+                let right = self.right_rows[i].iter().cloned();
+                self.pending
+                    .push_back(left.iter().cloned().chain(right).collect());
+            }
+        }
+
+        if matches!(self.join_type, JoinType::Right | JoinType::Full) {
+            while self.right_index < self.right_rows.len() {
+                let i = self.right_index;
+                self.right_index += 1;
+                if !self.right_matched[i] {
+                    let null_row = std::iter::repeat_n(Value::Null, self.left_cols);
+                    let right = self.right_rows[i].iter().cloned();
+                    return Ok(Some(null_row.chain(right).collect()));
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl Iterator for HashJoiner {
+    type Item = Result<Row, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.try_next().transpose()
+    }
+}
+
+/// Hash joins two inputs within the memory budget. If the right input doesn't fit in
+/// memory, both inputs are partitioned by the hash of their keys into temporary files, and
+/// each pair of partitions is joined separately (a grace hash join).
+pub fn hash_join(
+    left: RowIter,
+    right: RowIter,
+    keys: &[(usize, usize)],
+    left_cols: usize,
+    right_cols: usize,
+    join_type: JoinType,
+    budget: &MemoryBudget,
+) -> Result<RowIter, Error> {
+    let joiner = GraceHashJoiner {
+        partitions: VecDeque::new(),
+        current: None,
+        keys: keys.to_vec(),
+        left_cols,
+        right_cols,
+        join_type,
+        budget: budget.clone(),
+        depth: 0,
+    };
+    joiner.join(left, right, 0)
+}
+
+/// Joins the pairs of partitions of a grace hash join one at a time.
+#[derive(Clone)]
+struct GraceHashJoiner {
+    partitions: VecDeque<(SpillReader<Row>, SpillReader<Row>)>,
+    current: Option<RowIter>,
+    keys: Vec<(usize, usize)>,
+    left_cols: usize,
+    right_cols: usize,
+    join_type: JoinType,
+    budget: MemoryBudget,
+    /// How many times the inputs have been partitioned already.
+    depth: usize,
+}
+
+impl GraceHashJoiner {
+    /// Joins the inputs in memory if the right one fits, or partitions them otherwise.
+    fn join(&self, left: RowIter, mut right: RowIter, depth: usize) -> Result<RowIter, Error> {
+        let memory = self.budget.reserve();
+        let mut right_rows = Vec::new();
+        while let Some(row) = right.next().transpose()? {
+            let size = row_size(&row);
+            if depth >= MAX_SPILL_DEPTH {
+                memory.force_grow(size);
+            } else if !memory.try_grow(size)? {
+                right_rows.push(row);
+                let right = right_rows.into_iter().map(Ok).chain(right);
+                drop(memory);
+                return self.partition(left, right, depth);
+            }
+            right_rows.push(row);
+        }
+
+        let right = RowIter::new(right_rows.into_iter().map(Ok));
+        let joiner = HashJoiner::new(
+            left,
+            right,
+            &self.keys,
+            self.left_cols,
+            self.right_cols,
+            self.join_type,
+        )?;
+        Ok(RowIter::new(Reserved::new(joiner, memory)))
+    }
+
+    /// Writes both inputs to partitions on disk, returning a join of each pair of them.
+    fn partition(
+        &self,
+        left: RowIter,
+        right: impl Iterator<Item = Result<Row, Error>>,
+        depth: usize,
+    ) -> Result<RowIter, Error> {
+        let (left_keys, right_keys): (Vec<_>, Vec<_>) = self.keys.iter().copied().unzip();
+        let right = partition_rows(right, &right_keys, depth)?;
+        let left = partition_rows(left, &left_keys, depth)?;
+        Ok(RowIter::new(Self {
+            partitions: left.into_iter().zip(right).collect(),
+            current: None,
+            depth: depth + 1,
+            ..self.clone()
+        }))
+    }
+
+    fn try_next(&mut self) -> Result<Option<Row>, Error> {
+        loop {
+            if let Some(row) = self.current.as_mut().and_then(Iterator::next) {
+                return row.map(Some);
+            }
+            let Some((left, right)) = self.partitions.pop_front() else {
+                return Ok(None);
+            };
+            // The partition may itself be too large, and be partitioned again.
+            self.current = Some(self.join(RowIter::new(left), RowIter::new(right), self.depth)?);
+        }
+    }
+}
+
+impl Iterator for GraceHashJoiner {
+    type Item = Result<Row, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.try_next().transpose()
+    }
+}
+
+/// Writes rows to temporary files partitioned by the hash of their keys. Rows with a NULL
+/// key never match, so they all go into the first partition.
+fn partition_rows(
+    rows: impl Iterator<Item = Result<Row, Error>>,
+    keys: &[usize],
+    depth: usize,
+) -> Result<Vec<SpillReader<Row>>, Error> {
+    let mut partitions = (0..SPILL_PARTITIONS)
+        .map(|_| SpillFile::new())
+        .collect::<Result<Vec<_>, _>>()?;
+    for row in rows {
+        let row = row?;
+        let partition = join_key(&row, keys).map_or(0, |key| partition_of(&key, depth));
+        partitions[partition].write(&row)?;
+    }
+    partitions.into_iter().map(SpillFile::into_reader).collect()
+}
+
+/// Joins two inputs that are both sorted in ascending order of their key columns, by
+/// stepping through them in lockstep. Rows with a NULL key never match.
+#[derive(Clone)]
+pub struct MergeJoiner {
+    left: RowIter,
+    right: RowIter,
+    /// The next row of each input, which has not been joined yet.
+    left_next: Option<Row>,
+    right_next: Option<Row>,
+    left_keys: Vec<usize>,
+    right_keys: Vec<usize>,
+    left_cols: usize,
+    right_cols: usize,
+    pending: VecDeque<Row>,
+    join_type: JoinType,
+}
+
+impl MergeJoiner {
+    pub fn new(
+        mut left: RowIter,
+        mut right: RowIter,
+        keys: &[(usize, usize)],
+        left_cols: usize,
+        right_cols: usize,
+        join_type: JoinType,
+    ) -> Result<Self, Error> {
+        let (left_keys, right_keys) = keys.iter().copied().unzip();
+        let left_next = left.next().transpose()?;
+        let right_next = right.next().transpose()?;
+        Ok(Self {
+            left,
+            right,
+            left_next,
+            right_next,
+            left_keys,
+            right_keys,
+            left_cols,
+            right_cols,
+            pending: VecDeque::new(),
+            join_type,
+        })
+    }
+
+    fn try_next(&mut self) -> Result<Option<Row>, Error> {
+        loop {
+            if let Some(row) = self.pending.pop_front() {
+                return Ok(Some(row));
+            }
+
+            let left_key = self
+                .left_next
+                .as_ref()
+                .map(|row| join_key(row, &self.left_keys));
+            let right_key = self
+                .right_next
+                .as_ref()
+                .map(|row| join_key(row, &self.right_keys));
+            let ordering = match (&left_key, &right_key) {
+                (None, None) => return Ok(None),
+                (Some(None), _) | (Some(_), None) => Ordering::Less,
+                (_, Some(None)) | (None, Some(_)) => Ordering::Greater,
+                (Some(Some(left_key)), Some(Some(right_key))) => left_key.cmp(right_key),
+            };
+
+            match ordering {
+                Ordering::Less => {
+                    let left = self.advance_left()?;
+                    if matches!(self.join_type, JoinType::Left | JoinType::Full) {
+                        let null_row = std::iter::repeat_n(Value::Null, self.right_cols);
+                        return Ok(Some(left.into_iter().chain(null_row).collect()));
+                    }
+                }
+                Ordering::Greater => {
+                    let right = self.advance_right()?;
+                    if matches!(self.join_type, JoinType::Right | JoinType::Full) {
+                        let null_row = std::iter::repeat_n(Value::Null, self.left_cols);
+                        return Ok(Some(null_row.chain(right).collect()));
+                    }
+                }
+                Ordering::Equal => {
+                    // Both inputs may have several rows with this key, so join every
+                    // combination of them.
+                    let Some(Some(key)) = left_key else {
+                        return Err(Error::InvalidPlan);
+                    };
+                    let mut left_group = Vec::new();
+                    while self
+                        .left_next
+                        .as_ref()
+                        .is_some_and(|row| join_key(row, &self.left_keys).as_ref() == Some(&key))
+                    {
+                        left_group.push(self.advance_left()?);
+                    }
+                    let mut right_group = Vec::new();
+                    while self
+                        .right_next
+                        .as_ref()
+                        .is_some_and(|row| join_key(row, &self.right_keys).as_ref() == Some(&key))
+                    {
+                        right_group.push(self.advance_right()?);
+                    }
+                    for left in &left_group {
+                        for right in &right_group {
+                            self.pending
+                                .push_back(left.iter().chain(right.iter()).cloned().collect());
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the next left row, reading the one after it.
+    fn advance_left(&mut self) -> Result<Row, Error> {
+        let next = self.left.next().transpose()?;
+        std::mem::replace(&mut self.left_next, next).ok_or(Error::InvalidPlan)
+    }
+
+    /// Returns the next right row, reading the one after it.
+    fn advance_right(&mut self) -> Result<Row, Error> {
+        let next = self.right.next().transpose()?;
+        std::mem::replace(&mut self.right_next, next).ok_or(Error::InvalidPlan)
+    }
+}
+
+impl Iterator for MergeJoiner {
+    type Item = Result<Row, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.try_next().transpose()
+    }
+}
+
+/// Returns the values of the `keys` columns of `row`, or `None` if any of them is NULL.
+/// Floats with an integral value are converted to integers, so that they hash the same as
+/// the integers they are equal to.
+fn join_key(row: &Row, keys: &[usize]) -> Option<Vec<Value>> {
+    keys.iter()
+        .map(|&i| match &row[i] {
+            Value::Null => None,
+            Value::Float(f)
+                if f.fract() == 0.0 && *f >= i64::MIN as f64 && *f < i64::MAX as f64 =>
+            {
+                Some(Value::Integer(*f as i64))
+            }
+            value => Some(value.clone()),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use itertools::Itertools;
+
+    use super::*;
+    use crate::exec::expr::BinaryOp;
+
+    fn rows(rows: Vec<Row>) -> RowIter {
+        RowIter::new(rows.into_iter().map(Ok))
+    }
+
+    /// Runs the nested loop join of `left` and `right` on their first columns, where NULL
+    /// keys never match.
+    fn nested_loop_join(left: &[Row], right: &[Row], join_type: JoinType) -> Vec<Row> {
+        let on = Expr::BinaryOp(
+            Box::new(Expr::Column(0)),
+            BinaryOp::Equal,
+            Box::new(Expr::Column(2)),
+        );
+        let mut rows: Vec<Row> = NestedLoopJoiner::new(
+            rows(left.to_vec()),
+            rows(right.to_vec()),
+            2,
+            2,
+            Some(on),
+            join_type,
+        )
+        .try_collect()
+        .unwrap();
+        rows.sort();
+        rows
+    }
+
+    #[test]
+    fn test_merge_join_matches_nested_loop() {
+        let left = vec![
+            Row::from((Value::Null, "a")),
+            Row::from((1, "b")),
+            Row::from((2, "c")),
+            Row::from((2, "d")),
+            Row::from((4, "e")),
+        ];
+        let right = vec![
+            Row::from((Value::Null, "v")),
+            Row::from((2, "w")),
+            Row::from((2, "x")),
+            Row::from((3, "y")),
+            Row::from((4, "z")),
+        ];
+
+        for join_type in [
+            JoinType::Inner,
+            JoinType::Left,
+            JoinType::Right,
+            JoinType::Full,
+        ] {
+            let mut merged: Vec<Row> = MergeJoiner::new(
+                rows(left.clone()),
+                rows(right.clone()),
+                &[(0, 0)],
+                2,
+                2,
+                join_type,
+            )
+            .unwrap()
+            .try_collect()
+            .unwrap();
+            merged.sort();
+            assert_eq!(
+                merged,
+                nested_loop_join(&left, &right, join_type),
+                "{:?} join",
+                join_type
+            );
+        }
+    }
+
+    #[test]
+    fn test_hash_join_matches_nested_loop() {
+        let left = vec![
+            Row::from((1, "a")),
+            Row::from((2, "b")),
+            Row::from((2, "c")),
+            Row::from((Value::Null, "d")),
+        ];
+        let right = vec![
+            Row::from((2, "x")),
+            Row::from((3, "y")),
+            Row::from((2, "z")),
+            Row::from((Value::Null, "w")),
+        ];
+
+        for join_type in [
+            JoinType::Inner,
+            JoinType::Left,
+            JoinType::Right,
+            JoinType::Full,
+        ] {
+            let mut hashed: Vec<Row> = HashJoiner::new(
+                rows(left.clone()),
+                rows(right.clone()),
+                &[(0, 0)],
+                2,
+                2,
+                join_type,
+            )
+            .unwrap()
+            .try_collect()
+            .unwrap();
+            hashed.sort();
+            assert_eq!(
+                hashed,
+                nested_loop_join(&left, &right, join_type),
+                "{:?} join",
+                join_type
+            );
+        }
+    }
+
+    #[test]
+    fn test_grace_hash_join() {
+        let left: Vec<_> = (0..600)
+            .map(|i| Row::from(vec![Value::Integer(i % 50), Value::Integer(i)]))
+            .chain([Row::from((Value::Null, "a"))])
+            .collect();
+        let right: Vec<_> = (0..400)
+            .map(|i| Row::from(vec![Value::Integer(i % 70), Value::Integer(i)]))
+            .chain([Row::from((Value::Null, "b"))])
+            .collect();
+
+        for join_type in [
+            JoinType::Inner,
+            JoinType::Left,
+            JoinType::Right,
+            JoinType::Full,
+        ] {
+            let join = |budget: &MemoryBudget| -> Vec<Row> {
+                let mut rows: Vec<Row> = hash_join(
+                    rows(left.clone()),
+                    rows(right.clone()),
+                    &[(0, 0)],
+                    2,
+                    2,
+                    join_type,
+                    budget,
+                )
+                .unwrap()
+                .try_collect()
+                .unwrap();
+                rows.sort();
+                rows
+            };
+            let expected = join(&MemoryBudget::unlimited());
+            assert_eq!(expected, nested_loop_join(&left, &right, join_type));
+
+            // Too small for the right input, or even for most of its partitions.
+            let budget = MemoryBudget::new(Some(2_000), true);
+            assert_eq!(join(&budget), expected, "{:?} join", join_type);
+            assert_eq!(budget.used(), 0);
+
+            let result = hash_join(
+                rows(left.clone()),
+                rows(right.clone()),
+                &[(0, 0)],
+                2,
+                2,
+                join_type,
+                &MemoryBudget::new(Some(2_000), false),
+            );
+            assert!(matches!(result, Err(Error::MemoryLimitExceeded(2_000))));
+        }
+    }
+}
@@ -0,0 +1,1485 @@
+use std::collections::HashMap;
+
+use crate::{error::Error, types::value::Value};
+
+use super::{
+    cost::CostModel,
+    expr::{BinaryOp, Expr},
+    join::JoinType,
+    plan::{Direction, Plan},
+};
+
+/// A rewrite of query plans into equivalent plans that are cheaper to execute.
+pub trait OptimizerRule {
+    /// The name of the rule, as shown in traces.
+    fn name(&self) -> &'static str;
+
+    /// Rewrites the plan. Returns it unchanged if the rule doesn't apply.
+    fn optimize(&self, plan: Plan) -> Result<Plan, Error>;
+}
+
+/// Runs a sequence of optimizer rules over a plan until none of them changes it.
+pub struct Optimizer {
+    rules: Vec<Box<dyn OptimizerRule>>,
+    max_passes: usize,
+}
+
+impl Default for Optimizer {
+    fn default() -> Self {
+        Self::with_cost_model(CostModel::default())
+    }
+}
+
+impl Optimizer {
+    /// The number of passes over the rules after which the plan is assumed to be as good
+    /// as it gets, in case some rules keep undoing each other.
+    pub const DEFAULT_MAX_PASSES: usize = 8;
+
+    pub fn new(rules: Vec<Box<dyn OptimizerRule>>) -> Self {
+        Self {
+            rules,
+            max_passes: Self::DEFAULT_MAX_PASSES,
+        }
+    }
+
+    /// Creates an optimizer with the default rules, whose cost-based decisions are made
+    /// by the given cost model.
+    pub fn with_cost_model(cost_model: CostModel) -> Self {
+        Self::new(vec![
+            Box::new(FoldConstants),
+            Box::new(PushDownFilters),
+            Box::new(ReorderJoins::new(cost_model)),
+            Box::new(SelectJoins),
+            Box::new(SelectLookups),
+        ])
+    }
+
+    pub fn with_max_passes(mut self, max_passes: usize) -> Self {
+        self.max_passes = max_passes;
+        self
+    }
+
+    /// Optimizes a plan, including the plans of any subqueries in its expressions.
+    pub fn optimize(&self, plan: Plan) -> Result<Plan, Error> {
+        Ok(self.optimize_traced(plan)?.0)
+    }
+
+    /// Optimizes a plan like [`Optimizer::optimize`], also returning the names of the rules
+    /// that changed it, in the order they did.
+    pub fn optimize_traced(&self, mut plan: Plan) -> Result<(Plan, Vec<&'static str>), Error> {
+        let mut fired = Vec::new();
+        for pass in 0..self.max_passes {
+            let mut changed = false;
+            for rule in &self.rules {
+                let optimized = rule.optimize(plan.clone())?;
+                if optimized != plan {
+                    log::debug!(
+                        "Optimizer rule {} fired in pass {}:\n{}",
+                        rule.name(),
+                        pass,
+                        optimized
+                    );
+                    fired.push(rule.name());
+                    changed = true;
+                }
+                plan = optimized;
+            }
+            if !changed {
+                break;
+            }
+        }
+
+        let plan = plan.map_exprs(&mut |expr| {
+            expr.transform(&mut |expr| match expr {
+                Expr::Subquery(plan) => Ok(Expr::Subquery(Box::new(self.optimize(*plan)?))),
+                Expr::Exists(plan, negated) => {
+                    Ok(Expr::Exists(Box::new(self.optimize(*plan)?), negated))
+                }
+                Expr::InSubquery(expr, plan, negated) => Ok(Expr::InSubquery(
+                    expr,
+                    Box::new(self.optimize(*plan)?),
+                    negated,
+                )),
+                expr => Ok(expr),
+            })
+        })?;
+        Ok((plan, fired))
+    }
+}
+
+/// Evaluates the constant subexpressions of the plan's expressions ahead of time, and
+/// simplifies predicates with constant operands. Filters that are always true are removed,
+/// and plans whose filters are never true are replaced by [`Plan::Nothing`].
+pub struct FoldConstants;
+
+impl OptimizerRule for FoldConstants {
+    fn name(&self) -> &'static str {
+        "FoldConstants"
+    }
+
+    fn optimize(&self, plan: Plan) -> Result<Plan, Error> {
+        let plan = plan.map_exprs(&mut fold_expr)?;
+        plan.transform(&mut Ok, &mut |plan| {
+            let plan = match plan {
+                Plan::Filter { source, predicate } => match simplify_predicate(predicate) {
+                    Expr::Constant(Value::Boolean(true)) => *source,
+                    predicate if is_never_true(&predicate) => nothing(&source),
+                    predicate => Plan::Filter { source, predicate },
+                },
+                Plan::Scan {
+                    table,
+                    filter,
+                    alias,
+                } => {
+                    let filter = filter.map(simplify_predicate);
+                    let scan = Plan::Scan {
+                        table,
+                        filter: filter.filter(|filter| !filter.is_true()),
+                        alias,
+                    };
+                    match &scan {
+                        Plan::Scan {
+                            filter: Some(filter),
+                            ..
+                        } if is_never_true(filter) => nothing(&scan),
+                        _ => scan,
+                    }
+                }
+                Plan::Join {
+                    left,
+                    right,
+                    on,
+                    join_type,
+                } => {
+                    let on = on.map(simplify_predicate).filter(|on| !on.is_true());
+                    let join = Plan::Join {
+                        left,
+                        right,
+                        on,
+                        join_type,
+                    };
+                    let never_matches =
+                        matches!(&join, Plan::Join { on: Some(on), .. } if is_never_true(on));
+                    if never_matches && matches!(join_type, JoinType::Inner | JoinType::Semi) {
+                        nothing(&join)
+                    } else {
+                        join
+                    }
+                }
+                plan => plan,
+            };
+            Ok(prune_nothing(plan))
+        })
+    }
+}
+
+/// Folds the constant subexpressions of `expr` into constants. Subexpressions that fail to
+/// evaluate are left as they are, since the executor may never evaluate them, e.g. in a
+/// CASE branch that isn't taken, and otherwise reports the error itself.
+fn fold_expr(expr: Expr) -> Result<Expr, Error> {
+    expr.transform(&mut |expr| {
+        Ok(match expr {
+            Expr::Constant(_) => expr,
+            // An operand that decides the result makes the other one irrelevant.
+            Expr::BinaryOp(a, BinaryOp::And, b) if a.is_false() || b.is_false() => {
+                Expr::Constant(Value::Boolean(false))
+            }
+            Expr::BinaryOp(a, BinaryOp::Or, b) if a.is_true() || b.is_true() => {
+                Expr::Constant(Value::Boolean(true))
+            }
+            expr if expr.is_constant() => match expr.eval(None) {
+                Ok(value) => Expr::Constant(value),
+                Err(_) => expr,
+            },
+            expr => expr,
+        })
+    })
+}
+
+/// Simplifies a predicate whose truth is all that matters, such as a filter's. NULL and
+/// FALSE are interchangeable there, and so are `TRUE AND x` and `x`.
+fn simplify_predicate(expr: Expr) -> Expr {
+    match expr {
+        Expr::BinaryOp(a, BinaryOp::And, b) => {
+            match (simplify_predicate(*a), simplify_predicate(*b)) {
+                (a, b) if is_never_true(&a) || is_never_true(&b) => {
+                    Expr::Constant(Value::Boolean(false))
+                }
+                (a, b) if a.is_true() => b,
+                (a, b) if b.is_true() => a,
+                (a, b) => Expr::BinaryOp(Box::new(a), BinaryOp::And, Box::new(b)),
+            }
+        }
+        Expr::BinaryOp(a, BinaryOp::Or, b) => {
+            match (simplify_predicate(*a), simplify_predicate(*b)) {
+                (a, b) if a.is_true() || b.is_true() => Expr::Constant(Value::Boolean(true)),
+                (a, b) if is_never_true(&a) => b,
+                (a, b) if is_never_true(&b) => a,
+                (a, b) => Expr::BinaryOp(Box::new(a), BinaryOp::Or, Box::new(b)),
+            }
+        }
+        expr => expr,
+    }
+}
+
+/// Returns whether `predicate` is a constant that never satisfies a filter.
+fn is_never_true(predicate: &Expr) -> bool {
+    matches!(
+        predicate,
+        Expr::Constant(value) if !value.is_truthy()
+    )
+}
+
+/// Returns an empty plan with the same columns as `plan`.
+fn nothing(plan: &Plan) -> Plan {
+    Plan::Nothing {
+        columns: (0..plan.num_columns())
+            .map(|i| plan.column_label(i))
+            .collect(),
+    }
+}
+
+/// Replaces plans that can't produce rows if their input doesn't with [`Plan::Nothing`].
+fn prune_nothing(plan: Plan) -> Plan {
+    let is_nothing = |plan: &Plan| matches!(plan, Plan::Nothing { .. });
+    let empty = match &plan {
+        Plan::Filter { source, .. }
+        | Plan::Limit { source, .. }
+        | Plan::Offset { source, .. }
+        | Plan::Order { source, .. }
+        | Plan::Project { source, .. } => is_nothing(source),
+        Plan::Join {
+            left,
+            right,
+            join_type,
+            ..
+        }
+        | Plan::HashJoin {
+            left,
+            right,
+            join_type,
+            ..
+        }
+        | Plan::MergeJoin {
+            left,
+            right,
+            join_type,
+            ..
+        } => match join_type {
+            JoinType::Inner | JoinType::Semi => is_nothing(left) || is_nothing(right),
+            JoinType::Left => is_nothing(left),
+            JoinType::Right => is_nothing(right),
+            JoinType::Full => is_nothing(left) && is_nothing(right),
+        },
+        _ => false,
+    };
+    if empty { nothing(&plan) } else { plan }
+}
+
+/// Pushes filter predicates as far down the plan as they can go: through projections and
+/// into the inputs of joins and the filters of scans. Predicates that refer to both inputs
+/// of an inner join become part of its join predicate.
+pub struct PushDownFilters;
+
+impl OptimizerRule for PushDownFilters {
+    fn name(&self) -> &'static str {
+        "PushDownFilters"
+    }
+
+    fn optimize(&self, plan: Plan) -> Result<Plan, Error> {
+        plan.transform(
+            &mut |plan| match plan {
+                Plan::Filter { source, predicate } => push_predicate(*source, predicate),
+                plan => Ok(plan),
+            },
+            &mut Ok,
+        )
+    }
+}
+
+/// Filters the rows of `plan` by `predicate`, applying each of its conjuncts as far down
+/// the plan as possible. Conjuncts with subqueries stay where they are, since correlated
+/// subqueries refer to the columns of the row they are evaluated for.
+fn push_predicate(plan: Plan, predicate: Expr) -> Result<Plan, Error> {
+    Ok(match plan {
+        Plan::Filter {
+            source,
+            predicate: inner,
+        } => push_predicate(
+            *source,
+            Expr::BinaryOp(Box::new(inner), BinaryOp::And, Box::new(predicate)),
+        )?,
+        Plan::Scan {
+            table,
+            filter,
+            alias,
+        } => {
+            let (pushed, kept): (Vec<_>, Vec<_>) = predicate
+                .into_conjuncts()
+                .into_iter()
+                .partition(|conjunct| !conjunct.has_subquery());
+            let scan = Plan::Scan {
+                table,
+                filter: Expr::and_all(filter.into_iter().chain(pushed)),
+                alias,
+            };
+            filter_with(scan, kept)
+        }
+        Plan::Order { source, orders } => Plan::Order {
+            source: Box::new(push_predicate(*source, predicate)?),
+            orders,
+        },
+        Plan::Project {
+            source,
+            columns,
+            aliases,
+        } => {
+            let mut pushed = Vec::new();
+            let mut kept = Vec::new();
+            for conjunct in predicate.into_conjuncts() {
+                let pushable = !conjunct.has_subquery()
+                    && conjunct
+                        .columns()
+                        .iter()
+                        .all(|&i| columns.get(i).is_some_and(|column| !column.has_subquery()));
+                if pushable {
+                    pushed.push(conjunct.map_columns(&mut |i| Ok(columns[i].clone()))?);
+                } else {
+                    kept.push(conjunct);
+                }
+            }
+            let project = Plan::Project {
+                source: Box::new(push_predicates(*source, pushed)?),
+                columns,
+                aliases,
+            };
+            filter_with(project, kept)
+        }
+        Plan::Join {
+            left,
+            right,
+            on,
+            join_type,
+        } => {
+            let left_cols = left.num_columns();
+            let mut left_predicates = Vec::new();
+            let mut right_predicates = Vec::new();
+            let mut on_predicates = Vec::new();
+            let mut kept = Vec::new();
+            for conjunct in predicate.into_conjuncts() {
+                if conjunct.has_subquery() {
+                    kept.push(conjunct);
+                    continue;
+                }
+                let columns = conjunct.columns();
+                let uses_left = columns.iter().any(|&i| i < left_cols);
+                let uses_right = columns.iter().any(|&i| i >= left_cols);
+                // Predicates can only be pushed into an input whose rows are not padded with
+                // NULLs when they don't match, since the padded rows would escape them.
+                match join_type {
+                    JoinType::Inner | JoinType::Left | JoinType::Semi if !uses_right => {
+                        left_predicates.push(conjunct)
+                    }
+                    JoinType::Inner | JoinType::Right if !uses_left => right_predicates
+                        .push(conjunct.map_columns(&mut |i| Ok(Expr::Column(i - left_cols)))?),
+                    JoinType::Inner => on_predicates.push(conjunct),
+                    _ => kept.push(conjunct),
+                }
+            }
+            let join = Plan::Join {
+                left: Box::new(push_predicates(*left, left_predicates)?),
+                right: Box::new(push_predicates(*right, right_predicates)?),
+                on: Expr::and_all(on.into_iter().chain(on_predicates)),
+                join_type,
+            };
+            filter_with(join, kept)
+        }
+        plan => filter_with(plan, vec![predicate]),
+    })
+}
+
+/// Pushes the conjunction of `predicates` into `plan`, if there are any.
+fn push_predicates(plan: Plan, predicates: Vec<Expr>) -> Result<Plan, Error> {
+    match Expr::and_all(predicates) {
+        Some(predicate) => push_predicate(plan, predicate),
+        None => Ok(plan),
+    }
+}
+
+/// Filters the rows of `plan` by the conjunction of `predicates`, if there are any.
+fn filter_with(plan: Plan, predicates: Vec<Expr>) -> Plan {
+    match Expr::and_all(predicates) {
+        Some(predicate) => Plan::Filter {
+            source: Box::new(plan),
+            predicate,
+        },
+        None => plan,
+    }
+}
+
+/// Reorders trees of inner joins into the order that the cost model estimates to be the
+/// cheapest, so that the inputs are joined along their join predicates rather than in the
+/// order they were written. With up to [`ReorderJoins::MAX_EXHAUSTIVE_INPUTS`] inputs,
+/// every join tree is considered by dynamic programming. With more, the pair of inputs
+/// whose join produces the fewest rows is joined first, over and over. The columns are
+/// projected back into their original order above the reordered joins.
+pub struct ReorderJoins {
+    cost_model: CostModel,
+}
+
+impl ReorderJoins {
+    /// The largest number of inputs whose join trees are all considered.
+    pub const MAX_EXHAUSTIVE_INPUTS: usize = 10;
+
+    pub fn new(cost_model: CostModel) -> Self {
+        Self { cost_model }
+    }
+
+    fn reorder(&self, plan: Plan) -> Result<Plan, Error> {
+        if !matches!(
+            plan,
+            Plan::Join {
+                join_type: JoinType::Inner,
+                ..
+            }
+        ) {
+            return Ok(plan);
+        }
+
+        let mut graph = JoinGraph::default();
+        let original = graph.add(plan.clone(), 0)?;
+        // Correlated subqueries refer to the columns of the joined row by position, which
+        // would change.
+        if graph.inputs.len() > u64::BITS as usize
+            || graph.predicates.iter().any(|p| p.expr.has_subquery())
+        {
+            return Ok(plan);
+        }
+        graph.estimate(&self.cost_model);
+
+        let best = match graph.inputs.len() <= Self::MAX_EXHAUSTIVE_INPUTS {
+            true => graph.search_exhaustive(),
+            false => graph.search_greedy(),
+        };
+        let (best_cost, _) = graph.cost(&best);
+        let (original_cost, _) = graph.cost(&original);
+        if best_cost >= original_cost {
+            return Ok(plan);
+        }
+        log::debug!(
+            "Reordered joins from cost {:.2} to {:.2}",
+            original_cost,
+            best_cost
+        );
+
+        let mut placed = vec![false; graph.predicates.len()];
+        let (joined, columns) = graph.build(&best, &mut placed)?;
+        let positions: HashMap<_, _> = columns.iter().enumerate().map(|(i, c)| (*c, i)).collect();
+        Ok(Plan::Project {
+            columns: (0..columns.len())
+                .map(|column| position(&positions, column))
+                .collect::<Result<_, _>>()?,
+            aliases: (0..columns.len())
+                .map(|column| plan.column_label(column))
+                .collect(),
+            source: Box::new(joined),
+        })
+    }
+}
+
+impl OptimizerRule for ReorderJoins {
+    fn name(&self) -> &'static str {
+        "ReorderJoins"
+    }
+
+    fn optimize(&self, plan: Plan) -> Result<Plan, Error> {
+        // Reordering a tree also considers its subtrees, so trees are reordered from the
+        // top. Their subtrees are left as they are, since they are already the cheapest.
+        plan.transform(&mut |plan| self.reorder(plan), &mut Ok)
+    }
+}
+
+/// The inputs of a tree of inner joins and the conjuncts of its join predicates. Sets of
+/// inputs are bitmasks of their positions.
+#[derive(Default)]
+struct JoinGraph {
+    inputs: Vec<JoinInput>,
+    predicates: Vec<JoinPredicate>,
+}
+
+struct JoinInput {
+    plan: Plan,
+    /// The position of the input's first column in the rows of the original join tree.
+    offset: usize,
+    rows: f64,
+    cost: f64,
+}
+
+struct JoinPredicate {
+    /// The predicate, over the columns of the original join tree.
+    expr: Expr,
+    /// The inputs whose columns the predicate refers to.
+    inputs: u64,
+    selectivity: f64,
+    /// Whether the predicate requires a column of one input to equal one of another, so
+    /// that a join of them can be a hash join.
+    equi_join: bool,
+}
+
+/// The order in which a join tree's inputs are joined.
+#[derive(Clone, Debug)]
+enum JoinTree {
+    Input(usize),
+    Join(Box<JoinTree>, Box<JoinTree>),
+}
+
+impl JoinGraph {
+    /// Adds the inputs and predicates of a tree of inner joins whose columns start at
+    /// `offset`, returning the order in which it joins them.
+    fn add(&mut self, plan: Plan, offset: usize) -> Result<JoinTree, Error> {
+        match plan {
+            Plan::Join {
+                left,
+                right,
+                on,
+                join_type: JoinType::Inner,
+            } => {
+                let left_cols = left.num_columns();
+                let left = self.add(*left, offset)?;
+                let right = self.add(*right, offset + left_cols)?;
+                for conjunct in on.into_iter().flat_map(Expr::into_conjuncts) {
+                    let expr = conjunct.map_columns(&mut |i| Ok(Expr::Column(offset + i)))?;
+                    self.predicates.push(JoinPredicate {
+                        expr,
+                        inputs: 0,
+                        selectivity: 1.0,
+                        equi_join: false,
+                    });
+                }
+                Ok(JoinTree::Join(Box::new(left), Box::new(right)))
+            }
+            plan => {
+                self.inputs.push(JoinInput {
+                    plan,
+                    offset,
+                    rows: 0.0,
+                    cost: 0.0,
+                });
+                Ok(JoinTree::Input(self.inputs.len() - 1))
+            }
+        }
+    }
+
+    /// Returns the input that a column of the original join tree belongs to, and its
+    /// position within that input.
+    fn input_of(&self, column: usize) -> (usize, usize) {
+        let input = self
+            .inputs
+            .iter()
+            .rposition(|input| input.offset <= column)
+            .unwrap_or(0);
+        (input, column - self.inputs[input].offset)
+    }
+
+    /// Estimates the size and cost of the inputs, and the selectivity of the predicates.
+    fn estimate(&mut self, cost_model: &CostModel) {
+        for input in &mut self.inputs {
+            let estimate = cost_model.estimate(&input.plan);
+            input.rows = estimate.rows;
+            input.cost = estimate.cost;
+        }
+        for i in 0..self.predicates.len() {
+            let columns = self.predicates[i].expr.columns();
+            let inputs = columns
+                .iter()
+                .fold(0, |inputs, &column| inputs | 1 << self.input_of(column).0);
+            let column = |column| {
+                let (input, column) = self.input_of(column);
+                cost_model.column(&self.inputs[input].plan, column)
+            };
+            let predicate = &self.predicates[i];
+            let selectivity = cost_model.selectivity(&predicate.expr, &column);
+            let equi_join = match &predicate.expr {
+                Expr::BinaryOp(a, BinaryOp::Equal, b) => matches!(
+                    (&**a, &**b),
+                    (Expr::Column(a), Expr::Column(b))
+                        if self.input_of(*a).0 != self.input_of(*b).0
+                ),
+                _ => false,
+            };
+            self.predicates[i] = JoinPredicate {
+                expr: predicate.expr.clone(),
+                inputs,
+                selectivity,
+                equi_join,
+            };
+        }
+    }
+
+    /// The estimated number of rows in the join of a set of inputs.
+    fn rows(&self, set: u64) -> f64 {
+        let rows: f64 = (0..self.inputs.len())
+            .filter(|i| set & 1 << i != 0)
+            .map(|i| self.inputs[i].rows)
+            .product();
+        let selectivity: f64 = self
+            .predicates
+            .iter()
+            .filter(|predicate| predicate.inputs != 0 && predicate.inputs & !set == 0)
+            .map(|predicate| predicate.selectivity)
+            .product();
+        (rows * selectivity).max(1.0)
+    }
+
+    /// The cost of joining two disjoint sets of inputs that have already been joined.
+    fn join_cost(&self, left: u64, right: u64) -> f64 {
+        let equi_join = self.predicates.iter().any(|predicate| {
+            predicate.equi_join
+                && predicate.inputs & left != 0
+                && predicate.inputs & right != 0
+                && predicate.inputs & !(left | right) == 0
+        });
+        CostModel::join_cost(self.rows(left), self.rows(right), equi_join)
+    }
+
+    /// Returns the estimated cost of a join tree, and the set of its inputs.
+    fn cost(&self, tree: &JoinTree) -> (f64, u64) {
+        match tree {
+            JoinTree::Input(i) => (self.inputs[*i].cost, 1 << i),
+            JoinTree::Join(left, right) => {
+                let (left_cost, left) = self.cost(left);
+                let (right_cost, right) = self.cost(right);
+                (
+                    left_cost + right_cost + self.join_cost(left, right),
+                    left | right,
+                )
+            }
+        }
+    }
+
+    /// Finds the cheapest join tree by finding the cheapest tree for each set of inputs,
+    /// from those of smaller sets. Trees with fewer cross products are preferred over
+    /// cheaper ones, since their estimates are the least reliable.
+    fn search_exhaustive(&self) -> JoinTree {
+        let all = (1u64 << self.inputs.len()) - 1;
+        let mut best: Vec<Option<((usize, f64), JoinTree)>> = vec![None; all as usize + 1];
+        for (i, input) in self.inputs.iter().enumerate() {
+            best[1 << i] = Some(((0, input.cost), JoinTree::Input(i)));
+        }
+        // Every proper subset of a set is smaller than it, so has been visited before it.
+        for set in 1..=all {
+            if set.count_ones() < 2 {
+                continue;
+            }
+            let mut cheapest: Option<((usize, f64), u64)> = None;
+            let mut left = (set - 1) & set;
+            while left != 0 {
+                let right = set & !left;
+                if let (Some(((left_cross, left_cost), _)), Some(((right_cross, right_cost), _))) =
+                    (&best[left as usize], &best[right as usize])
+                {
+                    let cross = !self.connected(left, right) as usize;
+                    let key = (
+                        left_cross + right_cross + cross,
+                        left_cost + right_cost + self.join_cost(left, right),
+                    );
+                    if cheapest.is_none_or(|(cheapest, _)| {
+                        key.partial_cmp(&cheapest).is_some_and(|o| o.is_lt())
+                    }) {
+                        cheapest = Some((key, left));
+                    }
+                }
+                left = (left - 1) & set;
+            }
+            if let Some((key, left)) = cheapest {
+                let tree = |set: u64| best[set as usize].as_ref().map(|(_, tree)| tree.clone());
+                if let (Some(left_tree), Some(right_tree)) = (tree(left), tree(set & !left)) {
+                    best[set as usize] = Some((
+                        key,
+                        JoinTree::Join(Box::new(left_tree), Box::new(right_tree)),
+                    ));
+                }
+            }
+        }
+        match best.swap_remove(all as usize) {
+            Some((_, tree)) => tree,
+            None => JoinTree::Input(0),
+        }
+    }
+
+    /// Returns whether a predicate connects two disjoint sets of inputs, so that they can
+    /// be joined without a cross product.
+    fn connected(&self, left: u64, right: u64) -> bool {
+        self.predicates.iter().any(|predicate| {
+            predicate.inputs & left != 0
+                && predicate.inputs & right != 0
+                && predicate.inputs & !(left | right) == 0
+        })
+    }
+
+    /// Builds a join tree by repeatedly joining the two subtrees whose join produces the
+    /// fewest rows, preferring those that a predicate connects.
+    fn search_greedy(&self) -> JoinTree {
+        let mut trees: Vec<(u64, JoinTree)> = (0..self.inputs.len())
+            .map(|i| (1 << i, JoinTree::Input(i)))
+            .collect();
+        while trees.len() > 1 {
+            let mut cheapest: Option<((bool, f64, f64), usize, usize)> = None;
+            for i in 0..trees.len() {
+                for j in 0..trees.len() {
+                    let (left, right) = (trees[i].0, trees[j].0);
+                    if i == j {
+                        continue;
+                    }
+                    let key = (
+                        !self.connected(left, right),
+                        self.rows(left | right),
+                        self.join_cost(left, right),
+                    );
+                    if cheapest.as_ref().is_none_or(|(cheapest, ..)| {
+                        key.partial_cmp(cheapest).is_some_and(|o| o.is_lt())
+                    }) {
+                        cheapest = Some((key, i, j));
+                    }
+                }
+            }
+            let Some((_, i, j)) = cheapest else {
+                break;
+            };
+            let (left, right) = match i < j {
+                true => {
+                    let right = trees.remove(j);
+                    (trees.remove(i), right)
+                }
+                false => {
+                    let left = trees.remove(i);
+                    (left, trees.remove(j))
+                }
+            };
+            trees.push((
+                left.0 | right.0,
+                JoinTree::Join(Box::new(left.1), Box::new(right.1)),
+            ));
+        }
+        match trees.pop() {
+            Some((_, tree)) => tree,
+            None => JoinTree::Input(0),
+        }
+    }
+
+    /// Builds the plan of a join tree. Each predicate is evaluated by the lowest join that
+    /// has all the columns it refers to. Returns the plan along with the columns of the
+    /// original join tree that its columns correspond to.
+    fn build(&self, tree: &JoinTree, placed: &mut [bool]) -> Result<(Plan, Vec<usize>), Error> {
+        match tree {
+            JoinTree::Input(i) => {
+                let input = &self.inputs[*i];
+                let columns = input.plan.num_columns();
+                Ok((
+                    input.plan.clone(),
+                    (input.offset..input.offset + columns).collect(),
+                ))
+            }
+            JoinTree::Join(left, right) => {
+                let (left, mut columns) = self.build(left, placed)?;
+                let (right, right_columns) = self.build(right, placed)?;
+                columns.extend(right_columns);
+                let set = columns
+                    .iter()
+                    .fold(0, |set, &column| set | 1 << self.input_of(column).0);
+                let positions: HashMap<_, _> =
+                    columns.iter().enumerate().map(|(i, c)| (*c, i)).collect();
+
+                let mut on = Vec::new();
+                for (predicate, placed) in self.predicates.iter().zip(placed.iter_mut()) {
+                    if *placed || predicate.inputs & !set != 0 {
+                        continue;
+                    }
+                    *placed = true;
+                    on.push(
+                        predicate
+                            .expr
+                            .clone()
+                            .map_columns(&mut |i| position(&positions, i))?,
+                    );
+                }
+                let join = Plan::Join {
+                    left: Box::new(left),
+                    right: Box::new(right),
+                    on: Expr::and_all(on),
+                    join_type: JoinType::Inner,
+                };
+                Ok((join, columns))
+            }
+        }
+    }
+}
+
+/// Returns a reference to the column that a column of the original join tree was moved to.
+fn position(positions: &HashMap<usize, usize>, column: usize) -> Result<Expr, Error> {
+    let position = positions.get(&column).ok_or(Error::InvalidPlan)?;
+    Ok(Expr::Column(*position))
+}
+
+/// Replaces nested loop joins whose predicate requires left and right columns to be equal
+/// with merge joins if both inputs are sorted by the key columns, and hash joins otherwise.
+/// The remaining conjuncts of an inner join's predicate are applied by a filter above it.
+pub struct SelectJoins;
+
+impl OptimizerRule for SelectJoins {
+    fn name(&self) -> &'static str {
+        "SelectJoins"
+    }
+
+    fn optimize(&self, plan: Plan) -> Result<Plan, Error> {
+        plan.transform(&mut Ok, &mut |plan| {
+            Ok(match plan {
+                Plan::Join {
+                    left,
+                    right,
+                    on: Some(on),
+                    join_type,
+                } if join_type != JoinType::Semi => select_join(*left, *right, on, join_type),
+                plan => plan,
+            })
+        })
+    }
+}
+
+fn select_join(left: Plan, right: Plan, on: Expr, join_type: JoinType) -> Plan {
+    let left_cols = left.num_columns();
+    let mut keys = Vec::new();
+    let mut residual = Vec::new();
+    for conjunct in on.clone().into_conjuncts() {
+        match equi_join_key(&conjunct, left_cols) {
+            Some(key) => keys.push(key),
+            None => residual.push(conjunct),
+        }
+    }
+    // Outer joins must evaluate their entire predicate to know which rows matched.
+    if keys.is_empty() || (!residual.is_empty() && join_type != JoinType::Inner) {
+        return Plan::Join {
+            left: Box::new(left),
+            right: Box::new(right),
+            on: Some(on),
+            join_type,
+        };
+    }
+
+    let join = match merge_join_keys(&keys, &left, &right) {
+        Some(keys) => Plan::MergeJoin {
+            left: Box::new(left),
+            right: Box::new(right),
+            keys,
+            join_type,
+        },
+        None => Plan::HashJoin {
+            left: Box::new(left),
+            right: Box::new(right),
+            keys,
+            join_type,
+        },
+    };
+    filter_with(join, residual)
+}
+
+/// Returns the left and right column that `expr` requires to be equal, if it is such an
+/// equality. The right column index is relative to the right input.
+fn equi_join_key(expr: &Expr, left_cols: usize) -> Option<(usize, usize)> {
+    match expr {
+        Expr::BinaryOp(a, BinaryOp::Equal, b) => match (&**a, &**b) {
+            (Expr::Column(l), Expr::Column(r)) | (Expr::Column(r), Expr::Column(l))
+                if *l < left_cols && *r >= left_cols =>
+            {
+                Some((*l, *r - left_cols))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Orders the join keys by significance if both inputs are sorted in ascending order of
+/// them, so that they can be merge joined.
+fn merge_join_keys(
+    keys: &[(usize, usize)],
+    left: &Plan,
+    right: &Plan,
+) -> Option<Vec<(usize, usize)>> {
+    let left_ordering = left.ordering();
+    let right_ordering = right.ordering();
+    if left_ordering.len() < keys.len() || right_ordering.len() < keys.len() {
+        return None;
+    }
+    left_ordering
+        .iter()
+        .zip(&right_ordering)
+        .take(keys.len())
+        .map(|(&(l, left_direction), &(r, right_direction))| {
+            let ascending =
+                left_direction == Direction::Ascending && right_direction == Direction::Ascending;
+            (ascending && keys.contains(&(l, r))).then_some((l, r))
+        })
+        .collect()
+}
+
+/// Replaces scans whose filter restricts the primary key or an indexed column to a list of
+/// constants with lookups of the matching rows. The rest of the filter is applied to the
+/// rows that are fetched.
+pub struct SelectLookups;
+
+impl OptimizerRule for SelectLookups {
+    fn name(&self) -> &'static str {
+        "SelectLookups"
+    }
+
+    fn optimize(&self, plan: Plan) -> Result<Plan, Error> {
+        plan.transform(&mut Ok, &mut |plan| {
+            Ok(match plan {
+                Plan::Scan {
+                    table,
+                    filter: Some(filter),
+                    alias,
+                } => {
+                    let mut conjuncts = filter.clone().into_conjuncts();
+                    let lookup = conjuncts.iter().enumerate().find_map(|(i, conjunct)| {
+                        let (index, values) = lookup_values(conjunct)?;
+                        let column = &table.columns[index];
+                        if index != table.primary_key_index && !column.has_secondary_index {
+                            return None;
+                        }
+                        // Keys are looked up by their encoding, so they must have the column's type.
+                        let mut keys = values
+                            .iter()
+                            .map(|value| value.try_cast(&column.data_type))
+                            .collect::<Result<Vec<_>, _>>()
+                            .ok()?;
+                        // The lookup only guarantees the conjunct if the cast kept every value.
+                        let exact = keys.iter().zip(&values).all(|(key, value)| key == value);
+                        keys.sort();
+                        keys.dedup();
+                        Some((i, index, keys, exact))
+                    });
+
+                    let Some((i, index, keys, exact)) = lookup else {
+                        return Ok(Plan::Scan {
+                            table,
+                            filter: Some(filter),
+                            alias,
+                        });
+                    };
+                    if exact {
+                        conjuncts.remove(i);
+                    }
+                    let source = match index == table.primary_key_index {
+                        true => Plan::KeyLookup { table, keys, alias },
+                        false => Plan::IndexLookup {
+                            column: table.columns[index].name.clone(),
+                            table,
+                            values: keys,
+                            alias,
+                        },
+                    };
+                    match Expr::and_all(conjuncts) {
+                        Some(predicate) => Plan::Filter {
+                            source: Box::new(source),
+                            predicate,
+                        },
+                        None => source,
+                    }
+                }
+                plan => plan,
+            })
+        })
+    }
+}
+
+/// Returns the column and the non-NULL constants that `expr` requires it to equal one of,
+/// if it is such an equality or IN-list.
+fn lookup_values(expr: &Expr) -> Option<(usize, Vec<Value>)> {
+    let constant = |expr: &Expr| match expr {
+        Expr::Constant(Value::Null) => None,
+        Expr::Constant(value) => Some(value.clone()),
+        _ => None,
+    };
+    match expr {
+        Expr::BinaryOp(a, BinaryOp::Equal, b) => match (&**a, &**b) {
+            (Expr::Column(index), other) | (other, Expr::Column(index)) => {
+                Some((*index, vec![constant(other)?]))
+            }
+            _ => None,
+        },
+        Expr::InList(expr, list, false) => match &**expr {
+            Expr::Column(index) => {
+                Some((*index, list.iter().map(constant).collect::<Option<_>>()?))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlparser::{dialect::GenericDialect, parser::Parser};
+
+    use super::*;
+    use crate::{
+        engine::Engine,
+        exec::{planner::Planner, session::Session},
+        types::{
+            schema::{Column, Table},
+            value::{DataType, Row},
+        },
+        util::tests::{execute, query, test_engine_with_data},
+    };
+
+    fn plan(engine: &impl Engine, sql: &str) -> Plan {
+        let stmts = Parser::parse_sql(&GenericDialect {}, sql).unwrap();
+        let txn = engine.begin().unwrap();
+        Planner::new(&txn).plan(&stmts[0]).unwrap()
+    }
+
+    fn gt(column: usize, value: i64) -> Expr {
+        Expr::BinaryOp(
+            Box::new(Expr::Column(column)),
+            BinaryOp::GreaterThan,
+            Box::new(Expr::Constant(Value::Integer(value))),
+        )
+    }
+
+    #[test]
+    fn test_push_down_into_join_inputs() {
+        let engine = test_engine_with_data();
+        // The smaller table is listed last, so that it is already on the build side of
+        // the hash join and the joins aren't reordered.
+        let sql = "SELECT u.username, o.order_id FROM orders o, users u
+            WHERE u.user_id = o.user_id AND o.product_id > 1 AND u.user_id > 2
+            ORDER BY o.order_id";
+        let Plan::Query(source) = plan(&engine, sql) else {
+            panic!("expected a query plan");
+        };
+        let Plan::Order { source, .. } = *source else {
+            panic!("expected an order");
+        };
+        let Plan::Project { source, .. } = *source else {
+            panic!("expected a projection");
+        };
+        // The WHERE equality becomes the join predicate, and the other conjuncts are
+        // evaluated by the scans of the tables they refer to.
+        let Plan::HashJoin {
+            left, right, keys, ..
+        } = *source
+        else {
+            panic!("expected a hash join, got {source:?}");
+        };
+        assert_eq!(keys, vec![(1, 0)]);
+        let Plan::Scan { filter, .. } = *left else {
+            panic!("expected a scan, got {left:?}");
+        };
+        assert_eq!(filter, Some(gt(2, 1)));
+        let Plan::Scan { filter, .. } = *right else {
+            panic!("expected a scan, got {right:?}");
+        };
+        assert_eq!(filter, Some(gt(0, 2)));
+
+        let mut session = Session::new(&engine);
+        let rows = query(&mut session, sql);
+        let expected = query(
+            &mut session,
+            "SELECT u.username, o.order_id FROM users u, orders o
+            WHERE u.user_id + 0 = o.user_id + 0 AND o.product_id + 0 > 1 AND u.user_id + 0 > 2
+            ORDER BY o.order_id",
+        );
+        assert!(!rows.is_empty());
+        assert_eq!(rows, expected);
+    }
+
+    #[test]
+    fn test_push_down_through_projection() {
+        let engine = test_engine_with_data();
+        let sql = "WITH t AS (SELECT order_id AS id, quantity * 2 AS q FROM orders)
+            SELECT id FROM t WHERE q > 2";
+        let Plan::Query(mut source) = plan(&engine, sql) else {
+            panic!("expected a query plan");
+        };
+        while let Plan::Project { source: inner, .. } = *source {
+            source = inner;
+        }
+        let Plan::Scan { filter, .. } = *source else {
+            panic!("expected a scan, got {source:?}");
+        };
+        let quantity = Expr::BinaryOp(
+            Box::new(Expr::Column(3)),
+            BinaryOp::Multiply,
+            Box::new(Expr::Constant(Value::Integer(2))),
+        );
+        assert_eq!(
+            filter,
+            Some(Expr::BinaryOp(
+                Box::new(quantity),
+                BinaryOp::GreaterThan,
+                Box::new(Expr::Constant(Value::Integer(2))),
+            ))
+        );
+
+        let mut session = Session::new(&engine);
+        let rows = query(&mut session, sql);
+        let expected = query(
+            &mut session,
+            "SELECT order_id FROM orders WHERE quantity > 1",
+        );
+        assert_eq!(expected.len(), 1);
+        assert_eq!(rows, expected);
+    }
+
+    #[test]
+    fn test_no_push_down_into_outer_join() {
+        let engine = test_engine_with_data();
+        let sql = "SELECT u.user_id, o.order_id FROM users u LEFT JOIN orders o
+            ON u.user_id = o.user_id WHERE o.order_id > 1000";
+        let Plan::Query(source) = plan(&engine, sql) else {
+            panic!("expected a query plan");
+        };
+        let Plan::Project { source, .. } = *source else {
+            panic!("expected a projection");
+        };
+        // Unmatched users are padded with NULLs after the join, so the predicate on the
+        // right side has to stay above it.
+        let Plan::Filter { source, .. } = *source else {
+            panic!("expected a filter, got {source:?}");
+        };
+        assert!(matches!(
+            *source,
+            Plan::HashJoin {
+                join_type: JoinType::Left,
+                ..
+            } | Plan::MergeJoin {
+                join_type: JoinType::Left,
+                ..
+            }
+        ));
+
+        let mut session = Session::new(&engine);
+        assert!(query(&mut session, sql).is_empty());
+    }
+
+    #[test]
+    fn test_fold_constants() {
+        let engine = test_engine_with_data();
+        let sql = "SELECT order_id FROM orders WHERE order_id = 1 + 2 AND (quantity > 5 OR TRUE)";
+        let Plan::Query(source) = plan(&engine, sql) else {
+            panic!("expected a query plan");
+        };
+        let Plan::Project { source, .. } = *source else {
+            panic!("expected a projection");
+        };
+        // Only the folded key comparison is left, so the key is looked up and nothing else
+        // needs to be filtered on.
+        assert!(
+            matches!(*source, Plan::KeyLookup { ref keys, .. } if keys == &[Value::Integer(3)]),
+            "expected a key lookup, got {source:?}"
+        );
+
+        let mut session = Session::new(&engine);
+        assert_eq!(query(&mut session, sql).len(), 1);
+    }
+
+    #[test]
+    fn test_fold_always_false_filter() {
+        let engine = test_engine_with_data();
+        let sql = "SELECT u.username FROM users u JOIN orders o ON u.user_id = o.user_id
+            WHERE o.quantity > 1 AND 1 = 2";
+        let Plan::Query(source) = plan(&engine, sql) else {
+            panic!("expected a query plan");
+        };
+        assert!(matches!(*source, Plan::Nothing { .. }), "got {source:?}");
+
+        let mut session = Session::new(&engine);
+        assert!(query(&mut session, sql).is_empty());
+        let count = query(&mut session, "SELECT COUNT(*) FROM orders WHERE NULL");
+        assert_eq!(count, vec![Row::from(vec![Value::Integer(0)])]);
+    }
+
+    #[test]
+    fn test_fold_errors() {
+        let engine = test_engine_with_data();
+        let mut session = Session::new(&engine);
+        // Errors are raised when the expressions are evaluated, not when they are folded.
+        for sql in [
+            "SELECT * FROM orders WHERE quantity = 1 / 0",
+            "SELECT 9223372036854775807 + 1 FROM orders",
+        ] {
+            let result = execute(&mut session, sql);
+            assert!(
+                matches!(result, Err(Error::IntegerOverflow)),
+                "{sql}: {result:?}"
+            );
+        }
+        assert!(query(&mut session, "SELECT 1 / 0 FROM orders WHERE order_id = 0").is_empty());
+
+        // Operands that are never evaluated don't raise errors at all.
+        for sql in [
+            "SELECT CASE WHEN user_id > 0 THEN 1 ELSE 1 / 0 END FROM users",
+            "SELECT COALESCE(user_id, 1 / 0) FROM users",
+        ] {
+            assert_eq!(query(&mut session, sql).len(), 5, "{sql}");
+        }
+    }
+
+    /// A scan of a table with integer columns `c0..` and the primary key `c0`.
+    fn scan(name: &str, columns: usize, filter: Option<Expr>) -> Plan {
+        let table = Table {
+            name: name.to_string(),
+            primary_key_index: 0,
+            columns: (0..columns)
+                .map(|i| Column {
+                    name: format!("c{i}"),
+                    data_type: DataType::Integer,
+                    ..Default::default()
+                })
+                .collect(),
+        };
+        Plan::Scan {
+            table,
+            filter,
+            alias: None,
+        }
+    }
+
+    fn eq(a: usize, b: usize) -> Expr {
+        Expr::BinaryOp(
+            Box::new(Expr::Column(a)),
+            BinaryOp::Equal,
+            Box::new(Expr::Column(b)),
+        )
+    }
+
+    fn and(a: Expr, b: Expr) -> Expr {
+        Expr::BinaryOp(Box::new(a), BinaryOp::And, Box::new(b))
+    }
+
+    fn join(left: Plan, right: Plan, on: Option<Expr>) -> Plan {
+        Plan::Join {
+            left: Box::new(left),
+            right: Box::new(right),
+            on,
+            join_type: JoinType::Inner,
+        }
+    }
+
+    #[test]
+    fn test_fold_constants_rule() {
+        let filter = Plan::Filter {
+            source: Box::new(scan("a", 2, None)),
+            predicate: and(
+                gt(1, 0),
+                Expr::BinaryOp(
+                    Box::new(Expr::Constant(Value::Integer(1))),
+                    BinaryOp::Equal,
+                    Box::new(Expr::Constant(Value::Integer(2))),
+                ),
+            ),
+        };
+        let Plan::Nothing { columns } = FoldConstants.optimize(filter).unwrap() else {
+            panic!("expected nothing");
+        };
+        assert_eq!(columns.len(), 2);
+
+        let filter = Plan::Filter {
+            source: Box::new(scan("a", 2, None)),
+            predicate: and(Expr::Constant(Value::Boolean(true)), gt(1, 0)),
+        };
+        let Plan::Filter { predicate, .. } = FoldConstants.optimize(filter).unwrap() else {
+            panic!("expected a filter");
+        };
+        assert_eq!(predicate, gt(1, 0));
+    }
+
+    #[test]
+    fn test_push_down_filters_rule() {
+        let filter = Plan::Filter {
+            source: Box::new(join(scan("a", 2, None), scan("b", 2, None), None)),
+            predicate: and(and(gt(0, 1), gt(3, 2)), eq(1, 2)),
+        };
+        assert_eq!(
+            PushDownFilters.optimize(filter).unwrap(),
+            join(
+                scan("a", 2, Some(gt(0, 1))),
+                scan("b", 2, Some(gt(1, 2))),
+                Some(eq(1, 2)),
+            )
+        );
+    }
+
+    #[test]
+    fn test_select_joins_rule() {
+        let residual = Expr::BinaryOp(
+            Box::new(Expr::Column(0)),
+            BinaryOp::GreaterThan,
+            Box::new(Expr::Column(3)),
+        );
+        let hash_join = SelectJoins
+            .optimize(join(
+                scan("a", 2, None),
+                scan("b", 2, None),
+                Some(and(eq(1, 2), residual.clone())),
+            ))
+            .unwrap();
+        let Plan::Filter { source, predicate } = hash_join else {
+            panic!("expected a filter");
+        };
+        assert_eq!(predicate, residual);
+        assert!(matches!(*source, Plan::HashJoin { ref keys, .. } if keys == &[(1, 0)]));
+
+        // Both scans return rows in primary key order.
+        let merge_join = SelectJoins
+            .optimize(join(scan("a", 2, None), scan("b", 2, None), Some(eq(2, 0))))
+            .unwrap();
+        assert!(matches!(merge_join, Plan::MergeJoin { ref keys, .. } if keys == &[(0, 0)]));
+    }
+
+    /// Returns the join predicates of a tree of joins, or `None` for a cross product.
+    fn join_predicates(plan: &Plan) -> Vec<Option<Expr>> {
+        match plan {
+            Plan::Join {
+                left, right, on, ..
+            } => {
+                let mut predicates = join_predicates(left);
+                predicates.extend(join_predicates(right));
+                predicates.push(on.clone());
+                predicates
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_reorder_joins_rule() {
+        use crate::{
+            exec::cost::CostModel,
+            types::statistics::{ColumnStatistics, TableStatistics},
+        };
+
+        let statistics = |rows| {
+            TableStatistics::new(rows)
+                .with_column(ColumnStatistics::new(rows))
+                .with_column(ColumnStatistics::new(rows))
+        };
+        let cost_model = CostModel::default()
+            .with_table("a", statistics(10_000))
+            .with_table("b", statistics(10_000))
+            .with_table("c", statistics(10));
+
+        // a and b are only connected through c, so they shouldn't be joined first.
+        let original = join(
+            join(scan("a", 2, None), scan("b", 2, None), None),
+            scan("c", 2, None),
+            Some(and(eq(1, 4), eq(3, 5))),
+        );
+        let reordered = ReorderJoins::new(cost_model.clone())
+            .optimize(original.clone())
+            .unwrap();
+        let Plan::Project {
+            source, columns, ..
+        } = &reordered
+        else {
+            panic!("expected a projection");
+        };
+        assert!(join_predicates(source).iter().all(Option::is_some));
+        assert_eq!(columns.len(), 6);
+        assert!(
+            cost_model.estimate(&reordered).cost < cost_model.estimate(&original).cost,
+            "{reordered}"
+        );
+        // The reordered plan is as cheap as it gets.
+        let rule = ReorderJoins::new(cost_model);
+        assert_eq!(rule.optimize(reordered.clone()).unwrap(), reordered);
+
+        // A chain of joins that is too long to search exhaustively, where each table joins
+        // the next one but is written next to the one after that.
+        let inputs = ReorderJoins::MAX_EXHAUSTIVE_INPUTS + 2;
+        let order: Vec<_> = (0..inputs)
+            .step_by(2)
+            .chain((1..inputs).step_by(2))
+            .collect();
+        let position = |table| order.iter().position(|&t| t == table).unwrap();
+        let chain = (1..inputs)
+            .map(|t| eq(2 * position(t - 1) + 1, 2 * position(t)))
+            .reduce(and)
+            .unwrap();
+        let cross_products = order[1..]
+            .iter()
+            .map(|t| scan(&format!("t{t}"), 2, None))
+            .fold(scan("t0", 2, None), |plan, scan| join(plan, scan, None));
+        let Plan::Join { left, right, .. } = cross_products else {
+            panic!("expected a join");
+        };
+        let original = join(*left, *right, Some(chain));
+        let Plan::Project { source, .. } = rule.optimize(original).unwrap() else {
+            panic!("expected a projection");
+        };
+        let predicates = join_predicates(&source);
+        assert_eq!(predicates.len(), inputs - 1);
+        assert!(predicates.iter().all(Option::is_some));
+    }
+
+    #[test]
+    fn test_select_lookups_rule() {
+        let filter = Expr::InList(
+            Box::new(Expr::Column(0)),
+            vec![
+                Expr::Constant(Value::Integer(2)),
+                Expr::Constant(Value::Integer(1)),
+            ],
+            false,
+        );
+        let lookup = SelectLookups
+            .optimize(scan("a", 2, Some(filter.clone())))
+            .unwrap();
+        assert!(matches!(
+            lookup,
+            Plan::KeyLookup { ref keys, .. } if keys == &[Value::Integer(1), Value::Integer(2)]
+        ));
+
+        // Only the conjuncts that the lookup doesn't guarantee are still filtered on.
+        let lookup = SelectLookups
+            .optimize(scan("a", 2, Some(and(filter, gt(1, 0)))))
+            .unwrap();
+        let Plan::Filter { source, predicate } = lookup else {
+            panic!("expected a filter");
+        };
+        assert_eq!(predicate, gt(1, 0));
+        assert!(matches!(*source, Plan::KeyLookup { .. }));
+
+        // Column c1 has no index.
+        let scan = scan("a", 2, Some(gt(1, 0)));
+        assert_eq!(SelectLookups.optimize(scan.clone()).unwrap(), scan);
+    }
+
+    #[test]
+    fn test_optimizer_trace() {
+        let filter = Plan::Filter {
+            source: Box::new(join(scan("a", 2, None), scan("b", 2, None), None)),
+            predicate: and(eq(1, 2), gt(0, 1)),
+        };
+        let (optimized, fired) = Optimizer::default().optimize_traced(filter).unwrap();
+        // The filtered input is smaller, so it is moved to the build side of the join.
+        assert_eq!(
+            fired,
+            vec!["PushDownFilters", "ReorderJoins", "SelectJoins"]
+        );
+        let Plan::Project { source, .. } = optimized else {
+            panic!("expected a projection");
+        };
+        assert!(matches!(*source, Plan::HashJoin { ref keys, .. } if keys == &[(0, 1)]));
+
+        // A driver without rules leaves the plan alone.
+        let scan = scan("a", 2, Some(gt(0, 1)));
+        let (optimized, fired) = Optimizer::new(Vec::new())
+            .optimize_traced(scan.clone())
+            .unwrap();
+        assert_eq!(optimized, scan);
+        assert!(fired.is_empty());
+    }
+}
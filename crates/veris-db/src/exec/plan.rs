@@ -1,13 +1,22 @@
 use std::fmt::{self};
 
+use itertools::Itertools;
+
 use crate::{
     engine::Transaction,
     error::Error,
-    types::{schema::Table, value::ColumnLabel},
+    types::{
+        schema::Table,
+        value::{ColumnLabel, DataType, Value},
+    },
 };
 
-use super::{Executor, aggregate::Aggregate, expr::Expr, join::JoinType, session::StatementResult};
+use super::{
+    ExecutionOptions, Executor, aggregate::Aggregate, expr::Expr, function::check_arg,
+    join::JoinType, session::StatementResult,
+};
 
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Plan {
     CreateTable(Table),
     DropTable(String),
@@ -20,6 +29,12 @@ pub enum Plan {
         source: Expr,
     },
     Query(Box<Plan>),
+    /// Describes the plan of a statement instead of executing it. With `analyze`, the
+    /// statement is executed and each node is annotated with its runtime statistics.
+    Explain {
+        source: Box<Plan>,
+        analyze: bool,
+    },
     Aggregate {
         source: Box<Plan>,
         group_by: Vec<Expr>,
@@ -29,34 +44,104 @@ pub enum Plan {
         source: Box<Plan>,
         predicate: Expr,
     },
+    /// Joins rows whose `keys` columns are equal, given as pairs of left and right column
+    /// indexes. The right column indexes are relative to the right input.
+    HashJoin {
+        left: Box<Plan>,
+        right: Box<Plan>,
+        keys: Vec<(usize, usize)>,
+        join_type: JoinType,
+    },
+    /// Fetches the rows of `table` whose `column` has one of `values`, using its
+    /// secondary index.
+    IndexLookup {
+        table: Table,
+        column: String,
+        values: Vec<Value>,
+        alias: Option<String>,
+    },
     Join {
         left: Box<Plan>,
         right: Box<Plan>,
         on: Option<Expr>,
         join_type: JoinType,
     },
+    /// Fetches the rows of `table` with the given primary keys.
+    KeyLookup {
+        table: Table,
+        keys: Vec<Value>,
+        alias: Option<String>,
+    },
+    Limit {
+        source: Box<Plan>,
+        limit: usize,
+    },
+    /// Joins rows whose `keys` columns are equal, like `HashJoin`, given that both inputs
+    /// are sorted in ascending order of their key columns.
+    MergeJoin {
+        left: Box<Plan>,
+        right: Box<Plan>,
+        keys: Vec<(usize, usize)>,
+        join_type: JoinType,
+    },
     Nothing {
         columns: Vec<ColumnLabel>,
     },
+    Offset {
+        source: Box<Plan>,
+        offset: usize,
+    },
+    Order {
+        source: Box<Plan>,
+        orders: Vec<(Expr, Direction)>,
+    },
     Project {
         source: Box<Plan>,
         columns: Vec<Expr>,
         aliases: Vec<ColumnLabel>,
     },
+    /// Evaluates a recursive CTE by repeatedly running `recursive` against the rows
+    /// produced by the previous iteration, starting with those of `anchor`, until no new
+    /// rows are produced.
+    RecursiveCte {
+        name: String,
+        anchor: Box<Plan>,
+        recursive: Box<Plan>,
+        distinct: bool,
+    },
     Scan {
         table: Table,
         filter: Option<Expr>,
         alias: Option<String>,
     },
+    /// Combines the rows of two plans with the same number of columns. Duplicate rows
+    /// are removed from the output unless `all` is set.
+    SetOperation {
+        left: Box<Plan>,
+        right: Box<Plan>,
+        op: SetOperator,
+        all: bool,
+    },
     Values {
         rows: Vec<Vec<Expr>>,
     },
+    /// The rows produced by the previous iteration of the recursive CTE `name`.
+    WorkingTable {
+        name: String,
+        columns: Vec<ColumnLabel>,
+    },
 }
 
 impl Plan {
-    pub fn execute(self, transaction: &impl Transaction) -> Result<StatementResult, Error> {
+    pub fn execute(
+        self,
+        transaction: &impl Transaction,
+        options: &ExecutionOptions,
+    ) -> Result<StatementResult, Error> {
         log::debug!("Executing plan:\n{}", self);
-        Executor::new(transaction).execute(self)
+        Executor::new(transaction)
+            .with_options(options.clone())
+            .execute(self)
     }
 
     pub fn num_columns(&self) -> usize {
@@ -66,17 +151,33 @@ impl Plan {
             Plan::Delete { .. } => 0,
             Plan::Insert { source, .. } => source.num_columns(),
             Plan::Query(source) => source.num_columns(),
+            Plan::Explain { .. } => 1,
             Plan::Aggregate {
                 group_by,
                 aggregates,
                 ..
             } => group_by.len() + aggregates.len(),
             Plan::Filter { source, .. } => source.num_columns(),
-            Plan::Join { left, right, .. } => left.num_columns() + right.num_columns(),
+            Plan::Join {
+                left,
+                join_type: JoinType::Semi,
+                ..
+            } => left.num_columns(),
+            Plan::Join { left, right, .. }
+            | Plan::HashJoin { left, right, .. }
+            | Plan::MergeJoin { left, right, .. } => left.num_columns() + right.num_columns(),
+            Plan::Limit { source, .. } => source.num_columns(),
             Plan::Nothing { columns } => columns.len(),
+            Plan::Offset { source, .. } => source.num_columns(),
+            Plan::Order { source, .. } => source.num_columns(),
             Plan::Project { columns, .. } => columns.len(),
-            Plan::Scan { table, .. } => table.columns.len(),
+            Plan::RecursiveCte { anchor, .. } => anchor.num_columns(),
+            Plan::Scan { table, .. }
+            | Plan::IndexLookup { table, .. }
+            | Plan::KeyLookup { table, .. } => table.columns.len(),
+            Plan::SetOperation { left, .. } => left.num_columns(),
             Plan::Values { rows } => rows.first().map_or(0, |r| r.len()),
+            Plan::WorkingTable { columns, .. } => columns.len(),
         }
     }
 
@@ -87,6 +188,7 @@ impl Plan {
             Plan::Delete { .. } => ColumnLabel::None,
             Plan::Insert { source, .. } => source.column_label(index),
             Plan::Query(source) => source.column_label(index),
+            Plan::Explain { .. } => ColumnLabel::Unqualified("plan".to_string()),
             Plan::Aggregate {
                 source, group_by, ..
             } => match group_by.get(index) {
@@ -99,30 +201,32 @@ impl Plan {
                 right,
                 join_type,
                 ..
+            }
+            | Plan::HashJoin {
+                left,
+                right,
+                join_type,
+                ..
+            }
+            | Plan::MergeJoin {
+                left,
+                right,
+                join_type,
+                ..
             } => match join_type {
-                JoinType::Inner => {
-                    if index < left.num_columns() {
-                        left.column_label(index)
-                    } else {
-                        right.column_label(index - left.num_columns())
-                    }
-                }
-                JoinType::Left => {
+                JoinType::Inner | JoinType::Left | JoinType::Right | JoinType::Full => {
                     if index < left.num_columns() {
                         left.column_label(index)
                     } else {
                         right.column_label(index - left.num_columns())
                     }
                 }
-                JoinType::Right => {
-                    if index < right.num_columns() {
-                        right.column_label(index)
-                    } else {
-                        left.column_label(index - right.num_columns())
-                    }
-                }
+                JoinType::Semi => left.column_label(index),
             },
+            Plan::Limit { source, .. } => source.column_label(index),
             Plan::Nothing { columns } => columns.get(index).cloned().unwrap_or(ColumnLabel::None),
+            Plan::Offset { source, .. } => source.column_label(index),
+            Plan::Order { source, .. } => source.column_label(index),
             Plan::Project {
                 source,
                 columns,
@@ -134,11 +238,594 @@ impl Plan {
                 },
                 Some(label) => label.clone(),
             },
-            Plan::Scan { table, alias, .. } => ColumnLabel::Qualified(
+            Plan::RecursiveCte { anchor, .. } => anchor.column_label(index),
+            Plan::Scan { table, alias, .. }
+            | Plan::IndexLookup { table, alias, .. }
+            | Plan::KeyLookup { table, alias, .. } => ColumnLabel::Qualified(
                 alias.clone().unwrap_or_else(|| table.name.clone()),
                 table.columns[index].name.clone(),
             ),
+            Plan::SetOperation { left, .. } => left.column_label(index),
             Plan::Values { .. } => ColumnLabel::None,
+            Plan::WorkingTable { columns, .. } => {
+                columns.get(index).cloned().unwrap_or(ColumnLabel::None)
+            }
+        }
+    }
+
+    /// Infers the data type of the column at `index`. Returns `None` if the type is
+    /// unknown, e.g. for a column containing only NULLs.
+    pub fn column_type(&self, index: usize) -> Option<DataType> {
+        match self {
+            Plan::CreateTable { .. } => None,
+            Plan::DropTable { .. } => None,
+            Plan::Delete { .. } => None,
+            Plan::Insert { source, .. } => source.column_type(index),
+            Plan::Query(source) => source.column_type(index),
+            Plan::Explain { .. } => Some(DataType::String { length: None }),
+            Plan::Aggregate {
+                source,
+                group_by,
+                aggregates,
+            } => {
+                let column_type = |i| source.column_type(i);
+                match group_by.get(index) {
+                    Some(expr) => expr.data_type(&column_type),
+                    None => match aggregates.get(index - group_by.len())? {
+                        Aggregate::Count(_) => Some(DataType::Integer),
+                        Aggregate::User(function, _) => Some(function.return_type),
+                        aggregate => aggregate.expr().data_type(&column_type),
+                    },
+                }
+            }
+            Plan::Filter { source, .. } => source.column_type(index),
+            Plan::Join {
+                left,
+                right,
+                join_type,
+                ..
+            }
+            | Plan::HashJoin {
+                left,
+                right,
+                join_type,
+                ..
+            }
+            | Plan::MergeJoin {
+                left,
+                right,
+                join_type,
+                ..
+            } => match join_type {
+                JoinType::Inner | JoinType::Left | JoinType::Right | JoinType::Full => {
+                    if index < left.num_columns() {
+                        left.column_type(index)
+                    } else {
+                        right.column_type(index - left.num_columns())
+                    }
+                }
+                JoinType::Semi => left.column_type(index),
+            },
+            Plan::Limit { source, .. } => source.column_type(index),
+            Plan::Nothing { .. } => None,
+            Plan::Offset { source, .. } => source.column_type(index),
+            Plan::Order { source, .. } => source.column_type(index),
+            Plan::Project {
+                source, columns, ..
+            } => columns.get(index)?.data_type(&|i| source.column_type(i)),
+            Plan::RecursiveCte { anchor, .. } => anchor.column_type(index),
+            Plan::Scan { table, .. }
+            | Plan::IndexLookup { table, .. }
+            | Plan::KeyLookup { table, .. } => table.columns.get(index).map(|c| c.data_type),
+            Plan::SetOperation { left, right, .. } => {
+                left.column_type(index).or_else(|| right.column_type(index))
+            }
+            Plan::Values { rows } => rows
+                .iter()
+                .find_map(|row| row.get(index)?.data_type(&|_| None)),
+            Plan::WorkingTable { .. } => None,
+        }
+    }
+
+    /// Returns the columns that the rows produced by the plan are known to be sorted by,
+    /// most significant first.
+    pub fn ordering(&self) -> Vec<(usize, Direction)> {
+        match self {
+            Plan::Query(source)
+            | Plan::Filter { source, .. }
+            | Plan::Limit { source, .. }
+            | Plan::Offset { source, .. } => source.ordering(),
+            // Rows are stored in primary key order, and lookups fetch them in that order.
+            Plan::Scan { table, .. }
+            | Plan::IndexLookup { table, .. }
+            | Plan::KeyLookup { table, .. } => {
+                vec![(table.primary_key_index, Direction::Ascending)]
+            }
+            Plan::Order { orders, .. } => orders
+                .iter()
+                .map_while(|(expr, direction)| match expr {
+                    Expr::Column(index) => Some((*index, *direction)),
+                    _ => None,
+                })
+                .collect(),
+            Plan::Project {
+                source, columns, ..
+            } => source
+                .ordering()
+                .into_iter()
+                .map_while(|(index, direction)| {
+                    let position = columns.iter().position(|c| c == &Expr::Column(index))?;
+                    Some((position, direction))
+                })
+                .collect(),
+            // These joins emit the matches of each left row together, in left order. Hash
+            // joins don't once they spill, which they only find out while executing.
+            Plan::Join {
+                left,
+                join_type: JoinType::Inner | JoinType::Left | JoinType::Semi,
+                ..
+            }
+            | Plan::MergeJoin {
+                left,
+                join_type: JoinType::Inner | JoinType::Left,
+                ..
+            } => left.ordering(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Transforms the plan tree, applying `before` to each node before transforming its
+    /// children, and `after` to the result once they have been. Plans nested in subquery
+    /// expressions are not descended into.
+    pub fn transform(
+        self,
+        before: &mut impl FnMut(Plan) -> Result<Plan, Error>,
+        after: &mut impl FnMut(Plan) -> Result<Plan, Error>,
+    ) -> Result<Plan, Error> {
+        let plan = before(self)?;
+        let mut xform = |plan: Box<Plan>| -> Result<Box<Plan>, Error> {
+            Ok(Box::new(plan.transform(before, after)?))
+        };
+        let plan = match plan {
+            plan @ (Plan::CreateTable(_)
+            | Plan::DropTable(_)
+            | Plan::Delete { .. }
+            | Plan::IndexLookup { .. }
+            | Plan::KeyLookup { .. }
+            | Plan::Nothing { .. }
+            | Plan::Scan { .. }
+            | Plan::Values { .. }
+            | Plan::WorkingTable { .. }) => plan,
+            Plan::Insert { table, source } => Plan::Insert {
+                table,
+                source: xform(source)?,
+            },
+            Plan::Query(source) => Plan::Query(xform(source)?),
+            Plan::Explain { source, analyze } => Plan::Explain {
+                source: xform(source)?,
+                analyze,
+            },
+            Plan::Aggregate {
+                source,
+                group_by,
+                aggregates,
+            } => Plan::Aggregate {
+                source: xform(source)?,
+                group_by,
+                aggregates,
+            },
+            Plan::Filter { source, predicate } => Plan::Filter {
+                source: xform(source)?,
+                predicate,
+            },
+            Plan::HashJoin {
+                left,
+                right,
+                keys,
+                join_type,
+            } => Plan::HashJoin {
+                left: xform(left)?,
+                right: xform(right)?,
+                keys,
+                join_type,
+            },
+            Plan::Join {
+                left,
+                right,
+                on,
+                join_type,
+            } => Plan::Join {
+                left: xform(left)?,
+                right: xform(right)?,
+                on,
+                join_type,
+            },
+            Plan::Limit { source, limit } => Plan::Limit {
+                source: xform(source)?,
+                limit,
+            },
+            Plan::MergeJoin {
+                left,
+                right,
+                keys,
+                join_type,
+            } => Plan::MergeJoin {
+                left: xform(left)?,
+                right: xform(right)?,
+                keys,
+                join_type,
+            },
+            Plan::Offset { source, offset } => Plan::Offset {
+                source: xform(source)?,
+                offset,
+            },
+            Plan::Order { source, orders } => Plan::Order {
+                source: xform(source)?,
+                orders,
+            },
+            Plan::Project {
+                source,
+                columns,
+                aliases,
+            } => Plan::Project {
+                source: xform(source)?,
+                columns,
+                aliases,
+            },
+            Plan::RecursiveCte {
+                name,
+                anchor,
+                recursive,
+                distinct,
+            } => Plan::RecursiveCte {
+                name,
+                anchor: xform(anchor)?,
+                recursive: xform(recursive)?,
+                distinct,
+            },
+            Plan::SetOperation {
+                left,
+                right,
+                op,
+                all,
+            } => Plan::SetOperation {
+                left: xform(left)?,
+                right: xform(right)?,
+                op,
+                all,
+            },
+        };
+        after(plan)
+    }
+
+    /// Applies `f` to every expression of this node and its descendants. Plans nested in
+    /// subquery expressions are not descended into.
+    pub fn map_exprs(self, f: &mut impl FnMut(Expr) -> Result<Expr, Error>) -> Result<Self, Error> {
+        Ok(match self {
+            Plan::CreateTable(_)
+            | Plan::DropTable(_)
+            | Plan::Nothing { .. }
+            | Plan::IndexLookup { .. }
+            | Plan::KeyLookup { .. }
+            | Plan::WorkingTable { .. } => self,
+            Plan::Insert { table, source } => Plan::Insert {
+                table,
+                source: Box::new(source.map_exprs(f)?),
+            },
+            Plan::Delete { table, source } => Plan::Delete {
+                table,
+                source: f(source)?,
+            },
+            Plan::Query(source) => Plan::Query(Box::new(source.map_exprs(f)?)),
+            Plan::Explain { source, analyze } => Plan::Explain {
+                source: Box::new(source.map_exprs(f)?),
+                analyze,
+            },
+            Plan::Aggregate {
+                source,
+                group_by,
+                aggregates,
+            } => Plan::Aggregate {
+                source: Box::new(source.map_exprs(f)?),
+                group_by: group_by.into_iter().map(&mut *f).try_collect()?,
+                aggregates: aggregates
+                    .into_iter()
+                    .map(|aggregate| aggregate.map_expr(&mut *f))
+                    .try_collect()?,
+            },
+            Plan::Filter { source, predicate } => Plan::Filter {
+                source: Box::new(source.map_exprs(f)?),
+                predicate: f(predicate)?,
+            },
+            Plan::HashJoin {
+                left,
+                right,
+                keys,
+                join_type,
+            } => Plan::HashJoin {
+                left: Box::new(left.map_exprs(f)?),
+                right: Box::new(right.map_exprs(f)?),
+                keys,
+                join_type,
+            },
+            Plan::MergeJoin {
+                left,
+                right,
+                keys,
+                join_type,
+            } => Plan::MergeJoin {
+                left: Box::new(left.map_exprs(f)?),
+                right: Box::new(right.map_exprs(f)?),
+                keys,
+                join_type,
+            },
+            Plan::Join {
+                left,
+                right,
+                on,
+                join_type,
+            } => Plan::Join {
+                left: Box::new(left.map_exprs(f)?),
+                right: Box::new(right.map_exprs(f)?),
+                on: on.map(&mut *f).transpose()?,
+                join_type,
+            },
+            Plan::Limit { source, limit } => Plan::Limit {
+                source: Box::new(source.map_exprs(f)?),
+                limit,
+            },
+            Plan::Offset { source, offset } => Plan::Offset {
+                source: Box::new(source.map_exprs(f)?),
+                offset,
+            },
+            Plan::Order { source, orders } => Plan::Order {
+                source: Box::new(source.map_exprs(f)?),
+                orders: orders
+                    .into_iter()
+                    .map(|(expr, direction)| Ok::<_, Error>((f(expr)?, direction)))
+                    .try_collect()?,
+            },
+            Plan::Project {
+                source,
+                columns,
+                aliases,
+            } => Plan::Project {
+                source: Box::new(source.map_exprs(f)?),
+                columns: columns.into_iter().map(&mut *f).try_collect()?,
+                aliases,
+            },
+            Plan::RecursiveCte {
+                name,
+                anchor,
+                recursive,
+                distinct,
+            } => Plan::RecursiveCte {
+                name,
+                anchor: Box::new(anchor.map_exprs(f)?),
+                recursive: Box::new(recursive.map_exprs(f)?),
+                distinct,
+            },
+            Plan::Scan {
+                table,
+                filter,
+                alias,
+            } => Plan::Scan {
+                table,
+                filter: filter.map(&mut *f).transpose()?,
+                alias,
+            },
+            Plan::SetOperation {
+                left,
+                right,
+                op,
+                all,
+            } => Plan::SetOperation {
+                left: Box::new(left.map_exprs(f)?),
+                right: Box::new(right.map_exprs(f)?),
+                op,
+                all,
+            },
+            Plan::Values { rows } => Plan::Values {
+                rows: rows
+                    .into_iter()
+                    .map(|row| row.into_iter().map(&mut *f).try_collect())
+                    .try_collect()?,
+            },
+        })
+    }
+
+    /// Walks every expression of this node and its descendants, calling `visitor` on each
+    /// expression node until it returns false. Plans nested in subqueries are not descended into.
+    pub fn walk_exprs(&self, visitor: &mut impl FnMut(&Expr) -> bool) -> bool {
+        match self {
+            Plan::CreateTable(_)
+            | Plan::DropTable(_)
+            | Plan::Nothing { .. }
+            | Plan::IndexLookup { .. }
+            | Plan::KeyLookup { .. }
+            | Plan::WorkingTable { .. } => true,
+            Plan::Insert { source, .. }
+            | Plan::Query(source)
+            | Plan::Explain { source, .. }
+            | Plan::Limit { source, .. }
+            | Plan::Offset { source, .. } => source.walk_exprs(visitor),
+            Plan::Delete { source, .. } => source.walk(visitor),
+            Plan::Aggregate {
+                source,
+                group_by,
+                aggregates,
+            } => {
+                group_by.iter().all(|expr| expr.walk(visitor))
+                    && aggregates.iter().all(|agg| agg.expr().walk(visitor))
+                    && source.walk_exprs(visitor)
+            }
+            Plan::Filter { source, predicate } => {
+                predicate.walk(visitor) && source.walk_exprs(visitor)
+            }
+            Plan::HashJoin { left, right, .. } | Plan::MergeJoin { left, right, .. } => {
+                left.walk_exprs(visitor) && right.walk_exprs(visitor)
+            }
+            Plan::Join {
+                left, right, on, ..
+            } => {
+                on.as_ref().is_none_or(|on| on.walk(visitor))
+                    && left.walk_exprs(visitor)
+                    && right.walk_exprs(visitor)
+            }
+            Plan::Order { source, orders } => {
+                orders.iter().all(|(expr, _)| expr.walk(visitor)) && source.walk_exprs(visitor)
+            }
+            Plan::Project {
+                source, columns, ..
+            } => columns.iter().all(|expr| expr.walk(visitor)) && source.walk_exprs(visitor),
+            Plan::RecursiveCte {
+                anchor, recursive, ..
+            } => anchor.walk_exprs(visitor) && recursive.walk_exprs(visitor),
+            Plan::Scan { filter, .. } => filter.as_ref().is_none_or(|f| f.walk(visitor)),
+            Plan::SetOperation { left, right, .. } => {
+                left.walk_exprs(visitor) && right.walk_exprs(visitor)
+            }
+            Plan::Values { rows } => rows.iter().flatten().all(|expr| expr.walk(visitor)),
+        }
+    }
+
+    /// Returns whether the plan references columns of an enclosing query.
+    pub fn is_correlated(&self) -> bool {
+        !self.walk_exprs(&mut |expr| !matches!(expr, Expr::OuterColumn(_)))
+    }
+
+    /// Returns the number of parameters that the plan takes, including those of its
+    /// subqueries, i.e. one more than the highest parameter index.
+    pub fn parameter_count(&self) -> usize {
+        let mut count = 0;
+        self.walk_exprs(&mut |expr| {
+            match expr {
+                Expr::Parameter(index) => count = count.max(index + 1),
+                Expr::Subquery(plan) | Expr::Exists(plan, _) | Expr::InSubquery(_, plan, _) => {
+                    count = count.max(plan.parameter_count())
+                }
+                _ => {}
+            }
+            true
+        });
+        count
+    }
+
+    /// Replaces the parameters of the plan and its subqueries with the given values.
+    pub fn bind(self, parameters: &[Value]) -> Result<Self, Error> {
+        self.map_exprs(&mut |expr| {
+            expr.transform(&mut |expr| match expr {
+                Expr::Parameter(index) => parameters
+                    .get(index)
+                    .cloned()
+                    .map(Expr::Constant)
+                    .ok_or(Error::UnboundParameter(index + 1)),
+                Expr::Subquery(plan) => Ok(Expr::Subquery(Box::new(plan.bind(parameters)?))),
+                Expr::Exists(plan, negated) => {
+                    Ok(Expr::Exists(Box::new(plan.bind(parameters)?), negated))
+                }
+                Expr::InSubquery(expr, plan, negated) => Ok(Expr::InSubquery(
+                    expr,
+                    Box::new(plan.bind(parameters)?),
+                    negated,
+                )),
+                expr => Ok(expr),
+            })
+        })
+    }
+
+    /// Checks the types of the arguments of the function calls in the plan and its
+    /// subqueries, against the types of the columns each expression is evaluated on.
+    pub fn check_types(&self) -> Result<(), Error> {
+        match self {
+            Plan::CreateTable(_)
+            | Plan::DropTable(_)
+            | Plan::Nothing { .. }
+            | Plan::IndexLookup { .. }
+            | Plan::KeyLookup { .. }
+            | Plan::WorkingTable { .. } => Ok(()),
+            Plan::Insert { source, .. }
+            | Plan::Query(source)
+            | Plan::Explain { source, .. }
+            | Plan::Limit { source, .. }
+            | Plan::Offset { source, .. } => source.check_types(),
+            Plan::Delete { table, source } => {
+                source.check_types(&|i| table.columns.get(i).map(|c| c.data_type))
+            }
+            Plan::Aggregate {
+                source,
+                group_by,
+                aggregates,
+            } => {
+                let column_type = |i| source.column_type(i);
+                for expr in group_by
+                    .iter()
+                    .chain(aggregates.iter().map(|agg| agg.expr()))
+                {
+                    expr.check_types(&column_type)?;
+                }
+                for aggregate in aggregates {
+                    if let Aggregate::User(function, expr) = aggregate {
+                        check_arg(
+                            &function.name,
+                            0,
+                            expr.data_type(&column_type),
+                            &function.arg,
+                        )?;
+                    }
+                }
+                source.check_types()
+            }
+            Plan::Filter { source, predicate } => {
+                predicate.check_types(&|i| source.column_type(i))?;
+                source.check_types()
+            }
+            Plan::Join {
+                left, right, on, ..
+            } => {
+                if let Some(on) = on {
+                    on.check_types(&|i| match i.checked_sub(left.num_columns()) {
+                        Some(i) => right.column_type(i),
+                        None => left.column_type(i),
+                    })?;
+                }
+                left.check_types()?;
+                right.check_types()
+            }
+            Plan::HashJoin { left, right, .. }
+            | Plan::MergeJoin { left, right, .. }
+            | Plan::SetOperation { left, right, .. } => {
+                left.check_types()?;
+                right.check_types()
+            }
+            Plan::Order { source, orders } => {
+                for (expr, _) in orders {
+                    expr.check_types(&|i| source.column_type(i))?;
+                }
+                source.check_types()
+            }
+            Plan::Project {
+                source, columns, ..
+            } => {
+                for expr in columns {
+                    expr.check_types(&|i| source.column_type(i))?;
+                }
+                source.check_types()
+            }
+            Plan::RecursiveCte {
+                anchor, recursive, ..
+            } => {
+                anchor.check_types()?;
+                recursive.check_types()
+            }
+            Plan::Scan { table, filter, .. } => match filter {
+                Some(filter) => filter.check_types(&|i| table.columns.get(i).map(|c| c.data_type)),
+                None => Ok(()),
+            },
+            Plan::Values { rows } => rows
+                .iter()
+                .flatten()
+                .try_for_each(|expr| expr.check_types(&|_| None)),
         }
     }
 
@@ -148,6 +835,19 @@ impl Plan {
         prefix: &str,
         root: bool,
         last_child: bool,
+    ) -> fmt::Result {
+        self.format_annotated(f, prefix, root, last_child, &|_| None)
+    }
+
+    /// Formats the plan tree like [`Plan::format`], appending the annotation returned by
+    /// `annotate` for each node to the node's first line.
+    pub fn format_annotated(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        prefix: &str,
+        root: bool,
+        last_child: bool,
+        annotate: &dyn Fn(&Plan) -> Option<String>,
     ) -> fmt::Result {
         let prefix = if !last_child {
             write!(f, "{}├── ", prefix)?;
@@ -162,44 +862,86 @@ impl Plan {
 
         match self {
             Plan::CreateTable(table) => {
-                writeln!(f, "CreateTable: {}", table.name)?;
+                write!(f, "CreateTable: {}", table.name)?;
+                Self::end_header(f, annotate(self))?;
 
                 for column in &table.columns {
                     writeln!(f, "{}  └── {:?}", prefix, column)?;
                 }
             }
             Plan::DropTable(table) => {
-                writeln!(f, "DropTable: {}", table)?;
+                write!(f, "DropTable: {}", table)?;
+                Self::end_header(f, annotate(self))?;
             }
             Plan::Insert { table, source } => {
-                writeln!(f, "Insert: {}", table.name)?;
-                source.format(f, &prefix, false, true)?;
+                write!(f, "Insert: {}", table.name)?;
+                Self::end_header(f, annotate(self))?;
+                source.format_annotated(f, &prefix, false, true, annotate)?;
             }
             Plan::Delete { table, source } => {
-                writeln!(f, "Delete: {}", table.name)?;
+                write!(f, "Delete: {}", table.name)?;
+                Self::end_header(f, annotate(self))?;
                 writeln!(f, "{}└── {}", prefix, source)?;
             }
             Plan::Query(source) => {
-                writeln!(f, "Query")?;
-                source.format(f, &prefix, false, true)?;
+                write!(f, "Query")?;
+                Self::end_header(f, annotate(self))?;
+                source.format_annotated(f, &prefix, false, true, annotate)?;
+            }
+            Plan::Explain { source, analyze } => {
+                write!(f, "Explain{}", if *analyze { " Analyze" } else { "" })?;
+                Self::end_header(f, annotate(self))?;
+                source.format_annotated(f, &prefix, false, true, annotate)?;
             }
             Plan::Aggregate {
                 source,
                 group_by,
                 aggregates,
             } => {
-                writeln!(f, "Aggregate ({} groups)", group_by.len())?;
+                write!(f, "Aggregate ({} groups)", group_by.len())?;
+                Self::end_header(f, annotate(self))?;
                 for group in group_by.iter() {
                     writeln!(f, "{}├── {}", prefix, group)?;
                 }
                 for aggregate in aggregates.iter() {
                     writeln!(f, "{}├── {}", prefix, aggregate)?;
                 }
-                source.format(f, &prefix, false, true)?;
+                source.format_annotated(f, &prefix, false, true, annotate)?;
             }
             Plan::Filter { source, predicate } => {
-                writeln!(f, "Filter: {}", predicate)?;
-                source.format(f, &prefix, false, true)?;
+                write!(f, "Filter: {}", predicate)?;
+                Self::end_header(f, annotate(self))?;
+                source.format_annotated(f, &prefix, false, true, annotate)?;
+            }
+            Plan::HashJoin {
+                left,
+                right,
+                keys,
+                join_type,
+            }
+            | Plan::MergeJoin {
+                left,
+                right,
+                keys,
+                join_type,
+            } => {
+                let name = match self {
+                    Plan::MergeJoin { .. } => "MergeJoin",
+                    _ => "HashJoin",
+                };
+                let left_cols = left.num_columns();
+                write!(
+                    f,
+                    "{}: {} ({:?})",
+                    name,
+                    keys.iter()
+                        .map(|(l, r)| format!("col{} = col{}", l, left_cols + r))
+                        .join(" AND "),
+                    join_type
+                )?;
+                Self::end_header(f, annotate(self))?;
+                left.format_annotated(f, &prefix, false, false, annotate)?;
+                right.format_annotated(f, &prefix, false, true, annotate)?;
             }
             Plan::Join {
                 left,
@@ -207,43 +949,183 @@ impl Plan {
                 on,
                 join_type,
             } => {
-                writeln!(
+                write!(
                     f,
                     "Join: {} ({:?})",
                     on.as_ref().map_or("None".to_string(), |e| e.to_string()),
                     join_type
                 )?;
-                left.format(f, &prefix, false, false)?;
-                right.format(f, &prefix, false, true)?;
+                Self::end_header(f, annotate(self))?;
+                left.format_annotated(f, &prefix, false, false, annotate)?;
+                right.format_annotated(f, &prefix, false, true, annotate)?;
+            }
+            Plan::Limit { source, limit } => {
+                write!(f, "Limit: {}", limit)?;
+                Self::end_header(f, annotate(self))?;
+                source.format_annotated(f, &prefix, false, true, annotate)?;
             }
             Plan::Nothing { .. } => {
-                writeln!(f, "Nothing")?;
+                write!(f, "Nothing")?;
+                Self::end_header(f, annotate(self))?;
+            }
+            Plan::Offset { source, offset } => {
+                write!(f, "Offset: {}", offset)?;
+                Self::end_header(f, annotate(self))?;
+                source.format_annotated(f, &prefix, false, true, annotate)?;
+            }
+            Plan::Order { source, orders } => {
+                write!(
+                    f,
+                    "Order: {}",
+                    orders
+                        .iter()
+                        .map(|(expr, direction)| format!("{} {}", expr, direction))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )?;
+                Self::end_header(f, annotate(self))?;
+                source.format_annotated(f, &prefix, false, true, annotate)?;
             }
             Plan::Project {
                 source,
                 columns,
                 aliases,
             } => {
-                writeln!(f, "Project")?;
+                write!(f, "Project")?;
+                Self::end_header(f, annotate(self))?;
                 for (i, column) in columns.iter().enumerate() {
                     writeln!(f, "{}├── {}: {}", prefix, aliases[i], column)?;
                 }
-                source.format(f, &prefix, false, true)?;
+                source.format_annotated(f, &prefix, false, true, annotate)?;
             }
-            Plan::Scan { table, .. } => {
-                writeln!(f, "Scan")?;
-                writeln!(f, "{}└── {}", prefix, table.name)?;
+            Plan::RecursiveCte {
+                name,
+                anchor,
+                recursive,
+                distinct,
+            } => {
+                write!(
+                    f,
+                    "RecursiveCte: {} ({})",
+                    name,
+                    if *distinct { "DISTINCT" } else { "ALL" }
+                )?;
+                Self::end_header(f, annotate(self))?;
+                anchor.format_annotated(f, &prefix, false, false, annotate)?;
+                recursive.format_annotated(f, &prefix, false, true, annotate)?;
+            }
+            Plan::Scan { table, filter, .. } => {
+                write!(f, "Scan")?;
+                Self::end_header(f, annotate(self))?;
+                match filter {
+                    Some(filter) => {
+                        writeln!(f, "{}├── {}", prefix, table.name)?;
+                        writeln!(f, "{}└── Filter: {}", prefix, filter)?;
+                    }
+                    None => writeln!(f, "{}└── {}", prefix, table.name)?,
+                }
+            }
+            Plan::IndexLookup {
+                table,
+                column,
+                values,
+                ..
+            } => {
+                write!(
+                    f,
+                    "IndexLookup: {}.{} IN ({})",
+                    table.name,
+                    column,
+                    values.iter().join(", ")
+                )?;
+                Self::end_header(f, annotate(self))?;
+            }
+            Plan::KeyLookup { table, keys, .. } => {
+                write!(f, "KeyLookup: {} ({})", table.name, keys.iter().join(", "))?;
+                Self::end_header(f, annotate(self))?;
+            }
+            Plan::SetOperation {
+                left,
+                right,
+                op,
+                all,
+            } => {
+                write!(f, "{}{}", op, if *all { " ALL" } else { "" })?;
+                Self::end_header(f, annotate(self))?;
+                left.format_annotated(f, &prefix, false, false, annotate)?;
+                right.format_annotated(f, &prefix, false, true, annotate)?;
             }
             Plan::Values { rows } => {
-                writeln!(f, "Values")?;
+                write!(f, "Values")?;
+                Self::end_header(f, annotate(self))?;
                 for row in rows {
                     writeln!(f, "{}└── {:?}", prefix, row)?;
                 }
             }
+            Plan::WorkingTable { name, .. } => {
+                write!(f, "WorkingTable: {}", name)?;
+                Self::end_header(f, annotate(self))?;
+            }
         }
 
         Ok(())
     }
+
+    fn end_header(f: &mut fmt::Formatter<'_>, annotation: Option<String>) -> fmt::Result {
+        match annotation {
+            Some(annotation) => writeln!(f, " ({})", annotation),
+            None => writeln!(f),
+        }
+    }
+
+    /// Returns a displayable tree of the plan, with the annotation returned by `annotate`
+    /// for each node appended to the node's first line.
+    pub fn display_annotated<'a>(
+        &'a self,
+        annotate: &'a dyn Fn(&Plan) -> Option<String>,
+    ) -> impl fmt::Display + 'a {
+        struct Annotated<'a>(&'a Plan, &'a dyn Fn(&Plan) -> Option<String>);
+
+        impl fmt::Display for Annotated<'_> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                self.0.format_annotated(f, "", true, true, self.1)
+            }
+        }
+
+        Annotated(self, annotate)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Ascending,
+    Descending,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Direction::Ascending => write!(f, "ASC"),
+            Direction::Descending => write!(f, "DESC"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SetOperator {
+    Union,
+    Intersect,
+    Except,
+}
+
+impl fmt::Display for SetOperator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SetOperator::Union => write!(f, "Union"),
+            SetOperator::Intersect => write!(f, "Intersect"),
+            SetOperator::Except => write!(f, "Except"),
+        }
+    }
 }
 
 impl fmt::Display for Plan {
@@ -1,3 +1,6 @@
+use std::{cell::Cell, ops::ControlFlow};
+
+use itertools::Itertools;
 use sqlparser::ast;
 
 use crate::{
@@ -10,23 +13,121 @@ use crate::{
 };
 
 use super::{
-    aggregate::{Aggregate, aggregate_function_args, is_aggregate},
-    expr::Expr,
+    aggregate::{Aggregate, aggregate_function_args, find_aggregates, is_aggregate},
+    cost::CostModel,
+    expr::{BinaryOp, Expr, UnaryOp},
+    function::{DateField, FunctionRegistry, ScalarFunction},
     join::JoinType,
-    plan::Plan,
-    scope::Scope,
+    optimizer::Optimizer,
+    plan::{Direction, Plan, SetOperator},
+    scope::{Cte, Scope},
 };
 
+/// The name of the system table that lists the statistics of each column of each table,
+/// e.g. `SELECT * FROM veris_statistics WHERE table_name = 'users'`. A table with the same
+/// name takes precedence over it.
+pub const STATISTICS_TABLE: &str = "veris_statistics";
+
+/// The columns of the [`STATISTICS_TABLE`].
+const STATISTICS_COLUMNS: [&str; 8] = [
+    "table_name",
+    "column_name",
+    "row_count",
+    "null_fraction",
+    "distinct_count",
+    "min",
+    "max",
+    "histogram",
+];
+
 pub struct Planner<'a, C: Catalog> {
     catalog: &'a C,
+    /// Whether the plans built so far only depend on the catalog's schema, and not on data
+    /// that can change without it, like the statistics table.
+    cacheable: Cell<bool>,
+    /// The functions registered by the embedder, if any.
+    functions: Option<&'a FunctionRegistry>,
 }
 
 impl<'a, C: Catalog> Planner<'a, C> {
     pub fn new(catalog: &'a C) -> Self {
-        Self { catalog }
+        Self {
+            catalog,
+            cacheable: Cell::new(true),
+            functions: None,
+        }
+    }
+
+    /// Makes the functions in the registry callable from the planned statements.
+    pub fn with_functions(mut self, functions: &'a FunctionRegistry) -> Self {
+        self.functions = Some(functions);
+        self
     }
 
     pub fn plan(&self, statement: &ast::Statement) -> Result<Plan, Error> {
+        self.optimize(self.build(statement)?)
+    }
+
+    /// Builds the plan of a statement without optimizing it. Its parameters, written as
+    /// `$1`, `$2`, ... or as `?` in order of appearance, become [`Expr::Parameter`]s.
+    pub fn build(&self, statement: &ast::Statement) -> Result<Plan, Error> {
+        let mut positional = 0;
+        let _ = ast::visit_expressions(statement, |expr| {
+            if let ast::Expr::Value(value) = expr
+                && value.value == ast::Value::Placeholder("?".to_string())
+            {
+                positional += 1;
+            }
+            ControlFlow::<()>::Continue(())
+        });
+        if positional == 0 {
+            return self.plan_checked(statement);
+        }
+
+        let mut statement = statement.clone();
+        let mut index = 0;
+        let _ = ast::visit_expressions_mut(&mut statement, |expr| {
+            if let ast::Expr::Value(value) = expr
+                && value.value == ast::Value::Placeholder("?".to_string())
+            {
+                index += 1;
+                value.value = ast::Value::Placeholder(format!("${}", index));
+            }
+            ControlFlow::<()>::Continue(())
+        });
+        self.plan_checked(&statement)
+    }
+
+    /// Plans a statement, and checks the argument types of the function calls in it.
+    fn plan_checked(&self, statement: &ast::Statement) -> Result<Plan, Error> {
+        let plan = self.plan_statement(statement)?;
+        plan.check_types()?;
+        Ok(plan)
+    }
+
+    /// Optimizes a plan, with the statistics in the catalog.
+    pub fn optimize(&self, plan: Plan) -> Result<Plan, Error> {
+        let cost_model = CostModel::from_catalog(self.catalog)?;
+        Optimizer::with_cost_model(cost_model).optimize(plan)
+    }
+
+    /// Returns whether the plans built so far can be reused for as long as the catalog's
+    /// schema stays the same.
+    pub fn is_cacheable(&self) -> bool {
+        self.cacheable.get()
+    }
+
+    /// Evaluates a constant expression, such as a parameter value given to `EXECUTE`. It
+    /// is evaluated once, so it may call volatile functions.
+    pub fn build_value(&self, expr: &ast::Expr) -> Result<Value, Error> {
+        let expr = self.build_expr(expr, &Scope::default())?;
+        if expr.needs_context() {
+            return Err(Error::InvalidSql(format!("Expected a constant: {}", expr)));
+        }
+        expr.eval(None)
+    }
+
+    fn plan_statement(&self, statement: &ast::Statement) -> Result<Plan, Error> {
         match statement {
             ast::Statement::CreateTable(stmt) => self.plan_create_table(stmt),
             ast::Statement::Drop {
@@ -42,7 +143,13 @@ impl<'a, C: Catalog> Planner<'a, C> {
             }
             ast::Statement::Delete(stmt) => self.plan_delete(stmt),
             ast::Statement::Insert(stmt) => self.plan_insert(stmt),
-            ast::Statement::Query(stmt) => self.plan_query(stmt),
+            ast::Statement::Query(stmt) => Ok(Plan::Query(Box::new(self.plan_query(stmt, None)?))),
+            ast::Statement::Explain {
+                analyze, statement, ..
+            } => Ok(Plan::Explain {
+                source: Box::new(self.plan_statement(statement)?),
+                analyze: *analyze,
+            }),
             stmt => Err(Error::NotYetSupported(stmt.to_string())),
         }
     }
@@ -109,6 +216,39 @@ impl<'a, C: Catalog> Planner<'a, C> {
             columns.push(col);
         }
 
+        for constraint in &table.constraints {
+            let ast::TableConstraint::ForeignKey {
+                columns: fk_columns,
+                foreign_table,
+                referred_columns,
+                on_delete,
+                on_update,
+                characteristics,
+                ..
+            } = constraint
+            else {
+                continue;
+            };
+            if fk_columns.len() != 1 {
+                return Err(Error::NotYetSupported(
+                    "Foreign key with multiple columns".to_string(),
+                ));
+            }
+            if on_delete.is_some() || on_update.is_some() || characteristics.is_some() {
+                return Err(Error::NotYetSupported(constraint.to_string()));
+            }
+            let name = fk_columns[0].to_string();
+            let column = columns
+                .iter_mut()
+                .find(|column| column.name == name)
+                .ok_or(Error::ColumnNotFound(name))?;
+            column.references = Some(ForeignKey {
+                table: foreign_table.to_string(),
+                columns: referred_columns.iter().map(|col| col.to_string()).collect(),
+            });
+            column.has_secondary_index = true;
+        }
+
         let table = Table {
             name: table.name.to_string(),
             columns,
@@ -134,7 +274,7 @@ impl<'a, C: Catalog> Planner<'a, C> {
             .get_table(&table)?
             .ok_or_else(|| Error::TableDoesNotExist(table.clone()))?;
         let source = if let Some(source) = stmt.source.as_deref() {
-            self.plan_query(source)?
+            self.plan_query(source, None)?
         } else {
             return Err(Error::NotYetSupported(stmt.to_string()));
         };
@@ -161,20 +301,301 @@ impl<'a, C: Catalog> Planner<'a, C> {
             .ok_or_else(|| Error::TableDoesNotExist(table.clone()))?;
         let scope = Scope::from_table(&table, None)?;
         let source = if let Some(source) = stmt.selection.as_ref() {
-            Self::build_expr(source, &scope)?
+            self.build_expr(source, &scope)?
         } else {
             return Err(Error::NotYetSupported(stmt.to_string()));
         };
         Ok(Plan::Delete { table, source })
     }
 
-    fn plan_query(&self, stmt: &ast::Query) -> Result<Plan, Error> {
-        match &*stmt.body {
-            ast::SetExpr::Values(values) => self.plan_values(values),
-            ast::SetExpr::Select(select) => self.plan_select(select),
-            ast::SetExpr::Query(query) => self.plan_query(query),
-            _ => Err(Error::NotYetSupported(stmt.to_string())),
+    fn plan_query(&self, stmt: &ast::Query, outer: Option<&Scope>) -> Result<Plan, Error> {
+        let plan = match &stmt.with {
+            None => self.plan_set_expr(&stmt.body, stmt.order_by.as_ref(), outer)?,
+            Some(with) => {
+                // CTEs are registered in a scope that the body is planned within. Without an
+                // enclosing query it has no columns, so nothing resolves as an outer reference.
+                let mut scope = outer.cloned().unwrap_or_default();
+                for cte in &with.cte_tables {
+                    let name = cte.alias.name.value.clone();
+                    let cte = if with.recursive {
+                        self.plan_recursive_cte(cte, &scope)?
+                    } else {
+                        let plan = self.plan_query(&cte.query, Some(&scope))?;
+                        let columns = Self::cte_columns(cte, &plan)?;
+                        Cte { plan, columns }
+                    };
+                    scope.add_cte(name, cte);
+                }
+                self.plan_set_expr(&stmt.body, stmt.order_by.as_ref(), Some(&scope))?
+            }
+        };
+        self.plan_limit(plan, stmt.limit_clause.as_ref())
+    }
+
+    /// Applies a `LIMIT` and `OFFSET` clause to `plan`. Both must be constant,
+    /// non-negative integers.
+    fn plan_limit(&self, mut plan: Plan, limit: Option<&ast::LimitClause>) -> Result<Plan, Error> {
+        let (limit, offset) = match limit {
+            None => return Ok(plan),
+            Some(ast::LimitClause::LimitOffset {
+                limit,
+                offset,
+                limit_by,
+            }) => {
+                if !limit_by.is_empty() {
+                    return Err(Error::NotYetSupported("LIMIT BY".to_string()));
+                }
+                (limit.as_ref(), offset.as_ref().map(|offset| &offset.value))
+            }
+            Some(ast::LimitClause::OffsetCommaLimit { offset, limit }) => {
+                (Some(limit), Some(offset))
+            }
+        };
+
+        let eval_count = |expr: &ast::Expr| -> Result<usize, Error> {
+            match self.build_expr(expr, &Scope::default())?.eval(None)? {
+                Value::Integer(n) if n >= 0 => Ok(n as usize),
+                _ => Err(Error::InvalidSql(format!(
+                    "LIMIT and OFFSET must be non-negative integers: {}",
+                    expr
+                ))),
+            }
+        };
+        if let Some(offset) = offset {
+            plan = Plan::Offset {
+                source: Box::new(plan),
+                offset: eval_count(offset)?,
+            };
+        }
+        if let Some(limit) = limit {
+            plan = Plan::Limit {
+                source: Box::new(plan),
+                limit: eval_count(limit)?,
+            };
+        }
+        Ok(plan)
+    }
+
+    /// Plans a CTE of a `WITH RECURSIVE` clause. A CTE that refers to itself must be of the
+    /// form `anchor UNION [ALL] recursive`, where only the recursive term refers to it.
+    fn plan_recursive_cte(&self, cte: &ast::Cte, scope: &Scope) -> Result<Cte, Error> {
+        let name = cte.alias.name.value.clone();
+        let ast::SetExpr::SetOperation {
+            op: ast::SetOperator::Union,
+            set_quantifier,
+            left,
+            right,
+        } = &*cte.query.body
+        else {
+            let plan = self.plan_query(&cte.query, Some(scope))?;
+            let columns = Self::cte_columns(cte, &plan)?;
+            return Ok(Cte { plan, columns });
+        };
+        let distinct = match set_quantifier {
+            ast::SetQuantifier::All => false,
+            ast::SetQuantifier::Distinct | ast::SetQuantifier::None => true,
+            quantifier => {
+                return Err(Error::NotYetSupported(format!("UNION {}", quantifier)));
+            }
+        };
+        if cte.query.order_by.is_some() || cte.query.limit_clause.is_some() {
+            return Err(Error::NotYetSupported(
+                "ORDER BY or LIMIT in a recursive CTE".to_string(),
+            ));
+        }
+
+        let anchor = self.plan_set_expr(left, None, Some(scope))?;
+        let columns = Self::cte_columns(cte, &anchor)?;
+
+        let mut recursive_scope = scope.clone();
+        let working_table = Plan::WorkingTable {
+            name: name.clone(),
+            columns: (0..anchor.num_columns())
+                .map(|i| anchor.column_label(i))
+                .collect(),
+        };
+        recursive_scope.add_cte(
+            name.clone(),
+            Cte {
+                plan: working_table,
+                columns: columns.clone(),
+            },
+        );
+        let recursive = self.plan_set_expr(right, None, Some(&recursive_scope))?;
+        if recursive.num_columns() != anchor.num_columns() {
+            return Err(Error::InvalidSql(format!(
+                "Recursive term of {} must return {} columns",
+                name,
+                anchor.num_columns()
+            )));
+        }
+
+        let plan = Plan::RecursiveCte {
+            name,
+            anchor: Box::new(anchor),
+            recursive: Box::new(recursive),
+            distinct,
+        };
+        Ok(Cte { plan, columns })
+    }
+
+    /// Returns the column names of a CTE, taken from its alias if given.
+    fn cte_columns(cte: &ast::Cte, plan: &Plan) -> Result<Vec<Option<String>>, Error> {
+        if cte.alias.columns.is_empty() {
+            return Ok((0..plan.num_columns())
+                .map(|i| plan.column_label(i).column_name().cloned())
+                .collect());
+        }
+        if cte.alias.columns.len() != plan.num_columns() {
+            return Err(Error::InvalidSql(format!(
+                "{} has {} columns, but {} were named",
+                cte.alias.name,
+                plan.num_columns(),
+                cte.alias.columns.len()
+            )));
+        }
+        Ok(cte
+            .alias
+            .columns
+            .iter()
+            .map(|column| Some(column.name.value.clone()))
+            .collect())
+    }
+
+    fn plan_set_expr(
+        &self,
+        body: &ast::SetExpr,
+        order_by: Option<&ast::OrderBy>,
+        outer: Option<&Scope>,
+    ) -> Result<Plan, Error> {
+        match body {
+            ast::SetExpr::Values(values) => self.plan_order(self.plan_values(values)?, order_by),
+            ast::SetExpr::Select(select) => self.plan_select(select, order_by, outer),
+            ast::SetExpr::Query(query) => self.plan_query(query, outer),
+            ast::SetExpr::SetOperation {
+                op,
+                set_quantifier,
+                left,
+                right,
+            } => {
+                let plan = self.plan_set_operation(op, set_quantifier, left, right, outer)?;
+                self.plan_order(plan, order_by)
+            }
+            _ => Err(Error::NotYetSupported(body.to_string())),
+        }
+    }
+
+    fn plan_set_operation(
+        &self,
+        op: &ast::SetOperator,
+        set_quantifier: &ast::SetQuantifier,
+        left: &ast::SetExpr,
+        right: &ast::SetExpr,
+        outer: Option<&Scope>,
+    ) -> Result<Plan, Error> {
+        let op = match op {
+            ast::SetOperator::Union => SetOperator::Union,
+            ast::SetOperator::Intersect => SetOperator::Intersect,
+            ast::SetOperator::Except | ast::SetOperator::Minus => SetOperator::Except,
+        };
+        let all = match set_quantifier {
+            ast::SetQuantifier::All => true,
+            ast::SetQuantifier::Distinct | ast::SetQuantifier::None => false,
+            quantifier => {
+                return Err(Error::NotYetSupported(format!("{} {}", op, quantifier)));
+            }
+        };
+
+        let left = self.plan_set_expr(left, None, outer)?;
+        let right = self.plan_set_expr(right, None, outer)?;
+        if left.num_columns() != right.num_columns() {
+            return Err(Error::InvalidSql(format!(
+                "Each {} query must have the same number of columns, got {} and {}",
+                op,
+                left.num_columns(),
+                right.num_columns()
+            )));
+        }
+        for i in 0..left.num_columns() {
+            if let (Some(left_type), Some(right_type)) = (left.column_type(i), right.column_type(i))
+                && !left_type.is_compatible_with(&right_type)
+            {
+                return Err(Error::InvalidSql(format!(
+                    "{} column {} has incompatible types {} and {}",
+                    op,
+                    i + 1,
+                    left_type,
+                    right_type
+                )));
+            }
+        }
+
+        Ok(Plan::SetOperation {
+            left: Box::new(left),
+            right: Box::new(right),
+            op,
+            all,
+        })
+    }
+
+    /// Orders the output of `plan`, which the `ORDER BY` expressions may only refer to by
+    /// its column labels.
+    fn plan_order(&self, plan: Plan, order_by: Option<&ast::OrderBy>) -> Result<Plan, Error> {
+        let order_by = Self::order_by_exprs(order_by)?;
+        if order_by.is_empty() {
+            return Ok(plan);
+        }
+        let mut scope = Scope::default();
+        for i in 0..plan.num_columns() {
+            scope.add_column(plan.column_label(i))?;
+        }
+        let orders = order_by
+            .iter()
+            .map(|order| {
+                Ok((
+                    self.build_expr(&order.expr, &scope)?,
+                    Self::direction(order)?,
+                ))
+            })
+            .collect::<Result<_, Error>>()?;
+        Ok(Plan::Order {
+            source: Box::new(plan),
+            orders,
+        })
+    }
+
+    fn order_by_exprs(order_by: Option<&ast::OrderBy>) -> Result<&[ast::OrderByExpr], Error> {
+        match order_by.map(|order_by| &order_by.kind) {
+            None => Ok(&[]),
+            Some(ast::OrderByKind::Expressions(exprs)) => Ok(exprs),
+            Some(ast::OrderByKind::All(_)) => {
+                Err(Error::NotYetSupported("ORDER BY ALL".to_string()))
+            }
+        }
+    }
+
+    fn direction(order: &ast::OrderByExpr) -> Result<Direction, Error> {
+        if order.options.nulls_first.is_some() {
+            return Err(Error::NotYetSupported(
+                "ORDER BY with NULLS FIRST or NULLS LAST".to_string(),
+            ));
+        }
+        Ok(match order.options.asc {
+            Some(false) => Direction::Descending,
+            Some(true) | None => Direction::Ascending,
+        })
+    }
+
+    /// Plans a subquery of an expression in `scope`, which must produce a single column.
+    fn plan_subquery(&self, body: &ast::SetExpr, scope: &Scope) -> Result<Plan, Error> {
+        let plan = self.plan_set_expr(body, None, Some(scope))?;
+        if plan.num_columns() != 1 {
+            return Err(Error::InvalidSql(format!(
+                "Subquery must return exactly one column: {}",
+                body
+            )));
         }
+        Ok(plan)
     }
 
     fn plan_values(&self, values: &ast::Values) -> Result<Plan, Error> {
@@ -182,7 +603,7 @@ impl<'a, C: Catalog> Planner<'a, C> {
         for row in &values.rows {
             let mut values = Vec::new();
             for value in row {
-                let value = Self::build_expr(value, &Scope::default())?;
+                let value = self.build_expr(value, &Scope::default())?;
                 values.push(value);
             }
             rows.push(values);
@@ -190,62 +611,111 @@ impl<'a, C: Catalog> Planner<'a, C> {
         Ok(Plan::Values { rows })
     }
 
-    fn plan_select(&self, stmt: &ast::Select) -> Result<Plan, Error> {
+    fn plan_select(
+        &self,
+        stmt: &ast::Select,
+        order_by: Option<&ast::OrderBy>,
+        outer: Option<&Scope>,
+    ) -> Result<Plan, Error> {
         log::debug!("Planning select: {}", stmt);
-        let mut scope = Scope::default();
-
-        let mut plan = Plan::Nothing {
-            columns: Vec::new(),
-        };
+        let mut scope = outer.map(Scope::with_outer).unwrap_or_default();
 
+        let mut from_plan = None;
         for from in &stmt.from {
-            let mut table_plan = self.plan_scan(&from.relation, &mut scope)?;
+            // Each FROM item is planned in its own scope, so that its column indexes line
+            // up with its plan, and then appended to the scope of the select.
+            let mut item_scope = scope.spawn();
+            let mut table_plan = self.plan_scan(&from.relation, &mut item_scope)?;
             for join in &from.joins {
-                table_plan = self.plan_join(table_plan, join, &mut scope)?;
+                table_plan = self.plan_join(table_plan, join, &mut item_scope)?;
             }
-            if matches!(plan, Plan::Nothing { .. }) {
-                plan = table_plan;
-            } else {
-                plan = Plan::Join {
+            scope.merge_with(item_scope)?;
+            from_plan = Some(match from_plan {
+                None => table_plan,
+                Some(plan) => Plan::Join {
                     left: Box::new(plan),
                     right: Box::new(table_plan),
                     on: None,
                     join_type: JoinType::Inner,
-                };
-            }
+                },
+            });
         }
+        // Without a FROM clause, the projection is evaluated once over a single empty row.
+        let mut plan = from_plan.unwrap_or(Plan::Values {
+            rows: vec![Vec::new()],
+        });
 
         if let Some(where_clause) = &stmt.selection {
-            let predicate = Self::build_expr(where_clause, &scope)?;
-            plan = Plan::Filter {
-                source: Box::new(plan),
-                predicate,
-            };
-        }
-
-        let mut group_by = Vec::new();
-        match &stmt.group_by {
-            ast::GroupByExpr::Expressions(exprs, _) => {
-                for expr in exprs {
-                    let expr = Self::build_expr(expr, &scope)?;
-                    group_by.push(expr);
+            let mut predicates = Vec::new();
+            for conjunct in Self::split_conjuncts(where_clause) {
+                let ast::Expr::InSubquery {
+                    expr,
+                    subquery,
+                    negated,
+                } = conjunct
+                else {
+                    predicates.push(self.build_expr(conjunct, &scope)?);
+                    continue;
+                };
+                let expr = self.build_expr(expr, &scope)?;
+                let subquery = self.plan_subquery(subquery, &scope)?;
+                if *negated || subquery.is_correlated() {
+                    predicates.push(Expr::InSubquery(
+                        Box::new(expr),
+                        Box::new(subquery),
+                        *negated,
+                    ));
+                    continue;
                 }
+
+                // An uncorrelated IN in a top-level conjunct only needs to know whether a
+                // matching row exists, so it can be planned as a semi-join.
+                let on = Expr::BinaryOp(
+                    Box::new(expr),
+                    BinaryOp::Equal,
+                    Box::new(Expr::Column(plan.num_columns())),
+                );
+                plan = Plan::Join {
+                    left: Box::new(plan),
+                    right: Box::new(subquery),
+                    on: Some(on),
+                    join_type: JoinType::Semi,
+                };
+            }
+
+            if let Some(predicate) = predicates
+                .into_iter()
+                .reduce(|a, b| Expr::BinaryOp(Box::new(a), BinaryOp::And, Box::new(b)))
+            {
+                plan = Plan::Filter {
+                    source: Box::new(plan),
+                    predicate,
+                };
             }
+        }
+
+        let order_by = Self::order_by_exprs(order_by)?;
+
+        let group_exprs = match &stmt.group_by {
+            ast::GroupByExpr::Expressions(exprs, modifiers) if modifiers.is_empty() => exprs,
             _ => {
                 return Err(Error::NotYetSupported(stmt.to_string()));
             }
-        }
-        let functions_and_aggregates = self.collect_aggregates(&stmt.projection, &scope)?;
-        if !group_by.is_empty() || !functions_and_aggregates.is_empty() {
-            let mut child_scope = scope.spawn();
+        };
+        let functions_and_aggregates = self.collect_aggregates(stmt, order_by, &scope)?;
+        if !group_exprs.is_empty() || !functions_and_aggregates.is_empty() || stmt.having.is_some()
+        {
+            let mut child_scope = scope.spawn_grouped();
 
-            for expr in &group_by {
-                if let Expr::Column(index) = expr {
-                    let label = scope.get_column_label(*index)?;
-                    child_scope.add_column(label.clone())?;
-                } else {
-                    return Err(Error::NotYetSupported(expr.to_string()));
-                }
+            let mut group_by = Vec::new();
+            for group_expr in group_exprs {
+                let expr = self.build_expr(group_expr, &scope)?;
+                let label = match &expr {
+                    Expr::Column(index) => scope.get_column_label(*index)?.clone(),
+                    _ => ColumnLabel::None,
+                };
+                child_scope.add_group(group_expr.clone(), label)?;
+                group_by.push(expr);
             }
 
             let mut aggregates = Vec::new();
@@ -262,6 +732,14 @@ impl<'a, C: Catalog> Planner<'a, C> {
             };
         }
 
+        if let Some(having) = &stmt.having {
+            let predicate = self.build_expr(having, &scope)?;
+            plan = Plan::Filter {
+                source: Box::new(plan),
+                predicate,
+            };
+        }
+
         let mut columns = Vec::new();
         let mut aliases = Vec::new();
         for projection in &stmt.projection {
@@ -285,13 +763,69 @@ impl<'a, C: Catalog> Planner<'a, C> {
             return Err(Error::NotYetSupported(stmt.to_string()));
         }
 
+        // ORDER BY may refer to output columns (including aliases), or to input
+        // expressions that are not projected. The latter are appended as hidden
+        // columns and stripped again after sorting.
+        // Output columns that repeat an earlier column under the same name, e.g. `x` and
+        // `a.x` after `JOIN ... USING (x)`, don't make that name ambiguous.
+        let mut output_scope = scope.spawn();
+        for (i, label) in aliases.iter().enumerate() {
+            let repeated = (0..i).find(|&j| {
+                columns[j] == columns[i]
+                    && label.column_name().is_some()
+                    && aliases[j].column_name() == label.column_name()
+            });
+            match (repeated, label) {
+                (Some(j), ColumnLabel::Qualified(table, name)) => {
+                    output_scope.add_column(ColumnLabel::None)?;
+                    output_scope.add_qualified_alias(table.clone(), name.clone(), j);
+                }
+                (Some(_), _) => {
+                    output_scope.add_column(ColumnLabel::None)?;
+                }
+                (None, label) => {
+                    output_scope.add_column(label.clone())?;
+                }
+            }
+        }
+        let visible = aliases.clone();
+        let mut orders = Vec::new();
+        for order in order_by {
+            let expr = match self.build_expr(&order.expr, &output_scope) {
+                Ok(expr) => expr,
+                Err(Error::InvalidColumnLabel(_) | Error::AggregateNotFound(_)) => {
+                    columns.push(self.build_expr(&order.expr, &scope)?);
+                    aliases.push(ColumnLabel::None);
+                    Expr::Column(columns.len() - 1)
+                }
+                Err(err) => return Err(err),
+            };
+            orders.push((expr, Self::direction(order)?));
+        }
+        let hidden = columns.len() > visible.len();
+
         plan = Plan::Project {
             source: Box::new(plan),
             columns,
             aliases,
         };
 
-        Ok(Plan::Query(Box::new(plan)))
+        if !orders.is_empty() {
+            plan = Plan::Order {
+                source: Box::new(plan),
+                orders,
+            };
+        }
+
+        if hidden {
+            plan = Plan::Project {
+                source: Box::new(plan),
+                columns: (0..visible.len()).map(Expr::Column).collect(),
+                aliases: visible,
+            };
+        }
+
+        Ok(plan)
     }
 
     fn build_select_item(
@@ -302,7 +836,7 @@ impl<'a, C: Catalog> Planner<'a, C> {
         log::debug!("Building select item: {}", item);
         match item {
             ast::SelectItem::UnnamedExpr(expr) => {
-                let expr = Self::build_expr(expr, scope)?;
+                let expr = self.build_expr(expr, scope)?;
                 if let Expr::Column(index) = &expr {
                     let label = scope.get_column_label(*index)?;
                     Ok((label.clone(), Some(expr)))
@@ -311,7 +845,7 @@ impl<'a, C: Catalog> Planner<'a, C> {
                 }
             }
             ast::SelectItem::ExprWithAlias { expr, alias } => {
-                let expr = Self::build_expr(expr, scope)?;
+                let expr = self.build_expr(expr, scope)?;
                 let label = ColumnLabel::Unqualified(alias.value.clone());
                 Ok((label, Some(expr)))
             }
@@ -325,10 +859,37 @@ impl<'a, C: Catalog> Planner<'a, C> {
         match relation {
             ast::TableFactor::Table { name, alias, .. } => {
                 let table = name.to_string();
-                let table = self
-                    .catalog
-                    .get_table(&table)?
-                    .ok_or_else(|| Error::TableDoesNotExist(table.clone()))?;
+                if let Some(cte) = scope.get_cte(&table).cloned() {
+                    let name = alias
+                        .as_ref()
+                        .map_or(table, |alias| alias.name.value.clone());
+                    scope.add_relation(&name, cte.columns.iter().cloned())?;
+                    // Relabel the CTE's columns, so that they are qualified by its name.
+                    let aliases = cte
+                        .columns
+                        .into_iter()
+                        .map(|column| match column {
+                            Some(column) => ColumnLabel::Qualified(name.clone(), column),
+                            None => ColumnLabel::None,
+                        })
+                        .collect::<Vec<_>>();
+                    return Ok(Plan::Project {
+                        columns: (0..aliases.len()).map(Expr::Column).collect(),
+                        source: Box::new(cte.plan),
+                        aliases,
+                    });
+                }
+
+                let table = match self.catalog.get_table(&table)? {
+                    Some(table) => table,
+                    None if table == STATISTICS_TABLE => {
+                        let name = alias
+                            .as_ref()
+                            .map_or(table, |alias| alias.name.value.clone());
+                        return self.plan_statistics_table(&name, scope);
+                    }
+                    None => return Err(Error::TableDoesNotExist(table)),
+                };
 
                 let alias = alias.as_ref().map(|alias| alias.to_string());
 
@@ -356,6 +917,62 @@ impl<'a, C: Catalog> Planner<'a, C> {
         }
     }
 
+    /// Plans a scan of the [`STATISTICS_TABLE`], from the statistics currently in the
+    /// catalog. Columns of tables that haven't been analyzed yet only have a row count.
+    fn plan_statistics_table(&self, name: &String, scope: &mut Scope) -> Result<Plan, Error> {
+        self.cacheable.set(false);
+        let mut rows = Vec::new();
+        for table in self.catalog.list_tables()? {
+            let Some(statistics) = self.catalog.get_table_statistics(&table.name)? else {
+                continue;
+            };
+            for (i, column) in table.columns.iter().enumerate() {
+                let column_statistics = statistics.columns.get(i);
+                let display = |value: &Option<Value>| {
+                    value
+                        .as_ref()
+                        .map_or(Value::Null, |value| Value::String(value.to_string()))
+                };
+                let row = vec![
+                    Value::String(table.name.clone()),
+                    Value::String(column.name.clone()),
+                    Value::Integer(statistics.row_count as i64),
+                    column_statistics.map_or(Value::Null, |c| Value::Float(c.null_fraction)),
+                    column_statistics
+                        .map_or(Value::Null, |c| Value::Integer(c.distinct_count as i64)),
+                    column_statistics.map_or(Value::Null, |c| display(&c.min)),
+                    column_statistics.map_or(Value::Null, |c| display(&c.max)),
+                    column_statistics.map_or(Value::Null, |c| {
+                        Value::String(c.histogram.iter().join(", "))
+                    }),
+                ];
+                rows.push(row.into_iter().map(Expr::Constant).collect());
+            }
+        }
+
+        scope.add_relation(
+            name,
+            STATISTICS_COLUMNS
+                .iter()
+                .map(|column| Some(column.to_string())),
+        )?;
+        let aliases = STATISTICS_COLUMNS
+            .iter()
+            .map(|column| ColumnLabel::Qualified(name.clone(), column.to_string()))
+            .collect::<Vec<_>>();
+        let source = match rows.is_empty() {
+            true => Plan::Nothing {
+                columns: aliases.clone(),
+            },
+            false => Plan::Values { rows },
+        };
+        Ok(Plan::Project {
+            columns: (0..aliases.len()).map(Expr::Column).collect(),
+            source: Box::new(source),
+            aliases,
+        })
+    }
+
     fn plan_join(
         &self,
         plan: Plan,
@@ -365,13 +982,12 @@ impl<'a, C: Catalog> Planner<'a, C> {
         log::debug!("Planning join: {}", join);
         let left = plan;
         let right = self.plan_scan(&join.relation, parent_scope)?;
-        let constraint = match &join.join_operator {
-            ast::JoinOperator::Join(c)
-            | ast::JoinOperator::Inner(c)
-            | ast::JoinOperator::Left(c)
-            | ast::JoinOperator::Right(c)
-            | ast::JoinOperator::LeftOuter(c)
-            | ast::JoinOperator::RightOuter(c) => c,
+        let (join_type, constraint) = match &join.join_operator {
+            ast::JoinOperator::Join(c) | ast::JoinOperator::Inner(c) => (JoinType::Inner, c),
+            ast::JoinOperator::Left(c) | ast::JoinOperator::LeftOuter(c) => (JoinType::Left, c),
+            ast::JoinOperator::Right(c) | ast::JoinOperator::RightOuter(c) => (JoinType::Right, c),
+            ast::JoinOperator::FullOuter(c) => (JoinType::Full, c),
+            ast::JoinOperator::CrossJoin => (JoinType::Inner, &ast::JoinConstraint::None),
             _ => {
                 return Err(Error::NotYetSupported(format!(
                     "Unsupported join operator: {:?}",
@@ -383,32 +999,129 @@ impl<'a, C: Catalog> Planner<'a, C> {
         let on = match constraint {
             ast::JoinConstraint::None => None,
             ast::JoinConstraint::On(expr) => {
-                let expr = Self::build_expr(expr, parent_scope)?;
+                let expr = self.build_expr(expr, parent_scope)?;
                 Some(expr)
             }
-            _ => {
-                return Err(Error::NotYetSupported(format!(
-                    "Unsupported join constraint: {:?}",
-                    constraint
-                )));
+            ast::JoinConstraint::Using(names) => {
+                let names = names
+                    .iter()
+                    .map(|name| match &name.0[..] {
+                        [ast::ObjectNamePart::Identifier(ident)] => Ok(ident.value.clone()),
+                        _ => Err(Error::InvalidColumnLabel(name.to_string())),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                return self.plan_join_using(left, right, &names, join_type, parent_scope);
+            }
+            ast::JoinConstraint::Natural => {
+                // Join on every column name that both sides have in common.
+                let left_cols = left.num_columns();
+                let mut names = Vec::new();
+                for i in 0..left_cols {
+                    let Some(name) = parent_scope.get_column_label(i)?.column_name() else {
+                        continue;
+                    };
+                    let shared = (left_cols..parent_scope.num_columns()).any(|j| {
+                        parent_scope
+                            .get_column_label(j)
+                            .is_ok_and(|label| label.column_name() == Some(name))
+                    });
+                    if shared && !names.contains(name) {
+                        names.push(name.clone());
+                    }
+                }
+                return self.plan_join_using(left, right, &names, join_type, parent_scope);
             }
         };
 
-        let join_type = match &join.join_operator {
-            ast::JoinOperator::Join(_) => JoinType::Inner,
-            ast::JoinOperator::Inner(_) => JoinType::Inner,
-            ast::JoinOperator::Left(_) => JoinType::Left,
-            ast::JoinOperator::Right(_) => JoinType::Right,
-            ast::JoinOperator::LeftOuter(_) => JoinType::Left,
-            ast::JoinOperator::RightOuter(_) => JoinType::Right,
-            _ => return Err(Error::NotYetSupported(join.to_string())),
+        Ok(Plan::Join {
+            left: Box::new(left),
+            right: Box::new(right),
+            on,
+            join_type,
+        })
+    }
+
+    /// Plans a join on the equality of the `names` columns of both sides, as for `USING`
+    /// and `NATURAL` joins. Each pair of joined columns collapses into a single output
+    /// column, placed before the remaining columns of both sides.
+    fn plan_join_using(
+        &self,
+        left: Plan,
+        right: Plan,
+        names: &[String],
+        join_type: JoinType,
+        scope: &mut Scope,
+    ) -> Result<Plan, Error> {
+        let left_cols = left.num_columns();
+        let labels: Vec<ColumnLabel> = (0..scope.num_columns())
+            .map(|i| scope.get_column_label(i).cloned())
+            .collect::<Result<_, _>>()?;
+        let find = |columns: std::ops::Range<usize>, name: &String| {
+            let mut found = columns.filter(|&i| labels[i].column_name() == Some(name));
+            match (found.next(), found.next()) {
+                (Some(index), None) => Ok(index),
+                (None, _) => Err(Error::ColumnNotFound(name.clone())),
+                (Some(_), Some(_)) => Err(Error::AmbiguousColumn(name.clone())),
+            }
         };
+        let mut keys = Vec::new();
+        for name in names {
+            keys.push((
+                find(0..left_cols, name)?,
+                find(left_cols..labels.len(), name)?,
+            ));
+        }
 
-        Ok(Plan::Join {
+        let on = keys
+            .iter()
+            .map(|&(l, r)| {
+                Expr::BinaryOp(
+                    Box::new(Expr::Column(l)),
+                    BinaryOp::Equal,
+                    Box::new(Expr::Column(r)),
+                )
+            })
+            .reduce(|a, b| Expr::BinaryOp(Box::new(a), BinaryOp::And, Box::new(b)));
+        let join = Plan::Join {
             left: Box::new(left),
             right: Box::new(right),
             on,
             join_type,
+        };
+
+        let mut output_scope = scope.spawn();
+        let mut columns = Vec::new();
+        let mut aliases = Vec::new();
+        for (name, &(l, r)) in names.iter().zip(&keys) {
+            columns.push(match join_type {
+                JoinType::Right => Expr::Column(r),
+                JoinType::Full => Expr::Coalesce(vec![Expr::Column(l), Expr::Column(r)]),
+                _ => Expr::Column(l),
+            });
+            let label = ColumnLabel::Unqualified(name.clone());
+            let index = output_scope.add_column(label.clone())?;
+            aliases.push(label);
+            // The collapsed column can still be referred to through either table.
+            for i in [l, r] {
+                if let Some(table) = labels[i].table_name() {
+                    output_scope.add_qualified_alias(table.clone(), name.clone(), index);
+                }
+            }
+        }
+        for (i, label) in labels.into_iter().enumerate() {
+            if keys.iter().any(|&(l, r)| i == l || i == r) {
+                continue;
+            }
+            columns.push(Expr::Column(i));
+            output_scope.add_column(label.clone())?;
+            aliases.push(label);
+        }
+        *scope = output_scope;
+
+        Ok(Plan::Project {
+            source: Box::new(join),
+            columns,
+            aliases,
         })
     }
 
@@ -418,7 +1131,7 @@ impl<'a, C: Catalog> Planner<'a, C> {
         scope: &Scope,
     ) -> Result<Option<Aggregate>, Error> {
         log::debug!("Building aggregate: {}", func);
-        if !is_aggregate(func) {
+        if !self.is_aggregate(func) {
             return Ok(None);
         }
         let args = aggregate_function_args(func)?;
@@ -427,85 +1140,335 @@ impl<'a, C: Catalog> Planner<'a, C> {
                 "Aggregate function with multiple arguments".to_string(),
             ));
         }
-        let expr = Self::build_expr(&args[0], scope)?;
-        let aggregate = match func.name.to_string().to_lowercase().as_str() {
+        let name = func.name.to_string().to_lowercase();
+        let expr = match &args[0] {
+            // COUNT(*) counts every row, so count a constant that is never NULL.
+            ast::Expr::Wildcard(_) if name == "count" => Expr::Constant(Value::Boolean(true)),
+            ast::Expr::Wildcard(_) => {
+                return Err(Error::InvalidSql(format!(
+                    "{}(*) is not allowed",
+                    func.name
+                )));
+            }
+            arg => self.build_expr(arg, scope)?,
+        };
+        let aggregate = match name.as_str() {
             "avg" => Aggregate::Average(expr),
             "count" => Aggregate::Count(expr),
             "max" => Aggregate::Max(expr),
             "min" => Aggregate::Min(expr),
             "sum" => Aggregate::Sum(expr),
-            _ => {
-                return Err(Error::NotYetSupported({
-                    format!("Unsupported aggregate function: {}", func.name)
-                }));
-            }
+            name => match self
+                .functions
+                .and_then(|functions| functions.aggregate(name))
+            {
+                Some(function) => Aggregate::User(function, expr),
+                None => {
+                    return Err(Error::NotYetSupported({
+                        format!("Unsupported aggregate function: {}", func.name)
+                    }));
+                }
+            },
         };
         Ok(Some(aggregate))
     }
 
+    /// Returns whether a function call is one of a built-in or registered aggregate.
+    fn is_aggregate(&self, func: &ast::Function) -> bool {
+        is_aggregate(func)
+            || self
+                .functions
+                .is_some_and(|functions| functions.aggregate(&func.name.to_string()).is_some())
+    }
+
     fn collect_aggregates(
         &self,
-        exprs: &[ast::SelectItem],
+        stmt: &ast::Select,
+        order_by: &[ast::OrderByExpr],
         scope: &Scope,
     ) -> Result<Vec<(ast::Function, Aggregate)>, Error> {
-        log::debug!(
-            "Collecting aggregates: {:?}",
-            exprs.iter().map(|e| e.to_string()).collect::<Vec<_>>()
-        );
+        log::debug!("Collecting aggregates: {}", stmt);
+        let mut functions = Vec::new();
+        for item in &stmt.projection {
+            find_aggregates(item, &|func| self.is_aggregate(func), &mut functions);
+        }
+        if let Some(having) = &stmt.having {
+            find_aggregates(having, &|func| self.is_aggregate(func), &mut functions);
+        }
+        for order in order_by {
+            find_aggregates(&order.expr, &|func| self.is_aggregate(func), &mut functions);
+        }
+
         let mut aggregates = Vec::new();
-        for item in exprs {
-            match item {
-                ast::SelectItem::UnnamedExpr(ast::Expr::Function(func)) => {
-                    if func.over.is_none()
-                        && let Some(agg) = self.build_aggregate(func, scope)?
-                    {
-                        aggregates.push((func.clone(), agg));
-                    }
-                }
-                ast::SelectItem::ExprWithAlias {
-                    expr: ast::Expr::Function(func),
-                    ..
-                } => {
-                    if func.over.is_none()
-                        && let Some(agg) = self.build_aggregate(func, scope)?
-                    {
-                        aggregates.push((func.clone(), agg));
-                    }
-                }
-                _ => {}
+        for func in functions {
+            if let Some(agg) = self.build_aggregate(&func, scope)? {
+                aggregates.push((func, agg));
             }
         }
 
         Ok(aggregates)
     }
 
-    fn build_expr(expr: &ast::Expr, scope: &Scope) -> Result<Expr, Error> {
+    fn build_expr(&self, expr: &ast::Expr, scope: &Scope) -> Result<Expr, Error> {
         log::debug!("Building expression: {}", expr);
+        if let Some(index) = scope.get_group_index(expr) {
+            return Ok(Expr::Column(index));
+        }
         match expr {
             ast::Expr::Function(func) => {
                 if let Some(agg) = scope.get_aggregate_index(func) {
-                    Ok(Expr::Column(agg))
-                } else {
-                    Err(Error::NotYetSupported(format!(
-                        "Unsupported function: {}",
-                        func
-                    )))
+                    return Ok(Expr::Column(agg));
+                } else if self.is_aggregate(func) {
+                    return Err(Error::AggregateNotFound(func.to_string()));
+                }
+                let args = aggregate_function_args(func)?
+                    .iter()
+                    .map(|arg| match arg {
+                        ast::Expr::Wildcard(_) => Err(Error::InvalidSql(func.to_string())),
+                        arg => self.build_expr(arg, scope),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                let name = func.name.to_string();
+                if name.eq_ignore_ascii_case("coalesce") {
+                    if args.is_empty() {
+                        return Err(Error::InvalidArgument(
+                            "COALESCE takes at least one argument".to_string(),
+                        ));
+                    }
+                    return Ok(Expr::Coalesce(args));
                 }
+                let function = ScalarFunction::from_name(&name)
+                    .or_else(|| {
+                        let function = self.functions?.function(&name)?;
+                        Some(ScalarFunction::User(function))
+                    })
+                    .ok_or(Error::FunctionNotFound(name))?;
+                Self::build_function(function, args)
+            }
+            ast::Expr::Extract { field, expr, .. } => {
+                let field = ast::Expr::value(ast::Value::SingleQuotedString(field.to_string()));
+                Self::build_function(
+                    ScalarFunction::Extract,
+                    vec![
+                        self.build_expr(&field, scope)?,
+                        self.build_expr(expr, scope)?,
+                    ],
+                )
+            }
+            ast::Expr::Ceil {
+                expr: arg,
+                field: ast::CeilFloorKind::DateTimeField(ast::DateTimeField::NoDateTime),
+            } => Self::build_function(ScalarFunction::Ceil, vec![self.build_expr(arg, scope)?]),
+            ast::Expr::Floor {
+                expr: arg,
+                field: ast::CeilFloorKind::DateTimeField(ast::DateTimeField::NoDateTime),
+            } => Self::build_function(ScalarFunction::Floor, vec![self.build_expr(arg, scope)?]),
+            ast::Expr::Substring {
+                expr,
+                substring_from,
+                substring_for,
+                ..
+            } => {
+                let mut args = vec![
+                    self.build_expr(expr, scope)?,
+                    match substring_from {
+                        Some(from) => self.build_expr(from, scope)?,
+                        None => Expr::Constant(Value::Integer(1)),
+                    },
+                ];
+                if let Some(length) = substring_for {
+                    args.push(self.build_expr(length, scope)?);
+                }
+                Self::build_function(ScalarFunction::Substr, args)
+            }
+            ast::Expr::Trim {
+                expr,
+                trim_where,
+                trim_what,
+                trim_characters,
+            } => {
+                let function = match trim_where {
+                    None | Some(ast::TrimWhereField::Both) => ScalarFunction::Trim,
+                    Some(ast::TrimWhereField::Leading) => ScalarFunction::LTrim,
+                    Some(ast::TrimWhereField::Trailing) => ScalarFunction::RTrim,
+                };
+                let mut args = vec![self.build_expr(expr, scope)?];
+                for characters in trim_what
+                    .iter()
+                    .map(AsRef::as_ref)
+                    .chain(trim_characters.iter().flatten())
+                {
+                    args.push(self.build_expr(characters, scope)?);
+                }
+                Self::build_function(function, args)
             }
             ast::Expr::BinaryOp { left, op, right } => {
-                let left = Self::build_expr(left, scope)?;
-                let right = Self::build_expr(right, scope)?;
+                let left = self.build_expr(left, scope)?;
+                let right = self.build_expr(right, scope)?;
                 Ok(Expr::BinaryOp(
                     Box::new(left),
                     op.try_into()?,
                     Box::new(right),
                 ))
             }
+            ast::Expr::UnaryOp { op, expr: inner } => {
+                let inner = self.build_expr(inner, scope)?;
+                let op = match op {
+                    ast::UnaryOperator::Plus => return Ok(inner),
+                    ast::UnaryOperator::Minus => UnaryOp::Negate,
+                    ast::UnaryOperator::Not => UnaryOp::Not,
+                    _ => {
+                        return Err(Error::NotYetSupported(format!(
+                            "Unsupported expression: {}",
+                            expr
+                        )));
+                    }
+                };
+                // Fold negative literals so that e.g. `id = -1` is still a constant key.
+                match inner {
+                    Expr::Constant(value) if op == UnaryOp::Negate => {
+                        Ok(Expr::Constant(op.apply(&value)?))
+                    }
+                    inner => Ok(Expr::UnaryOp(op, Box::new(inner))),
+                }
+            }
+            ast::Expr::Nested(expr) => self.build_expr(expr, scope),
+            ast::Expr::IsNull(expr) => {
+                Ok(Expr::IsNull(Box::new(self.build_expr(expr, scope)?), false))
+            }
+            ast::Expr::IsNotNull(expr) => {
+                Ok(Expr::IsNull(Box::new(self.build_expr(expr, scope)?), true))
+            }
+            ast::Expr::Between {
+                expr,
+                negated,
+                low,
+                high,
+            } => Ok(Expr::Between(
+                Box::new(self.build_expr(expr, scope)?),
+                Box::new(self.build_expr(low, scope)?),
+                Box::new(self.build_expr(high, scope)?),
+                *negated,
+            )),
+            ast::Expr::Like {
+                negated,
+                any: false,
+                expr: value,
+                pattern,
+                escape_char,
+            }
+            | ast::Expr::ILike {
+                negated,
+                any: false,
+                expr: value,
+                pattern,
+                escape_char,
+            } => {
+                let escape = match escape_char.as_deref().map(|c| c.chars().collect_vec()) {
+                    None => None,
+                    Some(chars) if chars.len() == 1 => Some(chars[0]),
+                    Some(_) => {
+                        return Err(Error::InvalidSql(format!(
+                            "ESCAPE must be a single character: {}",
+                            expr
+                        )));
+                    }
+                };
+                Ok(Expr::Like {
+                    expr: Box::new(self.build_expr(value, scope)?),
+                    pattern: Box::new(self.build_expr(pattern, scope)?),
+                    negated: *negated,
+                    case_insensitive: matches!(expr, ast::Expr::ILike { .. }),
+                    escape,
+                })
+            }
+            ast::Expr::Case {
+                operand,
+                conditions,
+                else_result,
+            } => Ok(Expr::Case {
+                operand: match operand {
+                    Some(operand) => Some(Box::new(self.build_expr(operand, scope)?)),
+                    None => None,
+                },
+                branches: conditions
+                    .iter()
+                    .map(|when| {
+                        Ok((
+                            self.build_expr(&when.condition, scope)?,
+                            self.build_expr(&when.result, scope)?,
+                        ))
+                    })
+                    .collect::<Result<_, Error>>()?,
+                otherwise: match else_result {
+                    Some(otherwise) => Some(Box::new(self.build_expr(otherwise, scope)?)),
+                    None => None,
+                },
+            }),
+            ast::Expr::Cast {
+                kind: ast::CastKind::Cast | ast::CastKind::DoubleColon,
+                expr,
+                data_type,
+                format: None,
+            } => Ok(Expr::Cast(
+                Box::new(self.build_expr(expr, scope)?),
+                DataType::try_from(data_type)?,
+            )),
+            ast::Expr::Value(ast::ValueWithSpan {
+                value: ast::Value::Placeholder(placeholder),
+                ..
+            }) => placeholder
+                .strip_prefix('$')
+                .and_then(|index| index.parse::<usize>().ok())
+                .filter(|index| *index > 0)
+                .map(|index| Expr::Parameter(index - 1))
+                .ok_or_else(|| Error::InvalidSql(format!("Invalid parameter: {}", placeholder))),
             ast::Expr::Value(v) => Ok(Expr::Constant(Value::try_from_ast(&v.value, None)?)),
+            ast::Expr::Subquery(query) => {
+                let plan = self.plan_query(query, Some(scope))?;
+                if plan.num_columns() != 1 {
+                    return Err(Error::InvalidSql(format!(
+                        "Subquery must return exactly one column: {}",
+                        query
+                    )));
+                }
+                Ok(Expr::Subquery(Box::new(plan)))
+            }
+            ast::Expr::Exists { subquery, negated } => {
+                let plan = self.plan_query(subquery, Some(scope))?;
+                Ok(Expr::Exists(Box::new(plan), *negated))
+            }
+            ast::Expr::InSubquery {
+                expr,
+                subquery,
+                negated,
+            } => {
+                let expr = self.build_expr(expr, scope)?;
+                let plan = self.plan_subquery(subquery, scope)?;
+                Ok(Expr::InSubquery(Box::new(expr), Box::new(plan), *negated))
+            }
+            ast::Expr::InList {
+                expr,
+                list,
+                negated,
+            } => {
+                let expr = self.build_expr(expr, scope)?;
+                let list = list
+                    .iter()
+                    .map(|item| self.build_expr(item, scope))
+                    .collect::<Result<_, _>>()?;
+                Ok(Expr::InList(Box::new(expr), list, *negated))
+            }
             ast::Expr::Identifier(i) => {
                 let name = i.value.clone();
                 if let Some(index) = scope.get_column_index(None, &name) {
                     Ok(Expr::Column(index))
+                } else if scope.is_ambiguous(&name) {
+                    Err(Error::AmbiguousColumn(name))
+                } else if scope.is_ungrouped(None, &name) {
+                    Err(Error::ColumnNotGrouped(name))
+                } else if let Some(index) = scope.get_outer_column_index(None, &name) {
+                    Ok(Expr::OuterColumn(index))
                 } else {
                     Err(Error::InvalidColumnLabel(name))
                 }
@@ -518,7 +1481,13 @@ impl<'a, C: Catalog> Planner<'a, C> {
                 let column = idents[1].value.clone();
                 match scope.get_column_index(Some(&table), &column) {
                     Some(index) => Ok(Expr::Column(index)),
-                    None => Err(Error::InvalidColumnLabel(format!("{}.{}", table, column))),
+                    None if scope.is_ungrouped(Some(&table), &column) => {
+                        Err(Error::ColumnNotGrouped(format!("{}.{}", table, column)))
+                    }
+                    None => match scope.get_outer_column_index(Some(&table), &column) {
+                        Some(index) => Ok(Expr::OuterColumn(index)),
+                        None => Err(Error::InvalidColumnLabel(format!("{}.{}", table, column))),
+                    },
                 }
             }
             _ => Err(Error::NotYetSupported(format!(
@@ -527,4 +1496,779 @@ impl<'a, C: Catalog> Planner<'a, C> {
             ))),
         }
     }
+
+    /// Builds a call of a scalar function. The types of its arguments are checked once the
+    /// plan is complete, but the arity and constant date fields are checked right away.
+    fn build_function(function: ScalarFunction, args: Vec<Expr>) -> Result<Expr, Error> {
+        function.return_type(&vec![None; args.len()])?;
+        if let ScalarFunction::Extract | ScalarFunction::DateTrunc = function
+            && let Some(Expr::Constant(Value::String(field))) = args.first()
+        {
+            DateField::parse(field)?;
+        }
+        Ok(Expr::Function(function, args))
+    }
+
+    /// Splits an expression into its top-level `AND` conjuncts.
+    fn split_conjuncts(expr: &ast::Expr) -> Vec<&ast::Expr> {
+        match expr {
+            ast::Expr::BinaryOp {
+                left,
+                op: ast::BinaryOperator::And,
+                right,
+            } => {
+                let mut conjuncts = Self::split_conjuncts(left);
+                conjuncts.extend(Self::split_conjuncts(right));
+                conjuncts
+            }
+            ast::Expr::Nested(expr) => Self::split_conjuncts(expr),
+            expr => vec![expr],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlparser::{dialect::GenericDialect, parser::Parser};
+
+    use super::*;
+    use crate::{
+        engine::Engine,
+        exec::session::Session,
+        types::value::Row,
+        util::tests::{execute, query, test_engine, test_engine_with_data},
+    };
+
+    fn plan(engine: &impl Engine, sql: &str) -> Plan {
+        let stmts = Parser::parse_sql(&GenericDialect {}, sql).unwrap();
+        let txn = engine.begin().unwrap();
+        Planner::new(&txn).plan(&stmts[0]).unwrap()
+    }
+
+    #[test]
+    fn test_having() {
+        let engine = test_engine_with_data();
+        let mut session = Session::new(&engine);
+        let rows = query(
+            &mut session,
+            "SELECT u.username, COUNT(o.order_id) AS n
+            FROM users u JOIN orders o ON u.user_id = o.user_id
+            GROUP BY u.username
+            HAVING COUNT(o.order_id) > 3
+            ORDER BY n DESC, u.username",
+        );
+        assert_eq!(
+            rows,
+            vec![
+                Row::from(("alice", 5)),
+                Row::from(("bob", 4)),
+                Row::from(("carol", 4)),
+                Row::from(("dave", 4)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_aggregate_in_expression() {
+        let engine = test_engine_with_data();
+        let mut session = Session::new(&engine);
+        let rows = query(&mut session, "SELECT SUM(quantity) * 2 FROM orders");
+        assert_eq!(rows, vec![Row::from([Value::Integer(42)])]);
+    }
+
+    #[test]
+    fn test_order_by_hidden_aggregate() {
+        let engine = test_engine_with_data();
+        let mut session = Session::new(&engine);
+        let rows = query(
+            &mut session,
+            "SELECT u.username
+            FROM users u JOIN orders o ON u.user_id = o.user_id
+            GROUP BY u.username
+            ORDER BY COUNT(o.order_id), u.username",
+        );
+        assert_eq!(
+            rows,
+            vec![
+                Row::from(("eve",)),
+                Row::from(("bob",)),
+                Row::from(("carol",)),
+                Row::from(("dave",)),
+                Row::from(("alice",)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_order_by_ambiguous() {
+        let engine = test_engine_with_data();
+        let mut session = Session::new(&engine);
+        let result = execute(
+            &mut session,
+            "SELECT u.user_id AS id, o.order_id AS id
+            FROM users u JOIN orders o ON u.user_id = o.user_id
+            ORDER BY id",
+        );
+        assert_eq!(result, Err(Error::AmbiguousColumn("id".to_string())));
+
+        let result = execute(
+            &mut session,
+            "SELECT u.username FROM users u JOIN orders o ON u.user_id = o.user_id
+            ORDER BY user_id",
+        );
+        assert_eq!(result, Err(Error::AmbiguousColumn("user_id".to_string())));
+    }
+
+    #[test]
+    fn test_group_by_expression() {
+        let engine = test_engine_with_data();
+        let mut session = Session::new(&engine);
+        let rows = query(
+            &mut session,
+            "SELECT quantity * 10, COUNT(order_id) FROM orders
+            GROUP BY quantity * 10
+            ORDER BY quantity * 10 DESC",
+        );
+        assert_eq!(rows, vec![Row::from((20, 1)), Row::from((10, 19))]);
+    }
+
+    #[test]
+    fn test_ungrouped_column() {
+        let engine = test_engine_with_data();
+        let mut session = Session::new(&engine);
+        let result = execute(
+            &mut session,
+            "SELECT email, COUNT(user_id) FROM users GROUP BY username",
+        );
+        assert_eq!(result, Err(Error::ColumnNotGrouped("email".to_string())));
+
+        let result = execute(
+            &mut session,
+            "SELECT o.order_id, COUNT(o.order_id) FROM orders o GROUP BY o.user_id",
+        );
+        assert_eq!(
+            result,
+            Err(Error::ColumnNotGrouped("o.order_id".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_in_subquery_semi_join() {
+        let engine = test_engine_with_data();
+        let plan = plan(
+            &engine,
+            "SELECT username FROM users WHERE user_id IN (SELECT user_id FROM orders)",
+        );
+        let Plan::Query(project) = plan else {
+            panic!("expected a query plan");
+        };
+        let Plan::Project { source, .. } = *project else {
+            panic!("expected a projection");
+        };
+        assert!(matches!(
+            *source,
+            Plan::Join {
+                join_type: JoinType::Semi,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_cte() {
+        let engine = test_engine_with_data();
+        let mut session = Session::new(&engine);
+        let rows = query(
+            &mut session,
+            "WITH expensive AS (SELECT product_id FROM products WHERE price > 100)
+            SELECT p.product_name FROM products p
+            WHERE p.product_id IN (SELECT product_id FROM expensive)
+            ORDER BY p.product_name",
+        );
+        assert_eq!(
+            rows,
+            vec![
+                Row::from(("Chair",)),
+                Row::from(("Desk",)),
+                Row::from(("Laptop",)),
+                Row::from(("Microphone",)),
+                Row::from(("Monitor",)),
+            ]
+        );
+
+        let rows = query(
+            &mut session,
+            "WITH counts(uid, n) AS (SELECT user_id, COUNT(*) FROM orders GROUP BY user_id)
+            SELECT u.username, c.n FROM users u JOIN counts c ON c.uid = u.user_id
+            WHERE c.n > 3
+            ORDER BY u.username",
+        );
+        assert_eq!(
+            rows,
+            vec![
+                Row::from(("alice", 5)),
+                Row::from(("bob", 4)),
+                Row::from(("carol", 4)),
+                Row::from(("dave", 4)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_recursive_cte() {
+        let engine = test_engine_with_data();
+        let mut session = Session::new(&engine);
+        let rows = query(
+            &mut session,
+            "WITH RECURSIVE t(n) AS (SELECT 1 UNION ALL SELECT n + 1 FROM t WHERE n < 5)
+            SELECT n FROM t",
+        );
+        assert_eq!(rows, (1..=5).map(|n| Row::from((n,))).collect::<Vec<_>>());
+
+        execute(
+            &mut session,
+            "CREATE TABLE employees (id INT PRIMARY KEY, name VARCHAR, manager_id INT);
+            INSERT INTO employees VALUES
+                (1, 'ceo', NULL), (2, 'a', 1), (3, 'b', 1), (4, 'c', 2), (5, 'd', 4), (6, 'e', 3);",
+        )
+        .unwrap();
+        let rows = query(
+            &mut session,
+            "WITH RECURSIVE reports(id) AS (
+                SELECT id FROM employees WHERE id = 2
+                UNION
+                SELECT e.id FROM employees e JOIN reports r ON e.manager_id = r.id
+            )
+            SELECT id FROM reports ORDER BY id",
+        );
+        assert_eq!(
+            rows,
+            vec![Row::from((2,)), Row::from((4,)), Row::from((5,))]
+        );
+    }
+
+    #[test]
+    fn test_hash_join() {
+        let engine = test_engine_with_data();
+        let hash_plan = plan(
+            &engine,
+            "SELECT o.order_id, u.username
+            FROM orders o LEFT JOIN users u ON u.user_id = o.user_id",
+        );
+        let Plan::Query(project) = hash_plan else {
+            panic!("expected a query plan");
+        };
+        let Plan::Project { source, .. } = *project else {
+            panic!("expected a projection");
+        };
+        let Plan::HashJoin {
+            keys, join_type, ..
+        } = *source
+        else {
+            panic!("expected a hash join");
+        };
+        assert_eq!(keys, vec![(1, 0)]);
+        assert_eq!(join_type, JoinType::Left);
+
+        let loop_plan = plan(
+            &engine,
+            "SELECT o.order_id FROM orders o JOIN users u ON o.user_id < u.user_id",
+        );
+        let Plan::Query(project) = loop_plan else {
+            panic!("expected a query plan");
+        };
+        let Plan::Project { source, .. } = *project else {
+            panic!("expected a projection");
+        };
+        assert!(matches!(*source, Plan::Join { .. }));
+    }
+
+    #[test]
+    fn test_merge_join() {
+        let engine = test_engine_with_data();
+        let merge_plan = plan(
+            &engine,
+            "SELECT u.username, o.order_id
+            FROM orders o JOIN users u ON o.order_id = u.user_id",
+        );
+        let Plan::Query(project) = merge_plan else {
+            panic!("expected a query plan");
+        };
+        let Plan::Project { source, .. } = *project else {
+            panic!("expected a projection");
+        };
+        let Plan::MergeJoin { keys, .. } = *source else {
+            panic!("expected a merge join");
+        };
+        assert_eq!(keys, vec![(0, 0)]);
+
+        let mut session = Session::new(&engine);
+        let rows = query(
+            &mut session,
+            "SELECT u.username, o.order_id
+            FROM orders o JOIN users u ON o.order_id = u.user_id",
+        );
+        assert_eq!(
+            rows,
+            vec![
+                Row::from(("alice", 1)),
+                Row::from(("bob", 2)),
+                Row::from(("carol", 3)),
+                Row::from(("dave", 4)),
+                Row::from(("eve", 5)),
+            ]
+        );
+    }
+
+    /// Returns the node below the projection and filter, if any, of a single-table select.
+    fn scan_node(plan: Plan) -> Plan {
+        let Plan::Query(project) = plan else {
+            panic!("expected a query plan");
+        };
+        let Plan::Project { source, .. } = *project else {
+            panic!("expected a projection");
+        };
+        match *source {
+            Plan::Filter { source, .. } => *source,
+            source => source,
+        }
+    }
+
+    #[test]
+    fn test_index_lookup() {
+        let engine = test_engine_with_data();
+        let lookup = scan_node(plan(&engine, "SELECT * FROM orders WHERE user_id = 3"));
+        let Plan::IndexLookup { column, values, .. } = lookup else {
+            panic!("expected an index lookup, got {lookup:?}");
+        };
+        assert_eq!(column, "user_id");
+        assert_eq!(values, vec![Value::Integer(3)]);
+
+        let lookup = scan_node(plan(
+            &engine,
+            "SELECT * FROM orders WHERE quantity = 1 AND order_id IN (3, 1, 99, 3)",
+        ));
+        let Plan::KeyLookup { keys, .. } = lookup else {
+            panic!("expected a key lookup, got {lookup:?}");
+        };
+        assert_eq!(
+            keys,
+            vec![Value::Integer(1), Value::Integer(3), Value::Integer(99)]
+        );
+
+        // Columns without an index are still scanned.
+        let scan = scan_node(plan(&engine, "SELECT * FROM orders WHERE quantity = 2"));
+        assert!(matches!(
+            scan,
+            Plan::Scan {
+                filter: Some(_),
+                ..
+            }
+        ));
+
+        let mut session = Session::new(&engine);
+        let rows = query(
+            &mut session,
+            "SELECT order_id FROM orders WHERE user_id IN (3, NULL) OR order_id = 1",
+        );
+        assert_eq!(rows.len(), 5);
+        let rows = query(
+            &mut session,
+            "SELECT order_id FROM orders WHERE user_id = 3 AND order_id > 10",
+        );
+        let scanned = query(
+            &mut session,
+            "SELECT order_id FROM orders WHERE user_id + 0 = 3 AND order_id > 10",
+        );
+        assert!(!rows.is_empty());
+        assert_eq!(rows, scanned);
+    }
+
+    #[test]
+    fn test_scalar_functions() {
+        let engine = test_engine_with_data();
+        let mut session = Session::new(&engine);
+        let rows = query(
+            &mut session,
+            "SELECT UPPER(username), LENGTH(email), SUBSTRING(email FROM 1 FOR 3),
+                CONCAT(username, '#', user_id), REPLACE(email, '@example.com', '')
+            FROM users WHERE LOWER(username) = 'alice'",
+        );
+        assert_eq!(
+            rows,
+            vec![Row::from(("ALICE", 17, "ali", "alice#1", "alice"))]
+        );
+
+        let rows = query(
+            &mut session,
+            "SELECT ROUND(price), FLOOR(price), CEIL(price), ABS(0 - price), MOD(product_id, 3)
+            FROM products WHERE product_id = 2",
+        );
+        assert_eq!(
+            rows,
+            vec![Row::from([
+                Value::Float(26.0),
+                Value::Float(25.0),
+                Value::Float(26.0),
+                Value::Float(25.5),
+                Value::Integer(2),
+            ])]
+        );
+
+        let rows = query(
+            &mut session,
+            "SELECT EXTRACT(MONTH FROM order_date), DATE_TRUNC('month', order_date),
+                COALESCE(NULLIF(quantity, 1), 0)
+            FROM orders WHERE order_id = 2",
+        );
+        assert_eq!(
+            rows,
+            vec![Row::from([
+                Value::Integer(6),
+                Value::try_from_ast(
+                    &ast::Value::SingleQuotedString("2024-06-01".to_string()),
+                    Some(DataType::Date)
+                )
+                .unwrap(),
+                Value::Integer(2),
+            ])]
+        );
+
+        let rows = query(&mut session, "SELECT CURRENT_DATE, TRIM('  x  ')");
+        assert!(matches!(rows[0][0], Value::Date(_)));
+        assert_eq!(rows[0][1], Value::String("x".to_string()));
+    }
+
+    #[test]
+    fn test_scalar_function_errors() {
+        let engine = test_engine_with_data();
+        let mut session = Session::new(&engine);
+        for sql in [
+            "SELECT LOWER(user_id) FROM users",
+            "SELECT LOWER(username, email) FROM users",
+            "SELECT SUBSTR(username, email) FROM users",
+            "SELECT * FROM orders WHERE ABS(order_date) > 1",
+            "SELECT EXTRACT(HOUR FROM order_date) FROM orders",
+            "SELECT NULLIF(username, user_id) FROM users",
+            "SELECT COALESCE(username, user_id) FROM users",
+        ] {
+            let result = execute(&mut session, sql);
+            assert!(
+                matches!(result, Err(Error::InvalidArgument(_))),
+                "{sql}: {result:?}"
+            );
+        }
+        assert_eq!(
+            execute(&mut session, "SELECT COALESCE()"),
+            Err(Error::InvalidArgument(
+                "COALESCE takes at least one argument".to_string()
+            ))
+        );
+        assert_eq!(
+            execute(&mut session, "SELECT NO_SUCH_FUNCTION(1)"),
+            Err(Error::FunctionNotFound("NO_SUCH_FUNCTION".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_predicates() {
+        let engine = test_engine_with_data();
+        let mut session = Session::new(&engine);
+        let rows = query(
+            &mut session,
+            "SELECT product_id FROM products
+            WHERE price BETWEEN 45 AND 150 AND NOT (product_id > 7)
+            ORDER BY product_id",
+        );
+        assert_eq!(
+            rows,
+            vec![
+                Row::from((3,)),
+                Row::from((5,)),
+                Row::from((6,)),
+                Row::from((7,))
+            ]
+        );
+
+        let usernames = |session: &mut Session<'_, _>, predicate: &str| {
+            query(
+                session,
+                &format!("SELECT username FROM users WHERE {predicate} ORDER BY username"),
+            )
+            .into_iter()
+            .map(|row| row[0].clone())
+            .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            usernames(&mut session, "username LIKE '_a%'"),
+            vec![Value::from("carol"), Value::from("dave")]
+        );
+        assert_eq!(
+            usernames(&mut session, "username NOT LIKE '%e'"),
+            vec![Value::from("bob"), Value::from("carol")]
+        );
+        assert_eq!(
+            usernames(&mut session, "username ILIKE 'A%'"),
+            vec![Value::from("alice")]
+        );
+        assert_eq!(
+            usernames(
+                &mut session,
+                "user_id NOT BETWEEN 2 AND 4 AND email IS NOT NULL"
+            ),
+            vec![Value::from("alice"), Value::from("eve")]
+        );
+
+        let rows = query(
+            &mut session,
+            "SELECT NULL IS NULL, 1 IS NULL, NOT NULL, NULL BETWEEN 1 AND 2,
+                'a%b' LIKE 'a!%b' ESCAPE '!', 'axb' LIKE 'a!%b' ESCAPE '!', NULL LIKE 'a'",
+        );
+        assert_eq!(
+            rows,
+            vec![Row::from([
+                Value::Boolean(true),
+                Value::Boolean(false),
+                Value::Null,
+                Value::Null,
+                Value::Boolean(true),
+                Value::Boolean(false),
+                Value::Null,
+            ])]
+        );
+
+        let rows = query(
+            &mut session,
+            "SELECT 'A%' ILIKE 'aX%' ESCAPE 'X', 'Ab' ILIKE 'aX%' ESCAPE 'X',
+                'ax' ILIKE 'AXX' ESCAPE 'X'",
+        );
+        assert_eq!(
+            rows,
+            vec![Row::from([
+                Value::Boolean(true),
+                Value::Boolean(false),
+                Value::Boolean(true),
+            ])]
+        );
+    }
+
+    #[test]
+    fn test_three_valued_logic() {
+        let engine = test_engine_with_data();
+        let mut session = Session::new(&engine);
+        let rows = query(
+            &mut session,
+            "SELECT NOT NULL, FALSE AND NULL, TRUE AND NULL, TRUE OR NULL, FALSE OR NULL,
+                NOT (TRUE AND NULL)",
+        );
+        assert_eq!(
+            rows,
+            vec![Row::from([
+                Value::Null,
+                Value::Boolean(false),
+                Value::Null,
+                Value::Boolean(true),
+                Value::Null,
+                Value::Null,
+            ])]
+        );
+
+        // For alice the predicate is NULL, which doesn't satisfy the filter either.
+        let rows = query(
+            &mut session,
+            "SELECT username FROM users WHERE NOT (user_id <> 1 OR NULL)",
+        );
+        assert!(rows.is_empty());
+        let rows = query(
+            &mut session,
+            "SELECT username FROM users WHERE NOT (user_id = 1 AND NULL) ORDER BY username",
+        );
+        assert_eq!(
+            rows,
+            vec![
+                Row::from(("bob",)),
+                Row::from(("carol",)),
+                Row::from(("dave",)),
+                Row::from(("eve",)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_null_operands() {
+        let engine = test_engine();
+        let mut session = Session::new(&engine);
+        execute(
+            &mut session,
+            "CREATE TABLE t (id INT PRIMARY KEY, x INT, y FLOAT);
+            INSERT INTO t VALUES (1, 3, 1.5), (2, 7, NULL), (3, NULL, 2.5)",
+        )
+        .unwrap();
+
+        let rows = query(
+            &mut session,
+            "SELECT NULL = NULL, NULL <> 1, NULL > 5, 5 <= NULL, NULL + 1, 2 - NULL,
+                NULL * 3, 4 / NULL, NULL % 2, 5 % NULL",
+        );
+        assert_eq!(rows, vec![Row::from(vec![Value::Null; 10])]);
+
+        for vectorized in [false, true] {
+            execute(&mut session, &format!("SET vectorized = {vectorized}")).unwrap();
+            let ids = |session: &mut Session<'_, _>, predicate: &str| {
+                query(
+                    session,
+                    &format!("SELECT id FROM t WHERE {predicate} ORDER BY id"),
+                )
+                .into_iter()
+                .map(|row| row[0].clone())
+                .collect::<Vec<_>>()
+            };
+            // Comparisons with NULL are NULL, so negating them doesn't satisfy a filter.
+            assert_eq!(ids(&mut session, "NOT (x > 5)"), vec![Value::Integer(1)]);
+            assert_eq!(
+                ids(&mut session, "NOT (x BETWEEN 0 AND 5)"),
+                vec![Value::Integer(2)]
+            );
+            assert_eq!(ids(&mut session, "y <> 1.5"), vec![Value::Integer(3)]);
+            assert!(ids(&mut session, "x = NULL").is_empty());
+            assert!(ids(&mut session, "id = NULL").is_empty());
+
+            let rows = query(
+                &mut session,
+                "SELECT id, x + 1, x % 2, y * 2 FROM t ORDER BY id",
+            );
+            assert_eq!(
+                rows,
+                vec![
+                    Row::from((1, 4, 1, 3.0)),
+                    Row::from(vec![
+                        Value::Integer(2),
+                        Value::Integer(8),
+                        Value::Integer(1),
+                        Value::Null,
+                    ]),
+                    Row::from(vec![
+                        Value::Integer(3),
+                        Value::Null,
+                        Value::Null,
+                        Value::Float(5.0),
+                    ]),
+                ]
+            );
+        }
+
+        // The sum over no rows is NULL, and so is anything computed from it.
+        let rows = query(&mut session, "SELECT SUM(x) * 2 FROM t WHERE id < 0");
+        assert_eq!(rows, vec![Row::from([Value::Null])]);
+    }
+
+    #[test]
+    fn test_operators() {
+        let engine = test_engine_with_data();
+        let mut session = Session::new(&engine);
+        let rows = query(
+            &mut session,
+            "SELECT product_id % 3, -price, -(product_id + 1), +product_id, (1 + 2) * 3
+            FROM products WHERE product_id = 2",
+        );
+        assert_eq!(
+            rows,
+            vec![Row::from([
+                Value::Integer(2),
+                Value::Float(-25.5),
+                Value::Integer(-3),
+                Value::Integer(2),
+                Value::Integer(9),
+            ])]
+        );
+        let rows = query(
+            &mut session,
+            "SELECT username FROM users WHERE user_id = -(-3)",
+        );
+        assert_eq!(rows, vec![Row::from(("carol",))]);
+        assert_eq!(
+            execute(&mut session, "SELECT product_id % 0 FROM products"),
+            Err(Error::DivisionByZero)
+        );
+    }
+
+    #[test]
+    fn test_case_and_cast() {
+        let engine = test_engine_with_data();
+        let mut session = Session::new(&engine);
+        let rows = query(
+            &mut session,
+            "SELECT product_id,
+                CASE WHEN price < 50 THEN 'cheap' WHEN price < 250 THEN 'mid' ELSE 'pricey' END
+            FROM products WHERE product_id <= 4 ORDER BY product_id",
+        );
+        assert_eq!(
+            rows,
+            vec![
+                Row::from((1, "pricey")),
+                Row::from((2, "cheap")),
+                Row::from((3, "cheap")),
+                Row::from((4, "pricey")),
+            ]
+        );
+
+        let rows = query(
+            &mut session,
+            "SELECT CASE user_id WHEN 1 THEN 'one' WHEN 2 THEN 'two' END
+            FROM users WHERE user_id IN (1, 3) ORDER BY user_id",
+        );
+        assert_eq!(rows, vec![Row::from(("one",)), Row::from([Value::Null])]);
+
+        let rows = query(
+            &mut session,
+            "SELECT CAST(price AS INT), CAST(product_id AS FLOAT), CAST(product_id AS VARCHAR),
+                CAST('12' AS INT), CAST('yes' AS BOOLEAN), product_id::VARCHAR(1)
+            FROM products WHERE product_id = 2",
+        );
+        assert_eq!(
+            rows,
+            vec![Row::from([
+                Value::Integer(26),
+                Value::Float(2.0),
+                Value::from("2"),
+                Value::Integer(12),
+                Value::Boolean(true),
+                Value::from("2"),
+            ])]
+        );
+        let rows = query(
+            &mut session,
+            "SELECT order_id FROM orders WHERE order_date = CAST('2024-06-02' AS DATE)",
+        );
+        assert_eq!(rows, vec![Row::from((2,))]);
+    }
+
+    #[test]
+    fn test_expression_errors() {
+        let engine = test_engine_with_data();
+        let mut session = Session::new(&engine);
+        for sql in [
+            "SELECT * FROM users WHERE user_id LIKE '1%'",
+            "SELECT CASE WHEN user_id = 1 THEN username ELSE user_id END FROM users",
+            "SELECT NOT user_id FROM users",
+            "SELECT * FROM users WHERE NOT 'a'",
+            "SELECT * FROM users WHERE user_id = 1 AND 1",
+            "SELECT * FROM users WHERE username OR user_id = 1",
+            "SELECT -username FROM users",
+        ] {
+            let result = execute(&mut session, sql);
+            assert!(
+                matches!(result, Err(Error::InvalidArgument(_))),
+                "{sql}: {result:?}"
+            );
+        }
+        assert!(matches!(
+            execute(&mut session, "SELECT CAST(username AS INT) FROM users"),
+            Err(Error::InvalidCast { .. })
+        ));
+        assert!(matches!(
+            execute(&mut session, "SELECT 'a' LIKE 'a' ESCAPE 'ab'"),
+            Err(Error::InvalidSql(_))
+        ));
+    }
 }
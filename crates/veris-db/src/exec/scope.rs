@@ -7,15 +7,28 @@ use crate::{
     types::{schema::Table, value::ColumnLabel},
 };
 
-use super::aggregate::aggregate_function_args;
+use super::{aggregate::aggregate_function_args, plan::Plan};
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct Scope {
     columns: Vec<ColumnLabel>,
     tables: HashSet<String>,
     qualified: HashMap<(String, String), usize>,
     unqualified: HashMap<String, Vec<usize>>,
     aggregates: HashMap<ast::Function, usize>,
+    groups: HashMap<ast::Expr, usize>,
+    /// The scope of the rows fed into an aggregation, if this scope is its output.
+    input: Option<Box<Scope>>,
+    /// The scope of the enclosing query, if this is the scope of a subquery.
+    outer: Option<Box<Scope>>,
+    ctes: HashMap<String, Cte>,
+}
+
+/// A common table expression, which can be referenced as a relation by name.
+#[derive(Debug, Clone)]
+pub struct Cte {
+    pub plan: Plan,
+    pub columns: Vec<Option<String>>,
 }
 
 impl Scope {
@@ -25,9 +38,32 @@ impl Scope {
         Ok(scope)
     }
 
+    /// Creates the scope of a subquery, which can refer to the columns of `outer`.
+    pub fn with_outer(outer: &Scope) -> Self {
+        Self {
+            outer: Some(Box::new(outer.clone())),
+            ctes: outer.ctes.clone(),
+            ..Default::default()
+        }
+    }
+
     pub fn spawn(&self) -> Self {
         Self {
             tables: self.tables.clone(),
+            outer: self.outer.clone(),
+            ctes: self.ctes.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// Spawns the scope of an aggregation over this scope. Only group expressions and
+    /// aggregates are resolvable in it.
+    pub fn spawn_grouped(&self) -> Self {
+        Self {
+            tables: self.tables.clone(),
+            input: Some(Box::new(self.clone())),
+            outer: self.outer.clone(),
+            ctes: self.ctes.clone(),
             ..Default::default()
         }
     }
@@ -37,8 +73,15 @@ impl Scope {
             self.tables.insert(table);
         }
         let offset = self.columns.len();
-        for label in scope.columns {
-            self.add_column(label)?;
+        self.columns.extend(scope.columns);
+        for (key, index) in scope.qualified {
+            self.qualified.insert(key, index + offset);
+        }
+        for (name, indices) in scope.unqualified {
+            self.unqualified
+                .entry(name)
+                .or_default()
+                .extend(indices.into_iter().map(|index| index + offset));
         }
         for (agg, index) in scope.aggregates {
             self.aggregates.entry(agg).or_insert(index + offset);
@@ -49,12 +92,25 @@ impl Scope {
 
     pub fn add_table(&mut self, table: &Table, alias: Option<&String>) -> Result<(), Error> {
         let name = alias.unwrap_or(&table.name);
+        self.add_relation(name, table.columns.iter().map(|c| Some(c.name.clone())))
+    }
+
+    /// Adds a named relation, such as a table or CTE. Unnamed columns are added, but can
+    /// not be referenced by name.
+    pub fn add_relation(
+        &mut self,
+        name: &String,
+        columns: impl IntoIterator<Item = Option<String>>,
+    ) -> Result<(), Error> {
         if self.tables.contains(name) {
             return Err(Error::DuplicateTable(name.clone()));
         }
 
-        for column in &table.columns {
-            let label = ColumnLabel::Qualified(name.clone(), column.name.clone());
+        for column in columns {
+            let label = match column {
+                Some(column) => ColumnLabel::Qualified(name.clone(), column),
+                None => ColumnLabel::None,
+            };
             self.add_column(label)?;
         }
 
@@ -63,6 +119,14 @@ impl Scope {
         Ok(())
     }
 
+    pub fn add_cte(&mut self, name: String, cte: Cte) {
+        self.ctes.insert(name, cte);
+    }
+
+    pub fn get_cte(&self, name: &str) -> Option<&Cte> {
+        self.ctes.get(name)
+    }
+
     pub fn add_column(&mut self, label: ColumnLabel) -> Result<usize, Error> {
         let index = self.columns.len();
 
@@ -82,6 +146,15 @@ impl Scope {
         Ok(index)
     }
 
+    /// Makes the column at `index` resolvable as `table.name` as well.
+    pub fn add_qualified_alias(&mut self, table: String, name: String, index: usize) {
+        self.qualified.insert((table, name), index);
+    }
+
+    pub fn num_columns(&self) -> usize {
+        self.columns.len()
+    }
+
     pub fn add_aggregate(&mut self, expr: ast::Function) -> Result<usize, Error> {
         if self.aggregates.contains_key(&expr) {
             return Err(Error::DuplicateAggregate(expr.to_string()));
@@ -104,7 +177,9 @@ impl Scope {
             ColumnLabel::None
         };
 
-        let index = self.add_column(label)?;
+        // The label is only used for display; the aggregate column is not resolvable by name.
+        let index = self.columns.len();
+        self.columns.push(label);
         self.aggregates.insert(expr, index);
         Ok(index)
     }
@@ -113,6 +188,24 @@ impl Scope {
         self.aggregates.get(func).cloned()
     }
 
+    pub fn add_group(&mut self, expr: ast::Expr, label: ColumnLabel) -> Result<usize, Error> {
+        let index = self.add_column(label)?;
+        self.groups.entry(expr).or_insert(index);
+        Ok(index)
+    }
+
+    pub fn get_group_index(&self, expr: &ast::Expr) -> Option<usize> {
+        self.groups.get(expr).cloned()
+    }
+
+    /// Returns whether the column is visible to the input of this aggregation scope, but
+    /// neither grouped nor aggregated.
+    pub fn is_ungrouped(&self, table: Option<&String>, name: &String) -> bool {
+        self.input
+            .as_ref()
+            .is_some_and(|input| input.get_column_index(table, name).is_some())
+    }
+
     pub fn get_column_index(&self, table: Option<&String>, name: &String) -> Option<usize> {
         if self.columns.is_empty() {
             return None;
@@ -135,6 +228,19 @@ impl Scope {
         None
     }
 
+    /// Returns whether an unqualified column name refers to more than one column.
+    pub fn is_ambiguous(&self, name: &String) -> bool {
+        self.unqualified
+            .get(name)
+            .is_some_and(|indices| indices.len() > 1)
+    }
+
+    /// Resolves a column of the enclosing query. Only the immediately enclosing query is
+    /// considered.
+    pub fn get_outer_column_index(&self, table: Option<&String>, name: &String) -> Option<usize> {
+        self.outer.as_ref()?.get_column_index(table, name)
+    }
+
     pub fn get_column_label(&self, index: usize) -> Result<&ColumnLabel, Error> {
         self.columns
             .get(index)
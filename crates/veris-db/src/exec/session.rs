@@ -1,4 +1,4 @@
-use std::fmt;
+use std::{collections::HashMap, fmt, sync::Arc};
 
 use serde::{Deserialize, Serialize};
 use sqlparser::ast;
@@ -8,11 +8,17 @@ use crate::{
     error::Error,
     types::{
         schema::Table,
-        value::{ColumnLabel, Row},
+        value::{ColumnLabel, DataType, Row, Value},
     },
 };
 
-use super::planner::Planner;
+use super::{
+    aggregate::UserAggregate,
+    executor::ExecutionOptions,
+    function::{FunctionRegistry, UserFunction},
+    plan::Plan,
+    planner::Planner,
+};
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub enum StatementResult {
@@ -32,6 +38,10 @@ pub enum StatementResult {
         rows: Vec<Row>,
         columns: Vec<ColumnLabel>,
     },
+    Set(String),
+    Analyze(Vec<String>),
+    Prepare(String),
+    Deallocate(String),
 }
 
 impl fmt::Display for StatementResult {
@@ -48,13 +58,74 @@ impl fmt::Display for StatementResult {
             StatementResult::Delete(count) => write!(f, "Deleted {} rows", count),
             StatementResult::Insert(count) => write!(f, "Inserted {} rows", count),
             StatementResult::Query { .. } => write!(f, "Query ran"),
+            StatementResult::Set(name) => write!(f, "Set {}", name),
+            StatementResult::Analyze(tables) => write!(f, "Analyzed {}", tables.join(", ")),
+            StatementResult::Prepare(name) => write!(f, "Prepared statement {}", name),
+            StatementResult::Deallocate(name) => write!(f, "Deallocated statement {}", name),
         }
     }
 }
 
+/// A statement prepared with `PREPARE`, which can be executed repeatedly with different
+/// parameter values.
+struct PreparedStatement {
+    statement: ast::Statement,
+    /// The declared types of the parameters, which their values are cast to.
+    types: Vec<DataType>,
+    /// The unoptimized plan of the statement, along with the catalog version it was built
+    /// against. Plans are optimized once their parameters are bound, so that the values
+    /// can be used to pick key lookups and the like.
+    plan: Option<(Plan, u64)>,
+}
+
+impl PreparedStatement {
+    fn execute(
+        &mut self,
+        transaction: &impl Transaction,
+        parameters: Vec<Value>,
+        functions: &FunctionRegistry,
+        options: &ExecutionOptions,
+    ) -> Result<StatementResult, Error> {
+        let planner = Planner::new(transaction).with_functions(functions);
+        let version = transaction.catalog_version()?;
+        let plan = match &self.plan {
+            Some((plan, planned_version)) if *planned_version == version => plan.clone(),
+            _ => {
+                let plan = planner.build(&self.statement)?;
+                if planner.is_cacheable() {
+                    self.plan = Some((plan.clone(), version));
+                }
+                plan
+            }
+        };
+
+        let expected = plan.parameter_count().max(self.types.len());
+        if parameters.len() != expected {
+            return Err(Error::ParameterCount {
+                expected,
+                actual: parameters.len(),
+            });
+        }
+        let parameters = parameters
+            .into_iter()
+            .enumerate()
+            .map(|(i, value)| match self.types.get(i) {
+                Some(data_type) => value.try_cast(data_type),
+                None => Ok(value),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        planner
+            .optimize(plan.bind(&parameters)?)?
+            .execute(transaction, options)
+    }
+}
+
 pub struct Session<'a, E: Engine> {
     engine: &'a E,
     current_transaction: Option<E::Transaction>,
+    options: ExecutionOptions,
+    prepared: HashMap<String, PreparedStatement>,
+    functions: Arc<FunctionRegistry>,
 }
 
 impl<'a, E: Engine> Session<'a, E> {
@@ -62,9 +133,20 @@ impl<'a, E: Engine> Session<'a, E> {
         Self {
             engine,
             current_transaction: None,
+            options: ExecutionOptions::default(),
+            prepared: HashMap::new(),
+            functions: Arc::default(),
         }
     }
 
+    pub fn options(&self) -> &ExecutionOptions {
+        &self.options
+    }
+
+    pub fn options_mut(&mut self) -> &mut ExecutionOptions {
+        &mut self.options
+    }
+
     pub fn exec(&mut self, statement: &ast::Statement) -> Result<StatementResult, Error> {
         match statement {
             ast::Statement::StartTransaction { .. } => {
@@ -79,14 +161,197 @@ impl<'a, E: Engine> Session<'a, E> {
                 self.rollback()?;
                 Ok(StatementResult::Rollback)
             }
+            ast::Statement::Set(ast::Set::SingleAssignment {
+                variable, values, ..
+            }) => {
+                let name = variable.to_string().to_lowercase();
+                self.set(&name, values)?;
+                Ok(StatementResult::Set(name))
+            }
+            ast::Statement::Prepare {
+                name,
+                data_types,
+                statement,
+            } => {
+                let types = data_types
+                    .iter()
+                    .map(DataType::try_from)
+                    .collect::<Result<_, _>>()?;
+                self.prepare(&name.value, statement, types)
+            }
+            ast::Statement::Execute {
+                name: Some(name),
+                parameters,
+                using,
+                ..
+            } => {
+                let functions = self.functions.clone();
+                let parameters = self.with_transaction(|t| {
+                    let planner = Planner::new(t).with_functions(&functions);
+                    parameters
+                        .iter()
+                        .chain(using.iter().map(|param| &param.expr))
+                        .map(|param| planner.build_value(param))
+                        .collect::<Result<Vec<_>, _>>()
+                })?;
+                self.execute(&name.to_string(), parameters)
+            }
+            ast::Statement::Deallocate { name, .. } => self.deallocate(&name.value),
+            ast::Statement::Analyze { table_name, .. } => {
+                self.analyze(Some(&table_name.to_string()))
+            }
             ast::Statement::ShowTables { .. } => {
                 let tables = self.with_transaction(|t| t.list_tables())?;
                 Ok(StatementResult::ShowTables { tables })
             }
-            statement => self.with_transaction(|t| Planner::new(t).plan(statement)?.execute(t)),
+            statement => {
+                let options = self.options.clone();
+                let functions = self.functions.clone();
+                let engine = self.engine;
+                let explicit = self.current_transaction.is_some();
+                self.with_transaction(|t| {
+                    if !explicit {
+                        analyze_stale_tables(engine, t)?;
+                    }
+                    Planner::new(t)
+                        .with_functions(&functions)
+                        .plan(statement)?
+                        .execute(t, &options)
+                })
+            }
         }
     }
 
+    /// Changes one of the session's execution options, e.g. `SET workers = 4`.
+    fn set(&mut self, name: &str, values: &[ast::Expr]) -> Result<(), Error> {
+        let value = match values {
+            [ast::Expr::Value(value)] => &value.value,
+            _ => {
+                return Err(Error::InvalidSql(format!(
+                    "SET {} expects a single value",
+                    name
+                )));
+            }
+        };
+        match (name, value) {
+            ("vectorized", ast::Value::Boolean(vectorized)) => {
+                self.options.vectorized = *vectorized;
+            }
+            ("workers", ast::Value::Number(workers, _)) => {
+                self.options.workers = workers
+                    .parse()
+                    .ok()
+                    .filter(|workers| *workers > 0)
+                    .ok_or_else(|| Error::InvalidValue(Box::new(value.clone())))?;
+            }
+            ("memory_limit", ast::Value::Number(limit, _)) => {
+                let limit: usize = limit
+                    .parse()
+                    .map_err(|_| Error::InvalidValue(Box::new(value.clone())))?;
+                // A limit of zero removes it.
+                self.options.memory_limit = Some(limit).filter(|limit| *limit > 0);
+            }
+            ("spill", ast::Value::Boolean(spill)) => {
+                self.options.spill = *spill;
+            }
+            ("vectorized" | "workers" | "memory_limit" | "spill", value) => {
+                return Err(Error::InvalidValue(Box::new(value.clone())));
+            }
+            (name, _) => return Err(Error::UnknownSetting(name.to_string())),
+        }
+        Ok(())
+    }
+
+    /// Prepares a statement to be executed later by name, with `$1`, `$2`, ... or `?`
+    /// placeholders for parameter values. The parameter values are cast to `types`, if
+    /// given.
+    pub fn prepare(
+        &mut self,
+        name: &str,
+        statement: &ast::Statement,
+        types: Vec<DataType>,
+    ) -> Result<StatementResult, Error> {
+        if self.prepared.contains_key(name) {
+            return Err(Error::DuplicatePreparedStatement(name.to_string()));
+        }
+        // Plan the statement right away, to report any errors in it.
+        let functions = self.functions.clone();
+        let (plan, version, cacheable) = self.with_transaction(|t| {
+            let planner = Planner::new(t).with_functions(&functions);
+            let plan = planner.build(statement)?;
+            Ok((plan, t.catalog_version()?, planner.is_cacheable()))
+        })?;
+        let prepared = PreparedStatement {
+            statement: statement.clone(),
+            types,
+            plan: cacheable.then_some((plan, version)),
+        };
+        self.prepared.insert(name.to_string(), prepared);
+        Ok(StatementResult::Prepare(name.to_string()))
+    }
+
+    /// Executes a prepared statement with the given parameter values.
+    pub fn execute(
+        &mut self,
+        name: &str,
+        parameters: Vec<Value>,
+    ) -> Result<StatementResult, Error> {
+        let mut prepared = self
+            .prepared
+            .remove(name)
+            .ok_or_else(|| Error::PreparedStatementDoesNotExist(name.to_string()))?;
+        let options = self.options.clone();
+        let functions = self.functions.clone();
+        let engine = self.engine;
+        let explicit = self.current_transaction.is_some();
+        let result = self.with_transaction(|t| {
+            if !explicit {
+                analyze_stale_tables(engine, t)?;
+            }
+            prepared.execute(t, parameters, &functions, &options)
+        });
+        self.prepared.insert(name.to_string(), prepared);
+        result
+    }
+
+    /// Removes a prepared statement, or all of them for `ALL`.
+    pub fn deallocate(&mut self, name: &str) -> Result<StatementResult, Error> {
+        if name.eq_ignore_ascii_case("all") {
+            self.prepared.clear();
+        } else if self.prepared.remove(name).is_none() {
+            return Err(Error::PreparedStatementDoesNotExist(name.to_string()));
+        }
+        Ok(StatementResult::Deallocate(name.to_string()))
+    }
+
+    /// Registers a scalar function implemented in Rust, which statements can then call by
+    /// name. Its name must not be taken by another function.
+    pub fn register_function(&mut self, function: UserFunction) -> Result<(), Error> {
+        Arc::make_mut(&mut self.functions).register_function(function)
+    }
+
+    /// Registers an aggregate function implemented in Rust, which statements can then
+    /// call by name. Its name must not be taken by another function.
+    pub fn register_aggregate(&mut self, aggregate: UserAggregate) -> Result<(), Error> {
+        Arc::make_mut(&mut self.functions).register_aggregate(aggregate)
+    }
+
+    /// Refreshes the statistics of a table, or of every table if none is given. The SQL
+    /// parser only accepts `ANALYZE` with a table name, so this is the way to analyze
+    /// the whole database.
+    pub fn analyze(&mut self, table: Option<&str>) -> Result<StatementResult, Error> {
+        self.with_transaction(|t| {
+            let tables = match table {
+                Some(table) => vec![table.to_string()],
+                None => t.list_tables()?.into_iter().map(|t| t.name).collect(),
+            };
+            for table in &tables {
+                t.analyze(table)?;
+            }
+            Ok(StatementResult::Analyze(tables))
+        })
+    }
+
     pub fn with_transaction<F, R>(&mut self, f: F) -> Result<R, Error>
     where
         F: FnOnce(&mut E::Transaction) -> Result<R, Error>,
@@ -131,3 +396,507 @@ impl<'a, E: Engine> Session<'a, E> {
         Ok(())
     }
 }
+
+/// Analyzes the tables whose statistics have gone stale, so that statements are planned
+/// with fresh ones. Stale tables are found through `transaction`, but analyzed in a
+/// transaction of their own, so that the statement doesn't conflict with other sessions
+/// over the statistics. If analyzing fails, e.g. because another session is analyzing
+/// the same table, the tables are left for a later statement.
+///
+/// This is only done for statements outside of explicit transactions, as the rows an
+/// explicit transaction has written would be missing from the statistics.
+fn analyze_stale_tables<E: Engine>(engine: &E, transaction: &impl Catalog) -> Result<(), Error> {
+    let mut stale = Vec::new();
+    for table in transaction.list_tables()? {
+        let statistics = transaction.get_table_statistics(&table.name)?;
+        if statistics.is_some_and(|statistics| statistics.is_stale()) {
+            stale.push(table.name);
+        }
+    }
+    if stale.is_empty() {
+        return Ok(());
+    }
+
+    let txn = engine.begin()?;
+    match stale
+        .iter()
+        .try_for_each(|table| txn.analyze(table).map(|_| ()))
+    {
+        Ok(()) => txn.commit(),
+        Err(err) => {
+            log::debug!("Skipped analyzing {}: {}", stale.join(", "), err);
+            txn.rollback()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicI64, Ordering};
+
+    use super::*;
+    use crate::{
+        exec::function::Volatility,
+        util::tests::{create_generated_table, execute, query, test_engine, test_engine_with_data},
+    };
+
+    #[test]
+    fn test_prepared_statements() {
+        let engine = test_engine_with_data();
+        let mut session = Session::new(&engine);
+        execute(
+            &mut session,
+            "PREPARE user_name AS SELECT username FROM users WHERE user_id = $1",
+        )
+        .unwrap();
+        assert_eq!(
+            query(&mut session, "EXECUTE user_name(2)"),
+            vec![Row::from(vec![Value::String("bob".to_string())])]
+        );
+        let StatementResult::Query { rows, .. } = session
+            .execute("user_name", vec![Value::Integer(3)])
+            .unwrap()
+        else {
+            panic!("expected a query result");
+        };
+        assert_eq!(
+            rows,
+            vec![Row::from(vec![Value::String("carol".to_string())])]
+        );
+        assert_eq!(
+            session.execute("user_name", vec![]),
+            Err(Error::ParameterCount {
+                expected: 1,
+                actual: 0
+            })
+        );
+
+        // Positional parameters are numbered in order, and cast to the declared types.
+        execute(
+            &mut session,
+            "PREPARE add_user (INT, VARCHAR) AS INSERT INTO users VALUES (?, ?, ?)",
+        )
+        .unwrap();
+        assert_eq!(
+            execute(&mut session, "EXECUTE add_user(6, 'frank', NULL)"),
+            Ok(StatementResult::Insert(1))
+        );
+        assert_eq!(
+            query(&mut session, "EXECUTE user_name(6)"),
+            vec![Row::from(vec![Value::String("frank".to_string())])]
+        );
+
+        // Parameters can't be used outside of prepared statements.
+        assert_eq!(
+            execute(
+                &mut session,
+                "SELECT username FROM users WHERE user_id = $1"
+            ),
+            Err(Error::UnboundParameter(1))
+        );
+
+        assert_eq!(
+            execute(&mut session, "DEALLOCATE user_name"),
+            Ok(StatementResult::Deallocate("user_name".to_string()))
+        );
+        assert_eq!(
+            execute(&mut session, "EXECUTE user_name(2)"),
+            Err(Error::PreparedStatementDoesNotExist(
+                "user_name".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_prepared_statement_invalidation() {
+        let engine = test_engine_with_data();
+        let mut session = Session::new(&engine);
+        execute(&mut session, "CREATE TABLE t (id INT PRIMARY KEY, x INT)").unwrap();
+        execute(&mut session, "INSERT INTO t VALUES (1, 2)").unwrap();
+        execute(&mut session, "PREPARE scan AS SELECT * FROM t WHERE id = ?").unwrap();
+        assert_eq!(
+            query(&mut session, "EXECUTE scan(1)"),
+            vec![Row::from(vec![Value::Integer(1), Value::Integer(2)])]
+        );
+
+        // The cached plan is replaced once the table changes.
+        execute(&mut session, "DROP TABLE t").unwrap();
+        execute(
+            &mut session,
+            "CREATE TABLE t (id INT PRIMARY KEY, x INT, y INT)",
+        )
+        .unwrap();
+        execute(&mut session, "INSERT INTO t VALUES (1, 2, 3)").unwrap();
+        assert_eq!(
+            query(&mut session, "EXECUTE scan(1)"),
+            vec![Row::from(vec![
+                Value::Integer(1),
+                Value::Integer(2),
+                Value::Integer(3)
+            ])]
+        );
+    }
+
+    /// Registers `MY_AVG`, an average of numbers as floats.
+    fn register_average(session: &mut Session<'_, impl Engine>) {
+        let add = |state: &mut Vec<Value>, sum: Value, count: Value| -> Result<(), Error> {
+            state[0] = state[0].checked_add(&sum)?;
+            state[1] = state[1].checked_add(&count)?;
+            Ok(())
+        };
+        let average = UserAggregate::new(
+            "my_avg",
+            DataType::Float,
+            DataType::Float,
+            Volatility::Immutable,
+            || vec![Value::Float(0.0), Value::Integer(0)],
+            move |state, value| add(state, value, Value::Integer(1)),
+            move |state, other| {
+                let [sum, count] =
+                    <[Value; 2]>::try_from(other).map_err(|_| Error::InvalidRowState)?;
+                add(state, sum, count)
+            },
+            |state| match &state[..] {
+                [_, Value::Integer(0)] => Ok(Value::Null),
+                [sum, count] => sum.checked_div(count),
+                _ => Err(Error::InvalidRowState),
+            },
+        );
+        session.register_aggregate(average).unwrap();
+    }
+
+    #[test]
+    fn test_user_functions() {
+        let engine = test_engine_with_data();
+        let mut session = Session::new(&engine);
+        let greet = UserFunction::new(
+            "greet",
+            [DataType::String { length: None }, DataType::Integer],
+            DataType::String { length: None },
+            Volatility::Immutable,
+            |args| match args {
+                [Value::String(name), Value::Integer(n)] => Ok(Value::String(format!(
+                    "hello {}{}",
+                    name,
+                    "!".repeat(*n as usize)
+                ))),
+                _ => Ok(Value::Null),
+            },
+        );
+        session.register_function(greet).unwrap();
+        assert_eq!(
+            query(
+                &mut session,
+                "SELECT Greet(UPPER(username), user_id) FROM users WHERE user_id <= 2"
+            ),
+            vec![
+                Row::from(vec![Value::String("hello ALICE!".to_string())]),
+                Row::from(vec![Value::String("hello BOB!!".to_string())]),
+            ]
+        );
+
+        // Arguments are checked against the declared types when planning.
+        for sql in [
+            "SELECT greet(user_id, user_id) FROM users",
+            "SELECT greet(username) FROM users",
+        ] {
+            let result = execute(&mut session, sql);
+            assert!(
+                matches!(result, Err(Error::InvalidArgument(_))),
+                "{sql}: {result:?}"
+            );
+        }
+
+        // Functions must return values of their declared type.
+        let broken = UserFunction::new(
+            "broken",
+            [],
+            DataType::Integer,
+            Volatility::Volatile,
+            |_| Ok(Value::String("oops".to_string())),
+        );
+        session.register_function(broken).unwrap();
+        assert!(matches!(
+            execute(&mut session, "SELECT broken()"),
+            Err(Error::InvalidFunctionResult(_))
+        ));
+
+        // Names can't be taken twice, or shadow built-in functions.
+        for name in ["GREET", "lower", "sum"] {
+            let function =
+                UserFunction::new(name, [], DataType::Integer, Volatility::default(), |_| {
+                    Ok(Value::Null)
+                });
+            assert_eq!(
+                session.register_function(function),
+                Err(Error::DuplicateFunction(name.to_lowercase()))
+            );
+        }
+        assert!(matches!(
+            execute(&mut Session::new(&engine), "SELECT greet('x', 1)"),
+            Err(Error::FunctionNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_volatile_functions() {
+        let engine = test_engine_with_data();
+        let mut session = Session::new(&engine);
+        for (name, volatility) in [
+            ("next_id", Volatility::Volatile),
+            ("first_id", Volatility::Immutable),
+        ] {
+            let counter = AtomicI64::new(0);
+            let function = UserFunction::new(name, [], DataType::Integer, volatility, move |_| {
+                Ok(Value::Integer(counter.fetch_add(1, Ordering::Relaxed) + 1))
+            });
+            session.register_function(function).unwrap();
+        }
+
+        // Volatile functions are called for every row, rather than once ahead of time.
+        let rows = query(
+            &mut session,
+            "SELECT next_id(), first_id() FROM users ORDER BY user_id",
+        );
+        assert_eq!(rows, (1..=5).map(|i| Row::from((i, 1))).collect::<Vec<_>>());
+        // The counter carries on from 6.
+        let rows = query(
+            &mut session,
+            "SELECT user_id FROM users WHERE next_id() % 2 = 0 ORDER BY user_id",
+        );
+        assert_eq!(
+            rows,
+            vec![Row::from((1,)), Row::from((3,)), Row::from((5,))]
+        );
+    }
+
+    #[test]
+    fn test_user_aggregates() {
+        let engine = test_engine_with_data();
+        let mut session = Session::new(&engine);
+        register_average(&mut session);
+        let check = |rows: &[Row]| {
+            for row in rows {
+                let (Value::Integer(sum), Value::Integer(count)) = (&row[1], &row[2]) else {
+                    panic!("unexpected row {row:?}");
+                };
+                assert_eq!(row[3], Value::Float(*sum as f64 / *count as f64));
+            }
+        };
+
+        let rows = query(
+            &mut session,
+            "SELECT user_id, SUM(quantity), COUNT(quantity), my_avg(quantity) FROM orders
+            GROUP BY user_id ORDER BY my_avg(quantity) DESC",
+        );
+        assert_eq!(rows.len(), 5);
+        check(&rows);
+        assert!(rows.is_sorted_by(|a, b| a[3] >= b[3]));
+        assert_eq!(
+            query(
+                &mut session,
+                "SELECT my_avg(quantity) FROM orders WHERE user_id < 0"
+            ),
+            vec![Row::from(vec![Value::Null])]
+        );
+        assert!(matches!(
+            execute(&mut session, "SELECT my_avg(username) FROM users"),
+            Err(Error::InvalidArgument(_))
+        ));
+
+        // Partial states are merged across workers, and survive being spilled to disk.
+        create_generated_table(&mut session, 5000);
+        let sql =
+            "SELECT name, SUM(x), COUNT(x), my_avg(x) FROM t WHERE x IS NOT NULL GROUP BY name";
+        for setting in ["workers = 4", "memory_limit = 20000"] {
+            execute(&mut session, &format!("SET {setting}")).unwrap();
+            let rows = query(&mut session, sql);
+            assert_eq!(rows.len(), 299, "{setting}");
+            check(&rows);
+        }
+    }
+
+    #[test]
+    fn test_concurrent_inserts() {
+        let engine = test_engine();
+        let mut a = Session::new(&engine);
+        let mut b = Session::new(&engine);
+        execute(&mut a, "CREATE TABLE t (id INT PRIMARY KEY, x INT)").unwrap();
+
+        // Writers of different rows of a table don't conflict.
+        execute(&mut a, "BEGIN").unwrap();
+        execute(&mut a, "INSERT INTO t VALUES (1, 1)").unwrap();
+        execute(&mut b, "BEGIN").unwrap();
+        execute(&mut b, "INSERT INTO t VALUES (2, 2)").unwrap();
+        execute(&mut b, "INSERT INTO t VALUES (3, 3)").unwrap();
+        execute(&mut b, "COMMIT").unwrap();
+        execute(&mut a, "INSERT INTO t VALUES (4, 4)").unwrap();
+        execute(&mut a, "COMMIT").unwrap();
+
+        let rows = query(&mut a, "SELECT id FROM t ORDER BY id");
+        assert_eq!(
+            rows,
+            vec![
+                Row::from((1,)),
+                Row::from((2,)),
+                Row::from((3,)),
+                Row::from((4,))
+            ]
+        );
+        let statistics = engine.begin().unwrap().get_table_statistics("t").unwrap();
+        assert_eq!(statistics.map(|statistics| statistics.row_count), Some(4));
+    }
+
+    #[test]
+    fn test_analyze() {
+        let engine = test_engine_with_data();
+        let mut session = Session::new(&engine);
+        execute(&mut session, "CREATE TABLE t (id INT PRIMARY KEY, x INT)").unwrap();
+        let values = (0..1000)
+            .map(|i| match i % 5 {
+                0 => format!("({i}, NULL)"),
+                _ => format!("({i}, {})", i % 100),
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        execute(&mut session, &format!("INSERT INTO t VALUES {values}")).unwrap();
+        assert_eq!(
+            execute(&mut session, "ANALYZE TABLE t"),
+            Ok(StatementResult::Analyze(vec!["t".to_string()]))
+        );
+
+        let rows = query(
+            &mut session,
+            "SELECT column_name, row_count, null_fraction, distinct_count, min, max
+            FROM veris_statistics WHERE table_name = 't' ORDER BY column_name",
+        );
+        assert_eq!(
+            rows,
+            vec![
+                Row::from(vec![
+                    Value::String("id".to_string()),
+                    Value::Integer(1000),
+                    Value::Float(0.0),
+                    Value::Integer(1000),
+                    Value::String("0".to_string()),
+                    Value::String("999".to_string()),
+                ]),
+                Row::from(vec![
+                    Value::String("x".to_string()),
+                    Value::Integer(1000),
+                    Value::Float(0.2),
+                    Value::Integer(80),
+                    Value::String("1".to_string()),
+                    Value::String("99".to_string()),
+                ]),
+            ]
+        );
+
+        // The histogram of x puts about a quarter of its values below 25.
+        let plan = query(&mut session, "EXPLAIN SELECT * FROM t WHERE x < 25");
+        let Value::String(line) = &plan[0][0] else {
+            panic!("expected a plan line, got {:?}", plan[0]);
+        };
+        let rows: f64 = line
+            .rsplit_once("rows=")
+            .and_then(|(_, rows)| rows.trim_end_matches(')').parse().ok())
+            .unwrap();
+        assert!((190.0..=210.0).contains(&rows), "{line}");
+
+        // Every table can be analyzed at once.
+        let StatementResult::Analyze(tables) = session.analyze(None).unwrap() else {
+            panic!("expected an analyze result");
+        };
+        assert!(tables.contains(&"users".to_string()));
+        let rows = query(
+            &mut session,
+            "SELECT table_name FROM veris_statistics GROUP BY table_name",
+        );
+        assert_eq!(rows.len(), tables.len());
+    }
+
+    #[test]
+    fn test_stale_statistics() {
+        let engine = test_engine();
+        let mut session = Session::new(&engine);
+        execute(&mut session, "CREATE TABLE t (id INT PRIMARY KEY, x INT)").unwrap();
+        let values = (0..100)
+            .map(|i| format!("({i}, {})", i % 10))
+            .collect::<Vec<_>>()
+            .join(", ");
+        execute(&mut session, &format!("INSERT INTO t VALUES {values}")).unwrap();
+
+        // Writes only make the statistics stale.
+        let statistics = || {
+            let txn = engine.begin().unwrap();
+            let statistics = txn.get_table_statistics("t").unwrap().unwrap();
+            txn.commit().unwrap();
+            statistics
+        };
+        assert!(statistics().is_stale());
+        assert!(statistics().columns.is_empty());
+
+        // The table is analyzed before the next statement is planned.
+        query(&mut session, "SELECT * FROM t WHERE id = 1");
+        let statistics = statistics();
+        assert!(!statistics.is_stale());
+        assert_eq!(statistics.row_count, 100);
+        assert_eq!(statistics.columns[1].distinct_count, 10);
+    }
+
+    #[test]
+    fn test_statistics_in_transactions() {
+        let engine = test_engine();
+        let mut a = Session::new(&engine);
+        let mut b = Session::new(&engine);
+        execute(&mut a, "CREATE TABLE t (id INT PRIMARY KEY, x INT)").unwrap();
+        let insert = |session: &mut Session<'_, _>, ids: std::ops::Range<i64>| {
+            let values = ids
+                .map(|i| format!("({i}, {})", i % 10))
+                .collect::<Vec<_>>()
+                .join(", ");
+            execute(session, &format!("INSERT INTO t VALUES {values}")).unwrap();
+        };
+        let statistics = || {
+            let txn = engine.begin().unwrap();
+            let statistics = txn.get_table_statistics("t").unwrap().unwrap();
+            txn.commit().unwrap();
+            (statistics.row_count, statistics.modified_count)
+        };
+
+        // Rows are counted once they are committed, and the table isn't analyzed in the
+        // middle of the transaction that wrote them.
+        execute(&mut a, "BEGIN").unwrap();
+        insert(&mut a, 0..1000);
+        assert_eq!(
+            query(&mut a, "SELECT COUNT(*) FROM t"),
+            vec![Row::from((1000,))]
+        );
+        assert_eq!(statistics(), (0, 0));
+        query(&mut b, "SELECT * FROM t");
+        execute(&mut a, "COMMIT").unwrap();
+        assert_eq!(statistics(), (1000, 1000));
+        query(&mut a, "SELECT * FROM t");
+        assert_eq!(statistics(), (1000, 0));
+
+        // Neither rolled back writes nor a rolled back analysis change the counts.
+        execute(&mut a, "BEGIN").unwrap();
+        insert(&mut a, 1000..2000);
+        execute(&mut a, "ROLLBACK").unwrap();
+        assert_eq!(statistics(), (1000, 0));
+        insert(&mut a, 1000..2000);
+        execute(&mut a, "BEGIN").unwrap();
+        execute(&mut a, "ANALYZE TABLE t").unwrap();
+        execute(&mut a, "ROLLBACK").unwrap();
+        assert_eq!(statistics(), (2000, 1000));
+
+        // Rows committed while another session analyzes the table are still counted.
+        execute(&mut b, "BEGIN").unwrap();
+        insert(&mut b, 2000..3000);
+        execute(&mut a, "BEGIN").unwrap();
+        execute(&mut a, "ANALYZE TABLE t").unwrap();
+        execute(&mut b, "COMMIT").unwrap();
+        execute(&mut a, "COMMIT").unwrap();
+        assert_eq!(statistics(), (3000, 1000));
+    }
+}
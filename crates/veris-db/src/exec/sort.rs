@@ -0,0 +1,183 @@
+use std::cmp::Ordering;
+
+use crate::{
+    error::Error,
+    types::value::{Row, RowIter, Value},
+};
+
+use super::{
+    plan::Direction,
+    spill::{
+        MemoryBudget, MemoryReservation, Reserved, SpillFile, SpillReader, row_size, value_size,
+    },
+};
+
+/// A row along with the values it is sorted by.
+type SortRecord = (Vec<Value>, Row);
+
+/// Sorts rows by their keys. If the rows go over the memory budget, they are sorted in
+/// runs that are spilled to disk, which are merged back together at the end. The sort is
+/// stable.
+pub struct Sorter {
+    directions: Vec<Direction>,
+    memory: MemoryReservation,
+    rows: Vec<SortRecord>,
+    runs: Vec<SpillReader<SortRecord>>,
+}
+
+impl Sorter {
+    pub fn new(directions: Vec<Direction>, budget: &MemoryBudget) -> Self {
+        Self {
+            directions,
+            memory: budget.reserve(),
+            rows: Vec::new(),
+            runs: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, keys: Vec<Value>, row: Row) -> Result<(), Error> {
+        let size =
+            size_of::<SortRecord>() + keys.iter().map(value_size).sum::<usize>() + row_size(&row);
+        if !self.memory.try_grow(size)? {
+            self.spill()?;
+            self.memory.force_grow(size);
+        }
+        self.rows.push((keys, row));
+        Ok(())
+    }
+
+    /// Writes the buffered rows to disk as a sorted run.
+    fn spill(&mut self) -> Result<(), Error> {
+        self.sort_rows();
+        let mut run = SpillFile::new()?;
+        for record in self.rows.drain(..) {
+            run.write(&record)?;
+        }
+        self.rows = Vec::new();
+        self.memory.clear();
+        self.runs.push(run.into_reader()?);
+        Ok(())
+    }
+
+    fn sort_rows(&mut self) {
+        let directions = &self.directions;
+        self.rows
+            .sort_by(|(a, _), (b, _)| compare_keys(a, b, directions));
+    }
+
+    pub fn finish(mut self) -> Result<RowIter, Error> {
+        if self.runs.is_empty() {
+            self.sort_rows();
+            let rows = self.rows.into_iter().map(|(_, row)| Ok(row));
+            return Ok(RowIter::new(Reserved::new(rows, self.memory)));
+        }
+
+        if !self.rows.is_empty() {
+            self.spill()?;
+        }
+        Ok(RowIter::new(MergeRuns {
+            heads: Vec::new(),
+            runs: self.runs,
+            directions: self.directions,
+        }))
+    }
+}
+
+/// Compares the sort keys of two rows.
+pub fn compare_keys(a: &[Value], b: &[Value], directions: &[Direction]) -> Ordering {
+    for ((a, b), direction) in a.iter().zip(b).zip(directions) {
+        let ordering = match direction {
+            Direction::Ascending => a.cmp(b),
+            Direction::Descending => b.cmp(a),
+        };
+        if ordering.is_ne() {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
+
+/// Merges sorted runs into a single sorted stream of rows. Of rows with equal keys, those
+/// from earlier runs come first.
+#[derive(Clone)]
+struct MergeRuns {
+    /// The next record of each run, once the merge has started.
+    heads: Vec<Option<SortRecord>>,
+    runs: Vec<SpillReader<SortRecord>>,
+    directions: Vec<Direction>,
+}
+
+impl MergeRuns {
+    fn try_next(&mut self) -> Result<Option<Row>, Error> {
+        if self.heads.is_empty() {
+            self.heads = self
+                .runs
+                .iter_mut()
+                .map(|run| run.next().transpose())
+                .collect::<Result<_, _>>()?;
+        }
+
+        let mut min: Option<(usize, &SortRecord)> = None;
+        for (i, head) in self.heads.iter().enumerate() {
+            if let Some(head) = head
+                && min
+                    .is_none_or(|(_, min)| compare_keys(&head.0, &min.0, &self.directions).is_lt())
+            {
+                min = Some((i, head));
+            }
+        }
+        let Some((i, _)) = min else {
+            return Ok(None);
+        };
+
+        let next = self.runs[i].next().transpose()?;
+        let head = std::mem::replace(&mut self.heads[i], next);
+        Ok(head.map(|(_, row)| row))
+    }
+}
+
+impl Iterator for MergeRuns {
+    type Item = Result<Row, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.try_next().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use itertools::Itertools;
+
+    use super::*;
+
+    #[test]
+    fn test_external_sort() {
+        let rows: Vec<_> = (0..2000)
+            .map(|i| Row::from(vec![Value::Integer(i % 37), Value::Integer(i)]))
+            .collect();
+        let directions = vec![Direction::Descending];
+        let sort = |budget: &MemoryBudget| -> Vec<Row> {
+            let mut sorter = Sorter::new(directions.clone(), budget);
+            for row in &rows {
+                sorter.push(vec![row[0].clone()], row.clone()).unwrap();
+            }
+            sorter.finish().unwrap().try_collect().unwrap()
+        };
+
+        let mut expected = rows.clone();
+        expected.sort_by(|a, b| b[0].cmp(&a[0]));
+        assert_eq!(sort(&MemoryBudget::unlimited()), expected);
+
+        // Small enough to spill dozens of runs, which still merge into a stable sort.
+        let budget = MemoryBudget::new(Some(10_000), true);
+        assert_eq!(sort(&budget), expected);
+        assert_eq!(budget.used(), 0);
+
+        let mut sorter = Sorter::new(directions.clone(), &MemoryBudget::new(Some(10_000), false));
+        let result: Result<Vec<()>, _> = rows
+            .iter()
+            .map(|row| sorter.push(vec![row[0].clone()], row.clone()))
+            .collect();
+        assert_eq!(result, Err(Error::MemoryLimitExceeded(10_000)));
+    }
+}
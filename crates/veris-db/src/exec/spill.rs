@@ -0,0 +1,360 @@
+use std::{
+    collections::VecDeque,
+    fs::File,
+    hash::{DefaultHasher, Hash, Hasher},
+    io::{BufWriter, Read, Seek, SeekFrom, Write},
+    marker::PhantomData,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::{
+    encoding::{bincode_deserialize, bincode_serialize},
+    error::Error,
+    types::value::{Row, Value},
+};
+
+/// The number of partitions that grace hash joins and aggregations split their inputs
+/// into when they go over the memory budget.
+pub const SPILL_PARTITIONS: usize = 16;
+
+/// How many times a partition that is still too large is split up again, before it is
+/// processed in memory regardless of the budget. Partitions only stay too large when they
+/// hold many rows with the same key.
+pub const MAX_SPILL_DEPTH: usize = 4;
+
+/// The memory that the operators of a query may use for buffering rows, shared by all
+/// of them. Operators that go over it spill to temporary files, or fail if spilling is
+/// disabled.
+#[derive(Clone, Debug)]
+pub struct MemoryBudget {
+    limit: Option<usize>,
+    spill: bool,
+    used: Arc<AtomicUsize>,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: Option<usize>, spill: bool) -> Self {
+        Self {
+            limit,
+            spill,
+            used: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn unlimited() -> Self {
+        Self::new(None, true)
+    }
+
+    /// The number of bytes currently reserved.
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    /// Starts a reservation of memory for an operator.
+    pub fn reserve(&self) -> MemoryReservation {
+        MemoryReservation(Arc::new(ReservationInner {
+            budget: self.clone(),
+            bytes: AtomicUsize::new(0),
+        }))
+    }
+}
+
+impl Default for MemoryBudget {
+    fn default() -> Self {
+        Self::unlimited()
+    }
+}
+
+/// The memory reserved by an operator, which is returned to the budget once it and all
+/// its clones are dropped.
+#[derive(Clone, Debug)]
+pub struct MemoryReservation(Arc<ReservationInner>);
+
+#[derive(Debug)]
+struct ReservationInner {
+    budget: MemoryBudget,
+    bytes: AtomicUsize,
+}
+
+impl MemoryReservation {
+    /// Reserves `bytes` more memory. Returns `false` without reserving anything if that
+    /// would go over the budget, in which case the operator should spill what it holds
+    /// and call [`MemoryReservation::force_grow`]. Fails if spilling is disabled.
+    pub fn try_grow(&self, bytes: usize) -> Result<bool, Error> {
+        let budget = &self.0.budget;
+        if let Some(limit) = budget.limit {
+            let used = budget.used.load(Ordering::Relaxed);
+            if used.saturating_add(bytes) > limit {
+                if !budget.spill {
+                    return Err(Error::MemoryLimitExceeded(limit));
+                }
+                return Ok(false);
+            }
+        }
+        self.force_grow(bytes);
+        Ok(true)
+    }
+
+    /// Reserves `bytes` more memory, even if that goes over the budget.
+    pub fn force_grow(&self, bytes: usize) {
+        self.0.budget.used.fetch_add(bytes, Ordering::Relaxed);
+        self.0.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Returns all of the reserved memory to the budget, after spilling.
+    pub fn clear(&self) {
+        let bytes = self.0.bytes.swap(0, Ordering::Relaxed);
+        self.0.budget.used.fetch_sub(bytes, Ordering::Relaxed);
+    }
+
+    pub fn size(&self) -> usize {
+        self.0.bytes.load(Ordering::Relaxed)
+    }
+
+    /// The budget that the memory is reserved from.
+    pub fn budget(&self) -> &MemoryBudget {
+        &self.0.budget
+    }
+}
+
+impl Drop for ReservationInner {
+    fn drop(&mut self) {
+        let bytes = *self.bytes.get_mut();
+        self.budget.used.fetch_sub(bytes, Ordering::Relaxed);
+    }
+}
+
+/// An iterator that holds on to the memory reservation of the rows it returns.
+#[derive(Clone)]
+pub struct Reserved<I> {
+    inner: I,
+    _reservation: MemoryReservation,
+}
+
+impl<I> Reserved<I> {
+    pub fn new(inner: I, reservation: MemoryReservation) -> Self {
+        Self {
+            inner,
+            _reservation: reservation,
+        }
+    }
+}
+
+impl<I: Iterator> Iterator for Reserved<I> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+}
+
+/// Estimates the memory used by a value, including what it points to.
+pub fn value_size(value: &Value) -> usize {
+    let heap = match value {
+        Value::String(s) => s.capacity(),
+        _ => 0,
+    };
+    size_of::<Value>() + heap
+}
+
+/// Estimates the memory used by a row, including its values.
+pub fn row_size(row: &Row) -> usize {
+    size_of::<Row>() + row.iter().map(value_size).sum::<usize>()
+}
+
+/// Returns the partition of a key at the given depth of repartitioning. Each depth hashes
+/// keys differently, so that a partition is split up again when repartitioned.
+pub fn partition_of(key: &impl Hash, depth: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    depth.hash(&mut hasher);
+    key.hash(&mut hasher);
+    (hasher.finish() % SPILL_PARTITIONS as u64) as usize
+}
+
+/// A temporary file that records are spilled to. The file is deleted once it and every
+/// reader of it are dropped.
+pub struct SpillFile<T> {
+    writer: BufWriter<File>,
+    len: usize,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Serialize + DeserializeOwned> SpillFile<T> {
+    pub fn new() -> Result<Self, Error> {
+        Ok(Self {
+            writer: BufWriter::new(tempfile::tempfile()?),
+            len: 0,
+            _marker: PhantomData,
+        })
+    }
+
+    /// The number of records written to the file.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn write(&mut self, record: &T) -> Result<(), Error> {
+        let bytes = bincode_serialize(record)?;
+        self.writer.write_all(&(bytes.len() as u64).to_be_bytes())?;
+        self.writer.write_all(&bytes)?;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Finishes writing, returning a reader of the records in the order they were written.
+    pub fn into_reader(self) -> Result<SpillReader<T>, Error> {
+        let file = self
+            .writer
+            .into_inner()
+            .map_err(|err| Error::Io(err.to_string()))?;
+        Ok(SpillReader {
+            file: Arc::new(Mutex::new(file)),
+            offset: 0,
+            remaining: self.len,
+            buffer: VecDeque::new(),
+            _marker: PhantomData,
+        })
+    }
+}
+
+/// Reads back the records of a [`SpillFile`]. Clones of a reader read the file
+/// independently of each other.
+pub struct SpillReader<T> {
+    file: Arc<Mutex<File>>,
+    offset: u64,
+    remaining: usize,
+    buffer: VecDeque<T>,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Clone> Clone for SpillReader<T> {
+    fn clone(&self) -> Self {
+        Self {
+            file: self.file.clone(),
+            offset: self.offset,
+            remaining: self.remaining,
+            buffer: self.buffer.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: DeserializeOwned> SpillReader<T> {
+    /// The number of bytes read from the file at a time.
+    const CHUNK_SIZE: usize = 64 * 1024;
+
+    fn fill_buffer(&mut self) -> Result<(), Error> {
+        let mut file = self.file.lock()?;
+        file.seek(SeekFrom::Start(self.offset))?;
+        let mut chunk = Vec::with_capacity(Self::CHUNK_SIZE);
+        (&mut *file)
+            .take(Self::CHUNK_SIZE as u64)
+            .read_to_end(&mut chunk)?;
+
+        let mut consumed = 0;
+        while self.remaining > 0 {
+            let Some(header) = chunk.get(consumed..consumed + 8) else {
+                break;
+            };
+            let mut len = [0; 8];
+            len.copy_from_slice(header);
+            let len = u64::from_be_bytes(len) as usize;
+            let start = consumed + 8;
+            let record = match chunk.get(start..start + len) {
+                Some(bytes) => bincode_deserialize(bytes)?,
+                // A record that doesn't fit in the chunk is read on its own.
+                None if consumed == 0 => {
+                    let mut bytes = vec![0; len];
+                    file.seek(SeekFrom::Start(self.offset + 8))?;
+                    file.read_exact(&mut bytes)?;
+                    bincode_deserialize(&bytes)?
+                }
+                None => break,
+            };
+            self.buffer.push_back(record);
+            self.remaining -= 1;
+            consumed = start + len;
+        }
+        self.offset += consumed as u64;
+        Ok(())
+    }
+}
+
+impl<T: DeserializeOwned> Iterator for SpillReader<T> {
+    type Item = Result<T, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buffer.is_empty()
+            && self.remaining > 0
+            && let Err(err) = self.fill_buffer()
+        {
+            self.remaining = 0;
+            return Some(Err(err));
+        }
+        self.buffer.pop_front().map(Ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spill_file() {
+        let mut file = SpillFile::new().unwrap();
+        let rows: Vec<_> = (0..10_000)
+            .map(|i| {
+                Row::from(vec![
+                    Value::Integer(i),
+                    Value::String("x".repeat(i as usize % 50)),
+                ])
+            })
+            .collect();
+        for row in &rows {
+            file.write(row).unwrap();
+        }
+        // A record larger than a chunk.
+        let large = Row::from(vec![Value::String("y".repeat(200_000))]);
+        file.write(&large).unwrap();
+        assert_eq!(file.len(), rows.len() + 1);
+
+        let mut reader = file.into_reader().unwrap();
+        let first: Vec<Row> = reader.by_ref().take(100).map(Result::unwrap).collect();
+        assert_eq!(first, rows[..100]);
+        // Clones read on from where they were cloned.
+        let rest: Vec<Row> = reader.clone().map(Result::unwrap).collect();
+        assert_eq!(rest.len(), rows.len() - 100 + 1);
+        assert_eq!(rest[..rest.len() - 1], rows[100..]);
+        assert_eq!(rest.last(), Some(&large));
+        assert_eq!(reader.count(), rows.len() - 100 + 1);
+    }
+
+    #[test]
+    fn test_memory_budget() {
+        let budget = MemoryBudget::new(Some(100), true);
+        let a = budget.reserve();
+        assert!(a.try_grow(60).unwrap());
+        let b = budget.reserve();
+        assert!(!b.try_grow(60).unwrap());
+        assert_eq!(budget.used(), 60);
+        a.clear();
+        assert!(b.try_grow(60).unwrap());
+        drop(b.clone());
+        assert_eq!(budget.used(), 60);
+        drop(b);
+        assert_eq!(budget.used(), 0);
+
+        let budget = MemoryBudget::new(Some(100), false);
+        let a = budget.reserve();
+        assert_eq!(a.try_grow(101), Err(Error::MemoryLimitExceeded(100)));
+    }
+}
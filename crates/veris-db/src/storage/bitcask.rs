@@ -5,7 +5,7 @@ use std::{
 
 use crate::{ByteBounds, ByteVec, Bytes, KeyValue, ReadBytes, Result, WriteBytes};
 
-use super::engine::StorageEngine;
+use super::engine::{StorageEngine, split_keys};
 
 pub type KeyDir = BTreeMap<ByteVec, Location>;
 
@@ -160,7 +160,7 @@ impl<T: Read + Write + Seek> Log<T> {
     }
 }
 
-impl<T: Read + Write + Seek + 'static> StorageEngine for Bitcask<T> {
+impl<T: Read + Write + Seek + Send + 'static> StorageEngine for Bitcask<T> {
     type ScanIterator<'a> = BitcaskScanIterator<'a, T>;
 
     fn flush(&mut self) -> Result<()> {
@@ -218,6 +218,15 @@ impl<T: Read + Write + Seek + 'static> StorageEngine for Bitcask<T> {
         self.key_dir.remove(key);
         Ok(())
     }
+
+    fn split_range<B>(&mut self, range: B, parts: usize) -> Result<Vec<ByteVec>>
+    where
+        B: ByteBounds,
+    {
+        // The keys are all in memory, so there's no need to read any values.
+        let keys: Vec<_> = self.key_dir.range(range).map(|(key, _)| key).collect();
+        Ok(split_keys(&keys, parts))
+    }
 }
 
 impl<T: Read + Write + Seek> Drop for Bitcask<T> {
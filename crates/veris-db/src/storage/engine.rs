@@ -2,7 +2,7 @@ use std::io::Cursor;
 
 use crate::{ByteBounds, ByteVec, KeyValue, ReadBytes, Result, WriteBytes, key_prefix_range};
 
-pub trait StorageEngine {
+pub trait StorageEngine: Send {
     type ScanIterator<'a>: ScanIterator<'a>
     where
         Self: 'a;
@@ -40,6 +40,30 @@ pub trait StorageEngine {
     }
 
     fn delete(&mut self, key: &[u8]) -> Result<()>;
+
+    /// Returns keys that split the range into at most `parts` ranges holding roughly the
+    /// same number of keys, in ascending order. Each key is the start of a new range.
+    fn split_range<B>(&mut self, range: B, parts: usize) -> Result<Vec<ByteVec>>
+    where
+        B: ByteBounds,
+    {
+        let keys: Vec<_> = self
+            .scan(range)
+            .map(|item| item.map(|(key, _)| key.into_owned()))
+            .collect::<Result<_>>()?;
+        Ok(split_keys(&keys, parts))
+    }
+}
+
+/// Picks the keys that split a sorted list of keys into `parts` parts of equal size.
+pub fn split_keys(keys: &[impl AsRef<[u8]>], parts: usize) -> Vec<ByteVec> {
+    let mut splits: Vec<ByteVec> = (1..parts)
+        .map(|part| part * keys.len() / parts)
+        .filter(|&index| index > 0)
+        .map(|index| keys[index].as_ref().to_vec())
+        .collect();
+    splits.dedup();
+    splits
 }
 
 pub trait ScanIterator<'a>: DoubleEndedIterator<Item = Result<KeyValue<'a>>> {}
@@ -275,6 +275,28 @@ impl<E: StorageEngine> MvccTransaction<E> {
         Ok(None)
     }
 
+    /// Reads a key that is kept outside of transactions, see [`Self::update_unversioned`].
+    pub fn get_unversioned(&self, key: &[u8]) -> Result<Option<ByteVec>, Error> {
+        let mut engine = self.engine.lock()?;
+        engine.get(&Key::Unversioned(Bytes::Borrowed(key)).encode()?)
+    }
+
+    /// Replaces the value of a key that is kept outside of transactions with the result
+    /// of `f`, or removes it if that is `None`. The change is seen by every transaction
+    /// right away and survives a rollback, so it never conflicts with other writers.
+    pub fn update_unversioned(
+        &self,
+        key: &[u8],
+        f: impl FnOnce(Option<ByteVec>) -> Result<Option<ByteVec>, Error>,
+    ) -> Result<(), Error> {
+        let key = Key::Unversioned(Bytes::Borrowed(key)).encode()?;
+        let mut engine = self.engine.lock()?;
+        match f(engine.get(&key)?)? {
+            Some(value) => engine.set(&key, &value),
+            None => engine.delete(&key),
+        }
+    }
+
     pub fn scan(&self, range: impl ByteBounds) -> Result<MvccScanIterator<E>, Error> {
         let start = match range.start_bound() {
             Bound::Excluded(k) => {
@@ -311,6 +333,52 @@ impl<E: StorageEngine> MvccTransaction<E> {
             range,
         ))
     }
+
+    /// Splits a scan of the keys with the given prefix into at most `parts` scans over
+    /// disjoint key ranges, in key order. The scans share this transaction's snapshot and
+    /// can be run concurrently.
+    pub fn scan_prefix_partitions(
+        &self,
+        prefix: &[u8],
+        parts: usize,
+    ) -> Result<Vec<MvccScanIterator<E>>, Error> {
+        let mut prefix = KeyPrefix::Version(Bytes::Borrowed(prefix)).encode()?;
+        prefix.pop();
+        prefix.pop();
+        let (start, end) = key_prefix_range(&prefix);
+        let splits = self
+            .engine
+            .lock()?
+            .split_range((start.clone(), end.clone()), parts)?;
+
+        // All versions of a key must be in the same range, so ranges start at the first
+        // possible version of a key.
+        let mut bounds = Vec::new();
+        for split in splits {
+            match Key::decode(&split)? {
+                Key::Version(key, _) => bounds.push(Key::Version(key, 0).encode()?),
+                key => {
+                    return Err(Error::InvalidEngineState(format!(
+                        "expected a Version key, got {key:?}"
+                    )));
+                }
+            }
+        }
+        bounds.dedup();
+
+        let mut ranges = Vec::new();
+        let mut lower = start;
+        for bound in bounds {
+            ranges.push((lower, Bound::Excluded(bound.clone())));
+            lower = Bound::Included(bound);
+        }
+        ranges.push((lower, end));
+
+        Ok(ranges
+            .into_iter()
+            .map(|range| MvccScanIterator::new(self.engine.clone(), self.state.clone(), range))
+            .collect())
+    }
 }
 
 pub struct MvccScanIterator<E: StorageEngine> {
@@ -461,6 +529,31 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_mvcc_unversioned() -> Result<()> {
+        let engine = Bitcask::new(Cursor::new(Vec::new())).unwrap();
+        let mvcc = Mvcc::new(engine);
+
+        // Unversioned writes are seen by other transactions right away, survive a
+        // rollback, and are not part of scans.
+        let txn1 = mvcc.begin()?;
+        let txn2 = mvcc.begin()?;
+        txn1.update_unversioned(b"key", |_| Ok(Some(b"1".to_vec())))?;
+        txn2.update_unversioned(b"key", |value| {
+            assert_eq!(value, Some(b"1".to_vec()));
+            Ok(Some(b"2".to_vec()))
+        })?;
+        txn2.rollback()?;
+        assert_eq!(txn1.get_unversioned(b"key")?, Some(b"2".to_vec()));
+        assert_eq!(txn1.scan(..)?.count(), 0);
+
+        txn1.update_unversioned(b"key", |_| Ok(None))?;
+        assert_eq!(txn1.get_unversioned(b"key")?, None);
+        txn1.commit()?;
+
+        Ok(())
+    }
+
     #[test]
     fn test_mvcc_rollback() -> Result<()> {
         let engine = Bitcask::new(Cursor::new(Vec::new())).unwrap();
@@ -1,4 +1,5 @@
 pub mod schema;
+pub mod statistics;
 pub mod value;
 
-pub use self::{schema::*, value::*};
+pub use self::{schema::*, statistics::*, value::*};
@@ -0,0 +1,290 @@
+use chrono::Datelike;
+use serde::{Deserialize, Serialize};
+
+use crate::{encoding::ValueEncoding, error::Error};
+
+use super::{
+    schema::Table,
+    value::{Row, Value},
+};
+
+/// The number of rows sampled from a table when it is analyzed.
+pub const SAMPLE_SIZE: usize = 30_000;
+
+/// The number of buckets in the histogram of each column.
+pub const HISTOGRAM_BUCKETS: usize = 100;
+
+/// The number of rows that must be modified before a table's statistics are stale, on top
+/// of [`AUTO_ANALYZE_FRACTION`] of its rows.
+pub const AUTO_ANALYZE_THRESHOLD: u64 = 50;
+
+/// The fraction of a table's rows that must be modified, on top of
+/// [`AUTO_ANALYZE_THRESHOLD`], before its statistics are stale.
+pub const AUTO_ANALYZE_FRACTION: f64 = 0.1;
+
+/// Statistics about the rows of a table, which the planner uses to estimate the cost of
+/// plans.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TableStatistics {
+    pub row_count: u64,
+    /// The number of rows inserted or deleted since the table was last analyzed.
+    pub modified_count: u64,
+    /// The statistics of each column, in the order of the table's columns. Empty if the
+    /// table has not been analyzed yet.
+    pub columns: Vec<ColumnStatistics>,
+}
+
+impl ValueEncoding for TableStatistics {}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ColumnStatistics {
+    /// The number of distinct non-NULL values in the column.
+    pub distinct_count: u64,
+    /// The fraction of rows in which the column is NULL.
+    pub null_fraction: f64,
+    pub min: Option<Value>,
+    pub max: Option<Value>,
+    /// The bounds of an equi-depth histogram of the non-NULL values, in ascending order.
+    /// Each pair of adjacent bounds delimits a bucket holding about the same number of
+    /// rows. Frequent values may appear as several bounds.
+    pub histogram: Vec<Value>,
+}
+
+impl TableStatistics {
+    pub fn new(row_count: u64) -> Self {
+        Self {
+            row_count,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_column(mut self, column: ColumnStatistics) -> Self {
+        self.columns.push(column);
+        self
+    }
+
+    /// Returns whether enough rows have been modified since the table was last analyzed
+    /// that it should be analyzed again.
+    pub fn is_stale(&self) -> bool {
+        let threshold =
+            AUTO_ANALYZE_THRESHOLD as f64 + AUTO_ANALYZE_FRACTION * self.row_count as f64;
+        self.modified_count as f64 > threshold
+    }
+
+    /// Computes the statistics of a table from all of its rows. The row count and the
+    /// bounds of each column are exact, while the rest is estimated from a uniform sample
+    /// of at most [`SAMPLE_SIZE`] rows.
+    pub fn analyze(
+        table: &Table,
+        rows: impl Iterator<Item = Result<Row, Error>>,
+    ) -> Result<Self, Error> {
+        let mut sample = Vec::new();
+        let mut random = SplitMix64::new(table.columns.len() as u64);
+        let mut bounds: Vec<Option<(Value, Value)>> = vec![None; table.columns.len()];
+        let mut row_count = 0;
+        for row in rows {
+            let row = row?;
+            for (bounds, value) in bounds.iter_mut().zip(row.iter()) {
+                if *value == Value::Null {
+                    continue;
+                }
+                match bounds {
+                    Some((min, max)) => {
+                        if value < min {
+                            *min = value.clone();
+                        } else if value > max {
+                            *max = value.clone();
+                        }
+                    }
+                    None => *bounds = Some((value.clone(), value.clone())),
+                }
+            }
+
+            // Reservoir sampling: the n-th row replaces a random row of a full sample
+            // with a probability of SAMPLE_SIZE / n.
+            row_count += 1;
+            if sample.len() < SAMPLE_SIZE {
+                sample.push(row);
+            } else {
+                let i = random.next_u64() % row_count;
+                if let Some(slot) = sample.get_mut(i as usize) {
+                    *slot = row;
+                }
+            }
+        }
+
+        let columns = bounds
+            .into_iter()
+            .enumerate()
+            .map(|(i, bounds)| {
+                let mut values = sample
+                    .iter()
+                    .filter_map(|row| row.get(i).filter(|value| **value != Value::Null))
+                    .collect::<Vec<_>>();
+                values.sort();
+                let (min, max) = bounds.unzip();
+                ColumnStatistics {
+                    distinct_count: estimate_distinct(&values, sample.len(), row_count),
+                    null_fraction: match sample.len() {
+                        0 => 0.0,
+                        n => (n - values.len()) as f64 / n as f64,
+                    },
+                    min,
+                    max,
+                    histogram: histogram(&values),
+                }
+            })
+            .collect();
+
+        Ok(Self {
+            row_count,
+            modified_count: 0,
+            columns,
+        })
+    }
+}
+
+impl ColumnStatistics {
+    pub fn new(distinct_count: u64) -> Self {
+        Self {
+            distinct_count,
+            ..Default::default()
+        }
+    }
+
+    /// Estimates the fraction of the column's non-NULL values that are less than `value`,
+    /// or less than or equal to it with `inclusive`, from its histogram.
+    pub fn fraction_below(&self, value: &Value, inclusive: bool) -> Option<f64> {
+        if self.histogram.is_empty() {
+            return None;
+        }
+        let below = match inclusive {
+            true => self.histogram.partition_point(|bound| bound <= value),
+            false => self.histogram.partition_point(|bound| bound < value),
+        };
+        if below == 0 {
+            return Some(0.0);
+        }
+        if below == self.histogram.len() {
+            return Some(1.0);
+        }
+
+        // The value falls in the bucket between the last bound below it and the next one,
+        // which is assumed to be filled evenly if the values are numeric.
+        let (low, high) = (&self.histogram[below - 1], &self.histogram[below]);
+        let within = match (as_f64(low), as_f64(high), as_f64(value)) {
+            (Some(low), Some(high), Some(value)) if high > low => (value - low) / (high - low),
+            _ => 0.5,
+        };
+        let buckets = (self.histogram.len() - 1) as f64;
+        Some(((below - 1) as f64 + within) / buckets)
+    }
+}
+
+fn as_f64(value: &Value) -> Option<f64> {
+    match value {
+        Value::Integer(v) => Some(*v as f64),
+        Value::Float(v) => Some(*v),
+        Value::Date(v) => Some(v.num_days_from_ce() as f64),
+        _ => None,
+    }
+}
+
+/// Estimates the number of distinct values in a column from the sorted non-NULL values
+/// of a sample of `sample_rows` out of `total_rows` rows, with the Duj1 estimator of
+/// Haas and Stokes. Values that appear only once in the sample are taken as a sign that
+/// the column has more values that weren't sampled.
+fn estimate_distinct(values: &[&Value], sample_rows: usize, total_rows: u64) -> u64 {
+    let mut distinct = 0;
+    let mut singletons = 0;
+    let mut i = 0;
+    while i < values.len() {
+        let run = values[i..].iter().take_while(|v| **v == values[i]).count();
+        distinct += 1;
+        if run == 1 {
+            singletons += 1;
+        }
+        i += run;
+    }
+    if sample_rows as u64 >= total_rows || values.is_empty() {
+        return distinct;
+    }
+
+    let n = values.len() as f64;
+    let total = n * total_rows as f64 / sample_rows as f64;
+    let singletons = singletons as f64;
+    let estimate = n * distinct as f64 / (n - singletons + singletons * n / total);
+    (estimate.round() as u64).clamp(distinct, total as u64)
+}
+
+/// Picks the bounds of an equi-depth histogram from sorted values.
+fn histogram(values: &[&Value]) -> Vec<Value> {
+    if values.is_empty() {
+        return Vec::new();
+    }
+    let buckets = HISTOGRAM_BUCKETS.min(values.len() - 1).max(1);
+    (0..=buckets)
+        .map(|i| values[i * (values.len() - 1) / buckets].clone())
+        .collect()
+}
+
+/// A small, fast pseudo-random number generator, for picking rows to sample.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{schema::Column, value::DataType};
+
+    #[test]
+    fn test_analyze() {
+        let table = Table::new("t", 0).with_columns([
+            Column::new("id", DataType::Integer),
+            Column::new("x", DataType::Integer),
+        ]);
+        let rows = (0..100_000).map(|i| {
+            let x = match i % 4 {
+                0 => Value::Null,
+                _ => Value::Integer(i % 1000),
+            };
+            Ok(Row::from(vec![Value::Integer(i), x]))
+        });
+        let statistics = TableStatistics::analyze(&table, rows).unwrap();
+        assert_eq!(statistics.row_count, 100_000);
+
+        let id = &statistics.columns[0];
+        assert_eq!(id.null_fraction, 0.0);
+        assert_eq!(id.min, Some(Value::Integer(0)));
+        assert_eq!(id.max, Some(Value::Integer(99_999)));
+        assert!(id.distinct_count > 50_000, "{}", id.distinct_count);
+        assert_eq!(id.histogram.len(), HISTOGRAM_BUCKETS + 1);
+        let below = id.fraction_below(&Value::Integer(25_000), false).unwrap();
+        assert!((below - 0.25).abs() < 0.02, "{below}");
+
+        let x = &statistics.columns[1];
+        assert!((x.null_fraction - 0.25).abs() < 0.02, "{}", x.null_fraction);
+        assert_eq!(x.min, Some(Value::Integer(1)));
+        assert_eq!(x.max, Some(Value::Integer(999)));
+        assert!(
+            (700..=800).contains(&x.distinct_count),
+            "{}",
+            x.distinct_count
+        );
+        assert_eq!(x.fraction_below(&Value::Integer(0), true), Some(0.0));
+        assert_eq!(x.fraction_below(&Value::Integer(999), true), Some(1.0));
+    }
+}
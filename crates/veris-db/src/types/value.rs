@@ -74,6 +74,23 @@ impl TryFrom<&ast::DataType> for DataType {
     }
 }
 
+impl DataType {
+    /// Returns whether values of this type can be combined or compared with values of the
+    /// other type. Numeric types are compatible with each other, as are all string types.
+    pub fn is_compatible_with(&self, other: &DataType) -> bool {
+        matches!(
+            (self, other),
+            (DataType::Boolean, DataType::Boolean)
+                | (DataType::Date, DataType::Date)
+                | (DataType::String { .. }, DataType::String { .. })
+                | (
+                    DataType::Integer | DataType::Float | DataType::Decimal { .. },
+                    DataType::Integer | DataType::Float | DataType::Decimal { .. },
+                )
+        )
+    }
+}
+
 impl fmt::Display for DataType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -135,6 +152,18 @@ impl Value {
         matches!(self, Value::Boolean(true))
     }
 
+    /// Returns the data type of the value, or `None` if it is null.
+    pub fn data_type(&self) -> Option<DataType> {
+        match self {
+            Value::Null => None,
+            Value::Boolean(_) => Some(DataType::Boolean),
+            Value::Integer(_) => Some(DataType::Integer),
+            Value::Float(_) => Some(DataType::Float),
+            Value::String(_) => Some(DataType::String { length: None }),
+            Value::Date(_) => Some(DataType::Date),
+        }
+    }
+
     /// Checks if the value is compatible with the given data type.
     pub fn is_compatible(&self, data_type: &DataType) -> bool {
         match (self, data_type) {
@@ -253,6 +282,52 @@ impl Value {
         }
     }
 
+    /// Converts the value to the given data type as an explicit `CAST` would. Unlike
+    /// `try_cast`, this also converts between numeric types, booleans and strings.
+    pub fn cast(&self, data_type: &DataType) -> Result<Value> {
+        let invalid = || Error::InvalidCast {
+            value: self.clone(),
+            to: *data_type,
+        };
+        match (self, data_type) {
+            (Value::Integer(i), DataType::Float | DataType::Decimal { .. }) => {
+                Value::Float(*i as f64).try_cast(data_type)
+            }
+            (Value::Float(f), DataType::Integer) => {
+                let f = f.round();
+                if f.is_finite() && f >= i64::MIN as f64 && f < i64::MAX as f64 {
+                    Ok(Value::Integer(f as i64))
+                } else {
+                    Err(invalid())
+                }
+            }
+            (Value::Boolean(b), DataType::Integer) => Ok(Value::Integer(*b as i64)),
+            (Value::Integer(i), DataType::Boolean) => Ok(Value::Boolean(*i != 0)),
+            (Value::String(s), DataType::Boolean) => match s.trim().to_lowercase().as_str() {
+                "true" | "t" | "yes" | "y" | "1" => Ok(Value::Boolean(true)),
+                "false" | "f" | "no" | "n" | "0" => Ok(Value::Boolean(false)),
+                _ => Err(invalid()),
+            },
+            (Value::String(s), DataType::Integer | DataType::Float | DataType::Decimal { .. }) => {
+                match s.trim().parse::<f64>() {
+                    Ok(f) => Value::Float(f).cast(data_type),
+                    Err(_) => Err(invalid()),
+                }
+            }
+            (
+                Value::Boolean(_) | Value::Integer(_) | Value::Float(_) | Value::Date(_),
+                DataType::String { .. },
+            ) => {
+                let text = match self {
+                    Value::Date(d) => d.to_string(),
+                    value => value.to_string(),
+                };
+                Value::String(text).try_cast(data_type)
+            }
+            _ => self.try_cast(data_type),
+        }
+    }
+
     /// Attempts to create a value from an AST value and an optional type hint.
     pub fn try_from_ast(value: &ast::Value, type_hint: Option<DataType>) -> Result<Self> {
         match value {
@@ -347,6 +422,22 @@ impl Value {
         }
     }
 
+    /// Attempts to compute the remainder of dividing two values, which has the sign of the
+    /// dividend.
+    pub fn checked_rem(&self, other: &Self) -> Result<Self> {
+        match (self, other) {
+            (_, Value::Integer(0)) => Err(Error::DivisionByZero),
+            (_, Value::Float(b)) if *b == 0.0 => Err(Error::DivisionByZero),
+            (Value::Integer(a), Value::Integer(b)) => Ok(Value::Integer(
+                a.checked_rem(*b).ok_or(Error::IntegerOverflow)?,
+            )),
+            (Value::Integer(a), Value::Float(b)) => Ok(Self::Float(*a as f64 % *b)),
+            (Value::Float(a), Value::Integer(b)) => Ok(Self::Float(*a % *b as f64)),
+            (Value::Float(a), Value::Float(b)) => Ok(Self::Float(*a % *b)),
+            _ => Err(Error::NotYetSupported(format!("{self} % {other}"))),
+        }
+    }
+
     /// Attempts to subtract two values.
     /// This may result in a different type than one of the original values (e.g. subtracting an integer from a float results in a float).
     pub fn checked_sub(&self, other: &Self) -> Result<Self> {
@@ -508,6 +599,7 @@ impl From<NaiveDate> for Value {
     Eq,
     PartialOrd,
     Ord,
+    Hash,
     Deref,
     DerefMut,
     Index,
@@ -1,5 +1,21 @@
 #[cfg(test)]
 pub mod tests {
+    use std::{
+        alloc::{GlobalAlloc, Layout, System},
+        cell::Cell,
+        io::Cursor,
+    };
+
+    use sqlparser::{dialect::GenericDialect, parser::Parser};
+
+    use crate::{
+        engine::{Engine, local::Local},
+        error::Error,
+        exec::session::{Session, StatementResult},
+        storage::bitcask::Bitcask,
+        types::value::Row,
+    };
+
     #[macro_export]
     macro_rules! sql_stmt {
         ($stmt:ident, $sql:expr) => {{
@@ -14,4 +30,115 @@ pub mod tests {
             stmt
         }};
     }
+
+    pub type TestEngine = Local<Bitcask<Cursor<Vec<u8>>>>;
+
+    pub fn test_engine() -> TestEngine {
+        Local::new(Bitcask::new(Cursor::new(Vec::new())).unwrap())
+    }
+
+    /// Creates an in-memory engine populated with the tables from `sql/create.sql` and
+    /// the rows from `sql/insert.sql`.
+    pub fn test_engine_with_data() -> TestEngine {
+        let engine = test_engine();
+        let mut session = Session::new(&engine);
+        execute(&mut session, include_str!("../../../sql/create.sql")).unwrap();
+        execute(&mut session, include_str!("../../../sql/insert.sql")).unwrap();
+        engine
+    }
+
+    /// Creates a table `t (id, x, y, name)` with `count` generated rows. Every tenth row
+    /// only has an id, and the others cycle through 13 values of `x`, 7 of `y` and 299
+    /// names.
+    pub fn create_generated_table<E: Engine>(session: &mut Session<'_, E>, count: i64) {
+        execute(
+            session,
+            "CREATE TABLE t (id INT PRIMARY KEY, x INT, y FLOAT, name VARCHAR)",
+        )
+        .unwrap();
+        let values = (0..count)
+            .map(|i| match i % 10 {
+                0 => format!("({i}, NULL, NULL, NULL)"),
+                _ => format!("({i}, {}, {}.5, 'name{}')", i % 13, i % 7, i % 299),
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        execute(session, &format!("INSERT INTO t VALUES {values}")).unwrap();
+    }
+
+    /// Executes every statement in `sql`, returning the result of the last one.
+    pub fn execute<E: Engine>(
+        session: &mut Session<'_, E>,
+        sql: &str,
+    ) -> Result<StatementResult, Error> {
+        let stmts = Parser::parse_sql(&GenericDialect {}, sql)
+            .map_err(|e| Error::InvalidSql(e.to_string()))?;
+        let mut result = StatementResult::Null;
+        for stmt in &stmts {
+            result = session.exec(stmt)?;
+        }
+        Ok(result)
+    }
+
+    /// Executes `sql` and returns the rows of the resulting query.
+    pub fn query<E: Engine>(session: &mut Session<'_, E>, sql: &str) -> Vec<Row> {
+        match execute(session, sql).unwrap() {
+            StatementResult::Query { rows, .. } => rows,
+            result => panic!("expected a query result, got {result:?}"),
+        }
+    }
+
+    thread_local! {
+        static ALLOCATED: Cell<isize> = const { Cell::new(0) };
+        static PEAK_ALLOCATED: Cell<isize> = const { Cell::new(0) };
+    }
+
+    /// An allocator that keeps track of the memory allocated by each thread, so that tests
+    /// (which run on separate threads) can measure how much memory an operation needs.
+    struct TrackingAllocator;
+
+    impl TrackingAllocator {
+        fn track(delta: isize) {
+            let _ = ALLOCATED.try_with(|allocated| {
+                allocated.set(allocated.get() + delta);
+                let _ = PEAK_ALLOCATED.try_with(|peak| peak.set(peak.get().max(allocated.get())));
+            });
+        }
+    }
+
+    unsafe impl GlobalAlloc for TrackingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let ptr = unsafe { System.alloc(layout) };
+            if !ptr.is_null() {
+                Self::track(layout.size() as isize);
+            }
+            ptr
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            unsafe { System.dealloc(ptr, layout) };
+            Self::track(-(layout.size() as isize));
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            let new_ptr = unsafe { System.realloc(ptr, layout, new_size) };
+            if !new_ptr.is_null() {
+                Self::track(new_size as isize - layout.size() as isize);
+            }
+            new_ptr
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: TrackingAllocator = TrackingAllocator;
+
+    /// Runs `f` and returns its result along with the peak number of bytes it had allocated
+    /// on the current thread at any one time.
+    pub fn peak_memory<R>(f: impl FnOnce() -> R) -> (R, usize) {
+        let start = ALLOCATED.with(Cell::get);
+        PEAK_ALLOCATED.with(|peak| peak.set(start));
+        let result = f();
+        let peak = PEAK_ALLOCATED.with(Cell::get);
+        (result, (peak - start).max(0) as usize)
+    }
 }
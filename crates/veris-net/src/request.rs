@@ -1,11 +1,27 @@
 use derive_more::Display;
 use serde::{Deserialize, Serialize};
-use veris_db::exec::session::StatementResult;
+use veris_db::{exec::session::StatementResult, types::value::Value};
 
 #[derive(Debug, Serialize, Deserialize, Display)]
 pub enum Request {
     Execute(String),
     Debug(String),
+    /// Prepares a single statement, which later requests refer to by `name`. It may have
+    /// `$1`, `$2`, ... or `?` placeholders for parameters.
+    #[display("PREPARE {name} AS {sql}")]
+    Prepare {
+        name: String,
+        sql: String,
+    },
+    /// Executes a prepared statement, with a value for each of its parameters.
+    #[display("EXECUTE {name} with {} parameters", parameters.len())]
+    ExecutePrepared {
+        name: String,
+        parameters: Vec<Value>,
+    },
+    /// Removes a prepared statement.
+    #[display("DEALLOCATE {_0}")]
+    Deallocate(String),
 }
 
 #[derive(Debug, Serialize, Deserialize)]
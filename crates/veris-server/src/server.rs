@@ -7,6 +7,7 @@ use tokio::{
 };
 use veris_db::{
     engine::local::Local,
+    error::Error,
     exec::session::{Session, StatementResult},
     storage::bitcask::Bitcask,
 };
@@ -121,29 +122,63 @@ impl Server {
 
                 let mut results = Vec::new();
                 for statement in &ast {
-                    match session.exec(statement) {
-                        Ok(val) => {
-                            results.push((statement.to_string(), val));
-                        }
-                        Err(e) => {
-                            log::error!("Failed to execute SQL: {}", e);
-                            if let Err(e) = session.rollback() {
-                                log::error!("Failed to rollback: {}", e);
-                            } else {
-                                log::info!("Rolled back transaction");
-                            }
-
-                            results.push((
-                                statement.to_string(),
-                                StatementResult::Error(e.to_string()),
-                            ));
-                            return Response::Execute(results);
-                        }
+                    let result = session.exec(statement);
+                    let result = Self::statement_result(session, result);
+                    let failed = matches!(result, StatementResult::Error(_));
+                    results.push((statement.to_string(), result));
+                    if failed {
+                        break;
                     }
                 }
 
                 Response::Execute(results)
             }
+            Request::Prepare { name, sql } => {
+                let statement = match Parser::parse_sql(&GenericDialect {}, sql) {
+                    Ok(mut ast) if ast.len() == 1 => ast.remove(0),
+                    Ok(_) => return Response::Error("Expected a single statement".to_string()),
+                    Err(e) => {
+                        log::error!("Failed to parse SQL: {}", e);
+                        return Response::Error(e.to_string());
+                    }
+                };
+                let result = session.prepare(name, &statement, Vec::new());
+                Response::Execute(vec![(sql.clone(), Self::statement_result(session, result))])
+            }
+            Request::ExecutePrepared { name, parameters } => {
+                let result = session.execute(name, parameters.clone());
+                Response::Execute(vec![(
+                    name.clone(),
+                    Self::statement_result(session, result),
+                )])
+            }
+            Request::Deallocate(name) => {
+                let result = session.deallocate(name);
+                Response::Execute(vec![(
+                    name.clone(),
+                    Self::statement_result(session, result),
+                )])
+            }
+        }
+    }
+
+    /// Turns the result of a statement into a response, rolling back the session's
+    /// transaction if it failed.
+    fn statement_result(
+        session: &mut Session<'_, Engine>,
+        result: Result<StatementResult, Error>,
+    ) -> StatementResult {
+        match result {
+            Ok(result) => result,
+            Err(e) => {
+                log::error!("Failed to execute SQL: {}", e);
+                if let Err(e) = session.rollback() {
+                    log::error!("Failed to rollback: {}", e);
+                } else {
+                    log::info!("Rolled back transaction");
+                }
+                StatementResult::Error(e.to_string())
+            }
         }
     }
 }